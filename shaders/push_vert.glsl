@@ -0,0 +1,11 @@
+#version 450
+#pragma shader_stage(vertex)
+
+layout(push_constant) uniform PushConstants {
+    mat4 transform;
+    vec4 offset;
+} push;
+
+void main() {
+    gl_Position = push.transform * vec4(0.0, 0.0, 0.0, 1.0) + push.offset;
+}
@@ -0,0 +1,12 @@
+#version 450
+#pragma shader_stage(compute)
+
+layout(local_size_x = 64) in;
+
+layout(set = 0, binding = 0) buffer blk1 {
+    uint values[];
+};
+
+void main() {
+    values[gl_GlobalInvocationID.x % values.length()] += 1;
+}
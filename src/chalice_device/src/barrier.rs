@@ -0,0 +1,546 @@
+use crate::*;
+
+/// Describes how a command intends to access an image.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ImageUsage {
+    TransferSrc,
+    TransferDst,
+    SampleVertex,
+    SampleFragment,
+    SampleCompute,
+    /// Read and written as a storage image by a compute shader.
+    StorageCompute,
+    ColorAttachment,
+    DepthStencilAttachment,
+    /// Read-only depth testing while also sampling in fragment shaders.
+    DepthStencilReadOnly,
+    InputAttachment,
+    Present,
+}
+
+/// The pipeline stages, access types, and layout of an image access.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ImageAccess {
+    pub stages: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+    pub layout: vk::ImageLayout,
+}
+
+/// Tracked synchronization state of a single mip level and layer of an
+/// image. All aspects of a subresource are tracked together.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SubresourceState {
+    pub layout: vk::ImageLayout,
+    /// The owning queue family, or `QUEUE_FAMILY_IGNORED` if the image
+    /// has not been used yet.
+    pub queue_family: u32,
+    // Stages and accesses of the last write, including layout
+    // transitions.
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    // Stages and accesses the last write has been made visible to.
+    visible_stages: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+    // Stages which have read the image since the last write.
+    read_stages: vk::PipelineStageFlags,
+    // Set by a queue family ownership release; the source family and
+    // the layout prior to the release.
+    acquire: Option<(u32, vk::ImageLayout)>,
+}
+
+/// Per-subresource state of an image, indexed by mip level, then layer.
+#[derive(Debug)]
+pub(crate) struct ImageState {
+    layers: u32,
+    subresources: Vec<SubresourceState>,
+}
+
+/// Image barriers which are recorded together.
+#[derive(Debug, Default)]
+pub(crate) struct BarrierBatch {
+    pub(crate) src_stages: vk::PipelineStageFlags,
+    pub(crate) dst_stages: vk::PipelineStageFlags,
+    pub(crate) barriers: Vec<vk::ImageMemoryBarrier>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Transition {
+    src_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_stages: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_family: u32,
+    dst_family: u32,
+}
+
+/// Every access flag which denotes a write.
+pub fn write_access() -> vk::AccessFlags {
+    vk::AccessFlags::SHADER_WRITE_BIT
+        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE_BIT
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE_BIT
+        | vk::AccessFlags::TRANSFER_WRITE_BIT
+        | vk::AccessFlags::HOST_WRITE_BIT
+        | vk::AccessFlags::MEMORY_WRITE_BIT
+}
+
+impl ImageUsage {
+    pub fn access(self) -> ImageAccess {
+        use vk::AccessFlags as A;
+        use vk::ImageLayout as L;
+        use vk::PipelineStageFlags as S;
+        let (stages, access, layout) = match self {
+            Self::TransferSrc => (
+                S::TRANSFER_BIT,
+                A::TRANSFER_READ_BIT,
+                L::TRANSFER_SRC_OPTIMAL,
+            ),
+            Self::TransferDst => (
+                S::TRANSFER_BIT,
+                A::TRANSFER_WRITE_BIT,
+                L::TRANSFER_DST_OPTIMAL,
+            ),
+            Self::SampleVertex => (
+                S::VERTEX_SHADER_BIT,
+                A::SHADER_READ_BIT,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::SampleFragment => (
+                S::FRAGMENT_SHADER_BIT,
+                A::SHADER_READ_BIT,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::SampleCompute => (
+                S::COMPUTE_SHADER_BIT,
+                A::SHADER_READ_BIT,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::StorageCompute => (
+                S::COMPUTE_SHADER_BIT,
+                A::SHADER_READ_BIT | A::SHADER_WRITE_BIT,
+                L::GENERAL,
+            ),
+            Self::ColorAttachment => (
+                S::COLOR_ATTACHMENT_OUTPUT_BIT,
+                A::COLOR_ATTACHMENT_READ_BIT | A::COLOR_ATTACHMENT_WRITE_BIT,
+                L::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthStencilAttachment => (
+                S::EARLY_FRAGMENT_TESTS_BIT | S::LATE_FRAGMENT_TESTS_BIT,
+                A::DEPTH_STENCIL_ATTACHMENT_READ_BIT | A::DEPTH_STENCIL_ATTACHMENT_WRITE_BIT,
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthStencilReadOnly => (
+                S::EARLY_FRAGMENT_TESTS_BIT | S::LATE_FRAGMENT_TESTS_BIT | S::FRAGMENT_SHADER_BIT,
+                A::DEPTH_STENCIL_ATTACHMENT_READ_BIT | A::SHADER_READ_BIT,
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ),
+            Self::InputAttachment => (
+                S::FRAGMENT_SHADER_BIT,
+                A::INPUT_ATTACHMENT_READ_BIT,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::Present => (S::BOTTOM_OF_PIPE_BIT, A::empty(), L::PRESENT_SRC_KHR),
+        };
+        ImageAccess {
+            stages,
+            access,
+            layout,
+        }
+    }
+
+    #[inline]
+    pub fn is_write(self) -> bool {
+        self.access().is_write()
+    }
+}
+
+impl ImageAccess {
+    #[inline]
+    pub fn is_write(&self) -> bool {
+        self.access.intersects(write_access())
+    }
+}
+
+impl Default for SubresourceState {
+    fn default() -> Self {
+        Self {
+            layout: vk::ImageLayout::UNDEFINED,
+            queue_family: vk::QUEUE_FAMILY_IGNORED,
+            write_stages: Default::default(),
+            write_access: Default::default(),
+            visible_stages: Default::default(),
+            visible_access: Default::default(),
+            read_stages: Default::default(),
+            acquire: None,
+        }
+    }
+}
+
+impl SubresourceState {
+    /// True if the subresource was released by one queue family and
+    /// not yet acquired by another.
+    #[inline]
+    pub fn is_pending_acquire(&self) -> bool {
+        self.acquire.is_some()
+    }
+
+    fn prior_stages(&self) -> vk::PipelineStageFlags {
+        let stages = self.write_stages | self.read_stages;
+        if stages.is_empty() {
+            vk::PipelineStageFlags::TOP_OF_PIPE_BIT
+        } else {
+            stages
+        }
+    }
+
+    fn claim(&mut self, family: u32) {
+        if self.queue_family == vk::QUEUE_FAMILY_IGNORED {
+            self.queue_family = family;
+        } else if self.queue_family != family {
+            // Ownership of undefined contents may be taken freely.
+            assert_eq!(
+                self.layout,
+                vk::ImageLayout::UNDEFINED,
+                "image is owned by queue family {}; release it first",
+                self.queue_family,
+            );
+            self.queue_family = family;
+        }
+    }
+
+    fn after_write(&mut self, next: &ImageAccess) {
+        self.write_stages = next.stages;
+        self.write_access = next.access & write_access();
+        self.visible_stages = Default::default();
+        self.visible_access = Default::default();
+        self.read_stages = Default::default();
+    }
+
+    // Records a layout transition which is visible to `next`.
+    fn after_transition(&mut self, next: &ImageAccess) {
+        self.layout = next.layout;
+        self.write_stages = next.stages;
+        self.write_access = Default::default();
+        self.visible_stages = next.stages;
+        self.visible_access = next.access;
+        self.read_stages = next.stages;
+    }
+
+    fn acquire(&mut self, next: &ImageAccess, family: u32) -> Option<Transition> {
+        let (src_family, old_layout) = self.acquire?;
+        assert_eq!(
+            self.queue_family, family,
+            "image was released to another queue family"
+        );
+        self.acquire = None;
+        let transition = Transition {
+            src_stages: vk::PipelineStageFlags::TOP_OF_PIPE_BIT,
+            src_access: Default::default(),
+            dst_stages: next.stages,
+            dst_access: next.access,
+            old_layout,
+            new_layout: self.layout,
+            src_family,
+            dst_family: family,
+        };
+        self.after_transition(&ImageAccess {
+            layout: self.layout,
+            ..*next
+        });
+        Some(transition)
+    }
+
+    fn access(&mut self, next: &ImageAccess, family: u32) -> Option<Transition> {
+        self.claim(family);
+        let writes = next.is_write();
+        let old_layout = self.layout;
+        let relayout = next.layout != old_layout;
+        let transition = |src_stages, src_access| Transition {
+            src_stages,
+            src_access,
+            dst_stages: next.stages,
+            dst_access: next.access,
+            old_layout,
+            new_layout: next.layout,
+            src_family: vk::QUEUE_FAMILY_IGNORED,
+            dst_family: vk::QUEUE_FAMILY_IGNORED,
+        };
+
+        if relayout || writes {
+            let prior = self.prior_stages();
+            let has_prior = !(self.write_stages | self.read_stages).is_empty();
+            let res = (relayout || has_prior).then(|| transition(prior, self.write_access));
+            if writes {
+                self.layout = next.layout;
+                self.after_write(next);
+            } else {
+                self.after_transition(next);
+            }
+            return res;
+        }
+
+        // Read after read or a visible write requires no barrier.
+        let visible =
+            self.visible_stages.contains(next.stages) && self.visible_access.contains(next.access);
+        let res = if self.write_stages.is_empty() || visible {
+            None
+        } else {
+            self.visible_stages |= next.stages;
+            self.visible_access |= next.access;
+            Some(transition(self.write_stages, self.write_access))
+        };
+        self.read_stages |= next.stages;
+        res
+    }
+
+    fn release(&mut self, next: &ImageAccess, family: u32, dst_family: u32) -> Transition {
+        self.claim(family);
+        let transition = Transition {
+            src_stages: self.prior_stages(),
+            src_access: self.write_access,
+            dst_stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE_BIT,
+            dst_access: Default::default(),
+            old_layout: self.layout,
+            new_layout: next.layout,
+            src_family: family,
+            dst_family,
+        };
+        *self = Self {
+            layout: next.layout,
+            queue_family: dst_family,
+            acquire: Some((family, self.layout)),
+            ..Default::default()
+        };
+        transition
+    }
+}
+
+impl BarrierBatch {
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.barriers.is_empty()
+    }
+
+    fn push(&mut self, image: vk::Image, range: vk::ImageSubresourceRange, t: Transition) {
+        self.src_stages |= t.src_stages;
+        self.dst_stages |= t.dst_stages;
+        self.barriers.push(vk::ImageMemoryBarrier {
+            src_access_mask: t.src_access,
+            dst_access_mask: t.dst_access,
+            old_layout: t.old_layout,
+            new_layout: t.new_layout,
+            src_queue_family_index: t.src_family,
+            dst_queue_family_index: t.dst_family,
+            image,
+            subresource_range: range,
+            ..Default::default()
+        });
+    }
+}
+
+impl ImageState {
+    pub(crate) fn new(mip_levels: u32, layers: u32) -> Self {
+        Self {
+            layers,
+            subresources: vec![Default::default(); (mip_levels * layers) as usize],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, mip_level: u32, layer: u32) -> &SubresourceState {
+        &self.subresources[(mip_level * self.layers + layer) as usize]
+    }
+
+    // Applies `f` to each subresource, merging runs of layers with
+    // identical transitions into a single barrier.
+    fn for_each(
+        &mut self,
+        image: vk::Image,
+        sub: &ImageSubresources,
+        batch: &mut BarrierBatch,
+        mut f: impl FnMut(&mut SubresourceState) -> Option<Transition>,
+    ) {
+        let range = |mip_level, layers: [u32; 2]| vk::ImageSubresourceRange {
+            aspect_mask: sub.aspects,
+            base_mip_level: mip_level,
+            level_count: 1,
+            base_array_layer: layers[0],
+            layer_count: layers[1] - layers[0],
+        };
+        for mip_level in sub.mip_level_range() {
+            let mut run: Option<(Transition, [u32; 2])> = None;
+            for layer in sub.layers[0]..sub.layers[1] {
+                let idx = (mip_level * self.layers + layer) as usize;
+                let t = f(&mut self.subresources[idx]);
+                match run {
+                    Some((prev, ref mut layers)) if Some(prev) == t => layers[1] = layer + 1,
+                    _ => {
+                        if let Some((prev, layers)) = run.take() {
+                            batch.push(image, range(mip_level, layers), prev);
+                        }
+                        run = t.map(|t| (t, [layer, layer + 1]));
+                    }
+                }
+            }
+            if let Some((prev, layers)) = run {
+                batch.push(image, range(mip_level, layers), prev);
+            }
+        }
+    }
+
+    /// Updates the state for an access by `family` and appends any
+    /// required barriers. Ownership acquisitions are appended to
+    /// `acquires`, which must be recorded first.
+    pub(crate) fn access(
+        &mut self,
+        image: vk::Image,
+        sub: &ImageSubresources,
+        next: &ImageAccess,
+        family: u32,
+        acquires: &mut BarrierBatch,
+        batch: &mut BarrierBatch,
+    ) {
+        self.for_each(image, sub, acquires, |state| state.acquire(next, family));
+        self.for_each(image, sub, batch, |state| state.access(next, family));
+    }
+
+    pub(crate) fn release(
+        &mut self,
+        image: vk::Image,
+        sub: &ImageSubresources,
+        next: &ImageAccess,
+        family: u32,
+        dst_family: u32,
+        batch: &mut BarrierBatch,
+    ) {
+        self.for_each(image, sub, batch, |state| {
+            Some(state.release(next, family, dst_family))
+        });
+    }
+
+    pub(crate) fn discard(&mut self, sub: &ImageSubresources) {
+        self.for_each(vk::null(), sub, &mut Default::default(), |state| {
+            state.layout = vk::ImageLayout::UNDEFINED;
+            state.acquire = None;
+            None
+        });
+    }
+
+    pub(crate) fn assume(&mut self, sub: &ImageSubresources, usage: &ImageAccess, family: u32) {
+        self.for_each(vk::null(), sub, &mut Default::default(), |state| {
+            *state = SubresourceState {
+                layout: usage.layout,
+                queue_family: family,
+                write_stages: usage.stages,
+                write_access: usage.access & write_access(),
+                read_stages: usage.stages,
+                ..Default::default()
+            };
+            None
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: u32 = 0;
+
+    fn access(state: &mut SubresourceState, usage: ImageUsage) -> Option<Transition> {
+        state.access(&usage.access(), FAMILY)
+    }
+
+    #[test]
+    fn upload_then_sample() {
+        let mut state = SubresourceState::default();
+
+        let t = access(&mut state, ImageUsage::TransferDst).unwrap();
+        assert_eq!(t.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(t.new_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(t.src_stages, vk::PipelineStageFlags::TOP_OF_PIPE_BIT);
+        assert_eq!(state.queue_family, FAMILY);
+
+        let t = access(&mut state, ImageUsage::SampleFragment).unwrap();
+        assert_eq!(t.src_stages, vk::PipelineStageFlags::TRANSFER_BIT);
+        assert_eq!(t.src_access, vk::AccessFlags::TRANSFER_WRITE_BIT);
+        assert_eq!(t.dst_access, vk::AccessFlags::SHADER_READ_BIT);
+        assert_eq!(t.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        // Repeated reads require no barrier
+        assert_eq!(access(&mut state, ImageUsage::SampleFragment), None);
+
+        // Reading in a new stage only needs an execution dependency
+        let t = access(&mut state, ImageUsage::SampleCompute).unwrap();
+        assert_eq!(t.old_layout, t.new_layout);
+        assert_eq!(t.src_stages, vk::PipelineStageFlags::FRAGMENT_SHADER_BIT);
+        assert_eq!(t.src_access, vk::AccessFlags::empty());
+        assert_eq!(access(&mut state, ImageUsage::SampleCompute), None);
+    }
+
+    #[test]
+    fn write_after_read() {
+        let mut state = SubresourceState::default();
+        access(&mut state, ImageUsage::StorageCompute);
+        // Write after write in the same layout
+        let t = access(&mut state, ImageUsage::StorageCompute).unwrap();
+        assert_eq!(t.old_layout, t.new_layout);
+        assert_eq!(t.src_access, vk::AccessFlags::SHADER_WRITE_BIT);
+
+        access(&mut state, ImageUsage::TransferSrc).unwrap();
+        let t = access(&mut state, ImageUsage::TransferDst).unwrap();
+        assert_eq!(t.src_stages, vk::PipelineStageFlags::TRANSFER_BIT);
+        assert_eq!(t.old_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    }
+
+    #[test]
+    fn queue_family_transfer() {
+        let mut state = SubresourceState::default();
+        let next = ImageUsage::SampleFragment.access();
+        access(&mut state, ImageUsage::TransferDst);
+        let t = state.release(&next, FAMILY, 1);
+        assert_eq!((t.src_family, t.dst_family), (FAMILY, 1));
+        assert_eq!(t.old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(t.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert!(state.is_pending_acquire());
+
+        let t = state.acquire(&next, 1).unwrap();
+        assert_eq!((t.src_family, t.dst_family), (FAMILY, 1));
+        assert_eq!(t.old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(t.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(state.access(&next, 1), None);
+    }
+
+    #[test]
+    fn merge_layers() {
+        let mut state = ImageState::new(2, 4);
+        let (mut acquires, mut batch) = Default::default();
+        let aspects = vk::ImageAspectFlags::COLOR_BIT;
+        let usage = ImageUsage::SampleFragment.access();
+        let sub = ImageSubresources::new(aspects, [0, 1], [1, 3]);
+        state.access(vk::null(), &sub, &usage, FAMILY, &mut acquires, &mut batch);
+        assert!(acquires.is_empty());
+        assert_eq!(batch.barriers.len(), 1);
+
+        let mut batch = BarrierBatch::default();
+        let sub = ImageSubresources::new(aspects, [0, 2], [0, 4]);
+        state.access(vk::null(), &sub, &usage, FAMILY, &mut acquires, &mut batch);
+        // Layers 0 and 3 of level 0, all of level 1
+        assert_eq!(batch.barriers.len(), 3);
+        let ranges: Vec<_> = batch
+            .barriers
+            .iter()
+            .map(|b| {
+                let range = b.subresource_range;
+                (
+                    range.base_mip_level,
+                    range.base_array_layer,
+                    range.layer_count,
+                )
+            })
+            .collect();
+        assert_eq!(ranges, [(0, 0, 1), (0, 3, 1), (1, 0, 4)]);
+    }
+}
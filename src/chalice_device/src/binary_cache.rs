@@ -0,0 +1,283 @@
+use std::ffi::c_void;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian};
+use derive_more::Display;
+use log::{debug, trace};
+
+use crate::*;
+
+/// A driver-side cache of compiled pipeline binaries, i.e. a
+/// `VkPipelineCache`. Its contents may be saved to disk and reused on
+/// later runs to speed up pipeline creation.
+///
+/// Unlike `PipelineCache`, which maps descriptions to pipeline
+/// objects, this cache is opaque and owned by the driver.
+#[derive(Debug)]
+pub struct PipelineBinaryCache {
+    device: Arc<Device>,
+    inner: vk::PipelineCache,
+    name: Option<String>,
+}
+
+/// The reason a serialized pipeline cache was rejected.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+pub enum PipelineCacheDataError {
+    #[display(fmt = "pipeline cache data is truncated")]
+    Truncated,
+    #[display(fmt = "unsupported pipeline cache header version {}", _0)]
+    HeaderVersion(u32),
+    #[display(fmt = "pipeline cache vendor ID mismatch")]
+    VendorId,
+    #[display(fmt = "pipeline cache device ID mismatch")]
+    DeviceId,
+    #[display(fmt = "pipeline cache UUID mismatch")]
+    Uuid,
+}
+
+impl std::error::Error for PipelineCacheDataError {}
+
+impl From<PipelineCacheDataError> for io::Error {
+    fn from(err: PipelineCacheDataError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+// Size of VkPipelineCacheHeaderVersionOne.
+const HEADER_SIZE: usize = 32;
+const HEADER_VERSION_ONE: u32 = 1;
+
+/// Checks that `data` was produced by a device compatible with
+/// `props`. Drivers are supposed to ignore incompatible data, but not
+/// all of them are robust against it.
+pub fn validate_pipeline_cache_data(
+    props: &vk::PhysicalDeviceProperties,
+    data: &[u8],
+) -> Result<(), PipelineCacheDataError> {
+    use PipelineCacheDataError::*;
+
+    // N.B. The header is always little-endian.
+    if data.len() < HEADER_SIZE {
+        return Err(Truncated);
+    }
+    let header_size = LittleEndian::read_u32(&data[0..4]) as usize;
+    if header_size < HEADER_SIZE || header_size > data.len() {
+        return Err(Truncated);
+    }
+    let header_version = LittleEndian::read_u32(&data[4..8]);
+    if header_version != HEADER_VERSION_ONE {
+        return Err(HeaderVersion(header_version));
+    }
+    if LittleEndian::read_u32(&data[8..12]) != props.vendor_id {
+        return Err(VendorId);
+    }
+    if LittleEndian::read_u32(&data[12..16]) != props.device_id {
+        return Err(DeviceId);
+    }
+    if data[16..32] != props.pipeline_cache_uuid[..] {
+        return Err(Uuid);
+    }
+
+    Ok(())
+}
+
+impl Drop for PipelineBinaryCache {
+    fn drop(&mut self) {
+        let dt = &*self.device.table;
+        unsafe {
+            dt.destroy_pipeline_cache(self.inner, ptr::null());
+        }
+    }
+}
+
+impl PipelineBinaryCache {
+    /// Creates an empty cache.
+    pub fn new(device: Arc<Device>) -> Self {
+        unsafe { Self::create(device, &[]) }
+    }
+
+    /// Creates a cache with initial contents. Data from a different
+    /// device or driver version is rejected.
+    pub fn with_data(device: Arc<Device>, data: &[u8]) -> Result<Self, PipelineCacheDataError> {
+        validate_pipeline_cache_data(device.properties(), data)?;
+        Ok(unsafe { Self::create(device, data) })
+    }
+
+    /// Loads a cache previously written by `save`.
+    pub fn from_path(device: Arc<Device>, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        debug!("loading pipeline cache from {:?}", path);
+        let data = fs::read(path)?;
+        Ok(Self::with_data(device, &data)?)
+    }
+
+    unsafe fn create(device: Arc<Device>, data: &[u8]) -> Self {
+        let dt = &*device.table;
+        let create_info = vk::PipelineCacheCreateInfo {
+            initial_data_size: data.len(),
+            p_initial_data: data.as_ptr() as *const c_void,
+            ..Default::default()
+        };
+        let mut inner = vk::null();
+        dt.create_pipeline_cache(&create_info, ptr::null(), &mut inner)
+            .check()
+            .unwrap();
+        Self {
+            device,
+            inner,
+            name: None,
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    #[inline]
+    pub fn inner(&self) -> vk::PipelineCache {
+        self.inner
+    }
+
+    /// Returns the serialized contents of the cache.
+    pub fn data(&self) -> Vec<u8> {
+        let dt = &*self.device.table;
+        unsafe {
+            let mut size = 0;
+            dt.get_pipeline_cache_data(self.inner, &mut size, ptr::null_mut())
+                .check()
+                .unwrap();
+            let mut data = vec![0u8; size];
+            dt.get_pipeline_cache_data(self.inner, &mut size, data.as_mut_ptr() as _)
+                .check()
+                .unwrap();
+            data.truncate(size);
+            data
+        }
+    }
+
+    /// Writes the contents of the cache to a file. The file is replaced
+    /// atomically so a crash mid-write can't leave a corrupt cache.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        trace!("PipelineBinaryCache::save(path: {:?})", path);
+        // Append to the full name so that neither the extension nor a
+        // concurrent save by another process can make the paths collide
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        fs::write(&tmp, self.data())?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Merges the contents of other caches into this one. Takes `&mut
+    /// self` because the destination must be externally synchronized.
+    pub fn merge(&mut self, sources: &[&PipelineBinaryCache]) {
+        let dt = &*self.device.table;
+        let sources: SmallVec<_, 4> = sources
+            .iter()
+            .map(|src| {
+                assert_eq!(src.device, self.device);
+                assert_ne!(src.inner, self.inner, "cannot merge a cache into itself");
+                src.inner
+            })
+            .collect();
+        unsafe {
+            dt.merge_pipeline_caches(self.inner, sources.len() as _, sources.as_ptr())
+                .check()
+                .unwrap();
+        }
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        let name: String = name.into();
+        self.name = Some(name.clone());
+        unsafe {
+            self.device.set_name(self.inner, name);
+        }
+    }
+}
+
+impl Named for PipelineBinaryCache {
+    fn name(&self) -> Option<&str> {
+        Some(self.name.as_ref()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn create_pipeline(vars: &TestVars, cache: &PipelineCache) {
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let trivial = TrivialCompute::new(&resources);
+        unsafe {
+            let _pipe = cache.get_or_create_compute(&trivial.pipe_desc());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let mut cache = PipelineCache::new(device);
+        create_pipeline(&vars, &cache);
+
+        let data = cache.binary_cache().data();
+        validate_pipeline_cache_data(device.properties(), &data).unwrap();
+        let loaded = PipelineBinaryCache::with_data(Arc::clone(device), &data).unwrap();
+        cache.binary_cache_mut().merge(&[&loaded]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let cache = PipelineCache::new(device);
+        create_pipeline(&vars, &cache);
+
+        let path = std::env::temp_dir().join("chalice_pipeline_cache_test.bin");
+        cache.binary_cache().save(&path).unwrap();
+        let _loaded = PipelineBinaryCache::from_path(Arc::clone(device), &path).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_mismatched_data() {
+        use PipelineCacheDataError::*;
+
+        let vars = TestVars::new();
+        let device = vars.device();
+        let props = device.properties();
+        let data = PipelineBinaryCache::new(Arc::clone(device)).data();
+        validate_pipeline_cache_data(props, &data).unwrap();
+
+        let check = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = data.clone();
+            f(&mut data);
+            validate_pipeline_cache_data(props, &data).unwrap_err()
+        };
+        assert_eq!(check(&|data| data.truncate(16)), Truncated);
+        assert_eq!(
+            check(&|data| LittleEndian::write_u32(&mut data[0..4], 1 << 20)),
+            Truncated,
+        );
+        assert_eq!(
+            check(&|data| LittleEndian::write_u32(&mut data[4..8], 2)),
+            HeaderVersion(2),
+        );
+        assert_eq!(check(&|data| data[8] ^= 0xff), VendorId);
+        assert_eq!(check(&|data| data[12] ^= 0xff), DeviceId);
+        assert_eq!(check(&|data| data[31] ^= 0xff), Uuid);
+        assert_eq!(validate_pipeline_cache_data(props, &[]), Err(Truncated));
+
+        let mut bad = data.clone();
+        bad[16] ^= 0xff;
+        assert!(PipelineBinaryCache::with_data(Arc::clone(device), &bad).is_err());
+    }
+}
@@ -42,10 +42,21 @@ pub struct CmdBuffer<'pool> {
     cur_subpass: u32,
     cur_contents: SubpassContents,
     gfx_pipe: Option<Arc<GraphicsPipeline>>,
+    compute_pipe: Option<Arc<ComputePipeline>>,
 }
 
 pub(crate) type CmdBufferLevel = vk::CommandBufferLevel;
 
+/// An intended usage of a range of image subresources.
+#[derive(Clone, Copy, Derivative)]
+#[derivative(Debug)]
+pub struct ImageUse<'a> {
+    #[derivative(Debug(format_with = "write_named::<Image>"))]
+    pub image: &'a Image,
+    pub subresources: ImageSubresources,
+    pub usage: ImageUsage,
+}
+
 #[derive(Debug)]
 pub struct CommandBufferInheritanceInfo {
     pub framebuffer: Arc<Framebuffer>,
@@ -133,6 +144,11 @@ impl CmdPool {
         self.queue_family().supports_graphics()
     }
 
+    #[inline]
+    pub fn supports_compute(&self) -> bool {
+        self.queue_family().supports_compute()
+    }
+
     #[inline]
     pub fn supports_xfer(&self) -> bool {
         self.queue_family().supports_xfer()
@@ -225,6 +241,7 @@ impl<'pool> CmdBuffer<'pool> {
             cur_subpass: 0,
             cur_contents: Default::default(),
             gfx_pipe: None,
+            compute_pipe: None,
         }
     }
 
@@ -264,11 +281,21 @@ impl<'pool> CmdBuffer<'pool> {
         self.flags
     }
 
+    #[inline]
+    pub fn queue_family(&self) -> QueueFamily<'_> {
+        self.pool.queue_family()
+    }
+
     #[inline]
     pub fn supports_graphics(&self) -> bool {
         self.pool.supports_graphics()
     }
 
+    #[inline]
+    pub fn supports_compute(&self) -> bool {
+        self.pool.supports_compute()
+    }
+
     #[inline]
     pub fn supports_xfer(&self) -> bool {
         self.pool.supports_xfer()
@@ -404,7 +431,6 @@ impl<'pool> CmdBuffer<'pool> {
     }
 
     /// N.B.: values should be negative as depth buffer is reversed.
-    // TODO: Depth clamping (maybe good for first-person rendering)
     pub fn set_depth_bias(&mut self, constant_factor: f32, slope_factor: f32) {
         debug_assert!(self.supports_graphics());
         unsafe {
@@ -413,21 +439,48 @@ impl<'pool> CmdBuffer<'pool> {
         }
     }
 
+    pub fn set_stencil_reference(&mut self, faces: vk::StencilFaceFlags, reference: u32) {
+        debug_assert!(self.supports_graphics());
+        unsafe {
+            self.dt()
+                .cmd_set_stencil_reference(self.inner, faces, reference);
+        }
+    }
+
+    pub fn set_stencil_compare_mask(&mut self, faces: vk::StencilFaceFlags, mask: u32) {
+        debug_assert!(self.supports_graphics());
+        unsafe {
+            self.dt()
+                .cmd_set_stencil_compare_mask(self.inner, faces, mask);
+        }
+    }
+
+    pub fn set_stencil_write_mask(&mut self, faces: vk::StencilFaceFlags, mask: u32) {
+        debug_assert!(self.supports_graphics());
+        unsafe {
+            self.dt()
+                .cmd_set_stencil_write_mask(self.inner, faces, mask);
+        }
+    }
+
     pub fn reset_dynamic_state(&mut self) {
         self.set_viewport(self.framebuffer().unwrap().viewport());
         self.set_scissor(self.framebuffer().unwrap().render_area());
         // TODO: these numbers are somewhat arbitrary
         self.set_depth_bias(-0.005, -0.005);
+        let faces = vk::StencilFaceFlags::FRONT_AND_BACK;
+        self.set_stencil_reference(faces, 0);
+        self.set_stencil_compare_mask(faces, !0);
+        self.set_stencil_write_mask(faces, !0);
     }
 
-    pub fn bind_gfx_descs(&mut self, index: u32, set: &DescriptorSet) {
-        self.ensure_recording();
-        let bind_point = vk::PipelineBindPoint::GRAPHICS;
-        let pipeline = self
-            .gfx_pipe
-            .as_ref()
-            .expect("Must bind pipeline before descriptors");
-        let layout = &pipeline.layout();
+    fn bind_descs(
+        &mut self,
+        bind_point: vk::PipelineBindPoint,
+        layout: &PipelineLayout,
+        index: u32,
+        set: &DescriptorSet,
+    ) {
         assert!(Arc::ptr_eq(
             set.layout(),
             &layout.set_layouts()[index as usize]
@@ -447,6 +500,26 @@ impl<'pool> CmdBuffer<'pool> {
         }
     }
 
+    pub fn bind_gfx_descs(&mut self, index: u32, set: &DescriptorSet) {
+        self.ensure_recording();
+        let pipeline = self
+            .gfx_pipe
+            .as_ref()
+            .expect("Must bind pipeline before descriptors");
+        let layout = Arc::clone(pipeline.layout());
+        self.bind_descs(vk::PipelineBindPoint::GRAPHICS, &layout, index, set);
+    }
+
+    pub fn bind_compute_descs(&mut self, index: u32, set: &DescriptorSet) {
+        self.ensure_recording();
+        let pipeline = self
+            .compute_pipe
+            .as_ref()
+            .expect("Must bind pipeline before descriptors");
+        let layout = Arc::clone(pipeline.layout());
+        self.bind_descs(vk::PipelineBindPoint::COMPUTE, &layout, index, set);
+    }
+
     pub fn bind_gfx_pipe(&mut self, pipeline: &Arc<GraphicsPipeline>) {
         tryopt! {
             if Arc::ptr_eq(self.gfx_pipe.as_ref()?, pipeline) {
@@ -466,6 +539,49 @@ impl<'pool> CmdBuffer<'pool> {
         self.gfx_pipe = Some(Arc::clone(pipeline));
     }
 
+    pub fn bind_compute_pipe(&mut self, pipeline: &Arc<ComputePipeline>) {
+        tryopt! {
+            if Arc::ptr_eq(self.compute_pipe.as_ref()?, pipeline) {
+                return;
+            }
+        };
+        self.ensure_recording();
+        debug_assert!(self.supports_compute());
+        unsafe {
+            self.dt().cmd_bind_pipeline(
+                self.raw(),
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.inner(),
+            );
+        }
+        self.compute_pipe = Some(Arc::clone(pipeline));
+    }
+
+    /// Updates push constants for the currently bound pipeline. If
+    /// `stages` includes the compute stage, the compute pipeline's
+    /// layout is used; otherwise the graphics pipeline's.
+    pub fn push_constants<T: Pod>(&mut self, stages: vk::ShaderStageFlags, offset: u32, value: &T) {
+        self.ensure_recording();
+        let layout = if stages.intersects(vk::ShaderStageFlags::COMPUTE_BIT) {
+            self.compute_pipe.as_ref().map(|pipe| pipe.layout())
+        } else {
+            self.gfx_pipe.as_ref().map(|pipe| pipe.layout())
+        };
+        let layout = Arc::clone(layout.expect("Must bind pipeline before push constants"));
+        let bytes = std::slice::from_ref(value).as_bytes();
+        layout.validate_push_constants(stages, offset, bytes.len() as _);
+        unsafe {
+            self.dt().cmd_push_constants(
+                self.raw(),
+                layout.inner(),
+                stages,
+                offset,
+                bytes.len() as _,
+                bytes.as_ptr() as _,
+            );
+        }
+    }
+
     pub fn bind_index_buffer(&mut self, buffer: BufferRange<'_>, ty: IndexType) {
         unsafe {
             self.dt()
@@ -558,6 +674,40 @@ impl<'pool> CmdBuffer<'pool> {
         );
     }
 
+    fn pre_dispatch(&mut self) {
+        self.ensure_recording();
+        // TODO: Check bound descriptor sets
+        assert!(self.framebuffer.is_none(), "dispatch inside render pass");
+        assert!(self.compute_pipe.is_some());
+    }
+
+    pub unsafe fn dispatch(&mut self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        trace!(
+            "CmdBuffer::dispatch(group_count: {:?})",
+            [group_count_x, group_count_y, group_count_z],
+        );
+        self.pre_dispatch();
+        let limits = self.device().limits();
+        assert!(group_count_x <= limits.max_compute_work_group_count[0]);
+        assert!(group_count_y <= limits.max_compute_work_group_count[1]);
+        assert!(group_count_z <= limits.max_compute_work_group_count[2]);
+        self.dt()
+            .cmd_dispatch(self.raw(), group_count_x, group_count_y, group_count_z);
+    }
+
+    /// Dispatches with group counts read from a
+    /// `VkDispatchIndirectCommand` at the start of `buffer`.
+    pub unsafe fn dispatch_indirect(&mut self, buffer: BufferRange<'_>) {
+        trace!("CmdBuffer::dispatch_indirect(buffer: {:?})", buffer);
+        self.pre_dispatch();
+        assert!(buffer.buffer.usage().contains(BufferUsage::INDIRECT_BUFFER));
+        assert_eq!(buffer.offset % 4, 0);
+        let size = std::mem::size_of::<vk::DispatchIndirectCommand>() as vk::DeviceSize;
+        assert!(buffer.size >= size);
+        self.dt()
+            .cmd_dispatch_indirect(self.raw(), buffer.raw(), buffer.offset);
+    }
+
     fn check_state(&self) {
         if let Some(render_pass) = self.render_pass().as_ref() {
             let subpass_count = render_pass.subpasses().len();
@@ -627,6 +777,76 @@ impl<'pool> CmdBuffer<'pool> {
         }
     }
 
+    pub unsafe fn reset_query_pool(&mut self, pool: &QueryPool, first: u32, count: u32) {
+        trace!(
+            "CmdBuffer::reset_query_pool(pool: {:?}, first: {}, count: {})",
+            fmt_named(pool),
+            first,
+            count,
+        );
+        self.ensure_recording();
+        assert!(self.framebuffer.is_none(), "query reset inside render pass");
+        pool.check_range(first, count);
+        self.dt()
+            .cmd_reset_query_pool(self.raw(), pool.inner(), first, count);
+    }
+
+    pub unsafe fn write_timestamp(
+        &mut self,
+        stage: vk::PipelineStageFlags,
+        pool: &QueryPool,
+        query: u32,
+    ) {
+        trace!(
+            "CmdBuffer::write_timestamp(stage: {:?}, pool: {:?}, query: {})",
+            stage,
+            fmt_named(pool),
+            query,
+        );
+        self.ensure_recording();
+        assert_eq!(pool.ty(), QueryType::Timestamp);
+        assert_ne!(
+            self.queue_family().properties().timestamp_valid_bits,
+            0,
+            "timestamps not supported by queue family",
+        );
+        pool.check_range(query, 1);
+        self.dt()
+            .cmd_write_timestamp(self.raw(), stage, pool.inner(), query);
+    }
+
+    /// Begins an occlusion or pipeline statistics query.
+    pub unsafe fn begin_query(&mut self, pool: &QueryPool, query: u32, precise: bool) {
+        trace!(
+            "CmdBuffer::begin_query(pool: {:?}, query: {}, precise: {})",
+            fmt_named(pool),
+            query,
+            precise,
+        );
+        self.ensure_recording();
+        assert_ne!(pool.ty(), QueryType::Timestamp);
+        assert!(!precise || pool.ty() == QueryType::Occlusion);
+        pool.check_range(query, 1);
+        let flags = if precise {
+            vk::QueryControlFlags::PRECISE_BIT
+        } else {
+            Default::default()
+        };
+        self.dt()
+            .cmd_begin_query(self.raw(), pool.inner(), query, flags);
+    }
+
+    pub unsafe fn end_query(&mut self, pool: &QueryPool, query: u32) {
+        trace!(
+            "CmdBuffer::end_query(pool: {:?}, query: {})",
+            fmt_named(pool),
+            query,
+        );
+        self.ensure_recording();
+        pool.check_range(query, 1);
+        self.dt().cmd_end_query(self.raw(), pool.inner(), query);
+    }
+
     pub unsafe fn pipeline_barrier(
         &mut self,
         src_stage_mask: vk::PipelineStageFlags,
@@ -667,6 +887,104 @@ impl<'pool> CmdBuffer<'pool> {
         );
     }
 
+    fn record_barriers(&mut self, batch: BarrierBatch) {
+        if !batch.is_empty() {
+            unsafe {
+                self.pipeline_barrier(
+                    batch.src_stages,
+                    batch.dst_stages,
+                    Default::default(),
+                    &[],
+                    &[],
+                    &batch.barriers,
+                );
+            }
+        }
+    }
+
+    /// Declares that the following commands access images as described
+    /// and records the minimal set of barriers needed to do so, based on
+    /// the state tracked by each image.
+    ///
+    /// Image state is updated at record time, so command buffers must
+    /// be submitted in the order they were recorded in. The usages must
+    /// not overlap.
+    pub unsafe fn use_images(&mut self, uses: &[ImageUse<'_>]) {
+        self.ensure_recording();
+        assert!(self.framebuffer.is_none(), "barrier inside render pass");
+        trace!("CmdBuffer::use_images(uses: {:?})", uses);
+        let family = self.queue_family().index();
+        let mut acquires = BarrierBatch::default();
+        let mut batch = BarrierBatch::default();
+        for u in uses.iter() {
+            u.image.validate_subresources(&u.subresources);
+            u.image.state().lock().access(
+                u.image.inner(),
+                &u.subresources,
+                &u.usage.access(),
+                family,
+                &mut acquires,
+                &mut batch,
+            );
+        }
+        self.record_barriers(acquires);
+        self.record_barriers(batch);
+    }
+
+    #[inline]
+    pub unsafe fn use_image(
+        &mut self,
+        image: &Image,
+        subresources: ImageSubresources,
+        usage: ImageUsage,
+    ) {
+        self.use_images(&[ImageUse {
+            image,
+            subresources,
+            usage,
+        }]);
+    }
+
+    /// Releases ownership of image subresources to another queue
+    /// family, which will be acquired automatically by the first usage
+    /// recorded on that family. `usage` determines the layout the
+    /// image is transitioned to.
+    pub unsafe fn release_image(
+        &mut self,
+        image: &Image,
+        subresources: ImageSubresources,
+        usage: ImageUsage,
+        dst_family: u32,
+    ) {
+        self.ensure_recording();
+        assert!(self.framebuffer.is_none(), "barrier inside render pass");
+        trace!(
+            concat!(
+                "CmdBuffer::release_image(image: {:?}, subresources: {:?}, ",
+                "usage: {:?}, dst_family: {})",
+            ),
+            fmt_named(image),
+            subresources,
+            usage,
+            dst_family,
+        );
+        let family = self.queue_family().index();
+        if family == dst_family {
+            return self.use_image(image, subresources, usage);
+        }
+        image.validate_subresources(&subresources);
+        let mut batch = BarrierBatch::default();
+        image.state().lock().release(
+            image.inner(),
+            &subresources,
+            &usage.access(),
+            family,
+            dst_family,
+            &mut batch,
+        );
+        self.record_barriers(batch);
+    }
+
     // TODO: Could take an iterator over BufferRange pairs
     pub unsafe fn copy_buffer(
         &mut self,
@@ -729,6 +1047,339 @@ impl<'pool> CmdBuffer<'pool> {
             regions.as_ptr(),
         );
     }
+
+    fn pre_transfer(&self) {
+        self.ensure_recording();
+        assert!(self.framebuffer.is_none(), "transfer inside render pass");
+    }
+
+    pub unsafe fn copy_image(
+        &mut self,
+        src: &Image,
+        src_layout: vk::ImageLayout,
+        dst: &Image,
+        dst_layout: vk::ImageLayout,
+        regions: &[vk::ImageCopy],
+    ) {
+        trace!(
+            concat!(
+                "CmdBuffer::copy_image(src: {:?}, src_layout: {:?}, ",
+                "dst: {:?}, dst_layout: {:?}, regions: {:?})",
+            ),
+            fmt_named(src),
+            src_layout,
+            fmt_named(dst),
+            dst_layout,
+            regions,
+        );
+        self.pre_transfer();
+        validate_transfer_src(src, src_layout);
+        validate_transfer_dst(dst, dst_layout);
+        assert_eq!(src.samples(), dst.samples());
+        assert_eq!(src.format().size(), dst.format().size());
+        for region in regions.iter() {
+            validate_image_region(
+                src,
+                &region.src_subresource,
+                region.src_offset,
+                region.extent,
+            );
+            validate_image_region(
+                dst,
+                &region.dst_subresource,
+                region.dst_offset,
+                region.extent,
+            );
+        }
+        self.dt().cmd_copy_image(
+            self.raw(),
+            src.inner(),
+            src_layout,
+            dst.inner(),
+            dst_layout,
+            regions.len() as _,
+            regions.as_ptr(),
+        );
+    }
+
+    pub unsafe fn blit_image(
+        &mut self,
+        src: &Image,
+        src_layout: vk::ImageLayout,
+        dst: &Image,
+        dst_layout: vk::ImageLayout,
+        regions: &[vk::ImageBlit],
+        filter: Filter,
+    ) {
+        trace!(
+            concat!(
+                "CmdBuffer::blit_image(src: {:?}, src_layout: {:?}, ",
+                "dst: {:?}, dst_layout: {:?}, regions: {:?}, filter: {:?})",
+            ),
+            fmt_named(src),
+            src_layout,
+            fmt_named(dst),
+            dst_layout,
+            regions,
+            filter,
+        );
+        self.pre_transfer();
+        assert!(self.supports_graphics());
+        validate_transfer_src(src, src_layout);
+        validate_transfer_dst(dst, dst_layout);
+        assert_eq!(src.samples(), SampleCount::One);
+        assert_eq!(dst.samples(), SampleCount::One);
+        if filter != Filter::Nearest {
+            assert!(!src.format().is_depth_stencil());
+        }
+        for region in regions.iter() {
+            validate_blit_region(src, &region.src_subresource, &region.src_offsets);
+            validate_blit_region(dst, &region.dst_subresource, &region.dst_offsets);
+        }
+        self.dt().cmd_blit_image(
+            self.raw(),
+            src.inner(),
+            src_layout,
+            dst.inner(),
+            dst_layout,
+            regions.len() as _,
+            regions.as_ptr(),
+            filter.into(),
+        );
+    }
+
+    pub unsafe fn copy_image_to_buffer(
+        &mut self,
+        src: &Image,
+        layout: vk::ImageLayout,
+        dst: &DeviceBuffer,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        trace!(
+            concat!(
+                "CmdBuffer::copy_image_to_buffer(src: {:?}, layout: {:?}, ",
+                "dst: {:?}, regions: {:?})",
+            ),
+            fmt_named(src),
+            layout,
+            fmt_named(dst),
+            regions,
+        );
+        self.pre_transfer();
+        validate_transfer_src(src, layout);
+        for region in regions.iter() {
+            validate_image_region(
+                src,
+                &region.image_subresource,
+                region.image_offset,
+                region.image_extent,
+            );
+            let size = buffer_image_copy_size(region, src.format());
+            assert!(region.buffer_offset + size <= dst.size());
+        }
+        self.dt().cmd_copy_image_to_buffer(
+            self.raw(),
+            src.inner(),
+            layout,
+            dst.inner(),
+            regions.len() as _,
+            regions.as_ptr(),
+        );
+    }
+
+    /// Copies the contents of a swapchain image, e.g. to take a
+    /// screenshot. The swapchain must support `TRANSFER_SRC` usage.
+    pub unsafe fn copy_swapchain_image_to_buffer(
+        &mut self,
+        src: &SwapchainView,
+        layout: vk::ImageLayout,
+        dst: &DeviceBuffer,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        trace!(
+            concat!(
+                "CmdBuffer::copy_swapchain_image_to_buffer(src: {:?}, ",
+                "layout: {:?}, dst: {:?}, regions: {:?})",
+            ),
+            src.index(),
+            layout,
+            fmt_named(dst),
+            regions,
+        );
+        self.pre_transfer();
+        assert!(src.is_valid());
+        assert!(src.usage().contains(vk::ImageUsageFlags::TRANSFER_SRC_BIT));
+        assert!([
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::SHARED_PRESENT_KHR,
+        ]
+        .contains(&layout));
+        let extent: Extent3D = src.extent().into();
+        for region in regions.iter() {
+            let sub = &region.image_subresource;
+            assert_eq!(sub.aspect_mask, vk::ImageAspectFlags::COLOR_BIT);
+            assert_eq!(sub.mip_level, 0);
+            assert_eq!((sub.base_array_layer, sub.layer_count), (0, 1));
+            let (x, y, z) = region.image_offset.into();
+            let offset = math::Ivector3::new(x, y, z);
+            assert!(extent.contains_extent(offset, region.image_extent.into()));
+            let size = buffer_image_copy_size(region, src.format());
+            assert!(region.buffer_offset + size <= dst.size());
+        }
+        self.dt().cmd_copy_image_to_buffer(
+            self.raw(),
+            src.image(),
+            layout,
+            dst.inner(),
+            regions.len() as _,
+            regions.as_ptr(),
+        );
+    }
+
+    /// Resolves a multisample image into a single-sample image.
+    pub unsafe fn resolve_image(
+        &mut self,
+        src: &Image,
+        src_layout: vk::ImageLayout,
+        dst: &Image,
+        dst_layout: vk::ImageLayout,
+        regions: &[vk::ImageResolve],
+    ) {
+        trace!(
+            concat!(
+                "CmdBuffer::resolve_image(src: {:?}, src_layout: {:?}, ",
+                "dst: {:?}, dst_layout: {:?}, regions: {:?})",
+            ),
+            fmt_named(src),
+            src_layout,
+            fmt_named(dst),
+            dst_layout,
+            regions,
+        );
+        self.pre_transfer();
+        assert!(self.supports_graphics());
+        validate_transfer_src(src, src_layout);
+        validate_transfer_dst(dst, dst_layout);
+        assert_ne!(src.samples(), SampleCount::One);
+        assert_eq!(dst.samples(), SampleCount::One);
+        assert_eq!(src.format(), dst.format());
+        assert!(!src.format().is_depth_stencil());
+        for region in regions.iter() {
+            validate_image_region(
+                src,
+                &region.src_subresource,
+                region.src_offset,
+                region.extent,
+            );
+            validate_image_region(
+                dst,
+                &region.dst_subresource,
+                region.dst_offset,
+                region.extent,
+            );
+        }
+        self.dt().cmd_resolve_image(
+            self.raw(),
+            src.inner(),
+            src_layout,
+            dst.inner(),
+            dst_layout,
+            regions.len() as _,
+            regions.as_ptr(),
+        );
+    }
+}
+
+fn buffer_image_copy_size(region: &vk::BufferImageCopy, format: Format) -> vk::DeviceSize {
+    let row_length = match region.buffer_row_length {
+        0 => region.image_extent.width,
+        n => n,
+    } as vk::DeviceSize;
+    let image_height = match region.buffer_image_height {
+        0 => region.image_extent.height,
+        n => n,
+    } as vk::DeviceSize;
+    let layers = region.image_subresource.layer_count as vk::DeviceSize;
+    let slices = region.image_extent.depth as vk::DeviceSize * layers;
+    slices * row_length * image_height * format.size() as vk::DeviceSize
+}
+
+fn validate_transfer_src(image: &Image, layout: vk::ImageLayout) {
+    assert!(
+        image.flags().contains(ImageFlags::TRANSFER_SRC),
+        "image {:?} not created with TRANSFER_SRC",
+        fmt_named(image),
+    );
+    assert!([
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::ImageLayout::GENERAL,
+        vk::ImageLayout::SHARED_PRESENT_KHR,
+    ]
+    .contains(&layout));
+}
+
+fn validate_transfer_dst(image: &Image, layout: vk::ImageLayout) {
+    assert!(
+        image
+            .flags()
+            .usage()
+            .contains(vk::ImageUsageFlags::TRANSFER_DST_BIT),
+        "image {:?} cannot be a transfer destination",
+        fmt_named(image),
+    );
+    assert!([
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::GENERAL,
+        vk::ImageLayout::SHARED_PRESENT_KHR,
+    ]
+    .contains(&layout));
+}
+
+fn validate_subresource_layers(image: &Image, sub: &vk::ImageSubresourceLayers) {
+    assert!(image.format().aspects().contains(sub.aspect_mask));
+    assert!(sub.mip_level < image.mip_levels());
+    assert!(sub.base_array_layer + sub.layer_count <= image.layers());
+}
+
+fn validate_image_region(
+    image: &Image,
+    sub: &vk::ImageSubresourceLayers,
+    offset: vk::Offset3D,
+    extent: vk::Extent3D,
+) {
+    use math::Ivector3;
+    validate_subresource_layers(image, sub);
+    let mip_extent = image.extent().mip_level(sub.mip_level);
+    let offset = Ivector3::new(offset.x, offset.y, offset.z);
+    assert!(
+        mip_extent.contains_extent(offset, extent.into()),
+        "region {:?} + {:?} out of bounds for {:?}",
+        offset,
+        extent,
+        mip_extent,
+    );
+}
+
+fn validate_blit_region(
+    image: &Image,
+    sub: &vk::ImageSubresourceLayers,
+    offsets: &[vk::Offset3D; 2],
+) {
+    validate_subresource_layers(image, sub);
+    let mip_extent = image.extent().mip_level(sub.mip_level);
+    for offset in offsets.iter() {
+        let (x, y, z) = (*offset).into();
+        let in_bounds = mip_extent
+            .iter()
+            .zip([x, y, z].iter())
+            .all(|(&max, &x)| x >= 0 && x as u32 <= max);
+        assert!(
+            in_bounds,
+            "blit offset {:?} out of bounds for {:?}",
+            offset, mip_extent
+        );
+    }
 }
 
 #[cfg(debug_assertions)]
@@ -792,7 +1443,7 @@ mod tests {
         PipelineCache,
         TrivialRenderer,
         TrivialPass,
-        Vec<Arc<Framebuffer>>,
+        Arc<Framebuffer>,
         Box<CmdPool>,
     ) {
         let device = vars.device();
@@ -800,20 +1451,19 @@ mod tests {
         let pipelines = PipelineCache::new(device);
         let trivial = TrivialRenderer::new(&resources);
         let pass = TrivialPass::new(device);
-        let framebuffers = pass.create_framebuffers(vars.swapchain());
+        let framebuffer = pass.create_framebuffer(&resources.image_heap);
         let pool = Box::new(CmdPool::new(
             vars.gfx_queue().family(),
             vk::CommandPoolCreateFlags::TRANSIENT_BIT,
         ));
-        (resources, pipelines, trivial, pass, framebuffers, pool)
+        (resources, pipelines, trivial, pass, framebuffer, pool)
     }
 
     #[test]
     fn record_subpass() {
         unsafe {
             let vars = TestVars::new();
-            let (_res, pipelines, trivial, _pass, framebuffers, mut pool) = test_common(&vars);
-            let framebuffer = Arc::clone(&framebuffers[0]);
+            let (_res, pipelines, trivial, _pass, framebuffer, mut pool) = test_common(&vars);
             let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::SECONDARY);
             let inheritance = CommandBufferInheritanceInfo {
                 framebuffer,
@@ -832,9 +1482,8 @@ mod tests {
     fn record_render_pass() {
         unsafe {
             let vars = TestVars::new();
-            let (_res, pipelines, trivial, _, framebuffers, mut pool) = test_common(&vars);
+            let (_res, pipelines, trivial, _, framebuffer, mut pool) = test_common(&vars);
             let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
-            let framebuffer = Arc::clone(&framebuffers[0]);
             cmds.begin(Default::default(), None);
             cmds.begin_render_pass(framebuffer, &[], SubpassContents::Inline);
             trivial.render(&pipelines, &mut cmds);
@@ -847,9 +1496,8 @@ mod tests {
     fn subpass_out_of_bounds() {
         unsafe {
             let vars = TestVars::new();
-            let (_res, _, _, _, framebuffers, mut pool) = test_common(&vars);
+            let (_res, _, _, _, framebuffer, mut pool) = test_common(&vars);
             let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
-            let framebuffer = Arc::clone(&framebuffers[0]);
             cmds.begin(Default::default(), None);
             cmds.begin_render_pass(framebuffer, &[], SubpassContents::Inline);
             cmds.next_subpass(SubpassContents::Inline);
@@ -862,8 +1510,7 @@ mod tests {
     fn exec_in_inline_subpass() {
         unsafe {
             let vars = TestVars::new();
-            let (_res, _, _, _, framebuffers, mut pool) = test_common(&vars);
-            let framebuffer = Arc::clone(&framebuffers[0]);
+            let (_res, _, _, _, framebuffer, mut pool) = test_common(&vars);
             let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::SECONDARY);
             let inheritance = CommandBufferInheritanceInfo {
                 framebuffer,
@@ -878,6 +1525,100 @@ mod tests {
         }
     }
 
+    #[test]
+    fn record_dispatch() {
+        unsafe {
+            let vars = TestVars::new();
+            let (res, pipelines, _, _, _, mut pool) = test_common(&vars);
+            let compute = TrivialCompute::new(&res);
+            let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+            cmds.begin(Default::default(), None);
+            compute.dispatch(&pipelines, &mut cmds);
+            let _ = cmds.end();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn dispatch_in_render_pass() {
+        unsafe {
+            let vars = TestVars::new();
+            let (res, pipelines, _, _, framebuffer, mut pool) = test_common(&vars);
+            let compute = TrivialCompute::new(&res);
+            let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+            cmds.begin(Default::default(), None);
+            cmds.begin_render_pass(framebuffer, &[], SubpassContents::Inline);
+            compute.dispatch(&pipelines, &mut cmds);
+            cmds.end();
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default)]
+    struct PushConstants {
+        transform: [[f32; 4]; 4],
+        offset: [f32; 4],
+    }
+
+    unsafe impl Pod for PushConstants {}
+
+    unsafe fn push_constant_pipe(
+        resources: &TestResources,
+        pipelines: &PipelineCache,
+        trivial: &TrivialRenderer,
+        subpass: Subpass,
+    ) -> Arc<GraphicsPipeline> {
+        let mut desc = GraphicsPipelineDesc::new(subpass);
+        trivial.init_pipe_desc(&mut desc);
+        let vert_shader = &resources.shaders.push_vert;
+        desc.stages.insert(
+            ShaderStage::Vertex,
+            Arc::new(Arc::clone(vert_shader).into()),
+        );
+        desc.layout.add_push_constants(vert_shader);
+        pipelines.get_or_create_gfx(&desc).into_owned()
+    }
+
+    #[test]
+    fn push_constants() {
+        unsafe {
+            let vars = TestVars::new();
+            let (res, pipelines, trivial, _, framebuffer, mut pool) = test_common(&vars);
+            let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+            cmds.begin(Default::default(), None);
+            cmds.begin_render_pass(framebuffer, &[], SubpassContents::Inline);
+            let pipe = push_constant_pipe(&res, &pipelines, &trivial, cmds.subpass().unwrap());
+            assert_eq!(pipe.layout().push_constant_ranges().len(), 1);
+            cmds.bind_gfx_pipe(&pipe);
+            cmds.push_constants(
+                vk::ShaderStageFlags::VERTEX_BIT,
+                0,
+                &PushConstants::default(),
+            );
+            let _ = cmds.end();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn push_constants_out_of_range() {
+        unsafe {
+            let vars = TestVars::new();
+            let (res, pipelines, trivial, _, framebuffer, mut pool) = test_common(&vars);
+            let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+            cmds.begin(Default::default(), None);
+            cmds.begin_render_pass(framebuffer, &[], SubpassContents::Inline);
+            let pipe = push_constant_pipe(&res, &pipelines, &trivial, cmds.subpass().unwrap());
+            cmds.bind_gfx_pipe(&pipe);
+            cmds.push_constants(
+                vk::ShaderStageFlags::VERTEX_BIT,
+                16,
+                &PushConstants::default(),
+            );
+            cmds.end();
+        }
+    }
+
     fn copy_common(vars: &testing::TestVars) -> (TestResources, CmdPool) {
         let resources = TestResources::new(vars.device());
         let pool = CmdPool::new(
@@ -997,4 +1738,167 @@ mod tests {
             cmds.end();
         }
     }
+
+    fn image_2d(
+        resources: &TestResources,
+        flags: ImageFlags,
+        samples: SampleCount,
+        size: u32,
+        mip_levels: u32,
+    ) -> Arc<Image> {
+        Arc::new(Image::with(
+            &resources.image_heap,
+            flags,
+            ImageType::Dim2,
+            Format::RGBA8,
+            samples,
+            Extent3D::new(size, size, 1),
+            mip_levels,
+            1,
+        ))
+    }
+
+    #[test]
+    fn blit_image() {
+        unsafe {
+            let vars = TestVars::new();
+            let (resources, mut pool) = copy_common(&vars);
+            let mut cmds = CmdBuffer::new(&mut pool, CmdBufferLevel::PRIMARY);
+            let flags = ImageFlags::TRANSFER_SRC;
+            let image = image_2d(&resources, flags, SampleCount::One, 64, 2);
+            let sub = |mip_level| image.subresource_layers(mip_level, 0, 1).into();
+            cmds.begin(Default::default(), None);
+            cmds.blit_image(
+                &image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                &image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: sub(0),
+                    src_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D { x: 64, y: 64, z: 1 },
+                    ],
+                    dst_subresource: sub(1),
+                    dst_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D { x: 32, y: 32, z: 1 },
+                    ],
+                }],
+                Filter::Linear,
+            );
+            cmds.end();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn blit_out_of_bounds() {
+        unsafe {
+            let vars = TestVars::new();
+            let (resources, mut pool) = copy_common(&vars);
+            let mut cmds = CmdBuffer::new(&mut pool, CmdBufferLevel::PRIMARY);
+            let flags = ImageFlags::TRANSFER_SRC;
+            let image = image_2d(&resources, flags, SampleCount::One, 64, 2);
+            let sub = |mip_level| image.subresource_layers(mip_level, 0, 1).into();
+            cmds.begin(Default::default(), None);
+            cmds.blit_image(
+                &image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                &image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit {
+                    src_subresource: sub(0),
+                    src_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D { x: 64, y: 64, z: 1 },
+                    ],
+                    dst_subresource: sub(1),
+                    dst_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D { x: 64, y: 64, z: 1 },
+                    ],
+                }],
+                Filter::Linear,
+            );
+            cmds.end();
+        }
+    }
+
+    #[test]
+    fn copy_image_to_image_and_buffer() {
+        unsafe {
+            let vars = TestVars::new();
+            let (resources, mut pool) = copy_common(&vars);
+            let mut cmds = CmdBuffer::new(&mut pool, CmdBufferLevel::PRIMARY);
+            let src = image_2d(
+                &resources,
+                ImageFlags::TRANSFER_SRC,
+                SampleCount::One,
+                64,
+                1,
+            );
+            let dst = image_2d(&resources, ImageFlags::NO_SAMPLE, SampleCount::One, 64, 1);
+            let buffer = resources.buffer_heap.alloc(
+                BufferBinding::Storage,
+                Lifetime::Frame,
+                MemoryMapping::Mapped,
+                (64 * 64 * Format::RGBA8.size()) as _,
+            );
+            let sub = src.subresource_layers(0, 0, 1).into();
+            cmds.begin(Default::default(), None);
+            cmds.copy_image(
+                &src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                &dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageCopy {
+                    src_subresource: sub,
+                    dst_subresource: sub,
+                    extent: src.extent().into(),
+                    ..Default::default()
+                }],
+            );
+            cmds.copy_image_to_buffer(
+                &src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer(),
+                &[vk::BufferImageCopy {
+                    buffer_offset: buffer.range().offset,
+                    image_subresource: sub,
+                    image_extent: src.extent().into(),
+                    ..Default::default()
+                }],
+            );
+            cmds.end();
+        }
+    }
+
+    #[test]
+    fn resolve_image() {
+        unsafe {
+            let vars = TestVars::new();
+            let (resources, mut pool) = copy_common(&vars);
+            let mut cmds = CmdBuffer::new(&mut pool, CmdBufferLevel::PRIMARY);
+            let flags =
+                ImageFlags::COLOR_ATTACHMENT | ImageFlags::NO_SAMPLE | ImageFlags::TRANSFER_SRC;
+            let src = image_2d(&resources, flags, SampleCount::Four, 64, 1);
+            let dst = image_2d(&resources, ImageFlags::NO_SAMPLE, SampleCount::One, 64, 1);
+            let sub = src.subresource_layers(0, 0, 1).into();
+            cmds.begin(Default::default(), None);
+            cmds.resolve_image(
+                &src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                &dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageResolve {
+                    src_subresource: sub,
+                    dst_subresource: sub,
+                    extent: src.extent().into(),
+                    ..Default::default()
+                }],
+            );
+            cmds.end();
+        }
+    }
 }
@@ -0,0 +1,321 @@
+use std::ptr;
+use std::sync::Arc;
+
+use log::trace;
+use parking_lot::Mutex;
+
+use super::*;
+use crate::*;
+
+/// Binding holding the array of sampled images.
+pub const BINDLESS_IMAGE_BINDING: u32 = 0;
+/// Binding holding the array of samplers.
+pub const BINDLESS_SAMPLER_BINDING: u32 = 1;
+
+const MAX_BINDLESS_IMAGES: u32 = 0x1_0000;
+const MAX_BINDLESS_SAMPLERS: u32 = 0x400;
+
+/// A single, large descriptor set of sampled images and samplers which
+/// shaders index into dynamically.
+///
+/// The set is partially bound and update-after-bind, so registering or
+/// unregistering descriptors doesn't disturb command buffers which are
+/// pending execution, as long as they don't access the affected slots.
+#[derive(Debug)]
+pub struct BindlessHeap {
+    // N.B. The set must be dropped before the pool.
+    set: DescriptorSet,
+    pool: DescriptorPool,
+    state: Mutex<BindlessState>,
+}
+
+#[derive(Debug)]
+struct BindlessState {
+    images: Slots<ImageView>,
+    samplers: Slots<Sampler>,
+}
+
+#[derive(Debug)]
+struct Slots<T> {
+    items: Vec<Option<Arc<T>>>,
+    free: Vec<u32>,
+    capacity: u32,
+}
+
+impl<T> Slots<T> {
+    fn new(capacity: u32) -> Self {
+        Self {
+            items: Vec::new(),
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    fn insert(&mut self, item: &Arc<T>) -> u32 {
+        let idx = if let Some(idx) = self.free.pop() {
+            idx
+        } else {
+            let idx = self.items.len() as u32;
+            assert!(idx < self.capacity, "bindless heap is full");
+            self.items.push(None);
+            idx
+        };
+        self.items[idx as usize] = Some(Arc::clone(item));
+        idx
+    }
+
+    fn remove(&mut self, idx: u32) -> Arc<T> {
+        let item = self
+            .items
+            .get_mut(idx as usize)
+            .and_then(Option::take)
+            .expect("invalid bindless index");
+        self.free.push(idx);
+        item
+    }
+
+    fn get(&self, idx: u32) -> Option<&Arc<T>> {
+        self.items.get(idx as usize)?.as_ref()
+    }
+}
+
+fn bindless_binding_flags() -> vk::DescriptorBindingFlags {
+    vk::DescriptorBindingFlags::PARTIALLY_BOUND_BIT
+        | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND_BIT
+        | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING_BIT
+}
+
+impl BindlessHeap {
+    /// Creates the heap, or returns `None` if the device doesn't
+    /// support descriptor indexing.
+    pub fn new(device: Arc<Device>) -> Option<Self> {
+        if !device.supports_bindless() {
+            return None;
+        }
+
+        let limits = device.limits();
+        let props12 = unsafe { device.instance().get_properties12(device.pdev) };
+        let sampler_count = MAX_BINDLESS_SAMPLERS
+            .min(props12.max_per_stage_descriptor_update_after_bind_samplers)
+            .min(props12.max_descriptor_set_update_after_bind_samplers);
+        // Leave some room for descriptors in other sets.
+        let resources = props12
+            .max_per_stage_update_after_bind_resources
+            .saturating_sub(limits.max_per_stage_resources.min(0x100));
+        let image_count = MAX_BINDLESS_IMAGES
+            .min(props12.max_per_stage_descriptor_update_after_bind_sampled_images)
+            .min(props12.max_descriptor_set_update_after_bind_sampled_images)
+            .min(resources.saturating_sub(sampler_count));
+
+        let flags = bindless_binding_flags();
+        let layout = Arc::new(DescriptorSetLayout::new(
+            Arc::clone(&device),
+            SetLayoutDesc {
+                bindings: smallvec::smallvec![
+                    SetLayoutBinding {
+                        binding: BINDLESS_IMAGE_BINDING,
+                        ty: DescriptorType::SampledImage,
+                        count: image_count,
+                        flags,
+                        ..Default::default()
+                    },
+                    SetLayoutBinding {
+                        binding: BINDLESS_SAMPLER_BINDING,
+                        ty: DescriptorType::Sampler,
+                        count: sampler_count,
+                        flags,
+                        ..Default::default()
+                    },
+                ],
+            },
+        ));
+
+        let counts = [
+            (DescriptorType::SampledImage, image_count),
+            (DescriptorType::Sampler, sampler_count),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let mut pool = DescriptorPool::with_flags(
+            device,
+            1,
+            counts,
+            Lifetime::Static,
+            layout.required_pool_flags(),
+        );
+        pool.set_name("bindless_pool");
+        let mut set = pool.alloc(&layout);
+        set.set_name("bindless_set");
+
+        Some(Self {
+            set,
+            pool,
+            state: Mutex::new(BindlessState {
+                images: Slots::new(image_count),
+                samplers: Slots::new(sampler_count),
+            }),
+        })
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        self.pool.device()
+    }
+
+    #[inline]
+    pub fn layout(&self) -> &Arc<DescriptorSetLayout> {
+        self.set.layout()
+    }
+
+    /// The set to bind in order to access registered descriptors.
+    #[inline]
+    pub fn set(&self) -> &DescriptorSet {
+        &self.set
+    }
+
+    #[inline]
+    pub fn image_capacity(&self) -> u32 {
+        self.state.lock().images.capacity
+    }
+
+    #[inline]
+    pub fn sampler_capacity(&self) -> u32 {
+        self.state.lock().samplers.capacity
+    }
+
+    /// Adds an image to the heap and returns its index in the image
+    /// array. The image must be in `SHADER_READ_ONLY_OPTIMAL` layout
+    /// whenever it is accessed through the heap.
+    pub fn register_image(&self, view: &Arc<ImageView>) -> u32 {
+        assert!(!view.image().flags().contains(ImageFlags::NO_SAMPLE));
+        let mut state = self.state.lock();
+        let idx = state.images.insert(view);
+        trace!(
+            "BindlessHeap::register_image(view: {:?}) = {}",
+            fmt_named(&**view.image()),
+            idx
+        );
+        unsafe {
+            self.write_image(idx, view);
+        }
+        idx
+    }
+
+    /// Removes an image from the heap, freeing its index for reuse.
+    ///
+    /// # Safety
+    ///
+    /// No pending command buffer may access the image through the heap.
+    pub unsafe fn unregister_image(&self, idx: u32) -> Arc<ImageView> {
+        trace!("BindlessHeap::unregister_image(idx: {})", idx);
+        self.state.lock().images.remove(idx)
+    }
+
+    /// Returns the image registered at an index, if any.
+    pub fn image(&self, idx: u32) -> Option<Arc<ImageView>> {
+        self.state.lock().images.get(idx).cloned()
+    }
+
+    /// Counts the indices an image is registered at.
+    pub(crate) fn image_refs(&self, view: &Arc<ImageView>) -> usize {
+        let state = self.state.lock();
+        state
+            .images
+            .items
+            .iter()
+            .flatten()
+            .filter(|item| Arc::ptr_eq(item, view))
+            .count()
+    }
+
+    /// Replaces every registration of `old` with `new`, rewriting the
+    /// descriptors in place so that indices remain valid.
+    ///
+    /// # Safety
+    ///
+    /// No pending command buffer may access the image through the heap.
+    pub(crate) unsafe fn replace_image(&self, old: &Arc<ImageView>, new: &Arc<ImageView>) {
+        let mut state = self.state.lock();
+        for (idx, item) in state.images.items.iter_mut().enumerate() {
+            if matches!(item, Some(item) if Arc::ptr_eq(item, old)) {
+                trace!("BindlessHeap::replace_image(idx: {})", idx);
+                *item = Some(Arc::clone(new));
+                self.write_image(idx as _, new);
+            }
+        }
+    }
+
+    /// Adds a sampler to the heap and returns its index in the sampler
+    /// array.
+    pub fn register_sampler(&self, sampler: &Arc<Sampler>) -> u32 {
+        let mut state = self.state.lock();
+        let idx = state.samplers.insert(sampler);
+        trace!(
+            "BindlessHeap::register_sampler(sampler: {:?}) = {}",
+            sampler.inner(),
+            idx
+        );
+        let info = vk::DescriptorImageInfo {
+            sampler: sampler.inner(),
+            ..Default::default()
+        };
+        unsafe {
+            self.write(
+                BINDLESS_SAMPLER_BINDING,
+                idx,
+                DescriptorType::Sampler,
+                &info,
+            );
+        }
+        idx
+    }
+
+    /// Removes a sampler from the heap, freeing its index for reuse.
+    ///
+    /// # Safety
+    ///
+    /// No pending command buffer may access the sampler through the
+    /// heap.
+    pub unsafe fn unregister_sampler(&self, idx: u32) -> Arc<Sampler> {
+        trace!("BindlessHeap::unregister_sampler(idx: {})", idx);
+        self.state.lock().samplers.remove(idx)
+    }
+
+    unsafe fn write_image(&self, idx: u32, view: &ImageView) {
+        let info = vk::DescriptorImageInfo {
+            sampler: vk::null(),
+            image_view: view.inner(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        self.write(
+            BINDLESS_IMAGE_BINDING,
+            idx,
+            DescriptorType::SampledImage,
+            &info,
+        );
+    }
+
+    // Must be called with the state locked so writes to the same slot
+    // can't race.
+    unsafe fn write(
+        &self,
+        binding: u32,
+        element: u32,
+        ty: DescriptorType,
+        info: &vk::DescriptorImageInfo,
+    ) {
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.set.inner(),
+            dst_binding: binding,
+            dst_array_element: element,
+            descriptor_count: 1,
+            descriptor_type: ty.into(),
+            p_image_info: info,
+            ..Default::default()
+        };
+        self.device()
+            .table
+            .update_descriptor_sets(1, &write, 0, ptr::null());
+    }
+}
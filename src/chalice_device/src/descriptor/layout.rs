@@ -32,6 +32,8 @@ pub struct DescriptorSetLayoutBinding {
     pub count: u32,
     pub stage_flags: vk::ShaderStageFlags,
     pub samplers: Option<SmallVec<Arc<Sampler>, 2>>,
+    /// Requires descriptor indexing; see `Device::supports_bindless`.
+    pub flags: vk::DescriptorBindingFlags,
 }
 
 impl Drop for Layout {
@@ -51,6 +53,7 @@ impl Default for DescriptorSetLayoutBinding {
             count: 1,
             stage_flags: vk::ShaderStageFlags::ALL,
             samplers: None,
+            flags: Default::default(),
         }
     }
 }
@@ -80,6 +83,14 @@ fn validate_layout(desc: &SetLayoutDesc) {
     // TODO: wrap stage flags
 }
 
+fn update_after_bind(desc: &SetLayoutDesc) -> bool {
+    desc.bindings.iter().any(|binding| {
+        binding
+            .flags
+            .contains(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND_BIT)
+    })
+}
+
 impl Layout {
     #[inline]
     pub fn new(device: Arc<Device>, desc: SetLayoutDesc) -> Self {
@@ -117,7 +128,26 @@ impl Layout {
             })
             .collect();
 
+        let mut p_next = ptr::null();
+        let binding_flags: SmallVec<_, 4> =
+            desc.bindings.iter().map(|binding| binding.flags).collect();
+        let mut flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+            binding_count: binding_flags.len() as _,
+            p_binding_flags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+        if binding_flags.iter().any(|flags| !flags.is_empty()) {
+            assert!(device.supports_bindless());
+            add_to_pnext!(p_next, flags_info);
+        }
+        let flags = if update_after_bind(&desc) {
+            vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL_BIT
+        } else {
+            Default::default()
+        };
         let info = vk::DescriptorSetLayoutCreateInfo {
+            p_next,
+            flags,
             binding_count: bindings.len() as _,
             p_bindings: bindings.as_ptr(),
             ..Default::default()
@@ -158,6 +188,14 @@ impl Layout {
         &self.desc().bindings
     }
 
+    /// Looks up a binding by its binding number.
+    #[inline]
+    pub fn binding(&self, binding: u32) -> Option<&SetLayoutBinding> {
+        let bindings = self.bindings();
+        let idx = bindings.binary_search_by_key(&binding, |b| b.binding).ok()?;
+        Some(&bindings[idx])
+    }
+
     #[inline]
     pub fn counts(&self) -> &Counts {
         &self.counts
@@ -165,7 +203,11 @@ impl Layout {
 
     #[inline]
     pub fn required_pool_flags(&self) -> vk::DescriptorPoolCreateFlags {
-        Default::default()
+        if update_after_bind(&self.desc) {
+            vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND_BIT
+        } else {
+            Default::default()
+        }
     }
 
     pub fn set_name(&mut self, name: impl Into<String>) {
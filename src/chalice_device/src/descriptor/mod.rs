@@ -13,13 +13,17 @@ pub use self::{
     DescriptorSetLayoutCache as SetLayoutCache, DescriptorSetLayoutDesc as SetLayoutDesc,
 };
 
+mod bindless;
 mod layout;
 mod pool;
 mod set;
+mod template;
 
+pub use bindless::*;
 pub use layout::*;
 pub use pool::*;
 pub use set::*;
+pub use template::*;
 
 wrap_vk_enum! {
     #[derive(Derivative, Enum)]
@@ -139,6 +143,188 @@ mod tests {
         }
     }
 
+    fn test_layout(device: &Arc<Device>) -> Arc<SetLayout> {
+        Arc::new(SetLayout::new(
+            Arc::clone(device),
+            set_layout_desc![
+                (0, UniformBuffer[2]),
+                (1, SampledImage),
+                (2, CombinedImageSampler[2]),
+            ],
+        ))
+    }
+
+    #[test]
+    fn batched_update() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let descriptors = &resources.descriptors;
+        let layout = test_layout(device);
+
+        let buffers = [resources.empty_uniform_buffer.range(); 2];
+        let view = &*resources.empty_image_2d;
+        let image_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let views = [(view, image_layout); 2];
+        let sampler = &*resources.empty_sampler;
+
+        let mut src = descriptors.alloc(Lifetime::Static, &layout);
+        src.update(
+            &[
+                DescriptorWrite::Buffers {
+                    binding: 0,
+                    first_element: 0,
+                    buffers: &buffers,
+                },
+                DescriptorWrite::ImageSamplers {
+                    binding: 1,
+                    first_element: 0,
+                    views: &views[..1],
+                    samplers: &[],
+                },
+                DescriptorWrite::ImageSamplers {
+                    binding: 2,
+                    first_element: 0,
+                    views: &views,
+                    samplers: &[sampler, sampler],
+                },
+            ],
+            &[],
+        );
+
+        let mut dst = descriptors.alloc(Lifetime::Static, &layout);
+        dst.update(
+            &[DescriptorWrite::ImageSamplers {
+                binding: 1,
+                first_element: 0,
+                views: &views[..1],
+                samplers: &[],
+            }],
+            &[
+                DescriptorCopy {
+                    src: &src,
+                    src_binding: 0,
+                    src_element: 0,
+                    dst_binding: 0,
+                    dst_element: 0,
+                    count: 2,
+                },
+                DescriptorCopy {
+                    src: &src,
+                    src_binding: 2,
+                    src_element: 1,
+                    dst_binding: 2,
+                    dst_element: 0,
+                    count: 1,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn empty_update() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let layout = test_layout(device);
+
+        let mut set = resources.descriptors.alloc(Lifetime::Static, &layout);
+        set.write_buffers(0, 0, &[]);
+        set.write_image_samplers(2, 1, &[], &[]);
+        set.update(&[], &[]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn copy_mismatched_type() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let descriptors = &resources.descriptors;
+        let layout = test_layout(device);
+
+        let src = descriptors.alloc(Lifetime::Static, &layout);
+        let mut dst = descriptors.alloc(Lifetime::Static, &layout);
+        dst.update(
+            &[],
+            &[DescriptorCopy {
+                src: &src,
+                src_binding: 1,
+                src_element: 0,
+                dst_binding: 2,
+                dst_element: 0,
+                count: 1,
+            }],
+        );
+    }
+
+    #[test]
+    fn update_template() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let descriptors = &resources.descriptors;
+        let layout = test_layout(device);
+        let template = DescriptorUpdateTemplate::new(Arc::clone(&layout));
+
+        let uniform = resources.empty_uniform_buffer.range();
+        let view = &*resources.empty_image_2d;
+        let image_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let sampler = &*resources.empty_sampler;
+
+        let mut sets = descriptors.alloc_many(Lifetime::Frame, &layout, 2);
+        for set in sets.iter_mut() {
+            template.update(
+                set,
+                &[
+                    DescriptorWrite::Buffers {
+                        binding: 0,
+                        first_element: 0,
+                        buffers: &[uniform],
+                    },
+                    DescriptorWrite::Buffers {
+                        binding: 0,
+                        first_element: 1,
+                        buffers: &[uniform],
+                    },
+                    DescriptorWrite::ImageSamplers {
+                        binding: 1,
+                        first_element: 0,
+                        views: &[(view, image_layout)],
+                        samplers: &[],
+                    },
+                    DescriptorWrite::ImageSamplers {
+                        binding: 2,
+                        first_element: 0,
+                        views: &[(view, image_layout), (view, image_layout)],
+                        samplers: &[sampler, sampler],
+                    },
+                ],
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn update_template_incomplete() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let descriptors = &resources.descriptors;
+        let layout = test_layout(device);
+        let template = DescriptorUpdateTemplate::new(Arc::clone(&layout));
+
+        let mut set = descriptors.alloc(Lifetime::Static, &layout);
+        template.update(
+            &mut set,
+            &[DescriptorWrite::Buffers {
+                binding: 0,
+                first_element: 0,
+                buffers: &[resources.empty_uniform_buffer.range()],
+            }],
+        );
+    }
+
     #[test]
     #[should_panic]
     fn layout_zero_count() {
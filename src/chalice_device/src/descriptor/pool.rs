@@ -52,12 +52,25 @@ impl Pool {
         max_sets: u32,
         descriptor_counts: Counts,
         lifetime: Lifetime,
+    ) -> Self {
+        Self::with_flags(
+            device, max_sets, descriptor_counts, lifetime, Default::default())
+    }
+
+    /// Creates a pool with extra creation flags, e.g. to allocate
+    /// update-after-bind sets.
+    pub fn with_flags(
+        device: Arc<Device>,
+        max_sets: u32,
+        descriptor_counts: Counts,
+        lifetime: Lifetime,
+        mut flags: vk::DescriptorPoolCreateFlags,
     ) -> Self {
         let dt = &*device.table;
 
-        let flags = if lifetime == Lifetime::Static {
-            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET_BIT
-        } else { Default::default() };
+        if lifetime == Lifetime::Static {
+            flags |= vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET_BIT;
+        }
         let pool_sizes = pool_sizes(&descriptor_counts);
         let create_info = vk::DescriptorPoolCreateInfo {
             flags,
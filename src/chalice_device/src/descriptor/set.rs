@@ -1,4 +1,3 @@
-use std::ptr;
 use std::sync::{Arc, Weak};
 
 use derivative::Derivative;
@@ -52,45 +51,15 @@ impl Set {
 
     /// Writes uniform or storage buffers. Doesn't work with texel
     /// buffers as they require a buffer view object.
-    // N.B. direct writes scale poorly compared to update templates.
     pub fn write_buffers(&mut self, binding: u32, first_element: u32, buffers: &[BufferRange<'_>]) {
-        let dt = &self.layout.device().table;
-        assert_ne!(buffers.len(), 0);
-
-        let layout_binding = &self.layout.bindings()[binding as usize];
-        let len = buffers.len() as u32;
-        let ty = layout_binding.ty;
-
-        // Validation
-        {
-            // N.B. Overrunning writes are actually allowed by the spec
-            assert!(first_element + len <= layout_binding.count);
-            for buffer in buffers.iter() {
-                let required = match buffer.buffer.binding().unwrap() {
-                    BufferBinding::Uniform => DescriptorType::UniformBuffer,
-                    BufferBinding::Storage => DescriptorType::StorageBuffer,
-                    _ => panic!("incompatible descriptor type"),
-                };
-                assert_eq!(ty, required);
-            }
-        }
-
-        let info: Vec<_> = buffers
-            .iter()
-            .map(|buffer| buffer.descriptor_info())
-            .collect();
-        let writes = [vk::WriteDescriptorSet {
-            dst_set: self.inner(),
-            dst_binding: binding,
-            dst_array_element: first_element,
-            descriptor_count: info.len() as _,
-            descriptor_type: ty.into(),
-            p_buffer_info: info.as_ptr(),
-            ..Default::default()
-        }];
-        unsafe {
-            dt.update_descriptor_sets(writes.len() as _, writes.as_ptr(), 0, ptr::null());
-        }
+        self.update(
+            &[DescriptorWrite::Buffers {
+                binding,
+                first_element,
+                buffers,
+            }],
+            &[],
+        );
     }
 
     #[inline]
@@ -118,81 +87,92 @@ impl Set {
         views: &[(&ImageView, vk::ImageLayout)],
         samplers: &[&Sampler],
     ) {
+        self.update(
+            &[DescriptorWrite::ImageSamplers {
+                binding,
+                first_element,
+                views,
+                samplers,
+            }],
+            &[],
+        );
+    }
+
+    /// Applies a batch of writes and copies with a single call to
+    /// `vkUpdateDescriptorSets`. Writes are applied before copies.
+    /// Writes of zero descriptors are ignored.
+    // N.B. direct writes scale poorly compared to update templates.
+    pub fn update(&mut self, writes: &[DescriptorWrite<'_>], copies: &[DescriptorCopy<'_>]) {
         trace!(
-            concat!(
-                "DescriptorSet::write_image_samplers(self: {:?}, binding: {}, ",
-                "first_element: {}, views: {:?}, samplers: {:?})",
-            ),
+            "DescriptorSet::update(self: {:?}, writes: {:?}, copies: {:?})",
             fmt_named(&*self),
-            binding,
-            first_element,
-            views,
-            samplers,
+            writes,
+            copies,
         );
 
-        if !views.is_empty() && !samplers.is_empty() {
-            assert_eq!(samplers.len(), views.len());
+        for write in writes.iter() {
+            validate_write(&self.layout, write);
         }
-
-        let count = (views.len() | samplers.len()) as u32;
-        let layout_binding = &self.layout.bindings()[binding as usize];
-        let ty = layout_binding.ty;
-
-        // Validation
-        assert_le!(first_element + count, layout_binding.count);
-        for i in 0..(count as usize) {
-            use vk::ImageLayout as Il;
-            use DescriptorType as Dt;
-
-            if let Some(&(ref view, layout)) = views.get(i) {
-                let flags = view.image().flags();
-                match ty {
-                    Dt::CombinedImageSampler | Dt::SampledImage => {
-                        assert!(!flags.contains(ImageFlags::NO_SAMPLE))
-                    }
-                    Dt::StorageImage => assert!(flags.contains(ImageFlags::STORAGE)),
-                    Dt::InputAttachment => assert!(flags.contains(ImageFlags::INPUT_ATTACHMENT)),
-                    _ => unreachable!(),
-                }
-                match ty {
-                    Dt::CombinedImageSampler | Dt::SampledImage => {
-                        assert_eq!(layout, Il::SHADER_READ_ONLY_OPTIMAL)
-                    }
-                    Dt::StorageImage => assert_eq!(layout, Il::GENERAL),
-                    _ => {}
-                }
-                if ty == Dt::CombinedImageSampler && layout_binding.samplers.is_none() {
-                    assert!(samplers.get(i).is_some());
-                }
-            }
+        for copy in copies.iter() {
+            validate_copy(copy, &self.layout);
+        }
+        let writes: Vec<_> = writes.iter().filter(|write| write.count() > 0).collect();
+        if writes.is_empty() && copies.is_empty() {
+            return;
         }
 
-        let mut info: Vec<vk::DescriptorImageInfo> = Vec::new();
-        info.resize(count as _, Default::default());
-        for i in 0..(count as usize) {
-            if let Some(&(ref view, layout)) = views.get(i) {
-                info[i].image_view = view.inner();
-                info[i].image_layout = layout;
-            }
-            if let Some(sampler) = samplers.get(i) {
-                info[i].sampler = sampler.inner();
-            }
+        // Fill in all descriptor info before taking pointers into it.
+        let mut buffer_info = Vec::new();
+        let mut image_info = Vec::new();
+        for write in writes.iter() {
+            write.append_info(&mut buffer_info, &mut image_info);
         }
-        let writes = [vk::WriteDescriptorSet {
-            dst_set: self.inner(),
-            dst_binding: binding,
-            dst_array_element: first_element,
-            descriptor_count: info.len() as _,
-            descriptor_type: ty.into(),
-            p_image_info: info.as_ptr(),
-            ..Default::default()
-        }];
+
+        let (mut buffer_idx, mut image_idx) = (0, 0);
+        let vk_writes: Vec<_> = writes
+            .iter()
+            .map(|write| {
+                let ty = self.layout.binding(write.binding()).unwrap().ty;
+                let count = write.count() as usize;
+                let mut vk_write = vk::WriteDescriptorSet {
+                    dst_set: self.inner(),
+                    dst_binding: write.binding(),
+                    dst_array_element: write.first_element(),
+                    descriptor_count: count as _,
+                    descriptor_type: ty.into(),
+                    ..Default::default()
+                };
+                if ty.is_buffer() {
+                    vk_write.p_buffer_info = buffer_info[buffer_idx..].as_ptr();
+                    buffer_idx += count;
+                } else {
+                    vk_write.p_image_info = image_info[image_idx..].as_ptr();
+                    image_idx += count;
+                }
+                vk_write
+            })
+            .collect();
+
+        let vk_copies: Vec<_> = copies
+            .iter()
+            .map(|copy| vk::CopyDescriptorSet {
+                src_set: copy.src.inner(),
+                src_binding: copy.src_binding,
+                src_array_element: copy.src_element,
+                dst_set: self.inner(),
+                dst_binding: copy.dst_binding,
+                dst_array_element: copy.dst_element,
+                descriptor_count: copy.count,
+                ..Default::default()
+            })
+            .collect();
+
         unsafe {
             self.device().table.update_descriptor_sets(
-                writes.len() as _,
-                writes.as_ptr(),
-                0,
-                ptr::null(),
+                vk_writes.len() as _,
+                vk_writes.as_ptr(),
+                vk_copies.len() as _,
+                vk_copies.as_ptr(),
             );
         }
     }
@@ -211,3 +191,163 @@ impl Named for Set {
         Some(&self.name.as_ref()?)
     }
 }
+
+/// A write of consecutive array elements of a single binding.
+#[derive(Clone, Copy, Debug)]
+pub enum DescriptorWrite<'a> {
+    /// Uniform or storage buffers.
+    Buffers {
+        binding: u32,
+        first_element: u32,
+        buffers: &'a [BufferRange<'a>],
+    },
+    /// Images, samplers, or combined image samplers, depending on the
+    /// binding type. If both are given, they must be the same length.
+    ImageSamplers {
+        binding: u32,
+        first_element: u32,
+        views: &'a [(&'a ImageView, vk::ImageLayout)],
+        samplers: &'a [&'a Sampler],
+    },
+}
+
+/// Copies descriptors from another set into the set being updated.
+#[derive(Clone, Copy, Debug)]
+pub struct DescriptorCopy<'a> {
+    pub src: &'a DescriptorSet,
+    pub src_binding: u32,
+    pub src_element: u32,
+    pub dst_binding: u32,
+    pub dst_element: u32,
+    pub count: u32,
+}
+
+impl DescriptorWrite<'_> {
+    #[inline]
+    pub fn binding(&self) -> u32 {
+        match *self {
+            Self::Buffers { binding, .. } | Self::ImageSamplers { binding, .. } => binding,
+        }
+    }
+
+    #[inline]
+    pub fn first_element(&self) -> u32 {
+        match *self {
+            Self::Buffers { first_element, .. } | Self::ImageSamplers { first_element, .. } => {
+                first_element
+            }
+        }
+    }
+
+    /// The number of descriptors written.
+    #[inline]
+    pub fn count(&self) -> u32 {
+        match self {
+            Self::Buffers { buffers, .. } => buffers.len() as _,
+            Self::ImageSamplers {
+                views, samplers, ..
+            } => std::cmp::max(views.len(), samplers.len()) as _,
+        }
+    }
+
+    pub(super) fn append_info(
+        &self,
+        buffer_info: &mut Vec<vk::DescriptorBufferInfo>,
+        image_info: &mut Vec<vk::DescriptorImageInfo>,
+    ) {
+        match self {
+            Self::Buffers { buffers, .. } => {
+                buffer_info.extend(buffers.iter().map(|buffer| buffer.descriptor_info()));
+            }
+            Self::ImageSamplers {
+                views, samplers, ..
+            } => {
+                image_info.extend((0..self.count() as usize).map(|i| {
+                    let mut info = vk::DescriptorImageInfo::default();
+                    if let Some(&(view, layout)) = views.get(i) {
+                        info.image_view = view.inner();
+                        info.image_layout = layout;
+                    }
+                    if let Some(sampler) = samplers.get(i) {
+                        info.sampler = sampler.inner();
+                    }
+                    info
+                }));
+            }
+        }
+    }
+}
+
+pub(super) fn validate_write(layout: &DescriptorSetLayout, write: &DescriptorWrite<'_>) {
+    let layout_binding = layout
+        .binding(write.binding())
+        .unwrap_or_else(|| panic!("no such binding: {}", write.binding()));
+    let ty = layout_binding.ty;
+    let count = write.count();
+    // N.B. Overrunning writes are actually allowed by the spec
+    assert_le!(write.first_element() + count, layout_binding.count);
+
+    match *write {
+        DescriptorWrite::Buffers { buffers, .. } => {
+            for buffer in buffers.iter() {
+                let required = match buffer.buffer.binding().unwrap() {
+                    BufferBinding::Uniform => DescriptorType::UniformBuffer,
+                    BufferBinding::Storage | BufferBinding::Indirect => {
+                        DescriptorType::StorageBuffer
+                    }
+                    _ => panic!("incompatible descriptor type"),
+                };
+                assert_eq!(ty, required);
+            }
+        }
+        DescriptorWrite::ImageSamplers {
+            views, samplers, ..
+        } => validate_image_samplers(layout_binding, views, samplers),
+    }
+}
+
+fn validate_image_samplers(
+    layout_binding: &SetLayoutBinding,
+    views: &[(&ImageView, vk::ImageLayout)],
+    samplers: &[&Sampler],
+) {
+    use vk::ImageLayout as Il;
+    use DescriptorType as Dt;
+
+    let ty = layout_binding.ty;
+    assert!(ty.is_image() || ty == Dt::Sampler, "{:?}", ty);
+    if !views.is_empty() && !samplers.is_empty() {
+        assert_eq!(samplers.len(), views.len());
+    }
+
+    for (i, &(view, layout)) in views.iter().enumerate() {
+        let flags = view.image().flags();
+        match ty {
+            Dt::CombinedImageSampler | Dt::SampledImage => {
+                assert!(!flags.contains(ImageFlags::NO_SAMPLE))
+            }
+            Dt::StorageImage => assert!(flags.contains(ImageFlags::STORAGE)),
+            Dt::InputAttachment => assert!(flags.contains(ImageFlags::INPUT_ATTACHMENT)),
+            _ => unreachable!(),
+        }
+        match ty {
+            Dt::CombinedImageSampler | Dt::SampledImage => {
+                assert_eq!(layout, Il::SHADER_READ_ONLY_OPTIMAL)
+            }
+            Dt::StorageImage => assert_eq!(layout, Il::GENERAL),
+            _ => {}
+        }
+        if ty == Dt::CombinedImageSampler && layout_binding.samplers.is_none() {
+            assert!(samplers.get(i).is_some());
+        }
+    }
+}
+
+fn validate_copy(copy: &DescriptorCopy<'_>, dst_layout: &DescriptorSetLayout) {
+    let src = copy.src.layout().binding(copy.src_binding).unwrap();
+    let dst = dst_layout.binding(copy.dst_binding).unwrap();
+    assert_eq!(src.ty, dst.ty);
+    assert_ne!(copy.count, 0);
+    assert_le!(copy.src_element + copy.count, src.count);
+    assert_le!(copy.dst_element + copy.count, dst.count);
+}
@@ -0,0 +1,179 @@
+use std::ptr;
+use std::sync::Arc;
+
+use derivative::Derivative;
+use log::trace;
+
+use super::*;
+use crate::*;
+
+/// Storage for a single descriptor in update template data.
+#[repr(C)]
+#[derive(Clone, Copy)]
+union TemplateSlot {
+    buffer: vk::DescriptorBufferInfo,
+    image: vk::DescriptorImageInfo,
+}
+
+/// Rewrites every descriptor of a set in a single call. Usable with
+/// any set allocated with the layout it was created from.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct DescriptorUpdateTemplate {
+    #[derivative(Debug(format_with = "write_named::<DescriptorSetLayout>"))]
+    layout: Arc<DescriptorSetLayout>,
+    inner: vk::DescriptorUpdateTemplate,
+    // Index of the first slot of each binding of the layout, or None
+    // if the binding is not updatable.
+    offsets: SmallVec<Option<u32>, 4>,
+    slot_count: u32,
+    name: Option<String>,
+}
+
+impl Drop for DescriptorUpdateTemplate {
+    fn drop(&mut self) {
+        let dt = &*self.layout.device().table;
+        unsafe {
+            dt.destroy_descriptor_update_template(self.inner, ptr::null());
+        }
+    }
+}
+
+// Immutable samplers can't be written to, so bindings consisting
+// solely of them are skipped.
+fn is_updatable(binding: &SetLayoutBinding) -> bool {
+    !(binding.ty == DescriptorType::Sampler && binding.samplers.is_some())
+}
+
+impl DescriptorUpdateTemplate {
+    pub fn new(layout: Arc<DescriptorSetLayout>) -> Self {
+        let stride = std::mem::size_of::<TemplateSlot>();
+        let mut offsets = SmallVec::new();
+        let mut entries: SmallVec<_, 4> = SmallVec::new();
+        let mut slot_count = 0;
+        for binding in layout.bindings().iter() {
+            if !is_updatable(binding) {
+                offsets.push(None);
+                continue;
+            }
+            offsets.push(Some(slot_count));
+            entries.push(vk::DescriptorUpdateTemplateEntry {
+                dst_binding: binding.binding,
+                dst_array_element: 0,
+                descriptor_count: binding.count,
+                descriptor_type: binding.ty.into(),
+                offset: slot_count as usize * stride,
+                stride,
+            });
+            slot_count += binding.count;
+        }
+        assert!(!entries.is_empty(), "no updatable bindings");
+
+        let create_info = vk::DescriptorUpdateTemplateCreateInfo {
+            descriptor_update_entry_count: entries.len() as _,
+            p_descriptor_update_entries: entries.as_ptr(),
+            template_type: vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET,
+            descriptor_set_layout: layout.inner(),
+            ..Default::default()
+        };
+        let dt = &*layout.device().table;
+        let mut inner = vk::null();
+        unsafe {
+            dt.create_descriptor_update_template(&create_info, ptr::null(), &mut inner)
+                .check()
+                .unwrap();
+        }
+
+        Self {
+            layout,
+            inner,
+            offsets,
+            slot_count,
+            name: None,
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        self.layout.device()
+    }
+
+    #[inline]
+    pub fn inner(&self) -> vk::DescriptorUpdateTemplate {
+        self.inner
+    }
+
+    #[inline]
+    pub fn layout(&self) -> &Arc<DescriptorSetLayout> {
+        &self.layout
+    }
+
+    /// Updates `set` with `writes`, which must together cover every
+    /// descriptor of every binding, excluding immutable samplers.
+    pub fn update(&self, set: &mut DescriptorSet, writes: &[DescriptorWrite<'_>]) {
+        trace!(
+            "DescriptorUpdateTemplate::update(self: {:?}, set: {:?}, writes: {:?})",
+            fmt_named(self),
+            fmt_named(&*set),
+            writes,
+        );
+        assert!(Arc::ptr_eq(set.layout(), &self.layout));
+
+        let empty = TemplateSlot {
+            image: Default::default(),
+        };
+        let mut data = vec![empty; self.slot_count as usize];
+        let mut written = vec![false; self.slot_count as usize];
+        let mut buffer_info = Vec::new();
+        let mut image_info = Vec::new();
+        for write in writes.iter() {
+            validate_write(&self.layout, write);
+            let idx = self
+                .layout
+                .bindings()
+                .iter()
+                .position(|binding| binding.binding == write.binding())
+                .unwrap();
+            let offset = self.offsets[idx].expect("binding is not updatable");
+            let first = (offset + write.first_element()) as usize;
+
+            buffer_info.clear();
+            image_info.clear();
+            write.append_info(&mut buffer_info, &mut image_info);
+            let slots = buffer_info
+                .iter()
+                .map(|&buffer| TemplateSlot { buffer })
+                .chain(image_info.iter().map(|&image| TemplateSlot { image }));
+            for (i, slot) in slots.enumerate() {
+                data[first + i] = slot;
+                written[first + i] = true;
+            }
+        }
+        assert!(
+            written.iter().all(|&written| written),
+            "update template requires every descriptor to be written",
+        );
+
+        unsafe {
+            self.device().table.update_descriptor_set_with_template(
+                set.inner(),
+                self.inner,
+                data.as_ptr() as _,
+            );
+        }
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        let name: String = name.into();
+        self.name = Some(name.clone());
+        unsafe {
+            self.device().set_name(self.inner, name);
+        }
+    }
+}
+
+impl Named for DescriptorUpdateTemplate {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name.as_ref()?)
+    }
+}
@@ -1,4 +1,4 @@
-use std::ffi::CString;
+use std::ffi::{c_char, CStr, CString};
 use std::ptr;
 use std::sync::Arc;
 
@@ -12,8 +12,12 @@ pub struct Device {
     pub(crate) pdev: vk::PhysicalDevice,
     pub(crate) props: vk::PhysicalDeviceProperties,
     pub(crate) queue_families: Vec<vk::QueueFamilyProperties>,
+    pub(crate) queue_family_indices: QueueFamilyIndices,
     pub(crate) mem_props: vk::PhysicalDeviceMemoryProperties,
     pub(crate) features: vk::PhysicalDeviceFeatures,
+    pub(crate) descriptor_indexing: bool,
+    pub(crate) memory_budget: bool,
+    pub(crate) memory_tracker: MemoryTracker,
 }
 
 impl Drop for Device {
@@ -51,33 +55,82 @@ impl Device {
     pub unsafe fn new(
         instance: Arc<Instance>,
         pdev: vk::PhysicalDevice,
+    ) -> DeviceResult<(Arc<Self>, Vec<Vec<Arc<Queue>>>)> {
+        Self::create(instance, pdev, &[vk::KHR_SWAPCHAIN_EXTENSION_NAME])
+    }
+
+    /// Creates a device which is not capable of presenting to a
+    /// surface, i.e. which doesn't enable any swapchain extensions.
+    pub unsafe fn new_headless(
+        instance: Arc<Instance>,
+        pdev: vk::PhysicalDevice,
+    ) -> DeviceResult<(Arc<Self>, Vec<Vec<Arc<Queue>>>)> {
+        Self::create(instance, pdev, &[])
+    }
+
+    unsafe fn create(
+        instance: Arc<Instance>,
+        pdev: vk::PhysicalDevice,
+        // TODO: check that extensions are actually supported
+        exts: &[*const c_char],
     ) -> DeviceResult<(Arc<Self>, Vec<Vec<Arc<Queue>>>)> {
         let it = &instance.table;
         let app_info = Arc::clone(&instance.app_info);
 
-        let mut p_next = ptr::null_mut();
+        // Optional extensions are enabled only if supported.
+        let mut exts = exts.to_vec();
+        let available = instance.get_device_extensions(pdev)?;
+        let memory_budget = available.iter().any(|props| {
+            CStr::from_ptr(props.extension_name.as_ptr())
+                == CStr::from_ptr(vk::EXT_MEMORY_BUDGET_EXTENSION_NAME)
+        });
+        if memory_budget {
+            exts.push(vk::EXT_MEMORY_BUDGET_EXTENSION_NAME);
+        }
 
-        // TODO: check that extensions are actually supported
-        let exts = [vk::KHR_SWAPCHAIN_EXTENSION_NAME];
+        let mut p_next = ptr::null_mut();
 
+        // Optional features are enabled only if supported.
+        let supported = instance.get_features(pdev);
         let features = vk::PhysicalDeviceFeatures {
             image_cube_array: vk::TRUE, // Currently only used in tests
             sampler_anisotropy: vk::TRUE,
+            pipeline_statistics_query: supported.pipeline_statistics_query,
+            geometry_shader: supported.geometry_shader,
+            depth_clamp: supported.depth_clamp,
+            depth_bounds: supported.depth_bounds,
+            independent_blend: supported.independent_blend,
+            logic_op: supported.logic_op,
+            tessellation_shader: supported.tessellation_shader,
             ..Default::default()
         };
+        let supported12 = instance.get_features12(pdev);
+        let descriptor_indexing = supports_descriptor_indexing(&supported12);
+        let indexing = bool32(descriptor_indexing);
         let mut features12 = vk::PhysicalDeviceVulkan12Features {
             timeline_semaphore: vk::TRUE,
+            descriptor_indexing: indexing,
+            shader_sampled_image_array_non_uniform_indexing: indexing,
+            descriptor_binding_sampled_image_update_after_bind: indexing,
+            descriptor_binding_update_unused_while_pending: indexing,
+            descriptor_binding_partially_bound: indexing,
+            runtime_descriptor_array: indexing,
             ..Default::default()
         };
         add_to_pnext!(p_next, features12);
 
-        // TODO: Why is this even hardcoded?
-        let queue_infos = [vk::DeviceQueueCreateInfo {
-            queue_family_index: 0,
-            queue_count: 1,
-            p_queue_priorities: &1f32,
-            ..Default::default()
-        }];
+        let queue_families = instance.get_queue_family_properties(pdev);
+        let queue_family_indices =
+            QueueFamilyIndices::find(&queue_families).ok_or("no graphics queue family")?;
+        let queue_infos: SmallVec<_, 3> = queue_family_indices
+            .iter()
+            .map(|family| vk::DeviceQueueCreateInfo {
+                queue_family_index: family,
+                queue_count: 1,
+                p_queue_priorities: &1f32,
+                ..Default::default()
+            })
+            .collect();
 
         let create_info = vk::DeviceCreateInfo {
             p_next,
@@ -97,9 +150,8 @@ impl Device {
         let table = Arc::new(vkl::DeviceTable::load(dev, get_device_proc_addr));
 
         let props = instance.get_properties(pdev);
-        let queue_families = instance.get_queue_family_properties(pdev);
-        let mut mem_props = Default::default();
-        it.get_physical_device_memory_properties(pdev, &mut mem_props);
+        let mem_props = instance.get_memory_properties(pdev);
+        let memory_tracker = MemoryTracker::new(mem_props.memory_type_count);
 
         let device = Arc::new(Device {
             table,
@@ -108,8 +160,12 @@ impl Device {
             pdev,
             props,
             queue_families,
+            queue_family_indices,
             mem_props,
             features,
+            descriptor_indexing,
+            memory_budget,
+            memory_tracker,
         });
 
         let queues = Queue::get_device_queues(&device);
@@ -137,6 +193,12 @@ impl Device {
         QueueFamily::new(self, index)
     }
 
+    /// The queue families queues were created in.
+    #[inline]
+    pub fn queue_family_indices(&self) -> &QueueFamilyIndices {
+        &self.queue_family_indices
+    }
+
     #[inline]
     pub fn limits(&self) -> &vk::PhysicalDeviceLimits {
         &self.properties().limits
@@ -147,6 +209,31 @@ impl Device {
         &self.features
     }
 
+    /// True if `memory_budget` reports heap budgets.
+    #[inline]
+    pub fn supports_memory_budget(&self) -> bool {
+        self.memory_budget
+    }
+
+    /// True if the device supports the descriptor indexing features
+    /// needed by `BindlessHeap`.
+    #[inline]
+    pub fn supports_bindless(&self) -> bool {
+        self.descriptor_indexing
+    }
+
+    pub fn format_properties(&self, format: Format) -> vk::FormatProperties {
+        let mut props = Default::default();
+        unsafe {
+            self.instance.table.get_physical_device_format_properties(
+                self.pdev,
+                format.into(),
+                &mut props,
+            );
+        }
+        props
+    }
+
     pub unsafe fn set_name(&self, handle: impl DebugHandle, name: impl Into<String>) {
         if self.app_info.debug {
             let name = CString::new(name.into()).unwrap();
@@ -157,8 +244,10 @@ impl Device {
     pub unsafe fn create_swapchain(
         self: Arc<Self>,
         surface: Arc<Surface>,
+        window_extent: Extent2D,
+        options: SwapchainOptions,
     ) -> DeviceResult<Swapchain> {
-        let mut swapchain = Swapchain::new(surface, self)?;
+        let mut swapchain = Swapchain::new(surface, self, window_extent, options)?;
         set_name!(swapchain);
         Ok(swapchain)
     }
@@ -169,3 +258,16 @@ impl Device {
         }
     }
 }
+
+fn supports_descriptor_indexing(features: &vk::PhysicalDeviceVulkan12Features) -> bool {
+    [
+        features.descriptor_indexing,
+        features.shader_sampled_image_array_non_uniform_indexing,
+        features.descriptor_binding_sampled_image_update_after_bind,
+        features.descriptor_binding_update_unused_while_pending,
+        features.descriptor_binding_partially_bound,
+        features.runtime_descriptor_array,
+    ]
+    .iter()
+    .all(|&feature| feature == vk::TRUE)
+}
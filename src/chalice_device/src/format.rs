@@ -36,6 +36,17 @@ macro_rules! impl_format {
                 }
             }
         }
+
+        impl std::convert::TryFrom<vk::Format> for Format {
+            type Error = &'static str;
+
+            fn try_from(fmt: vk::Format) -> Result<Self, Self::Error> {
+                $(if fmt == vk::Format::$vk_format {
+                    return Ok(Format::$name);
+                })*
+                Err("unsupported format")
+            }
+        }
     }
 }
 
@@ -53,11 +64,15 @@ impl_format! {
     RGB16F(R16G16B16_SFLOAT, 6, COLOR_BIT),
     RGB32F(R32G32B32_SFLOAT, 12, COLOR_BIT),
     RGBA8(R8G8B8A8_UNORM, 4, COLOR_BIT),
+    RGBA8_SRGB(R8G8B8A8_SRGB, 4, COLOR_BIT),
     RGBA8U(R8G8B8A8_UINT, 4, COLOR_BIT),
     // Blender exports joints in this format (ugh)
     RGBA16U(R16G16B16A16_UINT, 8, COLOR_BIT),
     RGBA16F(R16G16B16A16_SFLOAT, 8, COLOR_BIT),
     RGBA32F(R32G32B32A32_SFLOAT, 16, COLOR_BIT),
+    // Packed with alpha in the high bits; used for HDR10 output
+    RGB10A2(A2B10G10R10_UNORM_PACK32, 4, COLOR_BIT),
+    BGR10A2(A2R10G10B10_UNORM_PACK32, 4, COLOR_BIT),
     BGRA8(B8G8R8A8_UNORM, 4, COLOR_BIT),
     BGRA8_SRGB(B8G8R8A8_SRGB, 4, COLOR_BIT),
     D16(D16_UNORM, 2, DEPTH_BIT),
@@ -99,6 +114,12 @@ impl Format {
         self.aspects()
             .intersects(Flags::DEPTH_BIT | Flags::STENCIL_BIT)
     }
+
+    /// True for formats that are sRGB-encoded in memory.
+    #[inline]
+    pub fn is_srgb(self) -> bool {
+        matches!(self, Format::RGBA8_SRGB | Format::BGRA8_SRGB)
+    }
 }
 
 #[cfg(test)]
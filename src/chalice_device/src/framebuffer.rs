@@ -254,7 +254,7 @@ mod tests {
 
     #[test]
     fn create() {
-        let vars = TestVars::new();
+        let vars = SwapchainTestVars::new();
         let _fb = create_test_framebuffer(vars.swapchain());
     }
 }
@@ -0,0 +1,86 @@
+//! Device initialization without a window system, e.g. for running
+//! render tests on a display-less machine with a software driver.
+
+use std::sync::Arc;
+
+use crate::*;
+
+/// Picks a physical device capable of graphics, compute, and transfer
+/// operations without regard for presentation support.
+pub unsafe fn headless_device(instance: &Instance) -> DeviceResult<vk::PhysicalDevice> {
+    Ok(instance.select_device(None)?.pdev)
+}
+
+/// Helper function which creates a logical device with no surface or
+/// swapchain support.
+pub fn init_device_headless(
+    app_info: AppInfo,
+) -> DeviceResult<(Arc<Device>, Vec<Vec<Arc<Queue>>>)> {
+    unsafe {
+        let entrypoint = crate::loader::load_vulkan().map_err(|_| "Failed to load libvulkan")?;
+        let instance = Arc::new(Instance::new(entrypoint, app_info, &[])?);
+        let pdev = headless_device(&instance)?;
+        instance.create_headless_device(pdev)
+    }
+}
+
+/// Creates an image which stands in for the swapchain when rendering
+/// offscreen. Pass the swapchain's format so that render passes may be
+/// used interchangeably.
+pub fn create_offscreen_backbuffer(
+    heap: &ImageHeap,
+    format: Format,
+    extent: Extent2D,
+) -> Arc<ImageView> {
+    ImageDef::new(
+        heap.device(),
+        ImageFlags::NO_SAMPLE | ImageFlags::COLOR_ATTACHMENT | ImageFlags::TRANSFER_SRC,
+        ImageType::Dim2,
+        format,
+        SampleCount::One,
+        extent.into(),
+        1,
+        1,
+    )
+    .with_name("offscreen_backbuffer")
+    .build_image(heap)
+    .create_full_view()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn offscreen_framebuffer() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let heap = ImageHeap::new(Arc::clone(device));
+        let backbuffer =
+            create_offscreen_backbuffer(&heap, Format::BGRA8_SRGB, Extent2D::new(320, 200));
+        assert_eq!(backbuffer.format(), Format::BGRA8_SRGB);
+
+        unsafe {
+            let pass = RenderPass::new(
+                Arc::clone(device),
+                vec![AttachmentDescription {
+                    format: Format::BGRA8_SRGB,
+                    final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ..Default::default()
+                }],
+                vec![SubpassDesc::new(
+                    vec![vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL],
+                    vec![],
+                    vec![0],
+                    vec![],
+                    vec![],
+                    None,
+                )],
+                vec![],
+            );
+            let fb = Framebuffer::new(pass, vec![backbuffer.into()]);
+            assert_eq!(fb.extent(), Extent2D::new(320, 200));
+        }
+    }
+}
@@ -6,6 +6,7 @@ use derivative::Derivative;
 use derive_more::Constructor;
 use log::trace;
 use more_asserts::{assert_gt, assert_le, assert_lt};
+use parking_lot::Mutex;
 
 use crate::*;
 
@@ -23,6 +24,9 @@ bitflags! {
         const DEPTH_STENCIL_ATTACHMENT = bit!(3);
         /// Image may be used as an input attachment.
         const INPUT_ATTACHMENT = bit!(4);
+        /// Image may be the source of a copy or blit, e.g. for mipmap
+        /// generation or readback.
+        const TRANSFER_SRC = bit!(5);
         // TODO: Image may be sampled in a vertex shader.
         //const SAMPLE_VERTEX = bit!(_);
     }
@@ -83,6 +87,8 @@ pub struct Image {
     inner: vk::Image,
     #[derivative(Debug = "ignore")]
     alloc: DeviceAlloc,
+    #[derivative(Debug = "ignore")]
+    state: Mutex<ImageState>,
 }
 
 // TODO: Convenient, but belongs in application code.
@@ -122,10 +128,42 @@ impl Drop for Image {
 impl Image {
     // TODO: Almost certainly want a separate allocation option for
     // framebuffer images.
+    /// Creates an image. Panics if memory cannot be allocated; see
+    /// `try_new`.
     pub fn new(heap: &ImageHeap, def: Arc<ImageDef>) -> Self {
+        Self::try_new(heap, def).unwrap_or_else(|e| panic!("failed to create image: {}", e))
+    }
+
+    /// Creates an image, returning an error if memory cannot be
+    /// allocated.
+    pub fn try_new(heap: &ImageHeap, def: Arc<ImageDef>) -> DeviceResult<Self> {
         trace!("Image::new(def: {:?})", fmt_named(&*def));
 
         let device = Arc::clone(heap.device());
+        unsafe { Self::create(device, def, |image| heap.bind(image)) }
+    }
+
+    /// Creates an image with the same definition, bound to `alloc`.
+    /// The contents and tracked state of this image are not carried
+    /// over.
+    pub(crate) unsafe fn relocated(&self, alloc: DeviceAlloc) -> DeviceResult<Self> {
+        let device = Arc::clone(&self.device);
+        Self::create(device, Arc::clone(&self.def), |image| {
+            let memory = alloc.memory().inner();
+            let offset = alloc.offset();
+            self.device
+                .table
+                .bind_image_memory(image, memory, offset)
+                .check()?;
+            Ok(alloc)
+        })
+    }
+
+    unsafe fn create(
+        device: Arc<Device>,
+        def: Arc<ImageDef>,
+        bind: impl FnOnce(vk::Image) -> DeviceResult<DeviceAlloc>,
+    ) -> DeviceResult<Self> {
         let dt = &*device.table;
 
         let &ImageDef {
@@ -152,26 +190,28 @@ impl Image {
             ..Default::default()
         };
         let mut image = vk::null();
-        unsafe {
-            dt.create_image(&create_info, ptr::null(), &mut image)
-                .check()
-                .unwrap();
-        }
-
-        let alloc = unsafe { heap.bind(image) };
+        dt.create_image(&create_info, ptr::null(), &mut image)
+            .check()?;
+
+        let alloc = match bind(image) {
+            Ok(alloc) => alloc,
+            Err(e) => {
+                dt.destroy_image(image, ptr::null());
+                return Err(e);
+            }
+        };
 
         if let Some(name) = &def.name {
-            unsafe {
-                device.set_name(image, name);
-            }
+            device.set_name(image, name);
         }
 
-        Self {
+        Ok(Self {
             device,
             def,
             inner: image,
             alloc,
-        }
+            state: Mutex::new(ImageState::new(mip_levels, layers)),
+        })
     }
 
     #[inline]
@@ -248,6 +288,11 @@ impl Image {
         &self.alloc
     }
 
+    #[inline]
+    pub(crate) fn alloc_mut(&mut self) -> &mut DeviceAlloc {
+        &mut self.alloc
+    }
+
     #[inline]
     pub fn validate_subresources(&self, sub: &ImageSubresources) {
         self.def.validate_subresources(sub);
@@ -310,6 +355,39 @@ impl Image {
     pub fn create_full_view(self: &Arc<Self>) -> Arc<ImageView> {
         self.create_view(self.all_subresources())
     }
+
+    /// Returns the tracked state of a single subresource as of the most
+    /// recently recorded command.
+    pub fn subresource_state(&self, mip_level: u32, layer: u32) -> SubresourceState {
+        assert_lt!(mip_level, self.mip_levels());
+        assert_lt!(layer, self.layers());
+        *self.state.lock().get(mip_level, layer)
+    }
+
+    /// Marks the contents of the subresources as no longer needed, so
+    /// the next usage may transition them from `UNDEFINED`.
+    pub fn discard(&self, sub: &ImageSubresources) {
+        self.validate_subresources(sub);
+        self.state.lock().discard(sub);
+    }
+
+    /// Overrides the tracked state of the subresources, e.g. after a
+    /// render pass or manual barrier has changed their layout. The
+    /// subresources are treated as last accessed by `usage` on
+    /// `queue_family`.
+    pub unsafe fn assume_usage(
+        &self,
+        sub: &ImageSubresources,
+        usage: ImageUsage,
+        queue_family: u32,
+    ) {
+        self.validate_subresources(sub);
+        self.state.lock().assume(sub, &usage.access(), queue_family);
+    }
+
+    pub(crate) fn state(&self) -> &Mutex<ImageState> {
+        &self.state
+    }
 }
 
 impl Named for Image {
@@ -431,6 +509,11 @@ impl ImageDef {
     pub fn build_image(self, heap: &ImageHeap) -> Arc<Image> {
         Arc::new(Image::new(heap, Arc::new(self)))
     }
+
+    #[inline]
+    pub fn try_build_image(self, heap: &ImageHeap) -> DeviceResult<Arc<Image>> {
+        Ok(Arc::new(Image::try_new(heap, Arc::new(self))?))
+    }
 }
 
 impl Named for ImageDef {
@@ -495,6 +578,12 @@ impl ImageView {
         &self.image
     }
 
+    /// Returns the image if the view is its only owner.
+    #[inline]
+    pub(crate) fn image_mut(&mut self) -> Option<&mut Image> {
+        Arc::get_mut(&mut self.image)
+    }
+
     #[inline]
     pub fn flags(&self) -> ImageViewFlags {
         self.flags
@@ -505,6 +594,11 @@ impl ImageView {
         self.format
     }
 
+    #[inline]
+    pub fn components(&self) -> vk::ComponentMapping {
+        self.components
+    }
+
     #[inline]
     pub fn samples(&self) -> SampleCount {
         self.image.samples()
@@ -556,6 +650,7 @@ impl ImageFlags {
                 F::DEPTH_STENCIL_ATTACHMENT_BIT,
             ),
             (Self::INPUT_ATTACHMENT, F::INPUT_ATTACHMENT_BIT),
+            (Self::TRANSFER_SRC, F::TRANSFER_SRC_BIT),
         ];
         let mut usage = pairs
             .iter()
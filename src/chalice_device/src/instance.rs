@@ -1,5 +1,5 @@
 // TODO: User-friendly errors
-use std::ffi::{c_char, CString};
+use std::ffi::{c_char, CStr, CString};
 use std::ptr;
 use std::sync::Arc;
 
@@ -18,6 +18,9 @@ pub struct Instance {
     #[derivative(Debug = "ignore")]
     pub(crate) table: Arc<vkl::InstanceTable>,
     pub(crate) app_info: Arc<AppInfo>,
+    /// True if `VK_EXT_swapchain_colorspace` is enabled, which is
+    /// needed to present in HDR color spaces.
+    pub(crate) swapchain_colorspace: bool,
     debug_messengers: Vec<DebugMessenger>,
     debug_handler: Arc<DefaultDebugMessageHandler>,
 }
@@ -29,6 +32,10 @@ pub struct AppInfo {
     pub version: [u32; 3],
     pub debug: bool,
     pub test: bool,
+    /// Selects a physical device by index or by a substring of its
+    /// name. The `CHALICE_DEVICE` environment variable takes
+    /// precedence. By default, the highest scoring device is used.
+    pub device: Option<String>,
 }
 
 impl Drop for Instance {
@@ -90,6 +97,23 @@ impl Instance {
             .collect();
         extensions.extend(required_extensions.iter().map(|ext| ext.as_ptr()));
 
+        // Enable extended color spaces if we intend to present.
+        let mut swapchain_colorspace = false;
+        if required_extensions
+            .iter()
+            .any(|ext| ext.as_bytes() == b"VK_KHR_surface")
+        {
+            let available =
+                vk::enumerate2!(entry, enumerate_instance_extension_properties, ptr::null())?;
+            let name = CStr::from_ptr(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION_NAME);
+            swapchain_colorspace = available
+                .iter()
+                .any(|props| CStr::from_ptr(props.extension_name.as_ptr()) == name);
+            if swapchain_colorspace {
+                extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION_NAME);
+            }
+        }
+
         if app_info.debug {
             layers.push(c_str!("VK_LAYER_KHRONOS_validation"));
             extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION_NAME);
@@ -121,6 +145,7 @@ impl Instance {
             entry,
             table,
             app_info,
+            swapchain_colorspace,
             debug_messengers: Vec::new(),
             debug_handler: Default::default(),
         };
@@ -163,6 +188,53 @@ impl Instance {
         res
     }
 
+    pub unsafe fn get_device_extensions(
+        &self,
+        pdev: vk::PhysicalDevice,
+    ) -> DeviceResult<Vec<vk::ExtensionProperties>> {
+        Ok(vk::enumerate2!(
+            self.table,
+            enumerate_device_extension_properties,
+            pdev,
+            ptr::null(),
+        )?)
+    }
+
+    pub unsafe fn get_features(&self, pdev: vk::PhysicalDevice) -> vk::PhysicalDeviceFeatures {
+        let mut res = Default::default();
+        self.table.get_physical_device_features(pdev, &mut res);
+        res
+    }
+
+    pub unsafe fn get_features12(
+        &self,
+        pdev: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceVulkan12Features {
+        let mut features12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut features12 as *mut _ as _,
+            ..Default::default()
+        };
+        self.table
+            .get_physical_device_features2(pdev, &mut features);
+        features12.p_next = ptr::null_mut();
+        features12
+    }
+
+    pub unsafe fn get_properties12(
+        &self,
+        pdev: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceVulkan12Properties {
+        let mut props12 = vk::PhysicalDeviceVulkan12Properties::default();
+        let mut props = vk::PhysicalDeviceProperties2 {
+            p_next: &mut props12 as *mut _ as _,
+            ..Default::default()
+        };
+        self.table.get_physical_device_properties2(pdev, &mut props);
+        props12.p_next = ptr::null_mut();
+        props12
+    }
+
     pub unsafe fn create_device(
         self: &Arc<Self>,
         pdev: vk::PhysicalDevice,
@@ -170,6 +242,13 @@ impl Instance {
         Ok(Device::new(Arc::clone(self), pdev)?)
     }
 
+    pub unsafe fn create_headless_device(
+        self: &Arc<Self>,
+        pdev: vk::PhysicalDevice,
+    ) -> DeviceResult<(Arc<Device>, Vec<Vec<Arc<Queue>>>)> {
+        Ok(Device::new_headless(Arc::clone(self), pdev)?)
+    }
+
     pub unsafe fn create_surface(
         self: &Arc<Self>,
         window: &impl Window,
@@ -9,6 +9,7 @@
     try_blocks,
     type_ascription
 )]
+#![cfg_attr(test, feature(test))]
 #![allow(incomplete_features, path_statements)]
 #![allow(
     clippy::missing_safety_doc,
@@ -19,6 +20,9 @@
     clippy::type_complexity
 )]
 
+#[cfg(test)]
+extern crate test;
+
 macro_rules! err_msg {
     ($msg:literal) => {
         crate::Error(anyhow::anyhow!($msg))
@@ -28,6 +32,8 @@ macro_rules! err_msg {
 #[macro_use]
 mod util;
 
+mod barrier;
+mod binary_cache;
 mod commands;
 mod debug;
 mod descriptor;
@@ -35,11 +41,14 @@ mod device;
 mod extent;
 mod format;
 mod framebuffer;
+mod headless;
 mod image;
 mod instance;
 mod loader;
 mod memory;
+mod physical_device;
 mod pipeline;
+mod query;
 mod queue;
 mod render_pass;
 mod sampler;
@@ -50,6 +59,8 @@ mod sync;
 mod vertex;
 mod window;
 
+pub use barrier::*;
+pub use binary_cache::*;
 pub use commands::*;
 pub use debug::*;
 pub use descriptor::*;
@@ -57,11 +68,14 @@ pub use device::*;
 pub use extent::*;
 pub use format::*;
 pub use framebuffer::*;
+pub use headless::*;
 pub use image::*;
 pub use instance::*;
 pub use loader::*;
 pub use memory::*;
+pub use physical_device::*;
 pub use pipeline::*;
+pub use query::*;
 pub use queue::*;
 pub use render_pass::*;
 pub use sampler::*;
@@ -108,6 +122,44 @@ impl<'a> From<&'a str> for Error {
     }
 }
 
+impl From<OutOfMemoryError> for Error {
+    fn from(err: OutOfMemoryError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl Error {
+    /// Returns the details of an allocation failure, if this error was
+    /// caused by one.
+    pub fn out_of_memory(&self) -> Option<&OutOfMemoryError> {
+        self.0.downcast_ref()
+    }
+
+    /// True if the device or host ran out of memory.
+    pub fn is_out_of_memory(&self) -> bool {
+        self.out_of_memory().is_some()
+            || matches!(
+                self.0.downcast_ref(),
+                Some(&vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)
+                    | Some(&vk::Result::ERROR_OUT_OF_HOST_MEMORY)
+            )
+    }
+}
+
+/// Returned when an allocation fails in every compatible memory type.
+#[derive(Clone, Copy, Debug, Display)]
+#[display(
+    fmt = "out of device memory (size: {}, memory type bits: {:#x})",
+    size,
+    type_bits
+)]
+pub struct OutOfMemoryError {
+    pub size: vk::DeviceSize,
+    pub type_bits: u32,
+}
+
+impl std::error::Error for OutOfMemoryError {}
+
 // TODO: This should kind of just be std::result::Result<T, vk::Result>
 pub type DeviceResult<T> = std::result::Result<T, Error>;
 
@@ -1,26 +1,59 @@
 use std::ops::Range;
 
+use derivative::Derivative;
+use fnv::FnvHashMap;
+
 use super::*;
 
 pub(super) trait Allocator: Default {
-    // TODO: Likely want to record two numbers: true memory usage and
-    // (approximate) memory usage including fragmentation.
-    //   true_usage = sum(alloc.size for each successful alloc)
-    //   frag_usage = capacity - amount available for allocation
     // TODO: This logic shouldn't need to be reimplemented for every
     // implementor of the Allocator trait.
     fn used(&self) -> vk::DeviceSize;
     fn capacity(&self) -> vk::DeviceSize;
+    fn chunk_count(&self) -> u32;
+    fn allocation_count(&self) -> u32;
+    /// Size of the largest block that could be allocated without
+    /// adding a chunk, ignoring alignment.
+    fn largest_free_block(&self) -> vk::DeviceSize;
     fn add_chunk(&mut self, size: vk::DeviceSize);
     fn alloc(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<Block>;
     fn free(&mut self, block: Block);
     fn clear(&mut self);
+
+    fn stats(&self) -> MemoryStats {
+        let used = self.used();
+        let reserved = self.capacity();
+        let largest_free_block = self.largest_free_block();
+        MemoryStats {
+            used,
+            reserved,
+            fragmented: reserved - used - largest_free_block,
+            allocation_count: self.allocation_count(),
+            block_count: self.chunk_count(),
+            largest_free_block,
+        }
+    }
+}
+
+/// Operations needed to move allocations between chunks and release
+/// chunks which end up empty.
+pub(super) trait Defragment: Allocator {
+    /// Size of each chunk, or zero if the chunk was removed.
+    fn chunks(&self) -> &[vk::DeviceSize];
+    /// Lists every free block.
+    fn free_blocks(&self) -> Vec<Block>;
+    /// Allocates a specific block, which must be free.
+    fn alloc_at(&mut self, block: Block);
+    /// Removes a chunk, which must be entirely free. Its index is not
+    /// reused.
+    fn remove_chunk(&mut self, chunk: u32);
 }
 
 /// Address-ordered FIFO allocation algorithm.
 #[derive(Debug, Default)]
 pub(super) struct FreeListAllocator {
     used: vk::DeviceSize,
+    count: u32,
     // List of chunk sizes
     chunks: Vec<vk::DeviceSize>,
     free: Vec<Block>,
@@ -72,6 +105,7 @@ impl FreeListAllocator {
         }
         let chunk = block.chunk;
         self.carve_block(block_idx, offset..offset + size);
+        self.count += 1;
         Some(Block {
             chunk,
             start: offset,
@@ -85,6 +119,7 @@ impl FreeListAllocator {
         let end = block.end;
 
         self.used -= end - start;
+        self.count -= 1;
 
         // Find insertion point
         // TODO: Binary search
@@ -93,7 +128,7 @@ impl FreeListAllocator {
         let mut idx = self.free.len();
         for i in 0..self.free.len() {
             let block = self.free[i];
-            if (block.chunk == chunk) & (start < block.start) {
+            if (block.chunk > chunk) | ((block.chunk == chunk) & (start < block.start)) {
                 idx = i;
                 break;
             }
@@ -139,6 +174,18 @@ impl Allocator for FreeListAllocator {
         self.chunks.iter().sum()
     }
 
+    fn chunk_count(&self) -> u32 {
+        self.chunks.iter().filter(|&&size| size > 0).count() as _
+    }
+
+    fn allocation_count(&self) -> u32 {
+        self.count
+    }
+
+    fn largest_free_block(&self) -> vk::DeviceSize {
+        self.free.iter().map(Block::size).max().unwrap_or(0)
+    }
+
     fn add_chunk(&mut self, size: vk::DeviceSize) {
         self.chunks.push(size);
         self.free.push(Block {
@@ -162,7 +209,11 @@ impl Allocator for FreeListAllocator {
     fn clear(&mut self) {
         self.free.clear();
         self.used = 0;
+        self.count = 0;
         for (i, &size) in self.chunks.iter().enumerate() {
+            if size == 0 {
+                continue;
+            }
             self.free.push(Block {
                 chunk: i as _,
                 start: 0,
@@ -172,6 +223,516 @@ impl Allocator for FreeListAllocator {
     }
 }
 
+impl Defragment for FreeListAllocator {
+    fn chunks(&self) -> &[vk::DeviceSize] {
+        &self.chunks
+    }
+
+    fn free_blocks(&self) -> Vec<Block> {
+        self.free.clone()
+    }
+
+    fn alloc_at(&mut self, block: Block) {
+        let index = self
+            .free
+            .iter()
+            .position(|free| {
+                free.chunk == block.chunk && free.start <= block.start && block.end <= free.end
+            })
+            .unwrap_or_else(|| panic!("block not free: {:?}", block));
+        self.carve_block(index, block.start..block.end);
+        self.count += 1;
+    }
+
+    fn remove_chunk(&mut self, chunk: u32) {
+        let whole = Block {
+            chunk,
+            start: 0,
+            end: self.chunks[chunk as usize],
+        };
+        let index = self
+            .free
+            .iter()
+            .position(|&free| free == whole)
+            .unwrap_or_else(|| panic!("chunk in use: {}", chunk));
+        self.free.remove(index);
+        self.chunks[chunk as usize] = 0;
+    }
+}
+
+/// Selects the algorithm used to suballocate memory from a pool.
+#[derive(Clone, Copy, Debug, Derivative, Eq, Hash, PartialEq)]
+#[derivative(Default)]
+pub enum AllocatorKind {
+    /// Address-ordered first fit. Allocation and free take time linear
+    /// in the number of free blocks.
+    #[derivative(Default)]
+    FreeList,
+    /// Two-level segregated fit. Allocation and free take constant
+    /// time.
+    Tlsf,
+}
+
+/// Allocator whose algorithm is chosen when the pool is created.
+#[derive(Debug)]
+pub(super) enum PoolAllocator {
+    FreeList(FreeListAllocator),
+    Tlsf(TlsfAllocator),
+}
+
+impl Default for PoolAllocator {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl PoolAllocator {
+    pub(super) fn new(kind: AllocatorKind) -> Self {
+        match kind {
+            AllocatorKind::FreeList => Self::FreeList(FreeListAllocator::new()),
+            AllocatorKind::Tlsf => Self::Tlsf(TlsfAllocator::new()),
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($self:expr, $alloc:ident => $expr:expr) => {
+        match $self {
+            PoolAllocator::FreeList($alloc) => $expr,
+            PoolAllocator::Tlsf($alloc) => $expr,
+        }
+    };
+}
+
+impl Allocator for PoolAllocator {
+    fn used(&self) -> vk::DeviceSize {
+        dispatch!(self, alloc => alloc.used())
+    }
+
+    fn capacity(&self) -> vk::DeviceSize {
+        dispatch!(self, alloc => alloc.capacity())
+    }
+
+    fn chunk_count(&self) -> u32 {
+        dispatch!(self, alloc => alloc.chunk_count())
+    }
+
+    fn allocation_count(&self) -> u32 {
+        dispatch!(self, alloc => alloc.allocation_count())
+    }
+
+    fn largest_free_block(&self) -> vk::DeviceSize {
+        dispatch!(self, alloc => alloc.largest_free_block())
+    }
+
+    fn add_chunk(&mut self, size: vk::DeviceSize) {
+        dispatch!(self, alloc => alloc.add_chunk(size))
+    }
+
+    fn alloc(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<Block> {
+        dispatch!(self, alloc => alloc.alloc(size, alignment))
+    }
+
+    fn free(&mut self, block: Block) {
+        dispatch!(self, alloc => alloc.free(block))
+    }
+
+    fn clear(&mut self) {
+        dispatch!(self, alloc => alloc.clear())
+    }
+}
+
+impl Defragment for PoolAllocator {
+    fn chunks(&self) -> &[vk::DeviceSize] {
+        dispatch!(self, alloc => alloc.chunks())
+    }
+
+    fn free_blocks(&self) -> Vec<Block> {
+        dispatch!(self, alloc => alloc.free_blocks())
+    }
+
+    fn alloc_at(&mut self, block: Block) {
+        dispatch!(self, alloc => alloc.alloc_at(block))
+    }
+
+    fn remove_chunk(&mut self, chunk: u32) {
+        dispatch!(self, alloc => alloc.remove_chunk(chunk))
+    }
+}
+
+// Each power-of-two size class is split into 2^SL_LOG2 linear classes.
+const SL_LOG2: u32 = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+// Sizes below SL_COUNT get a class of their own.
+const FL_COUNT: usize = 64 - SL_LOG2 as usize + 1;
+const NIL: u32 = !0;
+
+/// Two-level segregated fit allocator. Free blocks are binned by size
+/// into segregated lists indexed by a pair of bitmaps, so allocation
+/// and free are O(1) and fragmentation stays low under heavy churn.
+#[derive(Debug)]
+pub(super) struct TlsfAllocator {
+    used: vk::DeviceSize,
+    count: u32,
+    // List of chunk sizes
+    chunks: Vec<vk::DeviceSize>,
+    // Block headers, free and used; indexed by u32
+    blocks: Vec<TlsfBlock>,
+    // Unoccupied slots in `blocks`
+    spare: Vec<u32>,
+    // Used blocks by (chunk, start)
+    used_blocks: FnvHashMap<(u32, vk::DeviceSize), u32>,
+    fl_bitmap: u64,
+    sl_bitmaps: [u32; FL_COUNT],
+    // Head of each free list, indexed by fl * SL_COUNT + sl
+    heads: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TlsfBlock {
+    chunk: u32,
+    start: vk::DeviceSize,
+    end: vk::DeviceSize,
+    free: bool,
+    // Physically adjacent blocks
+    prev_phys: u32,
+    next_phys: u32,
+    // Neighbors in the free list
+    prev_free: u32,
+    next_free: u32,
+}
+
+/// Returns the (first-level, second-level) class of a block size.
+#[inline]
+fn tlsf_class(size: vk::DeviceSize) -> (usize, usize) {
+    if size < SL_COUNT as vk::DeviceSize {
+        return (0, size as usize);
+    }
+    let log2 = 63 - size.leading_zeros();
+    let sl = (size >> (log2 - SL_LOG2)) as usize ^ SL_COUNT;
+    ((log2 - SL_LOG2 + 1) as usize, sl)
+}
+
+/// Rounds a size up so that every block in its class is big enough.
+#[inline]
+fn tlsf_round_up(size: vk::DeviceSize) -> Option<vk::DeviceSize> {
+    if size < SL_COUNT as vk::DeviceSize {
+        return Some(size);
+    }
+    let log2 = 63 - size.leading_zeros();
+    size.checked_add((1 << (log2 - SL_LOG2)) - 1)
+}
+
+impl Default for TlsfAllocator {
+    fn default() -> Self {
+        Self {
+            used: 0,
+            count: 0,
+            chunks: Vec::new(),
+            blocks: Vec::new(),
+            spare: Vec::new(),
+            used_blocks: Default::default(),
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            heads: vec![NIL; FL_COUNT * SL_COUNT],
+        }
+    }
+}
+
+impl TlsfBlock {
+    #[inline]
+    fn size(&self) -> vk::DeviceSize {
+        self.end - self.start
+    }
+}
+
+impl TlsfAllocator {
+    pub(super) fn new() -> Self {
+        Default::default()
+    }
+
+    fn new_block(&mut self, block: TlsfBlock) -> u32 {
+        if let Some(idx) = self.spare.pop() {
+            self.blocks[idx as usize] = block;
+            idx
+        } else {
+            self.blocks.push(block);
+            (self.blocks.len() - 1) as _
+        }
+    }
+
+    fn insert_free(&mut self, idx: u32) {
+        let (fl, sl) = tlsf_class(self.blocks[idx as usize].size());
+        let head = self.heads[fl * SL_COUNT + sl];
+        let block = &mut self.blocks[idx as usize];
+        block.free = true;
+        block.prev_free = NIL;
+        block.next_free = head;
+        if head != NIL {
+            self.blocks[head as usize].prev_free = idx;
+        }
+        self.heads[fl * SL_COUNT + sl] = idx;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmaps[fl] |= 1 << sl;
+    }
+
+    fn remove_free(&mut self, idx: u32) {
+        let block = self.blocks[idx as usize];
+        debug_assert!(block.free);
+        if block.prev_free != NIL {
+            self.blocks[block.prev_free as usize].next_free = block.next_free;
+        }
+        if block.next_free != NIL {
+            self.blocks[block.next_free as usize].prev_free = block.prev_free;
+        }
+        let (fl, sl) = tlsf_class(block.size());
+        if self.heads[fl * SL_COUNT + sl] == idx {
+            self.heads[fl * SL_COUNT + sl] = block.next_free;
+            if block.next_free == NIL {
+                self.sl_bitmaps[fl] &= !(1 << sl);
+                if self.sl_bitmaps[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        }
+        self.blocks[idx as usize].free = false;
+    }
+
+    /// Finds the head of the first non-empty free list whose blocks
+    /// are all at least `size` bytes.
+    fn search(&self, size: vk::DeviceSize) -> Option<u32> {
+        let (fl, sl) = tlsf_class(tlsf_round_up(size)?);
+        let sl_map = self.sl_bitmaps.get(fl)? & (!0 << sl);
+        let (fl, sl_map) = if sl_map != 0 {
+            (fl, sl_map)
+        } else {
+            let fl_map = self.fl_bitmap & (!0 << (fl + 1));
+            if fl_map == 0 {
+                return None;
+            }
+            let fl = fl_map.trailing_zeros() as usize;
+            (fl, self.sl_bitmaps[fl])
+        };
+        let sl = sl_map.trailing_zeros() as usize;
+        Some(self.heads[fl * SL_COUNT + sl])
+    }
+
+    fn fits(&self, idx: u32, size: vk::DeviceSize, alignment: vk::DeviceSize) -> bool {
+        let block = &self.blocks[idx as usize];
+        align(alignment, block.start) + size <= block.end
+    }
+
+    fn find_free(&self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<u32> {
+        if let Some(idx) = self.search(size) {
+            if self.fits(idx, size, alignment) {
+                return Some(idx);
+            }
+        }
+        // Leave room for padding; any block found this way fits.
+        let idx = self.search(size.checked_add(alignment - 1)?)?;
+        debug_assert!(self.fits(idx, size, alignment));
+        Some(idx)
+    }
+
+    /// Splits a block in two at `offset`, returning the upper half.
+    fn split(&mut self, idx: u32, offset: vk::DeviceSize) -> u32 {
+        let block = self.blocks[idx as usize];
+        debug_assert!(block.start < offset && offset < block.end);
+        let upper = self.new_block(TlsfBlock {
+            start: offset,
+            prev_phys: idx,
+            ..block
+        });
+        if block.next_phys != NIL {
+            self.blocks[block.next_phys as usize].prev_phys = upper;
+        }
+        let lower = &mut self.blocks[idx as usize];
+        lower.end = offset;
+        lower.next_phys = upper;
+        upper
+    }
+
+    /// Merges a block into its lower physical neighbor.
+    fn merge_into_prev(&mut self, idx: u32) {
+        let block = self.blocks[idx as usize];
+        let prev = block.prev_phys;
+        self.blocks[prev as usize].end = block.end;
+        self.blocks[prev as usize].next_phys = block.next_phys;
+        if block.next_phys != NIL {
+            self.blocks[block.next_phys as usize].prev_phys = prev;
+        }
+        self.spare.push(idx);
+    }
+
+    fn is_free(&self, idx: u32) -> bool {
+        idx != NIL && self.blocks[idx as usize].free
+    }
+
+    /// Allocates `size` bytes at `start` from a free block.
+    fn take(&mut self, mut idx: u32, start: vk::DeviceSize, size: vk::DeviceSize) -> Block {
+        self.remove_free(idx);
+
+        // Return padding and excess to the free lists
+        let block = self.blocks[idx as usize];
+        debug_assert!(block.start <= start && start + size <= block.end);
+        if start > block.start {
+            let lower = idx;
+            idx = self.split(lower, start);
+            self.insert_free(lower);
+        }
+        if start + size < block.end {
+            let upper = self.split(idx, start + size);
+            self.insert_free(upper);
+        }
+
+        let chunk = block.chunk;
+        self.used_blocks.insert((chunk, start), idx);
+        self.used += size;
+        self.count += 1;
+        Block {
+            chunk,
+            start,
+            end: start + size,
+        }
+    }
+
+    fn iter_free(&self) -> impl Iterator<Item = (u32, &TlsfBlock)> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.free)
+            .map(|(idx, block)| (idx as u32, block))
+    }
+}
+
+impl Allocator for TlsfAllocator {
+    fn used(&self) -> vk::DeviceSize {
+        self.used
+    }
+
+    fn capacity(&self) -> vk::DeviceSize {
+        self.chunks.iter().sum()
+    }
+
+    fn chunk_count(&self) -> u32 {
+        self.chunks.iter().filter(|&&size| size > 0).count() as _
+    }
+
+    fn allocation_count(&self) -> u32 {
+        self.count
+    }
+
+    fn largest_free_block(&self) -> vk::DeviceSize {
+        if self.fl_bitmap == 0 {
+            return 0;
+        }
+        // Only the blocks of the largest class need to be compared
+        let fl = 63 - self.fl_bitmap.leading_zeros() as usize;
+        let sl = 31 - self.sl_bitmaps[fl].leading_zeros() as usize;
+        let mut idx = self.heads[fl * SL_COUNT + sl];
+        let mut largest = 0;
+        while idx != NIL {
+            let block = &self.blocks[idx as usize];
+            largest = largest.max(block.size());
+            idx = block.next_free;
+        }
+        largest
+    }
+
+    fn add_chunk(&mut self, size: vk::DeviceSize) {
+        self.chunks.push(size);
+        if size == 0 {
+            return;
+        }
+        let idx = self.new_block(TlsfBlock {
+            chunk: (self.chunks.len() - 1) as _,
+            start: 0,
+            end: size,
+            free: false,
+            prev_phys: NIL,
+            next_phys: NIL,
+            prev_free: NIL,
+            next_free: NIL,
+        });
+        self.insert_free(idx);
+    }
+
+    fn alloc(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<Block> {
+        let size = align(alignment, size);
+        let idx = self.find_free(size, alignment)?;
+        let start = align(alignment, self.blocks[idx as usize].start);
+        Some(self.take(idx, start, size))
+    }
+
+    fn free(&mut self, block: Block) {
+        let mut idx = self
+            .used_blocks
+            .remove(&(block.chunk, block.start))
+            .unwrap_or_else(|| panic!("block not allocated: {:?}", block));
+        assert_eq!(self.blocks[idx as usize].end, block.end);
+        self.used -= block.size();
+        self.count -= 1;
+
+        let next = self.blocks[idx as usize].next_phys;
+        if self.is_free(next) {
+            self.remove_free(next);
+            self.merge_into_prev(next);
+        }
+        let prev = self.blocks[idx as usize].prev_phys;
+        if self.is_free(prev) {
+            self.remove_free(prev);
+            self.merge_into_prev(idx);
+            idx = prev;
+        }
+        self.insert_free(idx);
+    }
+
+    fn clear(&mut self) {
+        let chunks = std::mem::take(&mut self.chunks);
+        *self = Default::default();
+        for size in chunks {
+            self.add_chunk(size);
+        }
+    }
+}
+
+impl Defragment for TlsfAllocator {
+    fn chunks(&self) -> &[vk::DeviceSize] {
+        &self.chunks
+    }
+
+    fn free_blocks(&self) -> Vec<Block> {
+        self.iter_free()
+            .map(|(_, block)| Block {
+                chunk: block.chunk,
+                start: block.start,
+                end: block.end,
+            })
+            .collect()
+    }
+
+    fn alloc_at(&mut self, block: Block) {
+        let (idx, _) = self
+            .iter_free()
+            .find(|(_, free)| {
+                free.chunk == block.chunk && free.start <= block.start && block.end <= free.end
+            })
+            .unwrap_or_else(|| panic!("block not free: {:?}", block));
+        self.take(idx, block.start, block.size());
+    }
+
+    fn remove_chunk(&mut self, chunk: u32) {
+        let size = self.chunks[chunk as usize];
+        let (idx, _) = self
+            .iter_free()
+            .find(|(_, free)| free.chunk == chunk && free.start == 0 && free.end == size)
+            .unwrap_or_else(|| panic!("chunk in use: {}", chunk));
+        self.remove_free(idx);
+        self.spare.push(idx);
+        self.chunks[chunk as usize] = 0;
+    }
+}
+
 /// Allocator that works by bumping a pointer. It can only free all used
 /// memory at one time.
 #[derive(Debug, Default)]
@@ -182,6 +743,7 @@ pub(super) struct LinearAllocator {
     chunk: usize,
     // Offset into current chunk
     offset: vk::DeviceSize,
+    count: u32,
 }
 
 impl LinearAllocator {
@@ -195,6 +757,7 @@ impl LinearAllocator {
         let end = self.offset + align(alignment, size);
         (end <= *self.chunks.get(self.chunk)?).then(|| {
             self.offset = end;
+            self.count += 1;
             Block {
                 chunk: self.chunk as _,
                 start,
@@ -220,6 +783,20 @@ impl Allocator for LinearAllocator {
         self.chunks.iter().sum()
     }
 
+    fn chunk_count(&self) -> u32 {
+        self.chunks.len() as _
+    }
+
+    fn allocation_count(&self) -> u32 {
+        self.count
+    }
+
+    fn largest_free_block(&self) -> vk::DeviceSize {
+        let rest = self.chunks.get(self.chunk + 1..).unwrap_or(&[]);
+        let current = self.chunks.get(self.chunk).map_or(0, |&size| size - self.offset);
+        rest.iter().copied().fold(current, std::cmp::max)
+    }
+
     fn add_chunk(&mut self, size: vk::DeviceSize) {
         self.chunks.push(size);
     }
@@ -237,11 +814,14 @@ impl Allocator for LinearAllocator {
     fn clear(&mut self) {
         self.chunk = 0;
         self.offset = 0;
+        self.count = 0;
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::VecDeque;
+
     use super::*;
 
     fn linear_inner(alloc: &mut LinearAllocator) {
@@ -267,6 +847,8 @@ mod tests {
         );
         assert_eq!(alloc.used(), 16);
         assert_eq!(alloc.capacity(), 2048);
+        assert_eq!(alloc.allocation_count(), 2);
+        assert_eq!(alloc.largest_free_block(), 1024);
 
         // Free is no-op
         alloc.free(Block {
@@ -296,6 +878,7 @@ mod tests {
         );
         assert_eq!(alloc.used(), 1088);
         assert_eq!(alloc.capacity(), 2048);
+        assert_eq!(alloc.largest_free_block(), 960);
 
         // Cannot alloc past the end of the chunk
         assert_eq!(alloc.alloc(1000, 8), None);
@@ -312,6 +895,17 @@ mod tests {
             })
         );
         assert_eq!(alloc.used(), alloc.capacity());
+        assert_eq!(
+            alloc.stats(),
+            MemoryStats {
+                used: 2048,
+                reserved: 2048,
+                fragmented: 0,
+                allocation_count: 5,
+                block_count: 2,
+                largest_free_block: 0,
+            }
+        );
 
         assert_eq!(alloc.alloc(8, 8), None);
     }
@@ -328,4 +922,266 @@ mod tests {
         alloc.clear();
         linear_inner(&mut alloc);
     }
+
+    #[test]
+    fn free_list_stats() {
+        let mut alloc = FreeListAllocator::new();
+        alloc.add_chunk(1024);
+        alloc.add_chunk(256);
+
+        let a = alloc.alloc(256, 16).unwrap();
+        let b = alloc.alloc(256, 16).unwrap();
+        let _c = alloc.alloc(256, 16).unwrap();
+        assert_eq!(alloc.allocation_count(), 3);
+        assert_eq!(alloc.largest_free_block(), 256);
+
+        // Freeing a block between two others fragments the chunk
+        alloc.free(b);
+        assert_eq!(
+            alloc.stats(),
+            MemoryStats {
+                used: 512,
+                reserved: 1280,
+                fragmented: 512,
+                allocation_count: 2,
+                block_count: 2,
+                largest_free_block: 256,
+            }
+        );
+
+        // Adjacent free blocks are merged
+        alloc.free(a);
+        assert_eq!(alloc.largest_free_block(), 512);
+        assert_eq!(alloc.stats().fragmented, 512);
+
+        alloc.clear();
+        assert_eq!(alloc.allocation_count(), 0);
+        assert_eq!(alloc.largest_free_block(), 1024);
+    }
+
+    #[test]
+    fn free_list_order() {
+        let mut alloc = FreeListAllocator::new();
+        alloc.add_chunk(256);
+        alloc.add_chunk(256);
+
+        let a = alloc.alloc(128, 16).unwrap();
+        let b = alloc.alloc(128, 16).unwrap();
+        let c = alloc.alloc(128, 16).unwrap();
+        let d = alloc.alloc(128, 16).unwrap();
+        assert_eq!((a.chunk, d.chunk), (0, 1));
+
+        // Free blocks in the second chunk before those in the first
+        alloc.free(d);
+        alloc.free(a);
+        alloc.free(c);
+        assert!(alloc
+            .free
+            .windows(2)
+            .all(|w| (w[0].chunk, w[0].end) <= (w[1].chunk, w[1].start)));
+
+        alloc.free(b);
+        assert_eq!(
+            alloc.free,
+            [
+                Block {
+                    chunk: 0,
+                    start: 0,
+                    end: 256,
+                },
+                Block {
+                    chunk: 1,
+                    start: 0,
+                    end: 256,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tlsf() {
+        let mut alloc = TlsfAllocator::new();
+        alloc.add_chunk(1024);
+        alloc.add_chunk(256);
+        assert_eq!(alloc.largest_free_block(), 1024);
+
+        let a = alloc.alloc(100, 16).unwrap();
+        assert_eq!(a.size(), 112);
+        assert_eq!(a.offset() % 16, 0);
+        let b = alloc.alloc(256, 256).unwrap();
+        assert_eq!(b.offset() % 256, 0);
+        let c = alloc.alloc(512, 16).unwrap();
+        assert_eq!(alloc.used(), 880);
+        assert_eq!(alloc.allocation_count(), 3);
+        for (x, y) in [(a, b), (a, c), (b, c)].iter() {
+            assert!(x.chunk != y.chunk || x.end <= y.start || y.end <= x.start);
+        }
+
+        // Too big for either chunk
+        assert_eq!(alloc.alloc(1024, 16), None);
+
+        // Freed blocks merge with their neighbors
+        alloc.free(b);
+        alloc.free(a);
+        alloc.free(c);
+        assert_eq!(alloc.used(), 0);
+        assert_eq!(alloc.allocation_count(), 0);
+        assert_eq!(alloc.largest_free_block(), 1024);
+        assert_eq!(alloc.stats().fragmented, 256);
+        assert_eq!(
+            alloc.alloc(1024, 16),
+            Some(Block {
+                chunk: 0,
+                start: 0,
+                end: 1024,
+            })
+        );
+
+        alloc.clear();
+        assert_eq!(alloc.used(), 0);
+        assert_eq!(alloc.capacity(), 1280);
+        assert_eq!(alloc.largest_free_block(), 1024);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tlsf_double_free() {
+        let mut alloc = TlsfAllocator::new();
+        alloc.add_chunk(1024);
+        let block = alloc.alloc(64, 16).unwrap();
+        alloc.free(block);
+        alloc.free(block);
+    }
+
+    /// Checks that live blocks are aligned and don't overlap.
+    fn check_blocks(blocks: &VecDeque<(Block, vk::DeviceSize)>, chunks: &[vk::DeviceSize]) {
+        let mut blocks: Vec<_> = blocks.iter().copied().collect();
+        blocks.sort_by_key(|(block, _)| (block.chunk, block.start));
+        for (block, alignment) in blocks.iter() {
+            assert_eq!(block.start % alignment, 0);
+            assert!(block.end <= chunks[block.chunk as usize]);
+        }
+        for pair in blocks.windows(2) {
+            let (a, b) = (pair[0].0, pair[1].0);
+            assert!(a.chunk < b.chunk || a.end <= b.start, "{:?}, {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn tlsf_churn() {
+        let trace = Trace::new(0x1234_5678, 20_000, false);
+        let mut alloc = TlsfAllocator::new();
+        let live = trace.replay(&mut alloc);
+        check_blocks(&live, &alloc.chunks);
+        let used: vk::DeviceSize = live.iter().map(|(block, _)| block.size()).sum();
+        assert_eq!(alloc.used(), used);
+        assert_eq!(alloc.allocation_count(), live.len() as u32);
+
+        // Everything merges back together
+        for &(block, _) in live.iter() {
+            alloc.free(block);
+        }
+        assert_eq!(alloc.used(), 0);
+        assert_eq!(alloc.largest_free_block(), Trace::CHUNK_SIZE);
+        for _ in 0..alloc.chunk_count() {
+            assert!(alloc.alloc(Trace::CHUNK_SIZE, 1).is_some());
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Op {
+        Alloc {
+            size: vk::DeviceSize,
+            alignment: vk::DeviceSize,
+        },
+        // Index into the list of live allocations
+        Free(usize),
+    }
+
+    /// A synthetic allocation trace resembling static buffer and image
+    /// allocations: mostly small uniform/storage buffers, some
+    /// vertex/index buffers, and a few large textures.
+    struct Trace {
+        ops: Vec<Op>,
+    }
+
+    impl Trace {
+        const CHUNK_SIZE: vk::DeviceSize = 0x100_0000;
+
+        /// If `fifo` is set, allocations are freed in order, as when
+        /// streaming assets in and out; otherwise at random.
+        fn new(mut seed: u64, len: usize, fifo: bool) -> Self {
+            let mut rand = move || {
+                // xorshift64
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed
+            };
+            let mut ops = Vec::with_capacity(len);
+            let mut live = 0;
+            for _ in 0..len {
+                // Hover around 2000 live allocations
+                if live > 0 && (live >= 2000 || rand() % 2000 < live as u64 / 2) {
+                    let idx = if fifo { 0 } else { rand() as usize % live };
+                    ops.push(Op::Free(idx));
+                    live -= 1;
+                    continue;
+                }
+                let (size, alignment) = match rand() % 100 {
+                    0..=69 => (64 + rand() % 0x1000, 256),
+                    70..=94 => (0x1000 + rand() % 0x4_0000, 16),
+                    _ => (0x4_0000 + rand() % 0x40_0000, 0x1_0000),
+                };
+                ops.push(Op::Alloc { size, alignment });
+                live += 1;
+            }
+            Self { ops }
+        }
+
+        /// Replays the trace, adding chunks as needed. Returns the
+        /// blocks still allocated with their alignments.
+        fn replay(&self, alloc: &mut impl Allocator) -> VecDeque<(Block, vk::DeviceSize)> {
+            let mut live = VecDeque::new();
+            for op in self.ops.iter() {
+                match *op {
+                    Op::Alloc { size, alignment } => {
+                        let block = alloc.alloc(size, alignment).unwrap_or_else(|| {
+                            alloc.add_chunk(Self::CHUNK_SIZE);
+                            alloc.alloc(size, alignment).unwrap()
+                        });
+                        live.push_back((block, alignment));
+                    }
+                    // N.B. order is preserved so that FIFO traces free
+                    // the oldest allocation
+                    Op::Free(idx) => alloc.free(live.remove(idx).unwrap().0),
+                }
+            }
+            live
+        }
+    }
+
+    #[bench]
+    fn free_list_random(b: &mut test::Bencher) {
+        let trace = Trace::new(0xdead_beef, 20_000, false);
+        b.iter(|| trace.replay(&mut FreeListAllocator::new()));
+    }
+
+    #[bench]
+    fn tlsf_random(b: &mut test::Bencher) {
+        let trace = Trace::new(0xdead_beef, 20_000, false);
+        b.iter(|| trace.replay(&mut TlsfAllocator::new()));
+    }
+
+    #[bench]
+    fn free_list_fifo(b: &mut test::Bencher) {
+        let trace = Trace::new(0xdead_beef, 20_000, true);
+        b.iter(|| trace.replay(&mut FreeListAllocator::new()));
+    }
+
+    #[bench]
+    fn tlsf_fifo(b: &mut test::Bencher) {
+        let trace = Trace::new(0xdead_beef, 20_000, true);
+        b.iter(|| trace.replay(&mut TlsfAllocator::new()));
+    }
 }
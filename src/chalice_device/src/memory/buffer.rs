@@ -1,4 +1,5 @@
 use std::mem::MaybeUninit;
+use std::ops::{Bound, Range, RangeBounds};
 use std::ptr::{self, NonNull};
 use std::sync::{Arc, Weak};
 
@@ -13,6 +14,7 @@ pub struct DeviceBuffer {
     pub(super) memory: Arc<DeviceMemory>,
     pub(super) inner: vk::Buffer,
     pub(super) usage: BufferUsage,
+    pub(super) mapping: MemoryMapping,
     pub(super) binding: Option<BufferBinding>,
     pub(super) heap: Weak<BufferHeap>,
     pub(super) name: Option<String>,
@@ -27,6 +29,7 @@ bitflags! {
         const UNIFORM_TEXEL_BUFFER = vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER_BIT.0;
         const VERTEX_BUFFER = vk::BufferUsageFlags::VERTEX_BUFFER_BIT.0;
         const INDEX_BUFFER = vk::BufferUsageFlags::INDEX_BUFFER_BIT.0;
+        const INDIRECT_BUFFER = vk::BufferUsageFlags::INDIRECT_BUFFER_BIT.0;
         const TRANSFER_SRC = vk::BufferUsageFlags::TRANSFER_SRC_BIT.0;
         const TRANSFER_DST = vk::BufferUsageFlags::TRANSFER_DST_BIT.0;
     }
@@ -40,9 +43,9 @@ pub enum BufferBinding {
     UniformTexel,
     Vertex,
     Index,
+    Indirect,
 }
 
-// TODO: Need BufferRangeMut (ugh)
 // A slice of a VkBuffer.
 #[derive(Clone, Copy, Debug)]
 pub struct BufferRange<'buf> {
@@ -51,6 +54,16 @@ pub struct BufferRange<'buf> {
     pub size: vk::DeviceSize,
 }
 
+/// A slice of a VkBuffer with exclusive access to its mapped memory.
+/// Obtained by mutably borrowing the allocation which owns the range,
+/// so ranges handed out at the same time never overlap.
+#[derive(Debug)]
+pub struct BufferRangeMut<'buf> {
+    buffer: &'buf DeviceBuffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
 /// An owned suballocation of a VkBuffer object.
 #[derive(Debug)]
 pub struct BufferAlloc {
@@ -79,6 +92,8 @@ impl BufferBinding {
             Self::UniformTexel => BufferUsage::UNIFORM_TEXEL_BUFFER,
             Self::Vertex => BufferUsage::VERTEX_BUFFER,
             Self::Index => BufferUsage::INDEX_BUFFER,
+            // Indirect commands are usually written by compute shaders
+            Self::Indirect => BufferUsage::INDIRECT_BUFFER | BufferUsage::STORAGE_BUFFER,
         }
     }
 }
@@ -98,20 +113,24 @@ impl Drop for DeviceBuffer {
     }
 }
 
-fn create_buffer(
+/// Creates a buffer whose memory is allocated from one of the types in
+/// `type_mask`.
+pub(super) fn create_buffer(
     device: Arc<Device>,
     size: vk::DeviceSize,
     usage: BufferUsage,
     mapping: MemoryMapping,
     lifetime: Lifetime,
-) -> DeviceBuffer {
+    type_mask: u32,
+) -> DeviceResult<DeviceBuffer> {
     trace!(
-        "create_buffer({:?}, {:?}, {:?}, {:?}, {:?})",
+        "create_buffer({:?}, {:?}, {:?}, {:?}, {:?}, {:#x})",
         device,
         size,
         usage,
         mapping,
-        lifetime
+        lifetime,
+        type_mask,
     );
 
     let dt = device.table();
@@ -124,21 +143,30 @@ fn create_buffer(
     let mut buffer = vk::null();
     unsafe {
         dt.create_buffer(&create_info, ptr::null(), &mut buffer)
-            .check()
-            .unwrap();
+            .check()?;
     }
 
-    let (reqs, dedicated_reqs) = unsafe { get_buffer_memory_reqs(&device, buffer) };
+    let (mut reqs, dedicated_reqs) = unsafe { get_buffer_memory_reqs(&device, buffer) };
+    reqs.memory_type_bits &= type_mask;
     let content = (dedicated_reqs.prefers_dedicated_allocation == vk::TRUE)
         .then_some(DedicatedAllocContent::Buffer(buffer));
-    let mut memory =
-        unsafe { alloc_resource_memory(device, mapping, &reqs, content, Tiling::Linear) };
+    let memory = unsafe {
+        alloc_resource_memory(Arc::clone(&device), mapping, &reqs, content, Tiling::Linear)
+    };
+    let mut memory = match memory {
+        Ok(memory) => memory,
+        Err(e) => {
+            unsafe { dt.destroy_buffer(buffer, ptr::null()) };
+            return Err(e);
+        }
+    };
     memory.lifetime = lifetime;
 
     let mut buffer = DeviceBuffer {
         memory: Arc::new(memory),
         inner: buffer,
         usage,
+        mapping,
         binding: None,
         heap: Weak::new(),
         name: None,
@@ -147,10 +175,12 @@ fn create_buffer(
         buffer.bind();
     }
 
-    buffer
+    Ok(buffer)
 }
 
 impl DeviceBuffer {
+    /// Creates a buffer. Panics if memory cannot be allocated; see
+    /// `try_new`.
     pub fn new(
         device: Arc<Device>,
         size: vk::DeviceSize,
@@ -158,7 +188,20 @@ impl DeviceBuffer {
         mapping: MemoryMapping,
         lifetime: Lifetime,
     ) -> Self {
-        create_buffer(device, size, usage, mapping, lifetime)
+        Self::try_new(device, size, usage, mapping, lifetime)
+            .unwrap_or_else(|e| panic!("failed to create buffer: {}", e))
+    }
+
+    /// Creates a buffer, returning an error if memory cannot be
+    /// allocated.
+    pub fn try_new(
+        device: Arc<Device>,
+        size: vk::DeviceSize,
+        usage: BufferUsage,
+        mapping: MemoryMapping,
+        lifetime: Lifetime,
+    ) -> DeviceResult<Self> {
+        create_buffer(device, size, usage, mapping, lifetime, !0)
     }
 
     #[inline]
@@ -191,6 +234,13 @@ impl DeviceBuffer {
         self.memory.mapped()
     }
 
+    /// The mapping the buffer was created with. The memory may have
+    /// fallen back to a type with different properties.
+    #[inline]
+    pub fn mapping(&self) -> MemoryMapping {
+        self.mapping
+    }
+
     #[inline]
     pub fn binding(&self) -> Option<BufferBinding> {
         self.binding
@@ -243,6 +293,8 @@ impl MemoryRegion for DeviceBuffer {
     }
 }
 
+impl MemoryRegionMut for DeviceBuffer {}
+
 impl<'a> MemoryRegion for BufferRange<'a> {
     fn memory(&self) -> &Arc<DeviceMemory> {
         &self.buffer.memory
@@ -257,6 +309,22 @@ impl<'a> MemoryRegion for BufferRange<'a> {
     }
 }
 
+impl<'a> MemoryRegion for BufferRangeMut<'a> {
+    fn memory(&self) -> &Arc<DeviceMemory> {
+        &self.buffer.memory
+    }
+
+    fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+}
+
+impl<'a> MemoryRegionMut for BufferRangeMut<'a> {}
+
 impl Drop for BufferAlloc {
     fn drop(&mut self) {
         if self.buffer.lifetime() == Lifetime::Static {
@@ -281,6 +349,8 @@ impl MemoryRegion for BufferAlloc {
     }
 }
 
+impl MemoryRegionMut for BufferAlloc {}
+
 impl<'a> BufferRange<'a> {
     #[inline]
     pub fn raw(&self) -> vk::Buffer {
@@ -295,6 +365,151 @@ impl<'a> BufferRange<'a> {
             range: self.size,
         }
     }
+
+    /// Returns a sub-range, with bounds relative to the start of
+    /// `self`. Panics if the bounds are out of range.
+    #[inline]
+    pub fn slice(&self, range: impl RangeBounds<vk::DeviceSize>) -> BufferRange<'a> {
+        let range = subrange(self.size, range);
+        BufferRange {
+            buffer: self.buffer,
+            offset: self.offset + range.start,
+            size: range.end - range.start,
+        }
+    }
+
+    /// Divides the range in two at `mid` bytes from its start.
+    #[inline]
+    pub fn split_at(&self, mid: vk::DeviceSize) -> (BufferRange<'a>, BufferRange<'a>) {
+        (self.slice(..mid), self.slice(mid..))
+    }
+}
+
+impl<'a> BufferRangeMut<'a> {
+    #[inline]
+    pub fn buffer(&self) -> &'a DeviceBuffer {
+        self.buffer
+    }
+
+    #[inline]
+    pub fn raw(&self) -> vk::Buffer {
+        self.buffer.inner
+    }
+
+    #[inline]
+    pub fn as_range(&self) -> BufferRange<'_> {
+        BufferRange {
+            buffer: self.buffer,
+            offset: self.offset,
+            size: self.size,
+        }
+    }
+
+    /// Reborrows a sub-range, with bounds relative to the start of
+    /// `self`. Panics if the bounds are out of range.
+    #[inline]
+    pub fn slice_mut(&mut self, range: impl RangeBounds<vk::DeviceSize>) -> BufferRangeMut<'_> {
+        let range = subrange(self.size, range);
+        BufferRangeMut {
+            buffer: self.buffer,
+            offset: self.offset + range.start,
+            size: range.end - range.start,
+        }
+    }
+
+    /// Divides the range in two non-overlapping ranges at `mid` bytes
+    /// from its start.
+    #[inline]
+    pub fn split_at_mut(self, mid: vk::DeviceSize) -> (BufferRangeMut<'a>, BufferRangeMut<'a>) {
+        assert!(mid <= self.size, "mid > len: {} > {}", mid, self.size);
+        let Self {
+            buffer,
+            offset,
+            size,
+        } = self;
+        (
+            BufferRangeMut {
+                buffer,
+                offset,
+                size: mid,
+            },
+            BufferRangeMut {
+                buffer,
+                offset: offset + mid,
+                size: size - mid,
+            },
+        )
+    }
+
+    /// Writes a value `offset` bytes into the range. Panics if the
+    /// memory isn't mapped or if the destination is out of range or
+    /// not aligned for `T`.
+    ///
+    /// `MemoryMapping::Cached` memory may be non-coherent, in which case
+    /// the write is only visible to the device after calling `flush`.
+    #[inline]
+    pub fn write<T: Copy>(&mut self, offset: vk::DeviceSize, val: T) {
+        self.copy_from_slice(offset, std::slice::from_ref(&val));
+    }
+
+    /// Copies a slice `offset` bytes into the range. Panics under the
+    /// same conditions as `write`, and likewise requires a `flush` on
+    /// non-coherent memory.
+    pub fn copy_from_slice<T: Copy>(&mut self, offset: vk::DeviceSize, src: &[T]) {
+        let size = std::mem::size_of_val(src) as vk::DeviceSize;
+        assert!(
+            offset + size <= self.size,
+            "write out of range: {}..{} > {}",
+            offset,
+            offset + size,
+            self.size,
+        );
+        let base = self.as_void().expect("buffer memory is not mapped");
+        unsafe {
+            let dst = base.as_ptr().cast::<u8>().add(offset as _).cast::<T>();
+            assert_eq!(
+                dst as usize % std::mem::align_of::<T>(),
+                0,
+                "misaligned write of {}",
+                std::any::type_name::<T>(),
+            );
+            ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+        }
+    }
+}
+
+impl<'a> From<BufferRangeMut<'a>> for BufferRange<'a> {
+    #[inline]
+    fn from(range: BufferRangeMut<'a>) -> Self {
+        BufferRange {
+            buffer: range.buffer,
+            offset: range.offset,
+            size: range.size,
+        }
+    }
+}
+
+/// Resolves range bounds against a length, panicking if they are out
+/// of range.
+fn subrange<T: base::num::PrimInt>(len: T, range: impl RangeBounds<T>) -> Range<T> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start + T::one(),
+        Bound::Unbounded => T::zero(),
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end + T::one(),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end && end <= len,
+        "range {}..{} out of range for length {}",
+        start,
+        end,
+        len,
+    );
+    start..end
 }
 
 impl BufferAlloc {
@@ -321,6 +536,39 @@ impl BufferAlloc {
         }
     }
 
+    #[inline]
+    pub fn range_mut(&mut self) -> BufferRangeMut<'_> {
+        BufferRangeMut {
+            buffer: &self.buffer,
+            offset: self.offset,
+            size: self.size,
+        }
+    }
+
+    /// Shorthand for `self.range().slice(range)`.
+    #[inline]
+    pub fn slice(&self, range: impl RangeBounds<vk::DeviceSize>) -> BufferRange<'_> {
+        self.range().slice(range)
+    }
+
+    #[inline]
+    pub fn slice_mut(&mut self, range: impl RangeBounds<vk::DeviceSize>) -> BufferRangeMut<'_> {
+        let range = subrange(self.size, range);
+        BufferRangeMut {
+            buffer: &self.buffer,
+            offset: self.offset + range.start,
+            size: range.end - range.start,
+        }
+    }
+
+    #[inline]
+    pub fn split_at_mut(
+        &mut self,
+        mid: vk::DeviceSize,
+    ) -> (BufferRangeMut<'_>, BufferRangeMut<'_>) {
+        self.range_mut().split_at_mut(mid)
+    }
+
     /// Destroys `self` without deallocating memory.
     fn leak(self) {
         let this = MaybeUninit::new(self);
@@ -416,6 +664,35 @@ impl<T: ?Sized> BufferBox<T> {
     }
 }
 
+impl<T> BufferBox<[T]> {
+    /// Returns the range occupied by a subslice, given in elements.
+    #[inline]
+    pub fn slice_range(this: &Self, range: impl RangeBounds<usize>) -> BufferRange<'_> {
+        let range = subrange(this.len(), range);
+        let stride = std::mem::size_of::<T>() as vk::DeviceSize;
+        this.alloc
+            .range()
+            .slice(range.start as vk::DeviceSize * stride..range.end as vk::DeviceSize * stride)
+    }
+
+    /// Like `slice_range`, but the range may be written to.
+    #[inline]
+    pub fn slice_range_mut(this: &mut Self, range: impl RangeBounds<usize>) -> BufferRangeMut<'_> {
+        let range = subrange(this.len(), range);
+        let stride = std::mem::size_of::<T>() as vk::DeviceSize;
+        this.alloc
+            .slice_mut(range.start as vk::DeviceSize * stride..range.end as vk::DeviceSize * stride)
+    }
+
+    /// Divides the occupied range in two at element `mid`.
+    #[inline]
+    pub fn split_at_mut(this: &mut Self, mid: usize) -> (BufferRangeMut<'_>, BufferRangeMut<'_>) {
+        assert!(mid <= this.len(), "mid > len: {} > {}", mid, this.len());
+        let stride = std::mem::size_of::<T>() as vk::DeviceSize;
+        Self::slice_range_mut(this, ..).split_at_mut(mid as vk::DeviceSize * stride)
+    }
+}
+
 impl<T> BufferBox<T> {
     #[inline]
     pub fn from_val(alloc: BufferAlloc, val: T) -> Self {
@@ -17,7 +17,7 @@ pub struct BufferHeap {
 #[derive(Debug)]
 pub struct BufferHeapInner {
     device: Arc<Device>,
-    static_pools: EnumMap<BufferBinding, BufferHeapEntry<FreeListAllocator>>,
+    static_pools: EnumMap<BufferBinding, BufferHeapEntry<PoolAllocator>>,
     frame_pools: EnumMap<BufferBinding, BufferHeapEntry<LinearAllocator>>,
 }
 
@@ -30,6 +30,9 @@ struct BufferHeapEntry<A: Allocator> {
     // Unmapped, device-local, non-host-visible pool. Only present on
     // discrete systems.
     unmapped_pool: Option<BufferPool<A>>,
+    // Host-cached, possibly non-coherent pool for readbacks and
+    // streaming. Empty until first used.
+    cached_pool: BufferPool<A>,
 }
 
 #[derive(Debug)]
@@ -40,20 +43,45 @@ struct BufferPool<A: Allocator> {
     lifetime: Lifetime,
     mapping: MemoryMapping,
     allocator: A,
-    chunks: Vec<Arc<DeviceBuffer>>,
+    // Chunks released by defragmentation are set to `None`.
+    chunks: Vec<Option<Arc<DeviceBuffer>>>,
+    // Set by the first chunk, which may have fallen back to another
+    // memory type. Later chunks are allocated from the same type.
+    type_index: Option<u32>,
 }
 
 impl BufferHeap {
     pub fn new(device: Arc<Device>) -> Arc<Self> {
+        Self::with_allocator(device, Default::default())
+    }
+
+    /// Creates a heap whose static pools use the given allocation
+    /// algorithm.
+    pub fn with_allocator(device: Arc<Device>, kind: AllocatorKind) -> Arc<Self> {
+        Self::with_allocators(device, |_, _| kind)
+    }
+
+    /// Creates a heap whose static pools each use the allocation
+    /// algorithm chosen by `select` for their binding and mapping.
+    /// Frame pools always use a linear allocator.
+    pub fn with_allocators(
+        device: Arc<Device>,
+        select: impl Fn(BufferBinding, MemoryMapping) -> AllocatorKind,
+    ) -> Arc<Self> {
         macro_rules! entry {
-            ($dev:expr, $lt:expr) => {
-                (|binding| BufferHeapEntry::new($dev, binding, $lt)).into()
+            ($dev:expr, $lt:expr, $alloc:expr) => {
+                (|binding| {
+                    BufferHeapEntry::new($dev, binding, $lt, |mapping| ($alloc)(binding, mapping))
+                })
+                .into()
             };
         }
         let heap = Arc::new(Self {
             inner: Mutex::new(BufferHeapInner {
-                static_pools: entry!(&device, Lifetime::Static),
-                frame_pools: entry!(&device, Lifetime::Frame),
+                static_pools: entry!(&device, Lifetime::Static, |binding, mapping| {
+                    PoolAllocator::new(select(binding, mapping))
+                }),
+                frame_pools: entry!(&device, Lifetime::Frame, |_, _| LinearAllocator::default()),
                 device,
             }),
         });
@@ -64,19 +92,20 @@ impl BufferHeap {
     // Assign weak back-pointer to self on each static pool/buffer
     // (this sucks on multiple layers but at least it's safe).
     fn assign_backpointers(self: &Arc<Self>) {
-        impl BufferHeapEntry<FreeListAllocator> {
+        impl BufferHeapEntry<PoolAllocator> {
             fn assign_backpointers(&mut self, ptr: &Arc<BufferHeap>) {
                 self.mapped_pool.assign_backpointers(ptr);
+                self.cached_pool.assign_backpointers(ptr);
                 tryopt! {
                     self.unmapped_pool.as_mut()?.assign_backpointers(ptr);
                 };
             }
         }
 
-        impl BufferPool<FreeListAllocator> {
+        impl BufferPool<PoolAllocator> {
             fn assign_backpointers(&mut self, ptr: &Arc<BufferHeap>) {
                 self.heap = Arc::downgrade(ptr);
-                for chunk in self.chunks.iter_mut() {
+                for chunk in self.chunks.iter_mut().flatten() {
                     let buffer = Arc::get_mut(chunk).unwrap();
                     buffer.heap = Arc::downgrade(ptr);
                 }
@@ -89,6 +118,8 @@ impl BufferHeap {
         }
     }
 
+    /// Suballocates a buffer. Panics if memory cannot be allocated;
+    /// see `try_alloc`.
     pub fn alloc(
         self: &Arc<Self>,
         binding: BufferBinding,
@@ -96,6 +127,19 @@ impl BufferHeap {
         mapping: MemoryMapping,
         size: vk::DeviceSize,
     ) -> BufferAlloc {
+        self.try_alloc(binding, lifetime, mapping, size)
+            .unwrap_or_else(|e| panic!("failed to allocate buffer: {}", e))
+    }
+
+    /// Suballocates a buffer, returning an error if the heap needs to
+    /// grow but memory cannot be allocated.
+    pub fn try_alloc(
+        self: &Arc<Self>,
+        binding: BufferBinding,
+        lifetime: Lifetime,
+        mapping: MemoryMapping,
+        size: vk::DeviceSize,
+    ) -> DeviceResult<BufferAlloc> {
         trace!(
             "BufferHeap::alloc({:?}, {:?}, {:?}, {:?})",
             binding,
@@ -172,23 +216,96 @@ impl BufferHeap {
             pool.clear();
         }
     }
+
+    /// Moves static allocations out of sparsely used chunks and
+    /// releases chunks which end up empty. Only chunks holding nothing
+    /// but allocations from `allocs` can be released; the copies are
+    /// recorded into `cmds` and each moved allocation is rebound to
+    /// its new chunk and offset.
+    ///
+    /// # Safety
+    ///
+    /// `cmds` must be executed before the allocations are next
+    /// accessed, and `defrag` must outlive its execution. Descriptors
+    /// and pointers referring to moved allocations must be recreated.
+    pub unsafe fn defragment(
+        &self,
+        cmds: &mut CmdBuffer<'_>,
+        allocs: &mut [&mut BufferAlloc],
+        defrag: &mut Defragmentation,
+    ) {
+        let moves = defrag.stats().moves;
+        cmds.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS_BIT,
+            vk::PipelineStageFlags::TRANSFER_BIT,
+            Default::default(),
+            &[vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::MEMORY_WRITE_BIT,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ_BIT,
+                ..Default::default()
+            }],
+            &[],
+            &[],
+        );
+
+        for entry in self.inner.lock().static_pools.values_mut() {
+            entry.defragment(cmds, allocs, defrag);
+        }
+
+        if defrag.stats().moves > moves {
+            cmds.pipeline_barrier(
+                vk::PipelineStageFlags::TRANSFER_BIT,
+                vk::PipelineStageFlags::ALL_COMMANDS_BIT | vk::PipelineStageFlags::HOST_BIT,
+                Default::default(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE_BIT,
+                    dst_access_mask: vk::AccessFlags::MEMORY_READ_BIT
+                        | vk::AccessFlags::MEMORY_WRITE_BIT
+                        | vk::AccessFlags::HOST_READ_BIT,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+        }
+    }
+}
+
+impl MemoryPools for BufferHeap {
+    fn add_pool_stats(&self, types: &mut [MemoryStats]) {
+        let inner = self.inner.lock();
+        for entry in inner.static_pools.values() {
+            entry.add_pool_stats(types);
+        }
+        for entry in inner.frame_pools.values() {
+            entry.add_pool_stats(types);
+        }
+    }
 }
 
 impl<A: Allocator> BufferHeapEntry<A> {
-    fn new(device: &Arc<Device>, binding: BufferBinding, lifetime: Lifetime) -> Self {
+    fn new(
+        device: &Arc<Device>,
+        binding: BufferBinding,
+        lifetime: Lifetime,
+        new_allocator: impl Fn(MemoryMapping) -> A,
+    ) -> Self {
         let mut mapped_pool = BufferPool::new(
             Arc::clone(&device),
             binding,
             lifetime,
             MemoryMapping::Mapped,
+            new_allocator(MemoryMapping::Mapped),
         );
 
         // We must call GetBufferMemoryRequirements to find out which
         // memory type index we will use. Thus, we pre-allocate a chunk
         // of memory to infer if we're on UMA.
         // TODO: Delete buffer instead of allocating?
-        mapped_pool.add_chunk(1);
-        let flags = mapped_pool.chunks.first().unwrap().memory().flags();
+        mapped_pool
+            .add_chunk(1)
+            .unwrap_or_else(|e| panic!("failed to create buffer pool: {}", e));
+        let flags = mapped_pool.chunks[0].as_ref().unwrap().memory().flags();
         let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL_BIT;
         let unmapped_pool = (!flags.contains(device_local)).then(|| {
             BufferPool::new(
@@ -196,39 +313,53 @@ impl<A: Allocator> BufferHeapEntry<A> {
                 binding,
                 lifetime,
                 MemoryMapping::DeviceLocal,
+                new_allocator(MemoryMapping::DeviceLocal),
             )
         });
+        let cached_pool = BufferPool::new(
+            Arc::clone(&device),
+            binding,
+            lifetime,
+            MemoryMapping::Cached,
+            new_allocator(MemoryMapping::Cached),
+        );
 
         BufferHeapEntry {
             binding,
             mapped_pool,
             unmapped_pool,
+            cached_pool,
         }
     }
 
     fn pick_pool(&mut self, mapping: MemoryMapping) -> &mut BufferPool<A> {
-        if let Some(ref mut pool) = self.unmapped_pool {
-            if mapping == MemoryMapping::DeviceLocal {
-                return pool;
+        match mapping {
+            MemoryMapping::Cached => return &mut self.cached_pool,
+            MemoryMapping::DeviceLocal => {
+                if let Some(ref mut pool) = self.unmapped_pool {
+                    return pool;
+                }
             }
+            MemoryMapping::Mapped => {}
         }
         &mut self.mapped_pool
     }
 
-    fn alloc(&mut self, mapping: MemoryMapping, size: vk::DeviceSize) -> BufferAlloc {
+    fn alloc(&mut self, mapping: MemoryMapping, size: vk::DeviceSize) -> DeviceResult<BufferAlloc> {
         self.pick_pool(mapping).alloc(size)
     }
 
-    fn get_pool(&mut self, mapped: bool) -> &mut BufferPool<A> {
-        if mapped {
-            &mut self.mapped_pool
-        } else {
-            self.unmapped_pool.as_mut().unwrap()
-        }
+    fn free(&mut self, alloc: &BufferAlloc) {
+        // Chunks are created with the mapping of their pool
+        self.pick_pool(alloc.buffer().mapping()).free(alloc);
     }
 
-    fn free(&mut self, alloc: &BufferAlloc) {
-        self.get_pool(alloc.buffer().mapped()).free(alloc);
+    fn add_pool_stats(&self, types: &mut [MemoryStats]) {
+        self.mapped_pool.add_pool_stats(types);
+        if let Some(pool) = self.unmapped_pool.as_ref() {
+            pool.add_pool_stats(types);
+        }
+        self.cached_pool.add_pool_stats(types);
     }
 
     unsafe fn clear(&mut self) {
@@ -236,6 +367,22 @@ impl<A: Allocator> BufferHeapEntry<A> {
         if let Some(pool) = self.unmapped_pool.as_mut() {
             pool.clear();
         }
+        self.cached_pool.clear();
+    }
+}
+
+impl<A: Defragment> BufferHeapEntry<A> {
+    unsafe fn defragment(
+        &mut self,
+        cmds: &mut CmdBuffer<'_>,
+        allocs: &mut [&mut BufferAlloc],
+        defrag: &mut Defragmentation,
+    ) {
+        self.mapped_pool.defragment(cmds, allocs, defrag);
+        if let Some(pool) = self.unmapped_pool.as_mut() {
+            pool.defragment(cmds, allocs, defrag);
+        }
+        self.cached_pool.defragment(cmds, allocs, defrag);
     }
 }
 
@@ -244,7 +391,7 @@ impl<A: Allocator> Drop for BufferPool<A> {
         if std::thread::panicking() {
             return;
         }
-        for chunk in self.chunks.iter() {
+        for chunk in self.chunks.iter().flatten() {
             assert_eq!(
                 Arc::strong_count(chunk),
                 1,
@@ -265,6 +412,7 @@ impl<A: Allocator> BufferPool<A> {
         binding: BufferBinding,
         lifetime: Lifetime,
         mapping: MemoryMapping,
+        allocator: A,
     ) -> Self {
         Self {
             device,
@@ -272,8 +420,9 @@ impl<A: Allocator> BufferPool<A> {
             binding,
             lifetime,
             mapping,
-            allocator: Default::default(),
+            allocator,
             chunks: Vec::new(),
+            type_index: None,
         }
     }
 
@@ -292,18 +441,32 @@ impl<A: Allocator> BufferPool<A> {
     }
 
     #[allow(dead_code)]
-    fn chunks(&self) -> &[Arc<DeviceBuffer>] {
+    fn chunks(&self) -> &[Option<Arc<DeviceBuffer>>] {
         &self.chunks
     }
 
+    fn add_pool_stats(&self, types: &mut [MemoryStats]) {
+        // All chunks share the same memory type.
+        if let Some(type_index) = self.type_index {
+            types[type_index as usize] += self.allocator.stats();
+        }
+    }
+
     fn alignment(&self) -> vk::DeviceSize {
         use BufferBinding::*;
         let limits = &self.device.limits();
-        match self.binding {
-            Storage => limits.min_storage_buffer_offset_alignment,
+        let alignment = match self.binding {
+            Storage | Indirect => limits.min_storage_buffer_offset_alignment,
             Uniform => limits.min_uniform_buffer_offset_alignment,
             StorageTexel | UniformTexel => limits.min_texel_buffer_offset_alignment,
             Vertex | Index => 1,
+        };
+        if self.mapping == MemoryMapping::Cached {
+            // Keep allocations in separate atoms so that invalidating
+            // one can't discard unflushed writes to another.
+            alignment.max(limits.non_coherent_atom_size)
+        } else {
+            alignment
         }
     }
 
@@ -321,16 +484,29 @@ impl<A: Allocator> BufferPool<A> {
         self.mapping
     }
 
-    fn add_chunk(&mut self, min_size: vk::DeviceSize) {
+    fn add_chunk(&mut self, min_size: vk::DeviceSize) -> DeviceResult<()> {
         let chunk = self.chunks.len() as u32;
-        let size = align(self.chunk_size(), min_size);
-        let mut buffer = DeviceBuffer::new(
-            Arc::clone(&self.device),
-            size,
-            self.usage(),
-            self.mapping,
-            self.lifetime,
-        );
+        let type_mask = self.type_index.map_or(!0, |type_index| 1 << type_index);
+        let create = |size| {
+            create_buffer(
+                Arc::clone(&self.device),
+                size,
+                self.usage(),
+                self.mapping,
+                self.lifetime,
+                type_mask,
+            )
+        };
+        let mut size = align(self.chunk_size(), min_size);
+        let mut buffer = match create(size) {
+            // Fall back to a chunk just big enough.
+            Err(e) if e.is_out_of_memory() && size > min_size => {
+                size = min_size;
+                create(size)?
+            }
+            res => res?,
+        };
+        self.type_index = Some(buffer.memory().type_index());
         buffer.binding = Some(self.binding);
         buffer.heap = Weak::clone(&self.heap);
         buffer.set_chunk(chunk);
@@ -340,11 +516,12 @@ impl<A: Allocator> BufferPool<A> {
             self.binding, self.lifetime, self.mapping, chunk,
         ));
 
-        self.chunks.push(Arc::new(buffer));
+        self.chunks.push(Some(Arc::new(buffer)));
         self.allocator.add_chunk(size);
+        Ok(())
     }
 
-    fn alloc(&mut self, size: vk::DeviceSize) -> BufferAlloc {
+    fn alloc(&mut self, size: vk::DeviceSize) -> DeviceResult<BufferAlloc> {
         assert_ne!(size, 0);
         let alignment = self.alignment();
         let orig_size = size;
@@ -357,27 +534,26 @@ impl<A: Allocator> BufferPool<A> {
             _ => (),
         }
 
-        let block = self
-            .allocator
-            .alloc(size, alignment)
-            .or_else(|| {
-                self.add_chunk(size);
-                self.allocator.alloc(size, alignment)
-            })
-            .unwrap();
-        let buffer = Arc::clone(&self.chunks[block.chunk as usize]);
-        BufferAlloc {
+        let block = match self.allocator.alloc(size, alignment) {
+            Some(block) => block,
+            None => {
+                self.add_chunk(size)?;
+                self.allocator.alloc(size, alignment).unwrap()
+            }
+        };
+        let buffer = Arc::clone(self.chunks[block.chunk as usize].as_ref().unwrap());
+        Ok(BufferAlloc {
             buffer,
             offset: block.offset(),
             size: orig_size,
-        }
+        })
     }
 
     fn free(&mut self, alloc: &BufferAlloc) {
         // Make sure the allocation came from this pool
         let chunk = &self.chunks[alloc.chunk() as usize];
         assert!(
-            Arc::ptr_eq(&alloc.buffer, chunk),
+            self.owns(alloc),
             "alloc: {:?},\nself.chunks[alloc.chunk]: {:?}",
             alloc,
             chunk,
@@ -390,8 +566,13 @@ impl<A: Allocator> BufferPool<A> {
         self.allocator.free(block);
     }
 
+    fn owns(&self, alloc: &BufferAlloc) -> bool {
+        let chunk = self.chunks.get(alloc.chunk() as usize);
+        matches!(chunk, Some(Some(chunk)) if Arc::ptr_eq(&alloc.buffer, chunk))
+    }
+
     unsafe fn clear(&mut self) {
-        for chunk in self.chunks.iter() {
+        for chunk in self.chunks.iter().flatten() {
             assert_eq!(
                 Arc::strong_count(chunk),
                 1,
@@ -403,6 +584,55 @@ impl<A: Allocator> BufferPool<A> {
     }
 }
 
+impl<A: Defragment> BufferPool<A> {
+    unsafe fn defragment(
+        &mut self,
+        cmds: &mut CmdBuffer<'_>,
+        allocs: &mut [&mut BufferAlloc],
+        defrag: &mut Defragmentation,
+    ) {
+        let alignment = self.alignment();
+        let mut owned: Vec<&mut BufferAlloc> = allocs
+            .iter_mut()
+            .map(|alloc| &mut **alloc)
+            .filter(|alloc| self.owns(alloc))
+            .collect();
+        let movable: Vec<_> = owned
+            .iter()
+            .map(|alloc| {
+                let mut block = to_block(&**alloc);
+                block.end = block.start + align(alignment, alloc.size());
+                Movable { block, alignment }
+            })
+            .collect();
+
+        let plan = plan_defrag(&self.allocator, &movable);
+        for &(i, dst) in plan.moves.iter() {
+            let alloc = &mut *owned[i];
+            let chunk = self.chunks[dst.chunk as usize].as_ref().unwrap();
+            cmds.copy_buffer(
+                &alloc.buffer,
+                chunk,
+                &[vk::BufferCopy {
+                    src_offset: alloc.offset,
+                    dst_offset: dst.start,
+                    size: alloc.size,
+                }],
+            );
+            self.allocator.free(movable[i].block);
+            self.allocator.alloc_at(dst);
+            let src = std::mem::replace(&mut alloc.buffer, Arc::clone(chunk));
+            alloc.offset = dst.start;
+            defrag.record_move(dst.size(), Retired::Buffer(src));
+        }
+        for &chunk in plan.freed_chunks.iter() {
+            self.allocator.remove_chunk(chunk);
+            let buffer = self.chunks[chunk as usize].take().unwrap();
+            defrag.record_free(buffer.size(), Retired::Buffer(buffer));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -433,11 +663,22 @@ mod tests {
         let x = heap.boxed(Uniform, Static, [0.0f32, 0.5, 0.5, 1.0]);
         assert_eq!(x[1], 0.5);
 
+        let allocation_count = || {
+            let mut stats = vec![MemoryStats::default(); 32];
+            heap.add_pool_stats(&mut stats);
+            stats
+                .iter()
+                .map(|stats| stats.allocation_count)
+                .sum::<u32>()
+        };
+        assert_eq!(allocation_count(), 1);
+
         heap.alloc(Uniform, Frame, DeviceLocal, 256);
+        assert_eq!(allocation_count(), 2);
         unsafe {
             heap.clear_frame();
         }
-        // TODO: Query used memory
+        assert_eq!(allocation_count(), 1);
     }
 
     #[test]
@@ -500,4 +741,193 @@ mod tests {
         let alloc = heap.alloc(Uniform, Static, Mapped, 32);
         assert_eq!(alloc.offset(), 0);
     }
+
+    #[test]
+    fn tlsf_free() {
+        use BufferBinding::*;
+        use Lifetime::*;
+        use MemoryMapping::*;
+
+        let vars = TestVars::new();
+        let heap = BufferHeap::with_allocator(Arc::clone(vars.device()), AllocatorKind::Tlsf);
+        let allocs = [
+            heap.alloc(Storage, Static, Mapped, 98),
+            heap.alloc(Storage, Static, Mapped, 99),
+            heap.alloc(Storage, Static, Mapped, 100),
+        ];
+        for (a, b) in allocs.iter().zip(allocs[1..].iter()) {
+            assert_ge!(b.offset(), a.offset() + a.size());
+        }
+        std::mem::drop(allocs);
+        let alloc = heap.alloc(Storage, Static, Mapped, 0x10_0000);
+        assert_eq!(alloc.offset(), 0);
+    }
+
+    #[test]
+    fn per_pool_allocators() {
+        use BufferBinding::*;
+        use MemoryMapping::*;
+
+        let vars = TestVars::new();
+        let select = |binding: BufferBinding, mapping: MemoryMapping| match (binding, mapping) {
+            (Storage, Cached) => AllocatorKind::Tlsf,
+            _ => AllocatorKind::FreeList,
+        };
+        let heap = BufferHeap::with_allocators(Arc::clone(vars.device()), select);
+        let is_tlsf = |pool: &BufferPool<_>| matches!(pool.allocator, PoolAllocator::Tlsf(_));
+        let inner = heap.inner.lock();
+        assert!(is_tlsf(&inner.static_pools[Storage].cached_pool));
+        assert!(!is_tlsf(&inner.static_pools[Storage].mapped_pool));
+        assert!(!is_tlsf(&inner.static_pools[Uniform].cached_pool));
+    }
+
+    #[test]
+    fn range_mut() {
+        use BufferBinding::*;
+        use Lifetime::*;
+        use MemoryMapping::*;
+
+        let vars = TestVars::new();
+        let heap = BufferHeap::new(Arc::clone(vars.device()));
+        let mut alloc = heap.alloc(Storage, Static, Mapped, 64);
+
+        let (mut lo, mut hi) = alloc.split_at_mut(32);
+        assert_eq!(lo.offset() + 32, hi.offset());
+        assert_eq!(hi.size(), 32);
+        lo.write(4, 0x0403_0201u32);
+        hi.copy_from_slice(0, &[5u16, 6]);
+        hi.slice_mut(28..).write(0, 7u32);
+        std::mem::drop(lo);
+        std::mem::drop(hi);
+
+        let bytes = alloc.as_bytes().unwrap();
+        assert_eq!(&bytes[4..8], &[1, 2, 3, 4]);
+        assert_eq!(&bytes[32..36], &[5, 0, 6, 0]);
+        assert_eq!(&bytes[60..], &[7, 0, 0, 0]);
+
+        let range = alloc.slice(8..=15);
+        assert_eq!(range.offset, alloc.offset() + 8);
+        assert_eq!(range.size, 8);
+        let (a, b) = range.split_at(2);
+        assert_eq!((a.size, b.offset), (2, range.offset + 2));
+
+        let mut boxed = heap.box_slice(Storage, Static, &[0u32; 16]);
+        let range = BufferBox::slice_range(&boxed, 4..);
+        assert_eq!(range.offset, BufferBox::alloc(&boxed).offset() + 16);
+        assert_eq!(range.size, 48);
+
+        let (mut lo, mut hi) = BufferBox::split_at_mut(&mut boxed, 4);
+        assert_eq!((lo.size(), hi.size()), (16, 48));
+        lo.write(0, 1u32);
+        hi.write(0, 2u32);
+        BufferBox::slice_range_mut(&mut boxed, 15..).write(0, 3u32);
+        assert_eq!(&boxed[..5], &[1, 0, 0, 0, 2]);
+        assert_eq!(boxed[15], 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn range_mut_misaligned() {
+        let vars = TestVars::new();
+        let heap = BufferHeap::new(Arc::clone(vars.device()));
+        let mut alloc = heap.alloc(
+            BufferBinding::Storage,
+            Lifetime::Static,
+            MemoryMapping::Mapped,
+            64,
+        );
+        alloc.range_mut().write(2, 0u32);
+    }
+
+    #[test]
+    #[should_panic]
+    fn range_mut_out_of_range() {
+        let vars = TestVars::new();
+        let heap = BufferHeap::new(Arc::clone(vars.device()));
+        let mut alloc = heap.alloc(
+            BufferBinding::Storage,
+            Lifetime::Static,
+            MemoryMapping::Mapped,
+            64,
+        );
+        alloc.slice_mut(..32).copy_from_slice(16, &[0u64; 3]);
+    }
+
+    #[test]
+    fn cached_alloc() {
+        use BufferBinding::*;
+        use Lifetime::*;
+        use MemoryMapping::*;
+
+        let vars = TestVars::new();
+        let device = Arc::clone(vars.device());
+        let heap = Arc::new(BufferHeap::new(Arc::clone(&device)));
+        let atom = device.limits().non_coherent_atom_size;
+
+        let mut alloc0 = heap.alloc(Storage, Static, Cached, 3);
+        let alloc1 = heap.alloc(Storage, Static, Cached, 3);
+        assert_eq!(alloc0.buffer().mapping(), Cached);
+        assert_eq!(alloc1.offset() % atom, 0);
+        assert_ge!(alloc1.offset(), atom);
+
+        alloc0.as_bytes_mut().unwrap().copy_from_slice(&[1, 2, 3]);
+        alloc0.flush().unwrap();
+        alloc0.invalidate().unwrap();
+        assert_eq!(alloc0.as_bytes().unwrap(), &[1, 2, 3]);
+
+        // Cached allocations return to the cached pool
+        std::mem::drop(alloc0);
+        let alloc = heap.alloc(Storage, Static, Cached, 3);
+        assert_eq!(alloc.offset(), 0);
+    }
+
+    #[test]
+    fn defragment() {
+        use BufferBinding::*;
+        use Lifetime::*;
+        use MemoryMapping::*;
+
+        let vars = TestVars::new();
+        let queue = vars.gfx_queue();
+        let heap = BufferHeap::new(Arc::clone(vars.device()));
+
+        // Leave a lone allocation in a second chunk
+        let big = heap.alloc(Storage, Static, Mapped, 0xc0_0000);
+        let mut moved = heap.alloc(Storage, Static, Mapped, 0xc0_0000);
+        let pinned = heap.alloc(Storage, Static, Mapped, 256);
+        assert_eq!(pinned.chunk(), big.chunk());
+        assert_ne!(moved.chunk(), big.chunk());
+        std::mem::drop(big);
+        let data: Vec<u8> = (0..moved.size()).map(|i| i as u8).collect();
+        moved.as_bytes_mut().unwrap().copy_from_slice(&data);
+
+        let mut pool = CmdPool::new_transient(queue.family());
+        let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+        let mut defrag = Defragmentation::default();
+        unsafe {
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            heap.defragment(&mut cmds, &mut [&mut moved], &mut defrag);
+            let cmds = cmds.end();
+            queue
+                .submit(&[SubmitInfo {
+                    cmds: &[cmds],
+                    ..Default::default()
+                }])
+                .wait(u64::MAX)
+                .unwrap();
+        }
+
+        let stats = defrag.stats();
+        assert_eq!(stats.moves, 1);
+        assert_eq!(stats.bytes_moved, moved.size());
+        assert_ge!(stats.chunks_freed, 1);
+        assert_eq!(moved.chunk(), pinned.chunk());
+        assert_eq!(moved.as_bytes().unwrap(), &data[..]);
+        std::mem::drop(defrag);
+
+        // Freed allocations are reusable
+        std::mem::drop(moved);
+        let alloc = heap.alloc(Storage, Static, Mapped, 0xc0_0000);
+        assert_eq!(alloc.chunk(), pinned.chunk());
+    }
 }
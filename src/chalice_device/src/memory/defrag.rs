@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use super::*;
+
+/// Work done by a defragmentation pass.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DefragStats {
+    /// Number of allocations moved.
+    pub moves: u32,
+    /// Size of the memory blocks moved, including alignment padding.
+    pub bytes_moved: vk::DeviceSize,
+    /// Number of chunks of device memory released.
+    pub chunks_freed: u32,
+    pub bytes_freed: vk::DeviceSize,
+}
+
+/// The outcome of one or more defragmentation passes. Holds the
+/// resources which allocations were moved out of, so it must outlive
+/// the execution of the command buffer the moves were recorded in.
+#[must_use]
+#[derive(Debug, Default)]
+pub struct Defragmentation {
+    stats: DefragStats,
+    retired: Vec<Retired>,
+}
+
+#[derive(Debug)]
+pub(super) enum Retired {
+    Memory(Arc<DeviceMemory>),
+    Buffer(Arc<DeviceBuffer>),
+    ImageView(Arc<ImageView>),
+}
+
+/// An allocation which may be moved to another chunk.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Movable {
+    pub(super) block: Block,
+    pub(super) alignment: vk::DeviceSize,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct DefragPlan {
+    // (index of movable allocation, destination)
+    pub(super) moves: Vec<(usize, Block)>,
+    pub(super) freed_chunks: Vec<u32>,
+}
+
+impl Defragmentation {
+    #[inline]
+    pub fn stats(&self) -> DefragStats {
+        self.stats
+    }
+
+    pub(super) fn record_move(&mut self, size: vk::DeviceSize, src: Retired) {
+        self.stats.moves += 1;
+        self.stats.bytes_moved += size;
+        self.retired.push(src);
+    }
+
+    pub(super) fn record_free(&mut self, size: vk::DeviceSize, chunk: Retired) {
+        self.stats.chunks_freed += 1;
+        self.stats.bytes_freed += size;
+        self.retired.push(chunk);
+    }
+}
+
+/// Plans moves which empty out as many chunks as possible. Chunks
+/// holding only movable allocations are evacuated, least used first,
+/// into the free space of the most used chunks. Allocations are only
+/// moved between chunks, never within one, and chunks which receive
+/// allocations are not evacuated themselves, so the moves can be
+/// carried out in any order.
+pub(super) fn plan_defrag(allocator: &impl Defragment, movable: &[Movable]) -> DefragPlan {
+    let chunks = allocator.chunks();
+    let mut free: Vec<Vec<Block>> = vec![Vec::new(); chunks.len()];
+    for block in allocator.free_blocks() {
+        free[block.chunk as usize].push(block);
+    }
+    for blocks in free.iter_mut() {
+        blocks.sort_by_key(|block| block.start);
+    }
+
+    let mut used: Vec<vk::DeviceSize> = chunks
+        .iter()
+        .zip(free.iter())
+        .map(|(&size, blocks)| size - blocks.iter().map(Block::size).sum::<vk::DeviceSize>())
+        .collect();
+    let mut movable_in: Vec<Vec<usize>> = vec![Vec::new(); chunks.len()];
+    for (i, m) in movable.iter().enumerate() {
+        movable_in[m.block.chunk as usize].push(i);
+    }
+    // Largest first
+    for indices in movable_in.iter_mut() {
+        indices.sort_by_key(|&i| std::cmp::Reverse(movable[i].block.size()));
+    }
+
+    let mut candidates: Vec<usize> = (0..chunks.len())
+        .filter(|&chunk| {
+            let movable_size: vk::DeviceSize = movable_in[chunk]
+                .iter()
+                .map(|&i| movable[i].block.size())
+                .sum();
+            chunks[chunk] > 0 && movable_size == used[chunk]
+        })
+        .collect();
+    candidates.sort_by_key(|&chunk| used[chunk]);
+
+    let mut plan = DefragPlan::default();
+    let mut evacuated = vec![false; chunks.len()];
+    let mut receiving = vec![false; chunks.len()];
+    for chunk in candidates {
+        if receiving[chunk] {
+            continue;
+        }
+
+        let mut targets: Vec<usize> = (0..chunks.len())
+            .filter(|&t| t != chunk && chunks[t] > 0 && !evacuated[t])
+            .collect();
+        targets.sort_by_key(|&t| std::cmp::Reverse(used[t]));
+
+        let mut trial = free.clone();
+        let moves: Option<Vec<_>> = movable_in[chunk]
+            .iter()
+            .map(|&i| {
+                let Movable { block, alignment } = movable[i];
+                let dst = targets
+                    .iter()
+                    .find_map(|&t| carve(&mut trial[t], block.size(), alignment))?;
+                Some((i, dst))
+            })
+            .collect();
+        let moves = match moves {
+            Some(moves) => moves,
+            None => continue,
+        };
+
+        for &(_, dst) in moves.iter() {
+            receiving[dst.chunk as usize] = true;
+            used[dst.chunk as usize] += dst.size();
+        }
+        free = trial;
+        used[chunk] = 0;
+        evacuated[chunk] = true;
+        plan.moves.extend(moves);
+        plan.freed_chunks.push(chunk as u32);
+    }
+
+    plan
+}
+
+/// Takes an aligned block out of the first free block it fits in.
+fn carve(free: &mut Vec<Block>, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<Block> {
+    let (index, start) = free.iter().enumerate().find_map(|(index, block)| {
+        let start = align(alignment, block.start);
+        (start + size <= block.end).then_some((index, start))
+    })?;
+    let block = free.remove(index);
+    let end = start + size;
+    if end < block.end {
+        free.insert(
+            index,
+            Block {
+                start: end,
+                ..block
+            },
+        );
+    }
+    if start > block.start {
+        free.insert(
+            index,
+            Block {
+                end: start,
+                ..block
+            },
+        );
+    }
+    Some(Block {
+        start,
+        end,
+        ..block
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(alloc: &mut impl Defragment, movable: &[Movable], plan: &DefragPlan) {
+        for &(i, dst) in plan.moves.iter() {
+            alloc.free(movable[i].block);
+            alloc.alloc_at(dst);
+        }
+        for &chunk in plan.freed_chunks.iter() {
+            alloc.remove_chunk(chunk);
+        }
+    }
+
+    #[test]
+    fn evacuate() {
+        let mut alloc = FreeListAllocator::new();
+        alloc.add_chunk(1024);
+        alloc.add_chunk(1024);
+        alloc.add_chunk(1024);
+
+        let _pinned = alloc.alloc(512, 16).unwrap();
+        let tmp = alloc.alloc(512, 16).unwrap();
+        let block = alloc.alloc(128, 16).unwrap();
+        assert_eq!(block.chunk, 1);
+        alloc.free(tmp);
+
+        let movable = [Movable {
+            block,
+            alignment: 256,
+        }];
+        let plan = plan_defrag(&alloc, &movable);
+        // The empty chunk is freed first
+        assert_eq!(plan.freed_chunks, [2, 1]);
+        assert_eq!(
+            plan.moves,
+            [(
+                0,
+                Block {
+                    chunk: 0,
+                    start: 512,
+                    end: 640,
+                }
+            )]
+        );
+
+        apply(&mut alloc, &movable, &plan);
+        assert_eq!(alloc.used(), 640);
+        assert_eq!(alloc.allocation_count(), 2);
+        assert_eq!(alloc.capacity(), 1024);
+        assert_eq!(alloc.chunk_count(), 1);
+    }
+
+    #[test]
+    fn pinned() {
+        let mut alloc = FreeListAllocator::new();
+        alloc.add_chunk(1024);
+        alloc.add_chunk(1024);
+
+        // Chunk 0 is full and chunk 1 holds a pinned block
+        let _pinned = alloc.alloc(1024, 16).unwrap();
+        let block = alloc.alloc(512, 16).unwrap();
+        let _pinned = alloc.alloc(256, 16).unwrap();
+        let movable = [Movable {
+            block,
+            alignment: 16,
+        }];
+        let plan = plan_defrag(&alloc, &movable);
+        assert!(plan.moves.is_empty());
+        assert!(plan.freed_chunks.is_empty());
+    }
+
+    #[test]
+    fn churn() {
+        for &kind in [AllocatorKind::FreeList, AllocatorKind::Tlsf].iter() {
+            let mut alloc = PoolAllocator::new(kind);
+            for _ in 0..8 {
+                alloc.add_chunk(0x1_0000);
+            }
+
+            // Allocate until full, then free most blocks
+            let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+            let mut rand = move || {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed
+            };
+            let mut blocks = Vec::new();
+            while let Some(block) = alloc.alloc(0x100 + rand() % 0x1000, 0x100) {
+                blocks.push(block);
+            }
+            let mut movable = Vec::new();
+            let mut pinned = Vec::new();
+            for block in blocks {
+                match rand() % 10 {
+                    0..=6 => alloc.free(block),
+                    7..=8 => movable.push(Movable {
+                        block,
+                        alignment: 0x100,
+                    }),
+                    _ => pinned.push(block),
+                }
+            }
+
+            let used = alloc.used();
+            let count = alloc.allocation_count();
+            let plan = plan_defrag(&alloc, &movable);
+            assert!(!plan.freed_chunks.is_empty());
+            apply(&mut alloc, &movable, &plan);
+            assert_eq!(alloc.used(), used);
+            assert_eq!(alloc.allocation_count(), count);
+            assert_eq!(alloc.chunk_count(), 8 - plan.freed_chunks.len() as u32);
+            for block in pinned.iter() {
+                assert!(!plan.freed_chunks.contains(&block.chunk));
+            }
+            for &(i, dst) in plan.moves.iter() {
+                assert_eq!(dst.size(), movable[i].block.size());
+                assert_eq!(dst.start % 0x100, 0);
+                assert!(!plan.freed_chunks.contains(&dst.chunk));
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use derivative::Derivative;
-use log::trace;
+use log::{debug, trace};
 use parking_lot::Mutex;
 
 use super::*;
@@ -61,8 +61,9 @@ struct HeapPool {
 
 #[derive(Debug)]
 struct HeapPoolInner {
-    allocator: FreeListAllocator,
-    chunks: Vec<Arc<DeviceMemory>>,
+    allocator: PoolAllocator,
+    // Chunks released by defragmentation are set to `None`.
+    chunks: Vec<Option<Arc<DeviceMemory>>>,
 }
 
 #[derive(Debug)]
@@ -70,11 +71,12 @@ pub struct ImageHeap {
     device: Arc<Device>,
     // One pool per memory type
     pools: Vec<Arc<HeapPool>>,
+    bindless: Option<Arc<BindlessHeap>>,
 }
 
 impl Drop for HeapPoolInner {
     fn drop(&mut self) {
-        for chunk in self.chunks.iter() {
+        for chunk in self.chunks.iter().flatten() {
             assert_eq!(
                 Arc::strong_count(chunk),
                 1,
@@ -86,12 +88,12 @@ impl Drop for HeapPoolInner {
 }
 
 impl HeapPool {
-    fn new(device: Arc<Device>, type_index: u32) -> Self {
+    fn new(device: Arc<Device>, type_index: u32, kind: AllocatorKind) -> Self {
         HeapPool {
             device,
             type_index,
             inner: Mutex::new(HeapPoolInner {
-                allocator: Default::default(),
+                allocator: PoolAllocator::new(kind),
                 chunks: Vec::new(),
             }),
         }
@@ -119,18 +121,27 @@ impl HeapPool {
         32
     }
 
-    unsafe fn add_chunk(&self, inner: &mut HeapPoolInner, min_size: vk::DeviceSize) {
+    unsafe fn add_chunk(
+        &self,
+        inner: &mut HeapPoolInner,
+        min_size: vk::DeviceSize,
+    ) -> Result<(), vk::Result> {
         let chunk = inner.chunks.len() as u32;
         // TODO: Possibly size should be a power of two times chunk size
-        let size = align(self.chunk_size(), min_size);
-        let mem = alloc_device_memory(
-            &self.device,
-            &vk::MemoryAllocateInfo {
-                allocation_size: size,
-                memory_type_index: self.type_index,
-                ..Default::default()
-            },
-        );
+        let alloc_info = |size| vk::MemoryAllocateInfo {
+            allocation_size: size,
+            memory_type_index: self.type_index,
+            ..Default::default()
+        };
+        let mut size = align(self.chunk_size(), min_size);
+        let mem = match alloc_device_memory(&self.device, &alloc_info(size)) {
+            // Fall back to a chunk just big enough.
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) if size > min_size => {
+                size = min_size;
+                alloc_device_memory(&self.device, &alloc_info(size))?
+            }
+            res => res?,
+        };
         let mut mem = DeviceMemory {
             device: Arc::clone(&self.device),
             inner: mem,
@@ -143,51 +154,169 @@ impl HeapPool {
             chunk,
         };
         mem.init();
-        inner.chunks.push(Arc::new(mem));
+        inner.chunks.push(Some(Arc::new(mem)));
         inner.allocator.add_chunk(size);
+        Ok(())
     }
 
     unsafe fn alloc(
         self: &Arc<Self>,
         size: vk::DeviceSize,
         alignment: vk::DeviceSize,
-    ) -> DeviceAlloc {
+    ) -> Result<DeviceAlloc, vk::Result> {
         trace!("HeapPool::alloc(size: {}, alignment: {})", size, alignment);
         let alignment = std::cmp::max(self.min_alignment(), alignment);
         let mut inner = self.inner.lock();
-        let block = inner
-            .allocator
-            .alloc(size, alignment)
-            .or_else(|| {
-                self.add_chunk(&mut *inner, size);
-                inner.allocator.alloc(size, alignment)
-            })
-            .unwrap();
+        let block = match inner.allocator.alloc(size, alignment) {
+            Some(block) => block,
+            None => {
+                self.add_chunk(&mut *inner, size)?;
+                inner.allocator.alloc(size, alignment).unwrap()
+            }
+        };
         let chunk = block.chunk;
-        let memory = Arc::clone(&inner.chunks[chunk as usize]);
+        let memory = Arc::clone(inner.chunks[chunk as usize].as_ref().unwrap());
         std::mem::drop(inner);
-        DeviceAlloc {
+        Ok(DeviceAlloc {
             memory,
             offset: block.offset(),
             size: block.size(),
             pool: Some(Arc::clone(self)),
-        }
+        })
     }
 
     unsafe fn free(&self, alloc: &DeviceAlloc) {
         let mut inner = self.inner.lock();
         // Make sure the allocation came from this pool
-        assert!(Arc::ptr_eq(
-            &alloc.memory,
+        assert!(matches!(
             &inner.chunks[alloc.memory.chunk as usize],
+            Some(chunk) if Arc::ptr_eq(&alloc.memory, chunk),
         ));
         inner.allocator.free(to_block(alloc));
     }
 
+    fn owns(self: &Arc<Self>, image: &Image) -> bool {
+        matches!(&image.alloc().pool, Some(pool) if Arc::ptr_eq(pool, self))
+    }
+
+    unsafe fn defragment(
+        self: &Arc<Self>,
+        cmds: &mut CmdBuffer<'_>,
+        bindless: Option<&BindlessHeap>,
+        views: &mut [&mut Arc<ImageView>],
+        defrag: &mut Defragmentation,
+    ) -> DeviceResult<()> {
+        // Views must be the sole owners of their images, and only
+        // shared with the bindless heap, which is updated in place.
+        let transfer =
+            vk::ImageUsageFlags::TRANSFER_SRC_BIT | vk::ImageUsageFlags::TRANSFER_DST_BIT;
+        let mut owned: Vec<&mut Arc<ImageView>> = views
+            .iter_mut()
+            .map(|view| &mut **view)
+            .filter(|view| {
+                let image = view.image();
+                let refs = bindless.map_or(0, |bindless| bindless.image_refs(view));
+                self.owns(image)
+                    && image.flags().usage().contains(transfer)
+                    && Arc::strong_count(image) == 1
+                    && Arc::strong_count(view) == 1 + refs
+            })
+            .collect();
+        let movable: Vec<_> = owned
+            .iter()
+            .map(|view| {
+                let image = view.image();
+                let (reqs, _) = get_image_memory_reqs(&self.device, image.inner());
+                Movable {
+                    block: to_block(image.alloc()),
+                    alignment: std::cmp::max(self.min_alignment(), reqs.alignment),
+                }
+            })
+            .collect();
+
+        let mut inner = self.inner.lock();
+        let plan = plan_defrag(&inner.allocator, &movable);
+        for &(i, dst) in plan.moves.iter() {
+            let view = &mut *owned[i];
+            let image = view.image();
+            let alloc = DeviceAlloc {
+                memory: Arc::clone(inner.chunks[dst.chunk as usize].as_ref().unwrap()),
+                offset: dst.start,
+                size: dst.size(),
+                pool: None,
+            };
+            let mut new_image = image.relocated(alloc)?;
+            inner.allocator.free(movable[i].block);
+            inner.allocator.alloc_at(dst);
+            new_image.alloc_mut().pool = Some(Arc::clone(self));
+
+            let all = image.all_subresources();
+            cmds.use_images(&[
+                ImageUse {
+                    image,
+                    subresources: all,
+                    usage: ImageUsage::TransferSrc,
+                },
+                ImageUse {
+                    image: &new_image,
+                    subresources: all,
+                    usage: ImageUsage::TransferDst,
+                },
+            ]);
+            let regions: Vec<_> = all
+                .mip_level_range()
+                .map(|mip_level| vk::ImageCopy {
+                    src_subresource: all.to_mip_layers(mip_level),
+                    dst_subresource: all.to_mip_layers(mip_level),
+                    extent: image.extent().mip_level(mip_level).into(),
+                    ..Default::default()
+                })
+                .collect();
+            cmds.copy_image(
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                &new_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+            if !image.flags().contains(ImageFlags::NO_SAMPLE) {
+                // Bindless descriptors expect sampled images to be
+                // ready for reading.
+                cmds.use_image(&new_image, all, ImageUsage::SampleFragment);
+            }
+
+            let new_view = Arc::new(ImageView::new(
+                Arc::new(new_image),
+                view.flags(),
+                view.format(),
+                view.components(),
+                view.subresources(),
+            ));
+            if let Some(bindless) = bindless {
+                bindless.replace_image(view, &new_view);
+            }
+            let mut old_view = std::mem::replace(view, new_view);
+            let old_image = Arc::get_mut(&mut old_view)
+                .and_then(ImageView::image_mut)
+                .expect("image shared during defragmentation");
+            // The old block was freed above
+            old_image.alloc_mut().pool = None;
+            defrag.record_move(dst.size(), Retired::ImageView(old_view));
+        }
+
+        for &chunk in plan.freed_chunks.iter() {
+            inner.allocator.remove_chunk(chunk);
+            let memory = inner.chunks[chunk as usize].take().unwrap();
+            defrag.record_free(memory.size, Retired::Memory(memory));
+        }
+
+        Ok(())
+    }
+
     #[allow(dead_code)]
     fn clear(&mut self) {
         let mut inner = self.inner.lock();
-        for chunk in inner.chunks.iter() {
+        for chunk in inner.chunks.iter().flatten() {
             assert_eq!(
                 Arc::strong_count(chunk),
                 1,
@@ -199,13 +328,43 @@ impl HeapPool {
     }
 }
 
+impl MemoryPools for ImageHeap {
+    fn add_pool_stats(&self, types: &mut [MemoryStats]) {
+        for pool in self.pools.iter() {
+            types[pool.type_index as usize] += pool.inner.lock().allocator.stats();
+        }
+    }
+}
+
 impl ImageHeap {
     pub fn new(device: Arc<Device>) -> Self {
+        Self::with_allocator(device, Default::default())
+    }
+
+    /// Creates a heap whose pools use the given allocation algorithm.
+    pub fn with_allocator(device: Arc<Device>, kind: AllocatorKind) -> Self {
+        Self::with_allocators(device, |_, _| kind)
+    }
+
+    /// Creates a heap whose pools, one per memory type, each use the
+    /// allocation algorithm chosen by `select` for their type index and
+    /// memory type.
+    pub fn with_allocators(
+        device: Arc<Device>,
+        select: impl Fn(u32, &vk::MemoryType) -> AllocatorKind,
+    ) -> Self {
         let pools: Vec<_> = iter_memory_types(&device)
             .enumerate()
-            .map(|(idx, _)| Arc::new(HeapPool::new(Arc::clone(&device), idx as _)))
+            .map(|(idx, ty)| {
+                let kind = select(idx as _, ty);
+                Arc::new(HeapPool::new(Arc::clone(&device), idx as _, kind))
+            })
             .collect();
-        Self { device, pools }
+        Self {
+            device,
+            pools,
+            bindless: None,
+        }
     }
 
     #[inline]
@@ -217,6 +376,40 @@ impl ImageHeap {
         &*self.device.table
     }
 
+    /// Creates a bindless descriptor heap which images can be
+    /// registered with. Returns false if the device doesn't support
+    /// descriptor indexing, in which case the heap is left unchanged.
+    pub fn enable_bindless(&mut self) -> bool {
+        if self.bindless.is_none() {
+            self.bindless = BindlessHeap::new(Arc::clone(&self.device)).map(Arc::new);
+        }
+        self.bindless.is_some()
+    }
+
+    #[inline]
+    pub fn bindless(&self) -> Option<&Arc<BindlessHeap>> {
+        self.bindless.as_ref()
+    }
+
+    /// Registers an image view with the bindless heap, returning its
+    /// index, or `None` if bindless mode is not enabled.
+    pub fn register_bindless(&self, view: &Arc<ImageView>) -> Option<u32> {
+        Some(self.bindless.as_ref()?.register_image(view))
+    }
+
+    /// Frees an index returned by `register_bindless`.
+    ///
+    /// # Safety
+    ///
+    /// No pending command buffer may access the image through the
+    /// bindless heap.
+    pub unsafe fn unregister_bindless(&self, idx: u32) {
+        self.bindless
+            .as_ref()
+            .expect("bindless mode not enabled")
+            .unregister_image(idx);
+    }
+
     fn pool(&self, type_idx: u32) -> &Arc<HeapPool> {
         &self.pools[type_idx as usize]
     }
@@ -234,20 +427,35 @@ impl ImageHeap {
         heaps
     }
 
-    /// Suballocates device memory.
-    unsafe fn alloc(&self, reqs: vk::MemoryRequirements) -> DeviceAlloc {
-        // TODO: fall back to incoherent memory on failure
-        let type_idx = find_memory_type(
-            &*self.device,
-            MemoryMapping::DeviceLocal.memory_property_flags(),
+    /// Suballocates device memory, falling back to other memory types
+    /// if the preferred ones are out of memory.
+    unsafe fn alloc(&self, reqs: vk::MemoryRequirements) -> DeviceResult<DeviceAlloc> {
+        let candidates = memory_type_candidates(
+            &self.device,
+            MemoryMapping::DeviceLocal,
             reqs.memory_type_bits,
-        )
-        .unwrap();
-        self.pool(type_idx).alloc(reqs.size, reqs.alignment)
+        );
+        for type_idx in candidates {
+            match self.pool(type_idx).alloc(reqs.size, reqs.alignment) {
+                Ok(alloc) => return Ok(alloc),
+                Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                    debug!(
+                        "image memory type {} out of memory (size: {}); trying next type",
+                        type_idx, reqs.size,
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(OutOfMemoryError {
+            size: reqs.size,
+            type_bits: reqs.memory_type_bits,
+        }
+        .into())
     }
 
     /// Binds an image to newly allocated memory.
-    pub unsafe fn bind(&self, image: vk::Image) -> DeviceAlloc {
+    pub unsafe fn bind(&self, image: vk::Image) -> DeviceResult<DeviceAlloc> {
         let device = &self.device;
         let (reqs, dedicated_reqs) = get_image_memory_reqs(device, image);
 
@@ -258,19 +466,45 @@ impl ImageHeap {
                 &reqs,
                 Some(DedicatedAllocContent::Image(image)),
                 Tiling::Nonlinear,
-            )))
+            )?))
         } else {
-            self.alloc(reqs)
+            self.alloc(reqs)?
         };
 
         let memory = alloc.memory().inner();
         let offset = alloc.offset();
-        self.dt()
-            .bind_image_memory(image, memory, offset)
-            .check()
-            .unwrap();
+        self.dt().bind_image_memory(image, memory, offset).check()?;
 
-        alloc
+        Ok(alloc)
+    }
+
+    /// Moves images out of sparsely used chunks and releases chunks
+    /// which end up empty. Only chunks holding nothing but images of
+    /// `views` can be released. The copies are recorded into `cmds`,
+    /// and each moved view is replaced with an equivalent view of a
+    /// new image, including where it is registered with the bindless
+    /// heap.
+    ///
+    /// An image can only be moved if it has `ImageFlags::TRANSFER_SRC`
+    /// and isn't a render target, if the view is its only owner, and if
+    /// the view isn't shared outside of `views` and the bindless heap.
+    ///
+    /// # Safety
+    ///
+    /// No pending command buffer may access the images, `cmds` must be
+    /// executed before they are next accessed, and `defrag` must
+    /// outlive its execution. Other descriptors referring to moved
+    /// views must be recreated.
+    pub unsafe fn defragment(
+        &self,
+        cmds: &mut CmdBuffer<'_>,
+        views: &mut [&mut Arc<ImageView>],
+        defrag: &mut Defragmentation,
+    ) -> DeviceResult<()> {
+        for pool in self.pools.iter() {
+            pool.defragment(cmds, self.bindless.as_deref(), views, defrag)?;
+        }
+        Ok(())
     }
 }
 
@@ -283,7 +517,7 @@ mod tests {
     #[test]
     fn alloc() {
         let vars = TestVars::new();
-        let device = Arc::clone(&vars.swapchain.device);
+        let device = Arc::clone(vars.device());
         let heap = ImageHeap::new(Arc::clone(&device));
 
         let reqs = vk::MemoryRequirements {
@@ -292,12 +526,151 @@ mod tests {
             memory_type_bits: !0,
         };
         unsafe {
-            let alloc0 = heap.alloc(reqs);
-            let alloc1 = heap.alloc(reqs);
+            let alloc0 = heap.alloc(reqs).unwrap();
+            let alloc1 = heap.alloc(reqs).unwrap();
             // Should not be mapped since ImageHeap is device-local
             assert_eq!(alloc0.as_raw(), 0 as _);
             assert_ne!(alloc0.offset, alloc1.offset);
             assert_eq!(alloc1.offset % 256, 0);
         }
     }
+
+    #[test]
+    fn memory_type_fallback() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let flags = |idx: u32| device.mem_props.memory_types[idx as usize].property_flags;
+
+        // Device-local types come first, followed by other types
+        let local = vk::MemoryPropertyFlags::DEVICE_LOCAL_BIT;
+        let candidates = super::memory_type_candidates(device, MemoryMapping::DeviceLocal, !0);
+        let fallback = candidates
+            .iter()
+            .position(|&idx| !flags(idx).contains(local))
+            .unwrap_or(candidates.len());
+        assert!(fallback > 0);
+        assert!(candidates[fallback..]
+            .iter()
+            .all(|&idx| !flags(idx).contains(local)));
+
+        // Mapped memory never falls back to incoherent memory
+        let candidates = super::memory_type_candidates(device, MemoryMapping::Mapped, !0);
+        assert!(!candidates.is_empty());
+        assert!(candidates
+            .iter()
+            .all(|&idx| flags(idx).contains(visible_coherent_flags())));
+
+        // Cached memory falls back to any host-visible memory
+        let visible = vk::MemoryPropertyFlags::HOST_VISIBLE_BIT;
+        let candidates = super::memory_type_candidates(device, MemoryMapping::Cached, !0);
+        assert!(!candidates.is_empty());
+        assert!(candidates.iter().all(|&idx| flags(idx).contains(visible)));
+
+        assert!(super::memory_type_candidates(device, MemoryMapping::DeviceLocal, 0).is_empty());
+    }
+
+    #[test]
+    fn bindless() {
+        let vars = TestVars::new();
+        let device = Arc::clone(vars.device());
+        let resources = TestResources::new(&device);
+        let mut heap = ImageHeap::new(Arc::clone(&device));
+        assert_eq!(heap.register_bindless(&resources.empty_image_2d), None);
+        if !heap.enable_bindless() {
+            assert!(!device.supports_bindless());
+            return;
+        }
+
+        let image = &resources.empty_image_2d;
+        let idx0 = heap.register_bindless(image).unwrap();
+        let idx1 = heap.register_bindless(image).unwrap();
+        assert_ne!(idx0, idx1);
+        let bindless = heap.bindless().unwrap();
+        assert!(Arc::ptr_eq(&bindless.image(idx1).unwrap(), image));
+        unsafe {
+            heap.unregister_bindless(idx0);
+        }
+        assert!(bindless.image(idx0).is_none());
+        // Freed indices are reused
+        assert_eq!(heap.register_bindless(image), Some(idx0));
+
+        let sampler = bindless.register_sampler(&resources.empty_sampler);
+        unsafe {
+            bindless.unregister_sampler(sampler);
+        }
+    }
+
+    #[test]
+    fn defragment() {
+        let vars = TestVars::new();
+        let device = Arc::clone(vars.device());
+        let queue = vars.gfx_queue();
+        let mut heap = ImageHeap::new(Arc::clone(&device));
+        heap.enable_bindless();
+
+        let image = |width, height| {
+            let view = Arc::new(Image::with(
+                &heap,
+                ImageFlags::TRANSFER_SRC,
+                ImageType::Dim2,
+                Format::RGBA8,
+                SampleCount::One,
+                Extent3D::new(width, height, 1),
+                1,
+                1,
+            ))
+            .create_full_view();
+            assert!(view.image().alloc().pool.is_some(), "dedicated allocation");
+            view
+        };
+        let chunk = |view: &Arc<ImageView>| view.image().alloc().memory().chunk;
+        // Fill the first chunk, leaving a lone image in a second one
+        let mut fillers = vec![image(1024, 1024)];
+        let first = chunk(&fillers[0]);
+        let mut moved = loop {
+            let view = image(1024, 1024);
+            if chunk(&view) != first {
+                break view;
+            }
+            fillers.push(view);
+        };
+        // Make room for the lone image next to a pinned one
+        fillers.truncate(fillers.len() - 2);
+        let pinned = image(64, 64);
+        assert_eq!(chunk(&pinned), first);
+        let idx = heap.register_bindless(&moved);
+
+        let mut pool = CmdPool::new_transient(queue.family());
+        let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+        let mut defrag = Defragmentation::default();
+        let old = moved.inner();
+        unsafe {
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            heap.defragment(&mut cmds, &mut [&mut moved], &mut defrag)
+                .unwrap();
+            let cmds = cmds.end();
+            queue
+                .submit(&[SubmitInfo {
+                    cmds: &[cmds],
+                    ..Default::default()
+                }])
+                .wait(u64::MAX)
+                .unwrap();
+        }
+
+        let stats = defrag.stats();
+        assert_eq!(stats.moves, 1);
+        assert_eq!(stats.bytes_moved, moved.image().alloc().size());
+        assert_eq!(stats.chunks_freed, 1);
+        assert_ne!(moved.inner(), old);
+        assert_eq!(chunk(&moved), chunk(&pinned));
+        if let Some(idx) = idx {
+            let bindless = heap.bindless().unwrap();
+            assert!(Arc::ptr_eq(&bindless.image(idx).unwrap(), &moved));
+            unsafe {
+                heap.unregister_bindless(idx);
+            }
+        }
+        std::mem::drop(defrag);
+    }
 }
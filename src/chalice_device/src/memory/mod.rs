@@ -14,14 +14,19 @@ use crate::*;
 mod alloc;
 mod buffer;
 mod buffer_heap;
+mod defrag;
 mod image;
 mod staging;
+mod stats;
 
 pub(self) use alloc::*;
+pub use alloc::AllocatorKind;
 pub use buffer::*;
 pub use buffer_heap::*;
+pub use defrag::*;
 pub use image::*;
 pub use staging::*;
+pub use stats::*;
 
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 struct Block {
@@ -54,8 +59,16 @@ pub enum Tiling {
 
 #[derive(Clone, Copy, Debug, Enum, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum MemoryMapping {
+    /// Device-local memory, which may not be host-visible.
     DeviceLocal,
+    /// Host-visible, host-coherent memory. Typically uncached, so it is
+    /// best suited to sequential writes from the host.
     Mapped,
+    /// Host-visible memory which is preferably host-cached, e.g. for
+    /// readbacks. The memory may be non-coherent, in which case host
+    /// writes must be flushed and device writes invalidated; see
+    /// `MemoryRegion::flush` and `MemoryRegion::invalidate`.
+    Cached,
 }
 
 /// Tells how long memory or other resources live for.
@@ -92,32 +105,42 @@ fn iter_memory_types(device: &Device) -> impl Iterator<Item = &vk::MemoryType> {
     props.memory_types.iter().take(props.memory_type_count as _)
 }
 
-fn find_memory_type(
-    device: &Device,
-    flags: vk::MemoryPropertyFlags,
-    type_mask: u32,
-) -> Option<u32> {
+/// Lists the memory types to allocate from in order of preference.
+/// Types with the properties requested by `mapping` come first. Device-
+/// local requests then fall back to any other compatible type, which is
+/// usually host memory, for when device memory is exhausted. Cached
+/// requests fall back to any host-visible type.
+fn memory_type_candidates(device: &Device, mapping: MemoryMapping, type_mask: u32) -> Vec<u32> {
     // According to the spec, implementations are to sort memory types
-    // in order of "performance", so the first memory type with the
-    // required properties is probably the best for general use.
-    iter_memory_types(device)
-        .enumerate()
-        .find(|&(idx, ty)| {
-            compatible_type(type_mask, idx as u32) && ty.property_flags.contains(flags)
-        })
+    // in order of "performance", so earlier types are probably better
+    // for general use.
+    let flags = mapping.memory_property_flags();
+    let compatible = || {
+        iter_memory_types(device)
+            .enumerate()
+            .filter(move |&(idx, _)| compatible_type(type_mask, idx as u32))
+    };
+    let mut candidates: Vec<u32> = compatible()
+        .filter(|(_, ty)| ty.property_flags.contains(flags))
         .map(|(idx, _)| idx as u32)
-}
-
-fn find_memory_type_2(
-    device: &Device,
-    mapping: MemoryMapping,
-    reqs: &vk::MemoryRequirements,
-) -> Option<u32> {
-    find_memory_type(
-        device,
-        mapping.memory_property_flags(),
-        reqs.memory_type_bits,
-    )
+        .collect();
+    let visible = vk::MemoryPropertyFlags::HOST_VISIBLE_BIT;
+    let fallback = |ty: &vk::MemoryType| match mapping {
+        // Device-local memory is never flushed, so mapped fallbacks must
+        // be coherent.
+        MemoryMapping::DeviceLocal => {
+            !ty.property_flags.contains(visible)
+                || ty.property_flags.contains(visible_coherent_flags())
+        }
+        MemoryMapping::Mapped => false,
+        MemoryMapping::Cached => ty.property_flags.contains(visible),
+    };
+    candidates.extend(
+        compatible()
+            .filter(|(_, ty)| !ty.property_flags.contains(flags) && fallback(ty))
+            .map(|(idx, _)| idx as u32),
+    );
+    candidates
 }
 
 #[inline(always)]
@@ -128,13 +151,55 @@ pub fn visible_coherent_flags() -> vk::MemoryPropertyFlags {
 unsafe fn alloc_device_memory(
     device: &Device,
     alloc_info: &vk::MemoryAllocateInfo,
-) -> vk::DeviceMemory {
+) -> Result<vk::DeviceMemory, vk::Result> {
     let dt = &*device.table;
     let mut memory = vk::null();
     dt.allocate_memory(alloc_info, ptr::null(), &mut memory)
-        .check()
-        .unwrap_or_else(|_| panic!("failed to allocate device memory: {:?}", alloc_info));
-    memory
+        .check()?;
+    device
+        .memory_tracker
+        .add(alloc_info.memory_type_index, alloc_info.allocation_size);
+    Ok(memory)
+}
+
+/// Allocates memory from the first candidate memory type which isn't
+/// out of memory. Returns the memory and its type index.
+unsafe fn alloc_with_fallback(
+    device: &Device,
+    mapping: MemoryMapping,
+    reqs: &vk::MemoryRequirements,
+    p_next: *mut c_void,
+) -> DeviceResult<(vk::DeviceMemory, u32)> {
+    let candidates = memory_type_candidates(device, mapping, reqs.memory_type_bits);
+    if candidates.is_empty() {
+        Err(format!(
+            "no {:?} memory type compatible with {:#x}",
+            mapping, reqs.memory_type_bits
+        ))?;
+    }
+    for type_index in candidates {
+        let alloc_info = vk::MemoryAllocateInfo {
+            p_next,
+            allocation_size: reqs.size,
+            memory_type_index: type_index,
+            ..Default::default()
+        };
+        match alloc_device_memory(device, &alloc_info) {
+            Ok(memory) => return Ok((memory, type_index)),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                debug!(
+                    "memory type {} out of memory (size: {}); trying next type",
+                    type_index, reqs.size,
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(OutOfMemoryError {
+        size: reqs.size,
+        type_bits: reqs.memory_type_bits,
+    }
+    .into())
 }
 
 unsafe fn alloc_resource_memory(
@@ -143,7 +208,7 @@ unsafe fn alloc_resource_memory(
     reqs: &vk::MemoryRequirements,
     content: Option<DedicatedAllocContent>,
     tiling: Tiling,
-) -> DeviceMemory {
+) -> DeviceResult<DeviceMemory> {
     use DedicatedAllocContent::*;
 
     // TODO: Can't actually see fields of VkMemoryRequirements...
@@ -167,23 +232,15 @@ unsafe fn alloc_resource_memory(
         }
     }
 
-    let type_index = find_memory_type_2(&device, mapping, reqs).unwrap();
-    let alloc_info = vk::MemoryAllocateInfo {
-        p_next,
-        allocation_size: reqs.size,
-        memory_type_index: type_index,
-        ..Default::default()
-    };
+    let (inner, type_index) = alloc_with_fallback(&device, mapping, reqs, p_next)?;
 
     if let Some(content) = content {
         debug!(
-            "creating dedicated allocation: size: {:?}, type: {:?}, {:?}",
+            "created dedicated allocation: size: {:?}, type: {:?}, {:?}",
             reqs.size, type_index, content
         );
     }
 
-    let inner = alloc_device_memory(&device, &alloc_info);
-
     // Fill out boilerplate
     let mut memory = DeviceMemory {
         device,
@@ -199,7 +256,7 @@ unsafe fn alloc_resource_memory(
         chunk: !0,
     };
     memory.init();
-    memory
+    Ok(memory)
 }
 
 unsafe fn get_buffer_memory_reqs(
@@ -298,11 +355,6 @@ pub trait MemoryRegion {
         unsafe { Some(&*self.as_ptr()?.as_ptr()) }
     }
 
-    #[inline]
-    fn as_mut<T>(&mut self) -> Option<&mut MaybeUninit<T>> {
-        unsafe { Some(&mut *self.as_ptr()?.as_ptr()) }
-    }
-
     #[inline]
     unsafe fn as_slice_ptr<T>(&self, len: usize) -> Option<NonNull<[T]>> {
         let ptr = self.as_void()?;
@@ -311,6 +363,38 @@ pub trait MemoryRegion {
         Some(NonNull::slice_from_raw_parts(ptr.cast(), len))
     }
 
+    #[inline]
+    fn as_bytes(&self) -> Option<&[u8]> {
+        unsafe {
+            let slice = &*self.as_slice_ptr::<MaybeUninit<u8>>(self.size() as _)?.as_ptr();
+            Some(MaybeUninit::slice_assume_init_ref(slice))
+        }
+    }
+
+    /// Makes host writes to the region available to the device. Does
+    /// nothing unless the memory is mapped and non-coherent.
+    #[inline]
+    fn flush(&self) -> DeviceResult<()> {
+        self.memory().flush_range(self.offset(), self.size())
+    }
+
+    /// Makes device writes to the region visible to the host. Does
+    /// nothing unless the memory is mapped and non-coherent.
+    #[inline]
+    fn invalidate(&self) -> DeviceResult<()> {
+        self.memory().invalidate_range(self.offset(), self.size())
+    }
+}
+
+/// A region which the holder has exclusive access to, and so may write
+/// to through the host mapping. Not implemented by `BufferRange`, which
+/// is `Copy`; use a `BufferRangeMut` instead.
+pub trait MemoryRegionMut: MemoryRegion {
+    #[inline]
+    fn as_mut<T>(&mut self) -> Option<&mut MaybeUninit<T>> {
+        unsafe { Some(&mut *self.as_ptr()?.as_ptr()) }
+    }
+
     #[inline]
     fn as_mut_slice<T>(&mut self, len: usize) -> Option<&mut [MaybeUninit<T>]> {
         unsafe { Some(&mut *self.as_slice_ptr(len)?.as_ptr()) }
@@ -342,6 +426,9 @@ impl Drop for DeviceMemory {
         unsafe {
             dt.free_memory(self.inner, ptr::null());
         }
+        self.device
+            .memory_tracker
+            .remove(self.type_index, self.size);
     }
 }
 
@@ -392,6 +479,66 @@ impl DeviceMemory {
         self.device.mem_props.memory_types[self.type_index as usize].property_flags
     }
 
+    /// True if the memory is host-coherent, i.e. mapped ranges never
+    /// need to be flushed or invalidated.
+    #[inline]
+    pub fn coherent(&self) -> bool {
+        self.flags()
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT_BIT)
+    }
+
+    /// Flushes host writes to a mapped range of memory. The range is
+    /// expanded to a multiple of `nonCoherentAtomSize`.
+    pub fn flush_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> DeviceResult<()> {
+        if let Some(range) = self.non_coherent_range(offset, size) {
+            unsafe {
+                self.device
+                    .table
+                    .flush_mapped_memory_ranges(1, &range)
+                    .check()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Invalidates host caches of a mapped range of memory. The range
+    /// is expanded to a multiple of `nonCoherentAtomSize`.
+    pub fn invalidate_range(
+        &self,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> DeviceResult<()> {
+        if let Some(range) = self.non_coherent_range(offset, size) {
+            unsafe {
+                self.device
+                    .table
+                    .invalidate_mapped_memory_ranges(1, &range)
+                    .check()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn non_coherent_range(
+        &self,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> Option<vk::MappedMemoryRange> {
+        if !self.mapped() || self.coherent() || size == 0 {
+            return None;
+        }
+        assert!(offset + size <= self.size);
+        let atom = self.device.limits().non_coherent_atom_size;
+        let start = offset / atom * atom;
+        let end = align(atom, offset + size).min(self.size);
+        Some(vk::MappedMemoryRange {
+            memory: self.inner,
+            offset: start,
+            size: end - start,
+            ..Default::default()
+        })
+    }
+
     unsafe fn init(&mut self) {
         if self
             .flags()
@@ -435,6 +582,9 @@ impl MemoryMapping {
     pub fn memory_property_flags(self) -> vk::MemoryPropertyFlags {
         match self {
             Self::Mapped => visible_coherent_flags(),
+            Self::Cached => {
+                vk::MemoryPropertyFlags::HOST_VISIBLE_BIT | vk::MemoryPropertyFlags::HOST_CACHED_BIT
+            }
             Self::DeviceLocal => vk::MemoryPropertyFlags::DEVICE_LOCAL_BIT,
         }
     }
@@ -0,0 +1,324 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::*;
+
+/// Memory usage numbers of an allocator, memory type, or heap.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryStats {
+    /// Bytes handed out to allocations.
+    pub used: vk::DeviceSize,
+    /// Bytes of device memory allocated from the driver.
+    pub reserved: vk::DeviceSize,
+    /// Free bytes outside of the largest free block of each pool.
+    pub fragmented: vk::DeviceSize,
+    /// Number of live allocations.
+    pub allocation_count: u32,
+    /// Number of `VkDeviceMemory` objects.
+    pub block_count: u32,
+    /// Largest amount of memory which can be allocated from a single
+    /// pool without reserving more memory.
+    pub largest_free_block: vk::DeviceSize,
+}
+
+/// Heap budget reported by `VK_EXT_memory_budget`, which accounts for
+/// memory used by other processes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryBudget {
+    /// Approximate amount of memory the process can use before
+    /// allocations start failing or performance degrades.
+    pub budget: vk::DeviceSize,
+    /// Approximate amount of memory used by the process.
+    pub usage: vk::DeviceSize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryTypeStats {
+    pub type_index: u32,
+    pub heap_index: u32,
+    pub flags: vk::MemoryPropertyFlags,
+    pub stats: MemoryStats,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryHeapStats {
+    pub heap_index: u32,
+    pub flags: vk::MemoryHeapFlags,
+    pub size: vk::DeviceSize,
+    /// Sum of the stats of each memory type in the heap.
+    pub stats: MemoryStats,
+    /// `None` if `VK_EXT_memory_budget` is unsupported.
+    pub budget: Option<MemoryBudget>,
+}
+
+/// A snapshot of device memory usage. The `Display` impl formats it as
+/// a table suitable for logging.
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub types: Vec<MemoryTypeStats>,
+    pub heaps: Vec<MemoryHeapStats>,
+}
+
+/// Implemented by heaps which suballocate device memory from pools.
+pub trait MemoryPools {
+    /// Adds the stats of each pool to those of its memory type.
+    fn add_pool_stats(&self, types: &mut [MemoryStats]);
+}
+
+/// Counts the device memory allocated from each memory type.
+#[derive(Debug)]
+pub(crate) struct MemoryTracker {
+    types: Vec<TypeCounters>,
+}
+
+#[derive(Debug, Default)]
+struct TypeCounters {
+    bytes: AtomicU64,
+    count: AtomicU32,
+}
+
+impl std::ops::AddAssign for MemoryStats {
+    fn add_assign(&mut self, other: Self) {
+        self.used += other.used;
+        self.reserved += other.reserved;
+        self.fragmented += other.fragmented;
+        self.allocation_count += other.allocation_count;
+        self.block_count += other.block_count;
+        self.largest_free_block = self.largest_free_block.max(other.largest_free_block);
+    }
+}
+
+impl MemoryStats {
+    #[inline]
+    pub fn free(&self) -> vk::DeviceSize {
+        self.reserved - self.used
+    }
+}
+
+impl MemoryTracker {
+    pub(crate) fn new(type_count: u32) -> Self {
+        Self {
+            types: (0..type_count).map(|_| Default::default()).collect(),
+        }
+    }
+
+    pub(super) fn add(&self, type_index: u32, size: vk::DeviceSize) {
+        let counters = &self.types[type_index as usize];
+        counters.bytes.fetch_add(size, Ordering::Relaxed);
+        counters.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn remove(&self, type_index: u32, size: vk::DeviceSize) {
+        let counters = &self.types[type_index as usize];
+        counters.bytes.fetch_sub(size, Ordering::Relaxed);
+        counters.count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn get(&self, type_index: usize) -> (vk::DeviceSize, u32) {
+        let counters = &self.types[type_index];
+        (
+            counters.bytes.load(Ordering::Relaxed),
+            counters.count.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl MemoryReport {
+    /// Collects memory usage of the device. Memory which doesn't
+    /// belong to any of `pools` is reported as dedicated allocations,
+    /// i.e. as fully used.
+    ///
+    /// N.B. This races with other threads, so the numbers may be
+    /// slightly inconsistent.
+    pub fn collect(device: &Device, pools: &[&dyn MemoryPools]) -> Self {
+        let mem_props = &device.mem_props;
+        let mut stats = vec![MemoryStats::default(); mem_props.memory_type_count as usize];
+        for pool in pools.iter() {
+            pool.add_pool_stats(&mut stats);
+        }
+
+        let types: Vec<_> = iter_memory_types(device)
+            .zip(stats)
+            .enumerate()
+            .map(|(idx, (ty, mut stats))| {
+                let (bytes, count) = device.memory_tracker.get(idx);
+                let dedicated = bytes.saturating_sub(stats.reserved);
+                let dedicated_count = count.saturating_sub(stats.block_count);
+                stats += MemoryStats {
+                    used: dedicated,
+                    reserved: dedicated,
+                    allocation_count: dedicated_count,
+                    block_count: dedicated_count,
+                    ..Default::default()
+                };
+                MemoryTypeStats {
+                    type_index: idx as _,
+                    heap_index: ty.heap_index,
+                    flags: ty.property_flags,
+                    stats,
+                }
+            })
+            .collect();
+
+        let budgets = unsafe { device.memory_budget() };
+        let heaps = mem_props.memory_heaps[..mem_props.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(idx, heap)| {
+                let mut stats = MemoryStats::default();
+                for ty in types.iter().filter(|ty| ty.heap_index == idx as u32) {
+                    stats += ty.stats;
+                }
+                MemoryHeapStats {
+                    heap_index: idx as _,
+                    flags: heap.flags,
+                    size: heap.size,
+                    stats,
+                    budget: budgets.as_ref().map(|budgets| budgets[idx]),
+                }
+            })
+            .collect();
+
+        Self { types, heaps }
+    }
+
+    /// Sums the stats of every heap.
+    pub fn total(&self) -> MemoryStats {
+        let mut total = MemoryStats::default();
+        for heap in self.heaps.iter() {
+            total += heap.stats;
+        }
+        total
+    }
+}
+
+impl Device {
+    /// Queries the budget of each memory heap, or returns `None` if
+    /// `VK_EXT_memory_budget` is unsupported.
+    pub unsafe fn memory_budget(&self) -> Option<Vec<MemoryBudget>> {
+        if !self.memory_budget {
+            return None;
+        }
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut props = vk::PhysicalDeviceMemoryProperties2 {
+            p_next: &mut budget as *mut _ as _,
+            ..Default::default()
+        };
+        self.instance
+            .table
+            .get_physical_device_memory_properties2(self.pdev, &mut props);
+        let heap_count = props.memory_properties.memory_heap_count as usize;
+        Some(
+            (0..heap_count)
+                .map(|idx| MemoryBudget {
+                    budget: budget.heap_budget[idx],
+                    usage: budget.heap_usage[idx],
+                })
+                .collect(),
+        )
+    }
+}
+
+struct Bytes(vk::DeviceSize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 1 << 20 {
+            write!(f, "{:.1} KiB", self.0 as f64 / (1 << 10) as f64)
+        } else {
+            write!(f, "{:.1} MiB", self.0 as f64 / (1 << 20) as f64)
+        }
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "used {} / reserved {}, fragmented {}, largest free {}, {} allocs in {} blocks",
+            Bytes(self.used),
+            Bytes(self.reserved),
+            Bytes(self.fragmented),
+            Bytes(self.largest_free_block),
+            self.allocation_count,
+            self.block_count,
+        )
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "memory usage: {}", self.total())?;
+        for heap in self.heaps.iter() {
+            write!(
+                f,
+                "\n  heap {} ({:?}, {}): {}",
+                heap.heap_index,
+                heap.flags,
+                Bytes(heap.size),
+                heap.stats,
+            )?;
+            if let Some(budget) = heap.budget {
+                write!(
+                    f,
+                    "; budget {}, usage {}",
+                    Bytes(budget.budget),
+                    Bytes(budget.usage),
+                )?;
+            }
+            for ty in self.types.iter() {
+                if ty.heap_index != heap.heap_index || ty.stats.reserved == 0 {
+                    continue;
+                }
+                write!(
+                    f,
+                    "\n    type {} ({:?}): {}",
+                    ty.type_index, ty.flags, ty.stats,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn report() {
+        use BufferBinding::*;
+        use Lifetime::*;
+        use MemoryMapping::*;
+
+        let vars = TestVars::new();
+        let device = Arc::clone(vars.device());
+        let buffer_heap = BufferHeap::new(Arc::clone(&device));
+        let image_heap = ImageHeap::new(Arc::clone(&device));
+        let pools: [&dyn MemoryPools; 2] = [&*buffer_heap, &image_heap];
+
+        let before = MemoryReport::collect(&device, &pools).total();
+        let _alloc = buffer_heap.alloc(Uniform, Static, Mapped, 256);
+        let _buffer = DeviceBuffer::new(
+            Arc::clone(&device),
+            0x10_0000,
+            BufferUsage::TRANSFER_SRC,
+            Mapped,
+            Static,
+        );
+        let report = MemoryReport::collect(&device, &pools);
+        let after = report.total();
+
+        assert_eq!(after.allocation_count, before.allocation_count + 2);
+        // The buffer is a dedicated block; the suballocation isn't
+        assert_eq!(after.block_count, before.block_count + 1);
+        assert!(after.used >= before.used + 0x10_0000 + 256);
+        assert!(after.reserved >= after.used + after.fragmented);
+        assert_eq!(
+            report.heaps.len(),
+            device.mem_props.memory_heap_count as usize
+        );
+        assert!(!format!("{}", report).is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use std::ffi::CStr;
+
+use log::{debug, info};
+
+use crate::*;
+
+/// Environment variable which overrides `AppInfo::device`.
+pub const DEVICE_ENV_VAR: &str = "CHALICE_DEVICE";
+
+/// The queue families a device is created with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct QueueFamilyIndices {
+    /// Supports graphics, compute, and transfer operations.
+    pub graphics: u32,
+    /// A compute family without graphics support, if any.
+    pub compute: Option<u32>,
+    /// A transfer family without graphics or compute support, if any.
+    pub transfer: Option<u32>,
+}
+
+/// Describes a physical device for the purposes of device selection.
+#[derive(Clone, Debug)]
+pub struct PhysicalDeviceInfo {
+    pub pdev: vk::PhysicalDevice,
+    /// Position in enumeration order.
+    pub index: usize,
+    pub name: String,
+    pub ty: vk::PhysicalDeviceType,
+    /// Total size of device-local memory heaps.
+    pub device_local_memory: vk::DeviceSize,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+    /// `None` if the device lacks a graphics queue family.
+    pub queue_family_indices: Option<QueueFamilyIndices>,
+    /// Whether the graphics family can present to the surface the
+    /// device was enumerated with. Always true when headless.
+    pub supports_present: bool,
+}
+
+impl QueueFamilyIndices {
+    pub fn find(families: &[vk::QueueFamilyProperties]) -> Option<Self> {
+        use vk::QueueFlags as F;
+        let find = |include: F, exclude: F| {
+            families
+                .iter()
+                .position(|family| {
+                    family.queue_count > 0
+                        && family.queue_flags.contains(include)
+                        && !family.queue_flags.intersects(exclude)
+                })
+                .map(|idx| idx as u32)
+        };
+        Some(Self {
+            graphics: find(F::GRAPHICS_BIT | F::COMPUTE_BIT, F::empty())?,
+            compute: find(F::COMPUTE_BIT, F::GRAPHICS_BIT),
+            transfer: find(F::TRANSFER_BIT, F::GRAPHICS_BIT | F::COMPUTE_BIT),
+        })
+    }
+
+    /// Iterates over each family to create queues in.
+    pub fn iter(&self) -> impl Iterator<Item = u32> {
+        std::iter::once(self.graphics)
+            .chain(self.compute)
+            .chain(self.transfer)
+    }
+}
+
+impl PhysicalDeviceInfo {
+    /// True if a device can be created from this physical device.
+    #[inline]
+    pub fn is_usable(&self) -> bool {
+        self.queue_family_indices.is_some() && self.supports_present
+    }
+
+    /// Ranks devices by type (discrete > integrated > virtual > CPU),
+    /// then by amount of device-local memory. Higher is better.
+    pub fn score(&self) -> (u32, vk::DeviceSize) {
+        let ty = match self.ty {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 1,
+            _ => 0,
+        };
+        (ty, self.device_local_memory)
+    }
+
+    /// Checks whether the device matches a selector, which is either
+    /// an index in enumeration order or a case-insensitive substring
+    /// of the device name.
+    pub fn matches(&self, selector: &str) -> bool {
+        if let Ok(index) = selector.parse::<usize>() {
+            return index == self.index;
+        }
+        self.name.to_lowercase().contains(&selector.to_lowercase())
+    }
+}
+
+impl Instance {
+    pub unsafe fn get_memory_properties(
+        &self,
+        pdev: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceMemoryProperties {
+        let mut res = Default::default();
+        self.table
+            .get_physical_device_memory_properties(pdev, &mut res);
+        res
+    }
+
+    /// Describes every physical device. If a surface is given, also
+    /// checks for presentation support.
+    pub unsafe fn enumerate_devices(
+        &self,
+        surface: Option<&Surface>,
+    ) -> DeviceResult<Vec<PhysicalDeviceInfo>> {
+        let mut infos = Vec::new();
+        for (index, pdev) in self.get_physical_devices().into_iter().enumerate() {
+            let props = self.get_properties(pdev);
+            let name = CStr::from_ptr(props.device_name.as_ptr())
+                .to_string_lossy()
+                .into_owned();
+            let mem_props = self.get_memory_properties(pdev);
+            let device_local_memory = mem_props.memory_heaps
+                [..mem_props.memory_heap_count as usize]
+                .iter()
+                .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL_BIT))
+                .map(|heap| heap.size)
+                .sum();
+            let queue_families = self.get_queue_family_properties(pdev);
+            let queue_family_indices = QueueFamilyIndices::find(&queue_families);
+
+            let mut supports_present = true;
+            if let (Some(surface), Some(indices)) = (surface, queue_family_indices) {
+                let mut supported = 0;
+                self.table
+                    .get_physical_device_surface_support_khr(
+                        pdev,
+                        indices.graphics,
+                        surface.inner,
+                        &mut supported,
+                    )
+                    .check()?;
+                supports_present = supported == vk::TRUE;
+            }
+
+            infos.push(PhysicalDeviceInfo {
+                pdev,
+                index,
+                name,
+                ty: props.device_type,
+                device_local_memory,
+                queue_families,
+                queue_family_indices,
+                supports_present,
+            });
+        }
+        Ok(infos)
+    }
+
+    /// Picks the best usable device, or the one named by the
+    /// `CHALICE_DEVICE` environment variable or `AppInfo::device`.
+    pub unsafe fn select_device(
+        &self,
+        surface: Option<&Surface>,
+    ) -> DeviceResult<PhysicalDeviceInfo> {
+        let devices = self.enumerate_devices(surface)?;
+        for device in devices.iter() {
+            info!(
+                "device {}: {} ({:?}, {} MiB, queue families: {:?}, usable: {})",
+                device.index,
+                device.name,
+                device.ty,
+                device.device_local_memory >> 20,
+                device.queue_family_indices,
+                device.is_usable(),
+            );
+        }
+
+        let selector = std::env::var(DEVICE_ENV_VAR)
+            .ok()
+            .or_else(|| self.app_info.device.clone());
+        let device = if let Some(selector) = selector {
+            debug!("selecting device matching {:?}", selector);
+            devices
+                .into_iter()
+                .filter(|device| device.is_usable())
+                .find(|device| device.matches(&selector))
+                .ok_or_else(|| format!("no usable device matching {:?}", selector))?
+        } else {
+            devices
+                .into_iter()
+                .filter(|device| device.is_usable())
+                .max_by_key(|device| device.score())
+                .ok_or_else(|| err_msg!("no usable graphics device"))?
+        };
+        info!("selected device {}: {}", device.index, device.name);
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(queue_flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags,
+            queue_count: 1,
+            ..Default::default()
+        }
+    }
+
+    fn device_info(ty: vk::PhysicalDeviceType, memory: vk::DeviceSize) -> PhysicalDeviceInfo {
+        PhysicalDeviceInfo {
+            pdev: vk::null(),
+            index: 0,
+            name: "Test Device".into(),
+            ty,
+            device_local_memory: memory,
+            queue_families: Vec::new(),
+            queue_family_indices: None,
+            supports_present: true,
+        }
+    }
+
+    #[test]
+    fn find_queue_families() {
+        use vk::QueueFlags as F;
+        let families = [
+            family(F::TRANSFER_BIT),
+            family(F::GRAPHICS_BIT | F::COMPUTE_BIT | F::TRANSFER_BIT),
+            family(F::COMPUTE_BIT | F::TRANSFER_BIT),
+        ];
+        let indices = QueueFamilyIndices::find(&families).unwrap();
+        assert_eq!(
+            indices,
+            QueueFamilyIndices {
+                graphics: 1,
+                compute: Some(2),
+                transfer: Some(0),
+            }
+        );
+        assert_eq!(indices.iter().collect::<Vec<_>>(), [1, 2, 0]);
+
+        let families = [family(F::GRAPHICS_BIT | F::COMPUTE_BIT | F::TRANSFER_BIT)];
+        let indices = QueueFamilyIndices::find(&families).unwrap();
+        assert_eq!((indices.compute, indices.transfer), (None, None));
+
+        assert!(QueueFamilyIndices::find(&[family(F::COMPUTE_BIT)]).is_none());
+    }
+
+    #[test]
+    fn score_and_match() {
+        use vk::PhysicalDeviceType as T;
+        let discrete = device_info(T::DISCRETE_GPU, 1 << 30);
+        let integrated = device_info(T::INTEGRATED_GPU, 4 << 30);
+        let cpu = device_info(T::CPU, 8 << 30);
+        assert!(discrete.score() > integrated.score());
+        assert!(integrated.score() > cpu.score());
+        assert!(integrated.score() > device_info(T::INTEGRATED_GPU, 1 << 30).score());
+
+        assert!(discrete.matches("0"));
+        assert!(!discrete.matches("1"));
+        assert!(discrete.matches("test dev"));
+        assert!(!discrete.matches("llvmpipe"));
+    }
+}
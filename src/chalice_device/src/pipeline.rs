@@ -28,11 +28,13 @@ pub struct PipelineLayout {
     device: Arc<Device>,
     inner: vk::PipelineLayout,
     set_layouts: SmallVec<Arc<DescriptorSetLayout>, 4>,
+    push_constants: SmallVec<vk::PushConstantRange, 2>,
 }
 
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
 pub struct PipelineLayoutDesc {
     pub set_layouts: SmallVec<Arc<DescriptorSetLayout>, 4>,
+    pub push_constants: SmallVec<vk::PushConstantRange, 2>,
 }
 
 pub type ShaderStageMap = PartialEnumMap<ShaderStage, Arc<ShaderSpec>>;
@@ -46,6 +48,14 @@ pub struct GraphicsPipeline {
     desc: GraphicsPipelineDesc,
 }
 
+#[derive(Debug)]
+pub struct ComputePipeline {
+    device: Arc<Device>,
+    inner: vk::Pipeline,
+    layout: Arc<PipelineLayout>,
+    desc: ComputePipelineDesc,
+}
+
 #[derive(Clone, Copy, Debug, Derivative, Enum, Eq, Hash, PartialEq)]
 #[derivative(Default)]
 pub enum CullMode {
@@ -56,6 +66,21 @@ pub enum CullMode {
     FrontAndBack = 3,
 }
 
+/// Stencil operations for one face. The reference value and masks are
+/// dynamic state set via `CmdBuffer`.
+#[derive(Clone, Copy, Debug, Derivative, Eq, Hash, PartialEq)]
+#[derivative(Default)]
+pub struct StencilOps {
+    #[derivative(Default(value = "vk::StencilOp::KEEP"))]
+    pub fail_op: vk::StencilOp,
+    #[derivative(Default(value = "vk::StencilOp::KEEP"))]
+    pub pass_op: vk::StencilOp,
+    #[derivative(Default(value = "vk::StencilOp::KEEP"))]
+    pub depth_fail_op: vk::StencilOp,
+    #[derivative(Default(value = "vk::CompareOp::ALWAYS"))]
+    pub compare_op: vk::CompareOp,
+}
+
 #[derive(Clone, Debug, Derivative)]
 #[derivative(Hash, PartialEq)]
 pub struct GraphicsPipelineDesc {
@@ -63,20 +88,42 @@ pub struct GraphicsPipelineDesc {
     pub layout: PipelineLayoutDesc,
     pub vertex_layout: VertexInputLayout,
     pub stages: ShaderStageMap,
+    /// The number of control points per patch. Must be nonzero if and
+    /// only if tessellation stages are present.
+    pub patch_control_points: u32,
     pub cull_mode: CullMode,
     pub wireframe: bool,
     pub depth_test: bool,
     pub depth_write: bool,
     pub depth_cmp_op: vk::CompareOp,
     pub depth_bias: bool,
-    // We have no use case yet for multiple color blending states.
-    pub blend_state: vk::PipelineColorBlendAttachmentState,
+    /// Clamps fragment depth to the viewport depth range instead of
+    /// clipping.
+    pub depth_clamp: bool,
+    pub depth_bounds_test: bool,
+    /// Min and max depth bounds used if `depth_bounds_test` is set.
+    #[derivative(Hash(hash_with = "byte_hash"))]
+    #[derivative(PartialEq(compare_with = "byte_eq"))]
+    pub depth_bounds: [f32; 2],
+    pub stencil_test: bool,
+    pub stencil_front: StencilOps,
+    pub stencil_back: StencilOps,
+    /// Blend state for each color attachment of the subpass.
+    pub blend_states: SmallVec<vk::PipelineColorBlendAttachmentState, 4>,
+    /// If set, replaces blending with a logic op for all attachments.
+    pub logic_op: Option<vk::LogicOp>,
     #[derivative(Hash(hash_with = "byte_hash"))]
     #[derivative(PartialEq(compare_with = "byte_eq"))]
     pub blend_consts: [f32; 4],
 }
 impl Eq for GraphicsPipelineDesc {}
 
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ComputePipelineDesc {
+    pub layout: PipelineLayoutDesc,
+    pub stage: Arc<ShaderSpec>,
+}
+
 impl Drop for PipelineLayout {
     fn drop(&mut self) {
         let dt = &*self.device.table;
@@ -89,15 +136,21 @@ impl Drop for PipelineLayout {
 impl PipelineLayout {
     pub fn new(device: Arc<Device>, desc: PipelineLayoutDesc) -> Self {
         let dt = &*device.table;
-        let set_layouts = desc.set_layouts;
+        let PipelineLayoutDesc {
+            set_layouts,
+            push_constants,
+        } = desc;
         let cap = device.limits().max_bound_descriptor_sets as usize;
         assert!(set_layouts.len() < cap);
+        validate_push_constant_ranges(&device, &push_constants);
 
         let vk_set_layouts: SmallVec<_, 4> =
             set_layouts.iter().map(|layout| layout.inner()).collect();
         let create_info = vk::PipelineLayoutCreateInfo {
             set_layout_count: vk_set_layouts.len() as _,
             p_set_layouts: vk_set_layouts.as_ptr(),
+            push_constant_range_count: push_constants.len() as _,
+            p_push_constant_ranges: push_constants.as_ptr(),
             ..Default::default()
         };
         let mut inner = vk::null();
@@ -111,6 +164,7 @@ impl PipelineLayout {
             device,
             inner,
             set_layouts,
+            push_constants,
         }
     }
 
@@ -128,6 +182,97 @@ impl PipelineLayout {
     pub fn set_layouts(&self) -> &[Arc<DescriptorSetLayout>] {
         &self.set_layouts
     }
+
+    #[inline]
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.push_constants
+    }
+
+    /// Checks that `stages` may update the bytes `offset..offset +
+    /// size` as per the rules for vkCmdPushConstants.
+    pub fn validate_push_constants(&self, stages: vk::ShaderStageFlags, offset: u32, size: u32) {
+        assert_eq!(offset % 4, 0);
+        assert_eq!(size % 4, 0);
+        assert!(size > 0);
+        let end = offset + size;
+        for range in self.push_constants.iter() {
+            let overlaps = range.offset < end && offset < range.offset + range.size;
+            if overlaps {
+                assert!(
+                    stages.contains(range.stage_flags),
+                    "push constant range {:?} not fully covered by {:?}",
+                    range,
+                    stages,
+                );
+            }
+        }
+        self.validate_coverage(stages, offset, end);
+    }
+
+    /// Checks that every stage in `stages` has a push constant range
+    /// containing `offset..end`.
+    fn validate_coverage(&self, stages: vk::ShaderStageFlags, offset: u32, end: u32) {
+        use ShaderStage::*;
+        for &stage in [Vertex, TessControl, TessEval, Geometry, Fragment, Compute].iter() {
+            let flag: vk::ShaderStageFlags = stage.into();
+            if !stages.contains(flag) {
+                continue;
+            }
+            assert!(
+                self.push_constants.iter().any(|range| {
+                    range.stage_flags.contains(flag)
+                        && range.offset <= offset
+                        && end <= range.offset + range.size
+                }),
+                "no push constant range for {:?} at {}..{}",
+                stage,
+                offset,
+                end,
+            );
+        }
+    }
+
+    /// Checks that the push constant block used by a shader is declared
+    /// by this layout.
+    fn validate_shader(&self, spec: &ShaderSpec) {
+        if let Some(range) = spec.shader().push_constant_range() {
+            self.validate_coverage(range.stage_flags, range.offset, range.offset + range.size);
+        }
+    }
+}
+
+fn validate_push_constant_ranges(device: &Device, ranges: &[vk::PushConstantRange]) {
+    let max_size = device.limits().max_push_constants_size;
+    let mut seen = vk::ShaderStageFlags::empty();
+    for range in ranges.iter() {
+        assert_eq!(range.offset % 4, 0);
+        assert_eq!(range.size % 4, 0);
+        assert!(range.size > 0);
+        assert!(range.offset + range.size <= max_size);
+        assert!(!range.stage_flags.is_empty());
+        assert!(!seen.intersects(range.stage_flags), "{:?}", ranges);
+        seen |= range.stage_flags;
+    }
+}
+
+impl PipelineLayoutDesc {
+    /// Adds the push constant block declared by a shader, merging it
+    /// with an identical range if another stage already declared one.
+    pub fn add_push_constants(&mut self, shader: &Shader) {
+        let range = match shader.push_constant_range() {
+            Some(range) => range,
+            None => return,
+        };
+        let existing = self
+            .push_constants
+            .iter_mut()
+            .find(|other| (other.offset, other.size) == (range.offset, range.size));
+        if let Some(other) = existing {
+            other.stage_flags |= range.stage_flags;
+        } else {
+            self.push_constants.push(range);
+        }
+    }
 }
 
 impl Drop for GraphicsPipeline {
@@ -140,8 +285,12 @@ impl Drop for GraphicsPipeline {
 }
 
 impl GraphicsPipeline {
-    unsafe fn new(layout: Arc<PipelineLayout>, desc: GraphicsPipelineDesc) -> Self {
-        create_graphics_pipeline(layout, desc)
+    unsafe fn new(
+        layout: Arc<PipelineLayout>,
+        desc: GraphicsPipelineDesc,
+        cache: vk::PipelineCache,
+    ) -> Self {
+        create_graphics_pipeline(layout, desc, cache)
     }
 
     #[inline]
@@ -185,31 +334,34 @@ impl GraphicsPipeline {
     }
 }
 
-fn color_write_mask_all() -> vk::ColorComponentFlags {
-    vk::ColorComponentFlags::R_BIT
-        | vk::ColorComponentFlags::G_BIT
-        | vk::ColorComponentFlags::B_BIT
-        | vk::ColorComponentFlags::A_BIT
-}
-
 impl GraphicsPipelineDesc {
+    /// Creates a pipeline description with blending disabled for all
+    /// color attachments of `subpass`.
     #[inline]
     pub fn new(subpass: Subpass) -> Self {
+        let blend_states = (0..subpass.color_attchs().len())
+            .map(|_| default_color_blend_state())
+            .collect();
         Self {
             subpass,
             layout: Default::default(),
             vertex_layout: Default::default(),
             stages: Default::default(),
+            patch_control_points: 0,
             cull_mode: Default::default(),
             wireframe: Default::default(),
             depth_test: Default::default(),
             depth_write: Default::default(),
             depth_cmp_op: Default::default(),
             depth_bias: Default::default(),
-            blend_state: vk::PipelineColorBlendAttachmentState {
-                color_write_mask: color_write_mask_all(),
-                ..Default::default()
-            },
+            depth_clamp: Default::default(),
+            depth_bounds_test: Default::default(),
+            depth_bounds: [0.0, 1.0],
+            stencil_test: Default::default(),
+            stencil_front: Default::default(),
+            stencil_back: Default::default(),
+            blend_states,
+            logic_op: None,
             blend_consts: Default::default(),
         }
     }
@@ -223,6 +375,7 @@ impl GraphicsPipelineDesc {
 unsafe fn create_graphics_pipeline(
     layout: Arc<PipelineLayout>,
     desc: GraphicsPipelineDesc,
+    cache: vk::PipelineCache,
 ) -> GraphicsPipeline {
     trace!("create_graphics_pipeline(desc: {:?})", desc);
 
@@ -240,23 +393,11 @@ unsafe fn create_graphics_pipeline(
         desc.layout.set_layouts,
     );
 
-    let have = |stage| desc.stages.contains_key(stage);
-    assert!(have(ShaderStage::Vertex));
-    assert_eq!(have(ShaderStage::TessControl), have(ShaderStage::TessEval));
-    // TODO: Tessellation
-    assert!(!have(ShaderStage::TessControl));
-    let mut stages = desc.stages.values();
-    let mut stage0 = stages.next().unwrap();
-    for stage1 in stages {
-        assert_eq!(
-            stage0.shader().outputs(),
-            stage1.shader().inputs(),
-            "{:?}, {:?}",
-            stage0,
-            stage1,
-        );
-        stage0 = stage1;
+    for spec in desc.stages.values() {
+        layout.validate_shader(spec);
     }
+
+    validate_stages(&device, &desc);
     let stages: Vec<_> = desc
         .stages
         .iter()
@@ -281,12 +422,12 @@ unsafe fn create_graphics_pipeline(
         assert_lt!(attr[0].location, attr[1].location);
     }
 
-    for &location in vertex_shader.inputs().iter() {
+    for input in vertex_shader.inputs().iter() {
         // TODO: Check that format is compatible with input.ty
         assert!(vertex_layout
             .attributes
             .iter()
-            .any(|attr| attr.location == location));
+            .any(|attr| attr.location == input.location));
     }
 
     let vertex_input = vk::PipelineVertexInputStateCreateInfo {
@@ -302,8 +443,11 @@ unsafe fn create_graphics_pipeline(
         ..Default::default()
     };
 
-    assert!(!desc.stages.contains_key(ShaderStage::TessControl));
-    assert!(!desc.stages.contains_key(ShaderStage::TessEval));
+    let tessellated = desc.stages.contains_key(ShaderStage::TessControl);
+    let tessellation = vk::PipelineTessellationStateCreateInfo {
+        patch_control_points: desc.patch_control_points,
+        ..Default::default()
+    };
 
     let viewport = vk::PipelineViewportStateCreateInfo {
         viewport_count: 1,
@@ -320,6 +464,7 @@ unsafe fn create_graphics_pipeline(
         },
         cull_mode: desc.cull_mode.into(),
         front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        depth_clamp_enable: bool32(desc.depth_clamp),
         depth_bias_enable: bool32(desc.depth_bias),
         // Depth bias parameters set dynamically
         line_width: 1.0,
@@ -331,18 +476,26 @@ unsafe fn create_graphics_pipeline(
         ..Default::default()
     };
 
+    validate_depth_stencil_state(&device, &desc);
     let depth = vk::PipelineDepthStencilStateCreateInfo {
         depth_test_enable: bool32(desc.depth_test),
         depth_write_enable: bool32(desc.depth_write),
         depth_compare_op: desc.depth_cmp_op,
+        depth_bounds_test_enable: bool32(desc.depth_bounds_test),
+        stencil_test_enable: bool32(desc.stencil_test),
+        front: desc.stencil_front.into(),
+        back: desc.stencil_back.into(),
+        min_depth_bounds: desc.depth_bounds[0],
+        max_depth_bounds: desc.depth_bounds[1],
         ..Default::default()
     };
 
-    let attachment: vk::PipelineColorBlendAttachmentState = desc.blend_state;
-    let attachments = vec![attachment; desc.subpass.color_attchs().len()];
+    validate_blend_states(&device, &desc);
     let color_blend = vk::PipelineColorBlendStateCreateInfo {
-        attachment_count: attachments.len() as _,
-        p_attachments: attachments.as_ptr(),
+        logic_op_enable: bool32(desc.logic_op.is_some()),
+        logic_op: desc.logic_op.unwrap_or(vk::LogicOp::COPY),
+        attachment_count: desc.blend_states.len() as _,
+        p_attachments: desc.blend_states.as_ptr(),
         blend_constants: desc.blend_consts,
         ..Default::default()
     };
@@ -351,6 +504,9 @@ unsafe fn create_graphics_pipeline(
         vk::DynamicState::VIEWPORT,
         vk::DynamicState::SCISSOR,
         vk::DynamicState::DEPTH_BIAS,
+        vk::DynamicState::STENCIL_COMPARE_MASK,
+        vk::DynamicState::STENCIL_WRITE_MASK,
+        vk::DynamicState::STENCIL_REFERENCE,
     ];
     let dynamic = vk::PipelineDynamicStateCreateInfo {
         dynamic_state_count: dynamic_states.len() as _,
@@ -363,7 +519,11 @@ unsafe fn create_graphics_pipeline(
         p_stages: stages.as_ptr(),
         p_vertex_input_state: &vertex_input,
         p_input_assembly_state: &input_assembly,
-        p_tessellation_state: ptr::null(),
+        p_tessellation_state: if tessellated {
+            &tessellation
+        } else {
+            ptr::null()
+        },
         p_viewport_state: &viewport,
         p_rasterization_state: &rasterization,
         p_multisample_state: &multisample,
@@ -380,7 +540,7 @@ unsafe fn create_graphics_pipeline(
     let dt = &*device.table;
     let mut pipeline = vk::null();
     dt.create_graphics_pipelines(
-        vk::null(),
+        cache,
         create_infos.len() as _,
         create_infos.as_ptr(),
         ptr::null(),
@@ -395,6 +555,271 @@ unsafe fn create_graphics_pipeline(
     }
 }
 
+fn validate_stages(device: &Device, desc: &GraphicsPipelineDesc) {
+    let have = |stage| desc.stages.contains_key(stage);
+    assert!(have(ShaderStage::Vertex));
+    assert!(!have(ShaderStage::Compute));
+
+    let tessellated = have(ShaderStage::TessControl);
+    assert_eq!(tessellated, have(ShaderStage::TessEval));
+    let topology = desc.vertex_layout.topology;
+    if tessellated {
+        assert_eq!(device.features().tessellation_shader, vk::TRUE);
+        assert_eq!(topology, PrimitiveTopology::PatchList);
+        let max_patch_size = device.limits().max_tessellation_patch_size;
+        assert!(
+            0 < desc.patch_control_points && desc.patch_control_points <= max_patch_size,
+            "invalid patch size: {}",
+            desc.patch_control_points,
+        );
+    } else {
+        assert_ne!(topology, PrimitiveTopology::PatchList);
+        assert_eq!(desc.patch_control_points, 0);
+    }
+    if have(ShaderStage::Geometry) {
+        assert_eq!(device.features().geometry_shader, vk::TRUE);
+    }
+
+    // Each stage may only consume outputs of the stage before it.
+    // Unconsumed outputs are allowed.
+    let mut stages = desc.stages.iter();
+    let (mut prev_stage, mut prev) = stages.next().unwrap();
+    for (stage, spec) in stages {
+        for input in spec.shader().inputs().iter() {
+            let output = prev
+                .shader()
+                .outputs()
+                .iter()
+                .find(|output| output.location == input.location)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{:?} input at location {} not written by {:?} stage",
+                        stage, input.location, prev_stage,
+                    )
+                });
+            assert!(
+                interface_types_match((prev_stage, output), (stage, input)),
+                "{:?} input at location {} has type {:?} but {:?} stage writes {:?}",
+                stage,
+                input.location,
+                input.ty,
+                prev_stage,
+                output.ty,
+            );
+        }
+        prev_stage = stage;
+        prev = spec;
+    }
+}
+
+/// Checks that an output of one stage may be consumed by an input of
+/// the next stage.
+fn interface_types_match(
+    (out_stage, output): (ShaderStage, &ShaderLocation),
+    (in_stage, input): (ShaderStage, &ShaderLocation),
+) -> bool {
+    // Tessellation control outputs and tessellation and geometry
+    // inputs have an extra array level indexed by vertex.
+    let per_vertex = |stage, is_output| match stage {
+        ShaderStage::TessControl => true,
+        ShaderStage::TessEval | ShaderStage::Geometry => !is_output,
+        _ => false,
+    };
+    let element = |var: &ShaderLocation, arrayed: bool| {
+        let ty = var.ty.as_ref()?;
+        let dims = if arrayed {
+            ty.dims.get(1..).unwrap_or(&[])
+        } else {
+            &ty.dims[..]
+        };
+        Some((ty.scalar, ty.components, dims))
+    };
+    element(output, per_vertex(out_stage, true)) == element(input, per_vertex(in_stage, false))
+}
+
+fn validate_depth_stencil_state(device: &Device, desc: &GraphicsPipelineDesc) {
+    if desc.depth_clamp {
+        assert_eq!(device.features().depth_clamp, vk::TRUE);
+    }
+    if desc.depth_bounds_test {
+        assert_eq!(device.features().depth_bounds, vk::TRUE);
+        let [min, max] = desc.depth_bounds;
+        assert!(
+            0.0 <= min && min <= max && max <= 1.0,
+            "invalid depth bounds: {:?}",
+            desc.depth_bounds,
+        );
+    }
+    if desc.stencil_test {
+        let attch = desc
+            .subpass
+            .depth_stencil_attch()
+            .expect("stencil test requires a depth/stencil attachment");
+        let format = desc.subpass.pass().attachments()[attch.attachment as usize].format;
+        assert!(
+            format.aspects().contains(vk::ImageAspectFlags::STENCIL_BIT),
+            "stencil test requires a stencil format; got {:?}",
+            format,
+        );
+    }
+}
+
+fn validate_blend_states(device: &Device, desc: &GraphicsPipelineDesc) {
+    let states = &desc.blend_states;
+    let color_attchs = desc.subpass.color_attchs();
+    assert_eq!(
+        states.len(),
+        color_attchs.len(),
+        "expected one blend state per color attachment",
+    );
+
+    let independent = states.windows(2).any(|pair| pair[0] != pair[1]);
+    if independent {
+        assert_eq!(device.features().independent_blend, vk::TRUE);
+    }
+    if desc.logic_op.is_some() {
+        assert_eq!(device.features().logic_op, vk::TRUE);
+    }
+
+    let attachments = desc.subpass.pass().attachments();
+    for (state, attch) in states.iter().zip(color_attchs.iter()) {
+        if state.blend_enable == vk::FALSE || attch.attachment == vk::ATTACHMENT_UNUSED {
+            continue;
+        }
+        let format = attachments[attch.attachment as usize].format;
+        let features = device.format_properties(format).optimal_tiling_features;
+        assert!(
+            features.contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND_BIT),
+            "blending not supported for attachment {} ({:?})",
+            attch.attachment,
+            format,
+        );
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        let dt = &*self.device.table;
+        unsafe {
+            dt.destroy_pipeline(self.inner, ptr::null());
+        }
+    }
+}
+
+impl ComputePipeline {
+    unsafe fn new(
+        layout: Arc<PipelineLayout>,
+        desc: ComputePipelineDesc,
+        cache: vk::PipelineCache,
+    ) -> Self {
+        create_compute_pipeline(layout, desc, cache)
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    #[inline]
+    pub fn inner(&self) -> vk::Pipeline {
+        self.inner
+    }
+
+    #[inline]
+    pub fn desc(&self) -> &ComputePipelineDesc {
+        &self.desc
+    }
+
+    #[inline]
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        &self.layout
+    }
+
+    #[inline]
+    pub fn stage(&self) -> &Arc<ShaderSpec> {
+        &self.desc.stage
+    }
+}
+
+impl ComputePipelineDesc {
+    #[inline]
+    pub fn new(stage: Arc<ShaderSpec>) -> Self {
+        Self {
+            layout: Default::default(),
+            stage,
+        }
+    }
+}
+
+unsafe fn create_compute_pipeline(
+    layout: Arc<PipelineLayout>,
+    desc: ComputePipelineDesc,
+    cache: vk::PipelineCache,
+) -> ComputePipeline {
+    trace!("create_compute_pipeline(desc: {:?})", desc);
+
+    let device = Arc::clone(&layout.device);
+
+    assert!(
+        layout
+            .set_layouts
+            .iter()
+            .zip(desc.layout.set_layouts.iter())
+            .all(|(layout, desc)| Arc::ptr_eq(layout, desc)),
+        "layout: {:?}, desc: {:?}",
+        layout.set_layouts,
+        desc.layout.set_layouts,
+    );
+
+    let shader = desc.stage.shader();
+    assert_eq!(shader.stage(), ShaderStage::Compute);
+    layout.validate_shader(&desc.stage);
+    let stage = vk::PipelineShaderStageCreateInfo {
+        module: shader.module(),
+        stage: ShaderStage::Compute.into(),
+        p_name: shader.entry_cstr().as_ptr(),
+        p_specialization_info: desc.stage.spec_info(),
+        ..Default::default()
+    };
+
+    let create_info = vk::ComputePipelineCreateInfo {
+        stage,
+        layout: layout.inner(),
+        ..Default::default()
+    };
+    let create_infos = std::slice::from_ref(&create_info);
+
+    let dt = &*device.table;
+    let mut pipeline = vk::null();
+    dt.create_compute_pipelines(
+        cache,
+        create_infos.len() as _,
+        create_infos.as_ptr(),
+        ptr::null(),
+        &mut pipeline,
+    );
+
+    ComputePipeline {
+        device,
+        inner: pipeline,
+        layout,
+        desc,
+    }
+}
+
+impl From<StencilOps> for vk::StencilOpState {
+    fn from(ops: StencilOps) -> Self {
+        Self {
+            fail_op: ops.fail_op,
+            pass_op: ops.pass_op,
+            depth_fail_op: ops.depth_fail_op,
+            compare_op: ops.compare_op,
+            // Set dynamically
+            ..Default::default()
+        }
+    }
+}
+
 impl From<CullMode> for vk::CullModeFlags {
     fn from(mode: CullMode) -> Self {
         match mode {
@@ -415,8 +840,10 @@ pub struct PipelineLayoutCache {
 /// Manages the creation, destruction, and lifetime of pipelines.
 #[derive(Debug)]
 pub struct PipelineCache {
+    binary: PipelineBinaryCache,
     layouts: PipelineLayoutCache,
     gfx: GraphicsPipelineCache,
+    compute: ComputePipelineCache,
 }
 
 macro_rules! pipeline_cache {
@@ -443,11 +870,16 @@ macro_rules! pipeline_cache {
 
             unsafe fn get_or_create_committed(
                 &mut self,
+                binary: &PipelineBinaryCache,
                 layout: &Arc<PipelineLayout>,
                 desc: &$desc,
             ) -> &Arc<$pipeline> {
                 self.inner.get_or_insert_committed_with(desc, || {
-                    Arc::new($pipeline::new(Arc::clone(layout), desc.clone()))
+                    Arc::new($pipeline::new(
+                        Arc::clone(layout),
+                        desc.clone(),
+                        binary.inner(),
+                    ))
                 })
             }
 
@@ -457,11 +889,16 @@ macro_rules! pipeline_cache {
 
             unsafe fn get_or_create(
                 &self,
+                binary: &PipelineBinaryCache,
                 layout: &Arc<PipelineLayout>,
                 desc: &$desc,
             ) -> Cow<Arc<$pipeline>> {
                 self.inner.get_or_insert_with(desc, || {
-                    Arc::new($pipeline::new(Arc::clone(layout), desc.clone()))
+                    Arc::new($pipeline::new(
+                        Arc::clone(layout),
+                        desc.clone(),
+                        binary.inner(),
+                    ))
                 })
             }
         }
@@ -474,6 +911,12 @@ pipeline_cache! {
     desc: GraphicsPipelineDesc,
 }
 
+pipeline_cache! {
+    name: ComputePipelineCache,
+    pipeline: ComputePipeline,
+    desc: ComputePipelineDesc,
+}
+
 impl PipelineLayoutCache {
     pub fn new(device: Arc<Device>) -> Self {
         Self {
@@ -507,15 +950,37 @@ impl PipelineLayoutCache {
 
 impl PipelineCache {
     pub fn new(device: &Arc<Device>) -> Self {
+        let mut binary = PipelineBinaryCache::new(Arc::clone(device));
+        set_name!(binary);
+        Self::with_binary_cache(binary)
+    }
+
+    /// Creates a pipeline cache backed by a pre-populated binary cache,
+    /// e.g. one loaded from disk.
+    pub fn with_binary_cache(binary: PipelineBinaryCache) -> Self {
+        let layouts = PipelineLayoutCache::new(Arc::clone(binary.device()));
         Self {
-            layouts: PipelineLayoutCache::new(Arc::clone(device)),
+            layouts,
             gfx: GraphicsPipelineCache::new(),
+            compute: ComputePipelineCache::new(),
+            binary,
         }
     }
 
+    #[inline]
+    pub fn binary_cache(&self) -> &PipelineBinaryCache {
+        &self.binary
+    }
+
+    #[inline]
+    pub fn binary_cache_mut(&mut self) -> &mut PipelineBinaryCache {
+        &mut self.binary
+    }
+
     pub fn commit(&mut self) {
         self.layouts.commit();
         self.gfx.commit();
+        self.compute.commit();
     }
 
     pub fn get_committed_layout(&self, desc: &PipelineLayoutDesc) -> Option<&Arc<PipelineLayout>> {
@@ -538,7 +1003,8 @@ impl PipelineCache {
         desc: &GraphicsPipelineDesc,
     ) -> &Arc<GraphicsPipeline> {
         let layout = self.layouts.get_or_create_committed(&desc.layout);
-        self.gfx.get_or_create_committed(&layout, desc)
+        self.gfx
+            .get_or_create_committed(&self.binary, &layout, desc)
     }
 
     pub fn get_or_create_layout(&self, desc: &PipelineLayoutDesc) -> Cow<Arc<PipelineLayout>> {
@@ -550,7 +1016,31 @@ impl PipelineCache {
         desc: &GraphicsPipelineDesc,
     ) -> Cow<Arc<GraphicsPipeline>> {
         let layout = self.layouts.get_or_create(&desc.layout);
-        self.gfx.get_or_create(&layout, desc)
+        self.gfx.get_or_create(&self.binary, &layout, desc)
+    }
+
+    pub fn get_committed_compute(
+        &self,
+        desc: &ComputePipelineDesc,
+    ) -> Option<&Arc<ComputePipeline>> {
+        self.compute.get_committed(desc)
+    }
+
+    pub unsafe fn get_or_create_committed_compute(
+        &mut self,
+        desc: &ComputePipelineDesc,
+    ) -> &Arc<ComputePipeline> {
+        let layout = self.layouts.get_or_create_committed(&desc.layout);
+        self.compute
+            .get_or_create_committed(&self.binary, &layout, desc)
+    }
+
+    pub unsafe fn get_or_create_compute(
+        &self,
+        desc: &ComputePipelineDesc,
+    ) -> Cow<Arc<ComputePipeline>> {
+        let layout = self.layouts.get_or_create(&desc.layout);
+        self.compute.get_or_create(&self.binary, &layout, desc)
     }
 }
 
@@ -575,7 +1065,233 @@ mod tests {
             desc.layout.clone(),
         ));
         unsafe {
-            let _pipeline = create_graphics_pipeline(layout, desc);
+            let _pipeline = create_graphics_pipeline(layout, desc, vk::null());
+        }
+    }
+
+    fn insert_stage(desc: &mut GraphicsPipelineDesc, shader: &Arc<Shader>) {
+        desc.stages
+            .insert(shader.stage(), Arc::new(Arc::clone(shader).into()));
+    }
+
+    #[test]
+    fn tessellation() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        if device.features().tessellation_shader != vk::TRUE {
+            return;
+        }
+        let resources = TestResources::new(device);
+        let pass = TrivialPass::new(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass.clone());
+        trivial.init_pipe_desc(&mut desc);
+        insert_stage(&mut desc, &resources.shaders.trivial_tesc);
+        insert_stage(&mut desc, &resources.shaders.trivial_tese);
+        desc.vertex_layout.topology = PrimitiveTopology::PatchList;
+        desc.patch_control_points = 3;
+        unsafe {
+            let _pipeline = pipelines.get_or_create_gfx(&desc);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn tessellation_requires_patch_list() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let pass = TrivialPass::new(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass.clone());
+        trivial.init_pipe_desc(&mut desc);
+        insert_stage(&mut desc, &resources.shaders.trivial_tesc);
+        insert_stage(&mut desc, &resources.shaders.trivial_tese);
+        desc.patch_control_points = 3;
+        unsafe {
+            let _pipeline = pipelines.get_or_create_gfx(&desc);
+        }
+    }
+
+    #[test]
+    fn geometry() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        if device.features().geometry_shader != vk::TRUE {
+            return;
+        }
+        let resources = TestResources::new(device);
+        let pass = TrivialPass::new(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass.clone());
+        trivial.init_pipe_desc(&mut desc);
+        insert_stage(&mut desc, &resources.shaders.trivial_geom);
+        unsafe {
+            let _pipeline = pipelines.get_or_create_gfx(&desc);
+        }
+    }
+
+    fn var(scalar: spv::ScalarType, components: u32, dims: &[u32]) -> ShaderLocation {
+        ShaderLocation {
+            location: 0,
+            ty: Some(spv::InterfaceType {
+                scalar,
+                components,
+                dims: dims.to_vec(),
+            }),
+        }
+    }
+
+    #[test]
+    fn interface_types() {
+        use spv::ScalarType::*;
+        use ShaderStage::*;
+        let matches = interface_types_match;
+
+        let vec4 = var(Float(32), 4, &[]);
+        assert!(matches((Vertex, &vec4), (Fragment, &vec4)));
+        // Mismatched component count or base type
+        assert!(!matches(
+            (Vertex, &vec4),
+            (Fragment, &var(Float(32), 1, &[]))
+        ));
+        assert!(!matches((Vertex, &vec4), (Fragment, &var(Int(32), 4, &[]))));
+        assert!(!matches(
+            (Vertex, &var(Int(32), 2, &[])),
+            (Fragment, &var(Uint(32), 2, &[])),
+        ));
+        assert!(!matches(
+            (Vertex, &vec4),
+            (Fragment, &var(Float(32), 4, &[2]))
+        ));
+        let opaque = ShaderLocation {
+            location: 0,
+            ty: None,
+        };
+        assert!(!matches((Vertex, &vec4), (Fragment, &opaque)));
+
+        // Per-vertex arrays
+        let vec4_array = var(Float(32), 4, &[32]);
+        assert!(matches((Vertex, &vec4), (TessControl, &vec4_array)));
+        assert!(matches((TessControl, &vec4_array), (TessEval, &vec4_array)));
+        assert!(matches(
+            (TessEval, &vec4),
+            (Geometry, &var(Float(32), 4, &[3]))
+        ));
+        assert!(matches((Geometry, &vec4), (Fragment, &vec4)));
+        assert!(!matches((Vertex, &vec4_array), (Fragment, &vec4)));
+        assert!(!matches(
+            (Vertex, &vec4),
+            (TessControl, &var(Float(32), 3, &[32])),
+        ));
+    }
+
+    #[test]
+    fn multiple_blend_states() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let pass = create_test_pass(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        // G-buffer subpass: normals and albedo
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass(0));
+        trivial.init_pipe_desc(&mut desc);
+        assert_eq!(desc.blend_states.len(), 2);
+        if device.features().independent_blend == vk::TRUE {
+            desc.blend_states[1].color_write_mask =
+                vk::ColorComponentFlags::R_BIT | vk::ColorComponentFlags::G_BIT;
+        }
+        unsafe {
+            let _pipeline = pipelines.get_or_create_gfx(&desc);
+        }
+
+        if device.features().logic_op == vk::TRUE {
+            desc.logic_op = Some(vk::LogicOp::XOR);
+            unsafe {
+                let _pipeline = pipelines.get_or_create_gfx(&desc);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn blend_state_count_mismatch() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let pass = create_test_pass(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass(0));
+        trivial.init_pipe_desc(&mut desc);
+        desc.blend_states.push(default_color_blend_state());
+        unsafe {
+            let _pipeline = pipelines.get_or_create_gfx(&desc);
+        }
+    }
+
+    #[test]
+    fn depth_stencil_state() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let pass = create_test_pass(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass(0));
+        trivial.init_pipe_desc(&mut desc);
+        let plain = desc.clone();
+        desc.depth_test = true;
+        desc.stencil_test = true;
+        desc.stencil_front = StencilOps {
+            pass_op: vk::StencilOp::REPLACE,
+            ..Default::default()
+        };
+        desc.stencil_back = StencilOps {
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            ..Default::default()
+        };
+        if device.features().depth_bounds == vk::TRUE {
+            desc.depth_bounds_test = true;
+            desc.depth_bounds = [0.25, 0.75];
+        }
+        if device.features().depth_clamp == vk::TRUE {
+            desc.depth_clamp = true;
+        }
+        assert_ne!(desc, plain);
+
+        unsafe {
+            let pipe0 = pipelines.get_or_create_gfx(&plain).into_owned();
+            let pipe1 = pipelines.get_or_create_gfx(&desc).into_owned();
+            assert!(!Arc::ptr_eq(&pipe0, &pipe1));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn stencil_test_without_stencil_attachment() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let pass = TrivialPass::new(device);
+        let trivial = TrivialRenderer::new(&resources);
+        let pipelines = PipelineCache::new(device);
+
+        let mut desc = GraphicsPipelineDesc::new(pass.subpass.clone());
+        trivial.init_pipe_desc(&mut desc);
+        desc.stencil_test = true;
+        unsafe {
+            let _pipeline = pipelines.get_or_create_gfx(&desc);
         }
     }
 
@@ -605,4 +1321,30 @@ mod tests {
             ));
         }
     }
+
+    #[test]
+    fn compute_cache() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let resources = TestResources::new(device);
+        let trivial = TrivialCompute::new(&resources);
+        let mut cache = PipelineCache::new(device);
+
+        unsafe {
+            let desc = trivial.pipe_desc();
+            let pipe0 = cache.get_or_create_compute(&desc).into_owned();
+            assert_eq!(pipe0.stage().shader().stage(), ShaderStage::Compute);
+
+            cache.commit();
+
+            assert!(Arc::ptr_eq(
+                cache.get_committed_compute(&desc).unwrap(),
+                &pipe0,
+            ));
+            assert!(Arc::ptr_eq(
+                cache.get_or_create_committed_compute(&desc),
+                &pipe0,
+            ));
+        }
+    }
 }
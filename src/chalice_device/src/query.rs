@@ -0,0 +1,256 @@
+use std::ptr;
+use std::sync::Arc;
+
+use log::trace;
+
+use crate::*;
+
+wrap_vk_enum! {
+    pub enum QueryType {
+        Occlusion = OCCLUSION,
+        PipelineStatistics = PIPELINE_STATISTICS,
+        Timestamp = TIMESTAMP,
+    }
+}
+
+#[derive(Debug)]
+pub struct QueryPool {
+    device: Arc<Device>,
+    inner: vk::QueryPool,
+    ty: QueryType,
+    count: u32,
+    statistics: vk::QueryPipelineStatisticFlags,
+    name: Option<String>,
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        let dt = &*self.device.table;
+        unsafe {
+            dt.destroy_query_pool(self.inner, ptr::null());
+        }
+    }
+}
+
+impl QueryPool {
+    /// Creates a pool of occlusion or timestamp queries.
+    pub fn new(device: Arc<Device>, ty: QueryType, count: u32) -> Self {
+        assert_ne!(ty, QueryType::PipelineStatistics);
+        unsafe { Self::create(device, ty, count, Default::default()) }
+    }
+
+    /// Creates a pool of pipeline statistics queries which each record
+    /// the counters in `statistics`. Requires the
+    /// `pipeline_statistics_query` feature.
+    pub fn with_statistics(
+        device: Arc<Device>,
+        statistics: vk::QueryPipelineStatisticFlags,
+        count: u32,
+    ) -> Self {
+        assert_eq!(device.features().pipeline_statistics_query, vk::TRUE);
+        assert!(!statistics.is_empty());
+        unsafe { Self::create(device, QueryType::PipelineStatistics, count, statistics) }
+    }
+
+    unsafe fn create(
+        device: Arc<Device>,
+        ty: QueryType,
+        count: u32,
+        statistics: vk::QueryPipelineStatisticFlags,
+    ) -> Self {
+        assert!(count > 0);
+        let dt = &*device.table;
+        let create_info = vk::QueryPoolCreateInfo {
+            query_type: ty.into(),
+            query_count: count,
+            pipeline_statistics: statistics,
+            ..Default::default()
+        };
+        let mut inner = vk::null();
+        dt.create_query_pool(&create_info, ptr::null(), &mut inner)
+            .check()
+            .unwrap();
+        Self {
+            device,
+            inner,
+            ty,
+            count,
+            statistics,
+            name: None,
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    #[inline]
+    pub fn inner(&self) -> vk::QueryPool {
+        self.inner
+    }
+
+    #[inline]
+    pub fn ty(&self) -> QueryType {
+        self.ty
+    }
+
+    #[inline]
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    #[inline]
+    pub fn statistics(&self) -> vk::QueryPipelineStatisticFlags {
+        self.statistics
+    }
+
+    /// The number of values written per query.
+    #[inline]
+    pub fn values_per_query(&self) -> u32 {
+        match self.ty {
+            QueryType::PipelineStatistics => self.statistics.bits().count_ones(),
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn check_range(&self, first: u32, count: u32) {
+        assert!(
+            first + count <= self.count,
+            "query range {}..{} out of bounds for pool of size {}",
+            first,
+            first + count,
+            self.count,
+        );
+    }
+
+    /// Reads back the results of `count` queries starting at `first`.
+    /// Returns `None` if any of the queries are not yet available.
+    /// Queries must have been reset and written before calling this.
+    pub fn get_results(&self, first: u32, count: u32) -> Option<Vec<u64>> {
+        trace!(
+            "QueryPool::get_results(self: {:?}, first: {}, count: {})",
+            fmt_named(self),
+            first,
+            count,
+        );
+        self.check_range(first, count);
+        let values = self.values_per_query() as usize;
+        let mut data = vec![0u64; count as usize * values];
+        let stride = (values * std::mem::size_of::<u64>()) as vk::DeviceSize;
+        let res = unsafe {
+            self.device.table.get_query_pool_results(
+                self.inner,
+                first,
+                count,
+                data.len() * std::mem::size_of::<u64>(),
+                data.as_mut_ptr() as _,
+                stride,
+                vk::QueryResultFlags::_64_BIT,
+            )
+        };
+        match res {
+            vk::Result::SUCCESS => Some(data),
+            vk::Result::NOT_READY => None,
+            _ => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        let name: String = name.into();
+        self.name = Some(name.clone());
+        unsafe {
+            self.device.set_name(self.inner, name);
+        }
+    }
+}
+
+impl Named for QueryPool {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name.as_ref()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    unsafe fn submit_and_wait(vars: &TestVars, f: impl FnOnce(&mut CmdBuffer<'_>)) {
+        let queue = vars.gfx_queue();
+        let mut pool = CmdPool::new_transient(queue.family());
+        let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+        cmds.begin(Default::default(), None);
+        f(&mut cmds);
+        let cmds = cmds.end();
+        queue
+            .submit(&[SubmitInfo {
+                cmds: &[cmds],
+                ..Default::default()
+            }])
+            .wait(u64::MAX)
+            .unwrap();
+    }
+
+    #[test]
+    fn timestamps() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let pool = QueryPool::new(Arc::clone(device), QueryType::Timestamp, 2);
+        assert_eq!(pool.values_per_query(), 1);
+
+        unsafe {
+            submit_and_wait(&vars, |cmds| {
+                cmds.reset_query_pool(&pool, 0, 2);
+                cmds.write_timestamp(vk::PipelineStageFlags::TOP_OF_PIPE_BIT, &pool, 0);
+                cmds.write_timestamp(vk::PipelineStageFlags::BOTTOM_OF_PIPE_BIT, &pool, 1);
+            });
+        }
+
+        let results = pool.get_results(0, 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0] <= results[1]);
+    }
+
+    #[test]
+    fn pipeline_statistics() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        if device.features().pipeline_statistics_query != vk::TRUE {
+            return;
+        }
+        let resources = TestResources::new(device);
+        let pipelines = PipelineCache::new(device);
+        let compute = TrivialCompute::new(&resources);
+        let pool = QueryPool::with_statistics(
+            Arc::clone(device),
+            vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS_BIT,
+            1,
+        );
+
+        unsafe {
+            submit_and_wait(&vars, |cmds| {
+                cmds.reset_query_pool(&pool, 0, 1);
+                cmds.begin_query(&pool, 0, false);
+                compute.dispatch(&pipelines, cmds);
+                cmds.end_query(&pool, 0);
+            });
+        }
+
+        let results = pool.get_results(0, 1).unwrap();
+        assert!(results[0] > 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn query_out_of_range() {
+        let vars = TestVars::new();
+        let device = vars.device();
+        let pool = QueryPool::new(Arc::clone(device), QueryType::Timestamp, 2);
+        unsafe {
+            submit_and_wait(&vars, |cmds| {
+                cmds.write_timestamp(vk::PipelineStageFlags::TOP_OF_PIPE_BIT, &pool, 2);
+            });
+        }
+    }
+}
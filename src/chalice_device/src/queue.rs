@@ -29,7 +29,10 @@ pub struct Queue {
     device: Arc<Device>,
     inner: vk::Queue,
     family: u32,
-    mutex: Mutex<()>,
+    // Holds the timeline value of the last submission.
+    mutex: Mutex<u64>,
+    // Signaled at the end of every submission.
+    timeline: Arc<TimelineSemaphore>,
     name: Option<String>,
 }
 
@@ -104,6 +107,11 @@ impl<'dev> QueueFamily<'dev> {
         self.ty().supports(QueueType::Graphics)
     }
 
+    #[inline]
+    pub fn supports_compute(&self) -> bool {
+        self.ty().supports(QueueType::Compute)
+    }
+
     #[inline]
     pub fn supports_xfer(&self) -> bool {
         self.ty().supports(QueueType::Xfer)
@@ -136,16 +144,50 @@ impl Queue {
         self.family().ty()
     }
 
-    // TODO: Verify that submitted commands are executable by this type
-    // of queue.
-    pub unsafe fn submit(&self, submissions: &[SubmitInfo<'_>]) {
+    /// Returns the timeline semaphore signaled by each submission.
+    #[inline]
+    pub fn timeline(&self) -> &Arc<TimelineSemaphore> {
+        &self.timeline
+    }
+
+    /// Submits work to the queue and returns a token which is signaled
+    /// once all of it has completed.
+    pub unsafe fn submit(&self, submissions: &[SubmitInfo<'_>]) -> Submission {
         trace!(
             "Queue::submit(self: {:?}, submissions: {:?}",
             fmt_named(self),
             submissions,
         );
+        let value = self.submit_inner(submissions, vk::null());
+        Submission::Timeline {
+            semaphore: Arc::clone(&self.timeline),
+            value,
+        }
+    }
 
-        let _lock = self.mutex.lock();
+    /// Like `submit`, but also signals `fence`, which backs the
+    /// returned token. The fence must be unsignaled.
+    pub unsafe fn submit_with_fence(
+        &self,
+        submissions: &[SubmitInfo<'_>],
+        fence: &Arc<Fence>,
+    ) -> Submission {
+        trace!(
+            "Queue::submit_with_fence(self: {:?}, submissions: {:?}, fence: {:?}",
+            fmt_named(self),
+            submissions,
+            fmt_named(&**fence),
+        );
+        assert_eq!(fence.device(), self.device());
+        self.submit_inner(submissions, fence.raw());
+        Submission::Fence(Arc::clone(fence))
+    }
+
+    // TODO: Verify that submitted commands are executable by this type
+    // of queue.
+    unsafe fn submit_inner(&self, submissions: &[SubmitInfo<'_>], fence: vk::Fence) -> u64 {
+        let mut last_value = self.mutex.lock();
+        let value = *last_value + 1;
 
         const MAX_SEMS: usize = 16;
         const MAX_SUBMITS: usize = 8;
@@ -165,7 +207,7 @@ impl Queue {
         let mut sig_sems = VecSem::with_capacity(sig_count);
         let mut sig_values = VecSem::with_capacity(sig_count);
         let mut timelines = VecSubmit::with_capacity(submissions.len());
-        let mut infos = VecSubmit::with_capacity(submissions.len());
+        let mut infos = VecSubmit::with_capacity(submissions.len() + 1);
         for info in submissions.iter() {
             let wait_offset = wait_sems.len();
             for wait in info.wait_sems.iter() {
@@ -208,11 +250,29 @@ impl Queue {
             infos.push(info);
         }
 
+        // Signal operations cover all previously submitted work, so a
+        // trailing empty batch suffices to track completion.
+        let timeline_sems = [self.timeline.raw()];
+        let timeline_values = [value];
+        let timeline_info = vk::TimelineSemaphoreSubmitInfo {
+            signal_semaphore_value_count: 1,
+            p_signal_semaphore_values: timeline_values.as_ptr(),
+            ..Default::default()
+        };
+        infos.push(vk::SubmitInfo {
+            p_next: &timeline_info as *const _ as _,
+            signal_semaphore_count: 1,
+            p_signal_semaphores: timeline_sems.as_ptr(),
+            ..Default::default()
+        });
+
         self.device
             .table
-            .queue_submit(self.inner, infos.len() as _, infos.as_ptr(), vk::null())
+            .queue_submit(self.inner, infos.len() as _, infos.as_ptr(), fence)
             .check()
             .unwrap();
+        *last_value = value;
+        value
     }
 
     pub unsafe fn present(
@@ -244,31 +304,59 @@ impl Queue {
             p_image_indices: images.as_ptr(),
             ..Default::default()
         };
-        self.device
+        let result = self
+            .device
             .table
-            .queue_present_khr(self.inner, &present_info)
+            .queue_present_khr(self.inner, &present_info);
+        swapchain.check_result(result);
+        result
     }
 
-    pub(super) unsafe fn get_device_queues(device: &Arc<Device>) -> Vec<Vec<Arc<Queue>>> {
-        // TODO: Ughhh... queues are actually hard
+    unsafe fn new(device: &Arc<Device>, family: u32) -> Self {
         let mut inner = vk::null();
-        device.table().get_device_queue(0, 0, &mut inner);
-
-        let mut gfx_queue = Queue {
+        device.table().get_device_queue(family, 0, &mut inner);
+        Queue {
             device: Arc::clone(device),
             inner,
-            family: 0,
-            mutex: Mutex::new(()),
+            family,
+            mutex: Mutex::new(0),
+            timeline: Arc::new(TimelineSemaphore::new(Arc::clone(device), 0)),
             name: None,
-        };
-        set_name!(gfx_queue);
+        }
+    }
 
-        vec![vec![Arc::new(gfx_queue)]]
+    /// Returns the queues of a device grouped by `QueueType`: graphics,
+    /// dedicated compute, then dedicated transfer. The last two are
+    /// empty if the device lacks such queue families.
+    pub(super) unsafe fn get_device_queues(device: &Arc<Device>) -> Vec<Vec<Arc<Queue>>> {
+        let indices = device.queue_family_indices;
+
+        let mut gfx_queue = Queue::new(device, indices.graphics);
+        set_name!(gfx_queue);
+        let compute_queues = indices.compute.map(|family| {
+            let mut compute_queue = Queue::new(device, family);
+            set_name!(compute_queue);
+            Arc::new(compute_queue)
+        });
+        let xfer_queues = indices.transfer.map(|family| {
+            let mut xfer_queue = Queue::new(device, family);
+            set_name!(xfer_queue);
+            Arc::new(xfer_queue)
+        });
+
+        vec![
+            vec![Arc::new(gfx_queue)],
+            compute_queues.into_iter().collect(),
+            xfer_queues.into_iter().collect(),
+        ]
     }
 
     pub fn set_name(&mut self, name: impl Into<String>) {
         let name: String = name.into();
         self.name = Some(name.clone());
+        if let Some(timeline) = Arc::get_mut(&mut self.timeline) {
+            timeline.set_name(format!("{}.timeline", name));
+        }
         unsafe {
             self.device().set_name(self.inner(), name);
         }
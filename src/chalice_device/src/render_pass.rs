@@ -384,7 +384,8 @@ pub fn create_test_pass(device: &Arc<Device>) -> Arc<RenderPass> {
                     format: Format::BGRA8_SRGB,
                     // TODO: Not sure if it's a better practice to set
                     // initial_layout or not.
-                    final_layout: Il::PRESENT_SRC_KHR,
+                    // PRESENT_SRC_KHR isn't valid on headless devices.
+                    final_layout: Il::COLOR_ATTACHMENT_OPTIMAL,
                     ..Default::default()
                 },
                 // HDR lighting buffer
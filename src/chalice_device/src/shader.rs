@@ -17,6 +17,7 @@ pub struct Shader {
     source_file: Option<String>,
     inputs: Vec<ShaderLocation>,
     outputs: Vec<ShaderLocation>,
+    push_constants: Option<vk::PushConstantRange>,
     // TODO: reflect uniforms so we can make sure all descriptors are
     // bound.
 }
@@ -36,8 +37,14 @@ pub struct ShaderSpec {
     spec_info: vk::SpecializationInfo,
 }
 
-// Probably should know the type and dimension too...
-pub type ShaderLocation = u32;
+/// A location-assigned variable in a shader's input or output
+/// interface.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ShaderLocation {
+    pub location: u32,
+    /// `None` if the variable has no simple numeric type, e.g. a struct.
+    pub ty: Option<spv::InterfaceType>,
+}
 
 #[derive(Clone, Copy, Debug, Enum, Eq, Hash, PartialEq)]
 pub enum ShaderStage {
@@ -75,8 +82,15 @@ impl Shader {
 
         let reflected = spv::parse_words(&code);
         let entry = reflected.get_entry_point(&"main").unwrap();
-        let stage = entry.execution_model().try_into().unwrap();
+        let stage: ShaderStage = entry.execution_model().try_into().unwrap();
         let (inputs, outputs) = get_shader_interface(&entry);
+        let push_constants = reflected
+            .push_constants()
+            .map(|block| vk::PushConstantRange {
+                stage_flags: stage.into(),
+                offset: block.offset(),
+                size: block.size(),
+            });
 
         if let Some(source) = &source_file {
             device.set_name(inner, source.clone());
@@ -90,6 +104,7 @@ impl Shader {
             source_file,
             inputs,
             outputs,
+            push_constants,
         }
     }
 
@@ -141,6 +156,12 @@ impl Shader {
     pub fn outputs(&self) -> &[ShaderLocation] {
         &self.outputs
     }
+
+    /// The range of push constants used by the shader, if any.
+    #[inline]
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.push_constants
+    }
 }
 
 impl Named for Shader {
@@ -189,10 +210,14 @@ impl ShaderSpec {
 }
 
 fn get_shader_interface(entry: &spv::EntryPoint<'_>) -> (Vec<ShaderLocation>, Vec<ShaderLocation>) {
-    let mut inputs: Vec<_> = entry.inputs().map(|input| input.location()).collect();
-    inputs.sort();
-    let mut outputs: Vec<_> = entry.outputs().map(|output| output.location()).collect();
-    outputs.sort();
+    let reflect = |var: spv::Variable<'_>| ShaderLocation {
+        location: var.location(),
+        ty: var.ty().cloned(),
+    };
+    let mut inputs: Vec<_> = entry.inputs().map(reflect).collect();
+    inputs.sort_by_key(|input| input.location);
+    let mut outputs: Vec<_> = entry.outputs().map(reflect).collect();
+    outputs.sort_by_key(|output| output.location);
     (inputs, outputs)
 }
 
@@ -1,3 +1,4 @@
+use std::convert::TryInto;
 use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -17,6 +18,25 @@ pub struct Surface {
     pub(crate) inner: vk::SurfaceKHR,
 }
 
+/// A combination of image format and color space to present in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SurfaceFormat {
+    pub format: Format,
+    pub color_space: vk::ColorSpaceKHR,
+}
+
+/// Preferences used to configure a swapchain. Each list is in order of
+/// preference, and the first entry supported by the surface is chosen.
+#[derive(Clone, Debug)]
+pub struct SwapchainOptions {
+    /// If none are supported, falls back to `FIFO_KHR`, which is
+    /// always available.
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    /// If none are supported, falls back to the first sRGB nonlinear
+    /// format reported by the surface, preferring `_SRGB` formats.
+    pub formats: Vec<SurfaceFormat>,
+}
+
 #[derive(Debug)]
 pub struct Swapchain {
     pub(crate) surface: Arc<Surface>,
@@ -24,8 +44,13 @@ pub struct Swapchain {
     pub(crate) inner: vk::SwapchainKHR,
     pub(crate) extent: Extent2D,
     pub(crate) images: Vec<vk::Image>,
+    pub(crate) usage: vk::ImageUsageFlags,
+    options: SwapchainOptions,
+    format: SurfaceFormat,
+    present_mode: vk::PresentModeKHR,
     views: Vec<Arc<SwapchainView>>,
     token: Token,
+    out_of_date: bool,
     name: Option<String>,
 }
 
@@ -36,7 +61,10 @@ pub struct SwapchainView {
     token: Token,
     device: Arc<Device>,
     extent: Extent2D,
+    format: Format,
     index: u32,
+    image: vk::Image,
+    usage: vk::ImageUsageFlags,
     inner: vk::ImageView,
 }
 
@@ -46,6 +74,115 @@ struct Token {
     inner: Arc<AtomicBool>,
 }
 
+impl SurfaceFormat {
+    /// 8-bit sRGB, which virtually every surface supports.
+    pub const SRGB: Self = Self {
+        format: Format::BGRA8_SRGB,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR_KHR,
+    };
+    /// HDR10 with the ST 2084 (PQ) transfer function.
+    pub const HDR10: Self = Self {
+        format: Format::RGB10A2,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    };
+    /// HDR10 with red and blue swapped, which some surfaces offer
+    /// instead.
+    pub const HDR10_BGR: Self = Self {
+        format: Format::BGR10A2,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    };
+    /// Linear, floating point extended sRGB.
+    pub const SCRGB: Self = Self {
+        format: Format::RGBA16F,
+        color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    };
+
+    /// True for the HDR10 and scRGB color spaces.
+    #[inline]
+    pub fn is_hdr(&self) -> bool {
+        use vk::ColorSpaceKHR as C;
+        matches!(
+            self.color_space,
+            C::HDR10_ST2084_EXT
+                | C::HDR10_HLG_EXT
+                | C::EXTENDED_SRGB_LINEAR_EXT
+                | C::EXTENDED_SRGB_NONLINEAR_EXT
+        )
+    }
+}
+
+impl Default for SwapchainOptions {
+    /// Vsync and 8-bit sRGB.
+    fn default() -> Self {
+        Self {
+            present_modes: vec![vk::PresentModeKHR::FIFO_KHR],
+            formats: vec![
+                SurfaceFormat::SRGB,
+                SurfaceFormat {
+                    format: Format::RGBA8_SRGB,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR_KHR,
+                },
+            ],
+        }
+    }
+}
+
+impl SwapchainOptions {
+    /// Prefers HDR output if the surface supports it.
+    pub fn hdr() -> Self {
+        let mut options = Self::default();
+        options.formats.splice(
+            0..0,
+            [
+                SurfaceFormat::HDR10,
+                SurfaceFormat::HDR10_BGR,
+                SurfaceFormat::SCRGB,
+            ]
+            .iter()
+            .cloned(),
+        );
+        options
+    }
+
+    /// Prefers present modes which don't block, i.e. mailbox, then
+    /// immediate, then relaxed FIFO.
+    pub fn low_latency() -> Self {
+        Self {
+            present_modes: vec![
+                vk::PresentModeKHR::MAILBOX_KHR,
+                vk::PresentModeKHR::IMMEDIATE_KHR,
+                vk::PresentModeKHR::FIFO_RELAXED_KHR,
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Picks the most preferred format among those supported by the
+    /// surface.
+    fn choose_format(&self, supported: &[SurfaceFormat]) -> Option<SurfaceFormat> {
+        if let Some(&format) = self.formats.iter().find(|fmt| supported.contains(fmt)) {
+            return Some(format);
+        }
+        let mut srgb = supported
+            .iter()
+            .filter(|fmt| fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR_KHR);
+        srgb.clone()
+            .find(|fmt| fmt.format.is_srgb())
+            .or_else(|| srgb.next())
+            .copied()
+    }
+
+    /// Picks the most preferred present mode among those supported by
+    /// the surface.
+    fn choose_present_mode(&self, supported: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        self.present_modes
+            .iter()
+            .copied()
+            .find(|mode| supported.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO_KHR)
+    }
+}
+
 impl Drop for Surface {
     fn drop(&mut self) {
         unsafe {
@@ -73,18 +210,33 @@ impl Drop for Swapchain {
 }
 
 impl Swapchain {
-    pub unsafe fn new(surface: Arc<Surface>, device: Arc<Device>) -> DeviceResult<Self> {
+    /// Creates a swapchain for a surface. `window_extent` is used if
+    /// the surface doesn't dictate the size of its images.
+    ///
+    /// If the window has zero area, e.g. because it is minimized, the
+    /// swapchain has no images and is out of date until recreated.
+    pub unsafe fn new(
+        surface: Arc<Surface>,
+        device: Arc<Device>,
+        window_extent: Extent2D,
+        options: SwapchainOptions,
+    ) -> DeviceResult<Self> {
         let mut result = Swapchain {
             surface,
             device,
             inner: vk::null(),
             extent: Default::default(),
             images: Vec::new(),
+            usage: Default::default(),
+            options,
+            format: SurfaceFormat::SRGB,
+            present_mode: vk::PresentModeKHR::FIFO_KHR,
             views: Vec::new(),
             token: Default::default(),
+            out_of_date: true,
             name: None,
         };
-        result.recreate()?;
+        result.recreate(window_extent)?;
 
         Ok(result)
     }
@@ -126,13 +278,42 @@ impl Swapchain {
         }
     }
 
+    /// True if the swapchain no longer matches the surface and should
+    /// be recreated. Set when acquiring or presenting reports the
+    /// swapchain as suboptimal or out of date.
+    #[inline]
+    pub fn is_out_of_date(&self) -> bool {
+        self.out_of_date
+    }
+
+    /// Flags the swapchain for recreation, e.g. because the window was
+    /// resized.
+    #[inline]
+    pub fn mark_out_of_date(&mut self) {
+        self.out_of_date = true;
+    }
+
+    pub(crate) fn check_result(&mut self, result: vk::Result) {
+        if result == vk::Result::SUBOPTIMAL_KHR || result == vk::Result::ERROR_OUT_OF_DATE_KHR {
+            debug!("swapchain out of date: {:?}", result);
+            self.out_of_date = true;
+        }
+    }
+
     unsafe fn destroy(&self) {
         self.device
             .table
             .destroy_swapchain_khr(self.inner, ptr::null());
     }
 
-    pub unsafe fn recreate(&mut self) -> DeviceResult<()> {
+    /// Recreates the swapchain to match the current state of the
+    /// surface, invalidating all views of the old swapchain. Returns
+    /// false without recreating anything if the extent is zero, which
+    /// happens while a window is minimized.
+    ///
+    /// The old swapchain is destroyed, so its images must not be in
+    /// use by the device.
+    pub unsafe fn recreate(&mut self, window_extent: Extent2D) -> DeviceResult<bool> {
         let dt = &*self.device.table;
         let it = &*self.device.instance.table;
         let pdev = self.device.pdev;
@@ -148,43 +329,75 @@ impl Swapchain {
         };
         let min_image_count = std::cmp::min(caps.min_image_count + 1, max_image_count);
 
-        // TODO: Is this a compatibility concern?
-        let format = vk::Format::B8G8R8A8_SRGB;
-        let color_space = vk::ColorSpaceKHR::SRGB_NONLINEAR_KHR;
         let formats = vk::enumerate2!(
             it,
             get_physical_device_surface_formats_khr,
             pdev,
             self.surface.inner,
         )?;
-        if !formats
+        let colorspace_ext = self.device.instance.swapchain_colorspace;
+        let formats: Vec<SurfaceFormat> = formats
             .iter()
-            .any(|fmt| fmt.format == format && fmt.color_space == color_space)
-        {
-            Err(err_msg!("surface format not supported"))?;
-        }
+            .filter(|fmt| {
+                colorspace_ext || fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR_KHR
+            })
+            .filter_map(|fmt| {
+                Some(SurfaceFormat {
+                    format: fmt.format.try_into().ok()?,
+                    color_space: fmt.color_space,
+                })
+            })
+            .collect();
+        let format = self
+            .options
+            .choose_format(&formats)
+            .ok_or_else(|| err_msg!("no supported surface format"))?;
 
-        // FIXME: On Wayland, the surface extent is defined by the
-        // application, so caller needs to pass us the window extent.
-        // (Should be obtained from winit or whatever lib is used.)
-        assert_ne!(caps.current_extent, (0xffff_ffff, 0xffff_ffff).into());
+        let present_modes = vk::enumerate2!(
+            it,
+            get_physical_device_surface_present_modes_khr,
+            pdev,
+            self.surface.inner,
+        )?;
+        let present_mode = self.options.choose_present_mode(&present_modes);
+
+        // On some platforms (e.g. Wayland), the surface extent is
+        // defined by the swapchain, so we use the window extent.
+        let extent: Extent2D = if caps.current_extent.width == 0xffff_ffff {
+            Extent2D::new(
+                window_extent
+                    .width
+                    .max(caps.min_image_extent.width)
+                    .min(caps.max_image_extent.width),
+                window_extent
+                    .height
+                    .max(caps.min_image_extent.height)
+                    .min(caps.max_image_extent.height),
+            )
+        } else {
+            caps.current_extent.into()
+        };
 
         // This can happen when a window is minimized, so don't try to
         // create a swapchain for a minimized window.
-        assert_ne!(caps.current_extent, (0, 0).into());
-
-        self.extent = caps.current_extent.into();
+        if extent.width == 0 || extent.height == 0 {
+            debug!("not recreating swapchain with zero extent");
+            return Ok(false);
+        }
 
         let composite_alpha = vk::CompositeAlphaFlagsKHR::OPAQUE_BIT_KHR;
         if !caps.supported_composite_alpha.intersects(composite_alpha) {
             Err(err_msg!("swapchain composite alpha mode not available"))?;
         }
 
-        let image_usage =
+        let mut image_usage =
             vk::ImageUsageFlags::COLOR_ATTACHMENT_BIT | vk::ImageUsageFlags::TRANSFER_DST_BIT;
         if !caps.supported_usage_flags.contains(image_usage) {
             Err(err_msg!("swapchain image usage not supported"))?;
         }
+        // Needed for screenshots, but not essential.
+        image_usage |= caps.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC_BIT;
+        self.usage = image_usage;
 
         let create_info = vk::SwapchainCreateInfoKHR {
             s_type: vk::StructureType::SWAPCHAIN_CREATE_INFO_KHR,
@@ -192,9 +405,9 @@ impl Swapchain {
             flags: Default::default(),
             surface: self.surface.inner,
             min_image_count,
-            image_format: format,
-            image_color_space: color_space,
-            image_extent: caps.current_extent,
+            image_format: format.format.into(),
+            image_color_space: format.color_space,
+            image_extent: extent.into(),
             image_array_layers: 1,
             image_usage,
             image_sharing_mode: vk::SharingMode::EXCLUSIVE,
@@ -202,7 +415,7 @@ impl Swapchain {
             p_queue_family_indices: ptr::null(),
             pre_transform: caps.current_transform,
             composite_alpha,
-            present_mode: vk::PresentModeKHR::FIFO_KHR,
+            present_mode,
             clipped: vk::FALSE,
             old_swapchain: self.inner,
         };
@@ -212,10 +425,19 @@ impl Swapchain {
 
         self.destroy();
         self.inner = new;
+        self.extent = extent;
+        self.format = format;
+        self.present_mode = present_mode;
         self.images = vk::enumerate2!(dt, get_swapchain_images_khr, self.inner)?;
+        self.token.invalidate();
         self.create_views();
+        self.out_of_date = false;
+        debug!(
+            "created swapchain: extent: {:?}, format: {:?}, present mode: {:?}",
+            extent, format, present_mode,
+        );
 
-        Ok(())
+        Ok(true)
     }
 
     #[inline]
@@ -232,11 +454,45 @@ impl Swapchain {
 
     #[inline]
     pub fn format(&self) -> Format {
-        Format::BGRA8_SRGB
+        self.format.format
+    }
+
+    #[inline]
+    pub fn color_space(&self) -> vk::ColorSpaceKHR {
+        self.format.color_space
+    }
+
+    #[inline]
+    pub fn surface_format(&self) -> SurfaceFormat {
+        self.format
     }
 
-    /// Note: A suboptimal swapchain will just return an error with no
-    /// swapchain index.
+    #[inline]
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.present_mode
+    }
+
+    #[inline]
+    pub fn options(&self) -> &SwapchainOptions {
+        &self.options
+    }
+
+    /// Changes the swapchain options. Takes effect when the swapchain
+    /// is next recreated.
+    pub fn set_options(&mut self, options: SwapchainOptions) {
+        self.options = options;
+        self.out_of_date = true;
+    }
+
+    #[inline]
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.usage
+    }
+
+    /// Acquires an image to render into. A suboptimal swapchain still
+    /// returns an image but is marked out of date. If the swapchain is
+    /// already out of date, `ERROR_OUT_OF_DATE_KHR` is returned and the
+    /// swapchain must be recreated.
     #[inline]
     pub fn acquire_next_image(&mut self, sem: &mut BinarySemaphore) -> Result<u32, vk::Result> {
         self.acquire_next_image_with_timeout(sem, u64::max_value())
@@ -253,10 +509,13 @@ impl Swapchain {
         );
         let dt = &*self.device.table;
         let mut idx = 0;
-        unsafe {
+        let result = unsafe {
             dt.acquire_next_image_khr(self.inner, timeout, sem.raw(), vk::null(), &mut idx)
-                .check()?;
         };
+        self.check_result(result);
+        if result != vk::Result::SUBOPTIMAL_KHR {
+            result.check()?;
+        }
         debug!("acquired swapchain image {}", idx);
         Ok(idx)
     }
@@ -313,7 +572,10 @@ impl SwapchainView {
             token: swapchain.token.clone(),
             device: Arc::clone(&swapchain.device),
             extent: swapchain.extent,
+            format: swapchain.format(),
             index,
+            image: swapchain.images[index as usize],
+            usage: swapchain.usage,
             inner: view,
         }
     }
@@ -330,7 +592,23 @@ impl SwapchainView {
 
     #[inline]
     pub fn format(&self) -> Format {
-        Format::BGRA8_SRGB
+        self.format
+    }
+
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The swapchain image this view refers to.
+    #[inline]
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    #[inline]
+    pub fn usage(&self) -> vk::ImageUsageFlags {
+        self.usage
     }
 
     #[inline]
@@ -340,7 +618,6 @@ impl SwapchainView {
 }
 
 impl Token {
-    #[allow(dead_code)]
     fn invalidate(&mut self) {
         self.inner.store(false, Ordering::Relaxed);
         *self = Default::default();
@@ -359,34 +636,9 @@ impl Default for Token {
     }
 }
 
+/// Picks a physical device capable of presenting to a surface.
 pub unsafe fn device_for_surface(surface: &Surface) -> DeviceResult<vk::PhysicalDevice> {
-    let instance = &*surface.instance;
-    let surface = surface.inner;
-
-    let pdevices = instance.get_physical_devices();
-    for pd in pdevices.into_iter() {
-        let qf = 0u32;
-        let props = instance.get_queue_family_properties(pd)[qf as usize];
-        let required_bits = vk::QueueFlags::GRAPHICS_BIT
-            | vk::QueueFlags::COMPUTE_BIT
-            | vk::QueueFlags::TRANSFER_BIT;
-        if !props.queue_flags.contains(required_bits) {
-            continue;
-        }
-
-        let mut surface_supp = 0;
-        instance
-            .table
-            .get_physical_device_surface_support_khr(pd, qf, surface, &mut surface_supp)
-            .check()?;
-        if surface_supp != vk::TRUE {
-            continue;
-        }
-
-        return Ok(pd);
-    }
-
-    Err(err_msg!("no presentable graphics device"))
+    Ok(surface.instance.select_device(Some(surface))?.pdev)
 }
 
 /// Helper function which creates a logical device capable of rendering
@@ -396,6 +648,7 @@ pub unsafe fn device_for_surface(surface: &Surface) -> DeviceResult<vk::Physical
 pub fn init_device_and_swapchain(
     app_info: AppInfo,
     window: &impl Window,
+    options: SwapchainOptions,
 ) -> DeviceResult<(Swapchain, Vec<Vec<Arc<Queue>>>)> {
     unsafe {
         let entrypoint = crate::loader::load_vulkan().map_err(|_| "Failed to load libvulkan")?;
@@ -405,18 +658,80 @@ pub fn init_device_and_swapchain(
             window.required_extensions(),
         )?);
         let surface = Arc::new(Surface::new(Arc::clone(&instance), window)?);
-        let pdev = device_for_surface(&surface).unwrap();
+        let pdev = device_for_surface(&surface)?;
         let (device, queues) = Device::new(instance, pdev)?;
-        Ok((device.create_swapchain(surface)?, queues))
+        Ok((
+            device.create_swapchain(surface, window.extent(), options)?,
+            queues,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::testing::*;
+    use crate::*;
 
     #[test]
     fn view_test() {
-        let _ = TestVars::new();
+        let _ = SwapchainTestVars::new();
+    }
+
+    #[test]
+    fn hdr_color_spaces() {
+        assert!(SurfaceFormat::HDR10.is_hdr());
+        assert!(SurfaceFormat::HDR10_BGR.is_hdr());
+        assert!(SurfaceFormat::SCRGB.is_hdr());
+        assert!(!SurfaceFormat::SRGB.is_hdr());
+        let p3 = SurfaceFormat {
+            format: Format::BGRA8,
+            color_space: vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+        };
+        assert!(!p3.is_hdr());
+    }
+
+    #[test]
+    fn choose_format() {
+        use vk::ColorSpaceKHR as C;
+        let fmt = |format, color_space| SurfaceFormat {
+            format,
+            color_space,
+        };
+        let p3 = fmt(Format::BGRA8, C::DISPLAY_P3_NONLINEAR_EXT);
+        let unorm = fmt(Format::BGRA8, C::SRGB_NONLINEAR_KHR);
+        let srgb = fmt(Format::RGBA8_SRGB, C::SRGB_NONLINEAR_KHR);
+
+        let sdr = SwapchainOptions::default();
+        let hdr = SwapchainOptions::hdr();
+        let supported = [p3, SurfaceFormat::HDR10, srgb, SurfaceFormat::SRGB];
+        assert_eq!(sdr.choose_format(&supported), Some(SurfaceFormat::SRGB));
+        assert_eq!(hdr.choose_format(&supported), Some(SurfaceFormat::HDR10));
+
+        // Falls back to sRGB nonlinear, preferring _SRGB formats
+        let options = SwapchainOptions {
+            formats: vec![SurfaceFormat::SCRGB],
+            ..Default::default()
+        };
+        assert_eq!(options.choose_format(&[p3, unorm, srgb]), Some(srgb));
+        assert_eq!(options.choose_format(&[p3, unorm]), Some(unorm));
+        assert_eq!(options.choose_format(&[p3]), None);
+        assert_eq!(options.choose_format(&[]), None);
+    }
+
+    #[test]
+    fn choose_present_mode() {
+        use vk::PresentModeKHR as P;
+        let options = SwapchainOptions::low_latency();
+        let supported = [P::FIFO_KHR, P::IMMEDIATE_KHR, P::MAILBOX_KHR];
+        assert_eq!(options.choose_present_mode(&supported), P::MAILBOX_KHR);
+        assert_eq!(
+            options.choose_present_mode(&[P::FIFO_KHR, P::IMMEDIATE_KHR]),
+            P::IMMEDIATE_KHR,
+        );
+        assert_eq!(options.choose_present_mode(&[P::FIFO_KHR]), P::FIFO_KHR);
+        assert_eq!(
+            SwapchainOptions::default().choose_present_mode(&supported),
+            P::FIFO_KHR,
+        );
     }
 }
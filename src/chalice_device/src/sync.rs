@@ -241,6 +241,136 @@ impl Named for TimelineSemaphore {
     }
 }
 
+#[derive(Debug)]
+pub struct Fence {
+    device: Arc<Device>,
+    raw: vk::Fence,
+    name: Option<String>,
+}
+
+impl Drop for Fence {
+    fn drop(&mut self) {
+        let dt = self.device.table();
+        unsafe {
+            dt.destroy_fence(self.raw, ptr::null());
+        }
+    }
+}
+
+impl Fence {
+    pub fn new(device: Arc<Device>, signaled: bool) -> Self {
+        let dt = device.table();
+        let flags = if signaled {
+            vk::FenceCreateFlags::SIGNALED_BIT
+        } else {
+            Default::default()
+        };
+        let create_info = vk::FenceCreateInfo {
+            flags,
+            ..Default::default()
+        };
+        let mut raw = vk::null();
+        unsafe {
+            dt.create_fence(&create_info, ptr::null(), &mut raw)
+                .check()
+                .unwrap();
+        }
+        Self {
+            device,
+            raw,
+            name: None,
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    #[inline]
+    pub fn raw(&self) -> vk::Fence {
+        self.raw
+    }
+
+    pub fn wait(&self, timeout: u64) -> WaitResult {
+        trace!(
+            "Fence::wait(self: {:?}, timeout: {})",
+            fmt_named(self),
+            timeout
+        );
+        unsafe {
+            self.device
+                .table()
+                .wait_for_fences(1, &self.raw, vk::TRUE, timeout)
+                .try_into()
+                .unwrap()
+        }
+    }
+
+    /// Returns the fence to the unsignaled state. The fence must not
+    /// be associated with a pending queue submission.
+    pub unsafe fn reset(&self) {
+        trace!("Fence::reset(self: {:?})", fmt_named(self));
+        self.device
+            .table()
+            .reset_fences(1, &self.raw)
+            .check()
+            .unwrap();
+    }
+
+    /// Returns true if the fence is signaled.
+    pub fn status(&self) -> bool {
+        let res = unsafe { self.device.table().get_fence_status(self.raw) };
+        match res {
+            vk::Result::SUCCESS => true,
+            vk::Result::NOT_READY => false,
+            _ => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        let name: String = name.into();
+        self.name = Some(name.clone());
+        unsafe {
+            self.device.set_name(self.raw, name);
+        }
+    }
+}
+
+impl Named for Fence {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name.as_ref()?)
+    }
+}
+
+/// A handle to a queue submission which may be polled or waited on
+/// for completion.
+#[derive(Clone, Debug)]
+pub enum Submission {
+    Fence(Arc<Fence>),
+    Timeline {
+        semaphore: Arc<TimelineSemaphore>,
+        value: u64,
+    },
+}
+
+impl Submission {
+    pub fn wait(&self, timeout: u64) -> WaitResult {
+        match self {
+            Self::Fence(fence) => fence.wait(timeout),
+            Self::Timeline { semaphore, value } => semaphore.wait(*value, timeout),
+        }
+    }
+
+    /// Returns true if all work in the submission has completed.
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Self::Fence(fence) => fence.status(),
+            Self::Timeline { semaphore, value } => semaphore.get_value() >= *value,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -318,4 +448,68 @@ mod tests {
 
         queue.device().wait_idle();
     }
+
+    #[test]
+    fn fence_host_ops() {
+        let vars = TestVars::new();
+        let device = Arc::clone(vars.device());
+
+        let fence = Fence::new(Arc::clone(&device), true);
+        assert!(fence.status());
+        assert_eq!(fence.wait(0), WaitResult::Success);
+        unsafe {
+            fence.reset();
+        }
+        assert!(!fence.status());
+        assert_eq!(fence.wait(1000), WaitResult::Timeout);
+    }
+
+    #[test]
+    fn submission_tokens() {
+        let vars = TestVars::new();
+        let queue = vars.gfx_queue();
+        let mut pool = CmdPool::new_transient(queue.family());
+
+        let make_cmds = |pool: &mut CmdPool| {
+            let mut cmds = CmdBuffer::new(pool, vk::CommandBufferLevel::PRIMARY);
+            cmds.begin(Default::default(), None);
+            cmds.end()
+        };
+
+        unsafe {
+            let cmds = make_cmds(&mut pool);
+            let first = queue.submit(&[SubmitInfo {
+                cmds: &[cmds],
+                ..Default::default()
+            }]);
+            let cmds = make_cmds(&mut pool);
+            let second = queue.submit(&[SubmitInfo {
+                cmds: &[cmds],
+                ..Default::default()
+            }]);
+            match (&first, &second) {
+                (Submission::Timeline { value: a, .. }, Submission::Timeline { value: b, .. }) => {
+                    assert!(a < b)
+                }
+                _ => panic!(),
+            }
+            second.wait(u64::MAX).unwrap();
+            assert!(first.is_complete());
+
+            let fence = Arc::new(Fence::new(Arc::clone(queue.device()), false));
+            let cmds = make_cmds(&mut pool);
+            let third = queue.submit_with_fence(
+                &[SubmitInfo {
+                    cmds: &[cmds],
+                    ..Default::default()
+                }],
+                &fence,
+            );
+            third.wait(u64::MAX).unwrap();
+            assert!(third.is_complete());
+            assert!(fence.status());
+        }
+
+        queue.device().wait_idle();
+    }
 }
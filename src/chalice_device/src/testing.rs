@@ -8,7 +8,7 @@ use crate::*;
 const WINDOW_NAME: &str = "cooper test";
 const WINDOW_DIMS: (u32, u32) = (1920, 1080);
 
-fn app_info() -> AppInfo {
+pub(crate) fn app_info() -> AppInfo {
     AppInfo {
         name: WINDOW_NAME.to_owned(),
         version: [0, 1, 0],
@@ -18,9 +18,18 @@ fn app_info() -> AppInfo {
     }
 }
 
-#[allow(dead_code)]
+/// Headless device for tests which don't present. Needs no display.
 #[derive(Debug)]
 pub(crate) struct TestVars {
+    pub(crate) device: Arc<Device>,
+    pub(crate) queues: Vec<Vec<Arc<Queue>>>,
+}
+
+/// Device with a hidden window and swapchain for tests which need
+/// presentation support. Requires a display.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(crate) struct SwapchainTestVars {
     pub(crate) window: Window,
     pub(crate) swapchain: Swapchain,
     pub(crate) queues: Vec<Vec<Arc<Queue>>>,
@@ -68,11 +77,28 @@ fn create_window() -> Window {
 static INIT_LOGGING: std::sync::Once = std::sync::Once::new();
 
 impl TestVars {
+    pub(crate) fn new() -> Self {
+        INIT_LOGGING.call_once(env_logger::init);
+        let (device, queues) = init_device_headless(app_info()).unwrap();
+        TestVars { device, queues }
+    }
+
+    pub(crate) fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub(crate) fn gfx_queue(&self) -> &Arc<Queue> {
+        &self.queues[0][0]
+    }
+}
+
+impl SwapchainTestVars {
     pub(crate) fn new() -> Self {
         INIT_LOGGING.call_once(env_logger::init);
         let window = create_window();
-        let (swapchain, queues) = init_device_and_swapchain(app_info(), &window).unwrap();
-        TestVars {
+        let (swapchain, queues) =
+            init_device_and_swapchain(app_info(), &window, Default::default()).unwrap();
+        SwapchainTestVars {
             window,
             swapchain,
             queues,
@@ -82,14 +108,6 @@ impl TestVars {
     pub(crate) fn swapchain(&self) -> &Swapchain {
         &self.swapchain
     }
-
-    pub(crate) fn device(&self) -> &Arc<Device> {
-        &self.swapchain.device
-    }
-
-    pub(crate) fn gfx_queue(&self) -> &Arc<Queue> {
-        &self.queues[0][0]
-    }
 }
 
 macro_rules! test_shaders {
@@ -119,7 +137,12 @@ macro_rules! test_shaders {
 test_shaders! {
     trivial_vert,
     trivial_frag,
+    trivial_comp,
+    trivial_tesc,
+    trivial_tese,
+    trivial_geom,
     static_vert,
+    push_vert,
 }
 
 #[allow(dead_code)]
@@ -226,18 +249,14 @@ impl TrivialPass {
         unsafe { create_trivial_pass(Arc::clone(device)) }
     }
 
-    pub(crate) fn create_framebuffers(&self, swapchain: &Swapchain) -> Vec<Arc<Framebuffer>> {
+    pub(crate) fn create_framebuffer(&self, heap: &ImageHeap) -> Arc<Framebuffer> {
+        let extent = Extent2D::new(WINDOW_DIMS.0, WINDOW_DIMS.1);
+        let backbuffer = create_offscreen_backbuffer(heap, Format::BGRA8_SRGB, extent);
         unsafe {
-            swapchain
-                .views()
-                .into_iter()
-                .map(|view| {
-                    Arc::new(Framebuffer::new(
-                        Arc::clone(&self.pass),
-                        vec![Arc::clone(view).into()],
-                    ))
-                })
-                .collect()
+            Arc::new(Framebuffer::new(
+                Arc::clone(&self.pass),
+                vec![backbuffer.into()],
+            ))
         }
     }
 }
@@ -248,7 +267,7 @@ unsafe fn create_trivial_pass(device: Arc<Device>) -> TrivialPass {
         device,
         vec![AttachmentDescription {
             format: Format::BGRA8_SRGB,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             ..Default::default()
         }],
         vec![SubpassDesc {
@@ -363,3 +382,53 @@ impl TrivialRenderer {
         }
     }
 }
+
+/// Compute pipeline which writes to a single storage buffer.
+#[derive(Debug)]
+pub(crate) struct TrivialCompute {
+    shader: Arc<ShaderSpec>,
+    set_layout: Arc<SetLayout>,
+    desc: DescriptorSet,
+}
+
+impl TrivialCompute {
+    pub(crate) const fn group_count() -> u32 {
+        1
+    }
+
+    pub(crate) fn new(resources: &TestResources) -> Self {
+        let device = resources.device();
+        let set_layout = Arc::new(SetLayout::new(
+            Arc::clone(device),
+            set_layout_desc![(0, StorageBuffer, COMPUTE_BIT)],
+        ));
+        let shader = Arc::new(Arc::clone(&resources.shaders.trivial_comp).into());
+
+        let mut desc = resources.descriptors.alloc(Lifetime::Static, &set_layout);
+        desc.write_buffer(0, resources.empty_storage_buffer.range());
+
+        TrivialCompute {
+            shader,
+            set_layout,
+            desc,
+        }
+    }
+
+    pub(crate) fn pipe_desc(&self) -> ComputePipelineDesc {
+        let mut desc = ComputePipelineDesc::new(Arc::clone(&self.shader));
+        desc.layout.set_layouts = smallvec::smallvec![Arc::clone(&self.set_layout)];
+        desc
+    }
+
+    pub(crate) fn dispatch(&self, pipelines: &PipelineCache, cmds: &mut CmdBuffer) {
+        assert_eq!(cmds.state(), CmdBufferState::Recording);
+
+        let pipe = unsafe { pipelines.get_or_create_compute(&self.pipe_desc()) };
+        cmds.bind_compute_pipe(&pipe);
+        cmds.bind_compute_descs(0, &self.desc);
+
+        unsafe {
+            cmds.dispatch(Self::group_count(), 1, 1);
+        }
+    }
+}
@@ -56,6 +56,26 @@ impl<T> SliceExt for [T] {
     }
 }
 
+/// Marker for plain data which may be viewed as bytes, such as push
+/// constants.
+///
+/// # Safety
+///
+/// The type must contain no padding, pointers, or references. In
+/// practice, structs should be `#[repr(C)]` with fields laid out to
+/// avoid padding.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*$(,)?) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
 #[macro_export]
 macro_rules! c_str {
     ($($str:expr),*$(,)*) => {
@@ -1,4 +1,4 @@
-use crate::Instance;
+use crate::{Extent2D, Instance};
 
 /// Trait to create a surface for a particular window system.
 pub trait Window {
@@ -8,6 +8,10 @@ pub trait Window {
 
     /// Creates a surface attached to this window.
     fn create_surface(&self, instance: &Instance) -> Result<vk::SurfaceKHR, vk::Result>;
+
+    /// The size of the window's drawable area in pixels. Used as the
+    /// swapchain extent when the surface doesn't dictate one.
+    fn extent(&self) -> Extent2D;
 }
 
 #[cfg(any(
@@ -58,4 +62,9 @@ impl Window for winit::window::Window {
         }
         Ok(handle)
     }
+
+    fn extent(&self) -> Extent2D {
+        let size = self.inner_size();
+        Extent2D::new(size.width, size.height)
+    }
 }
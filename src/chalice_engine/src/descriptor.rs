@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use device::{
     BufferRange, DescriptorHeap, DescriptorSet, DescriptorSetLayoutBinding,
-    DescriptorSetLayoutCache, DescriptorType, ImageView, Lifetime, Sampler,
+    DescriptorSetLayoutCache, DescriptorType, DescriptorWrite, ImageView, Lifetime, Sampler,
 };
 
 #[derive(Debug)]
@@ -38,6 +38,7 @@ pub(crate) fn create_descriptor_set<'r>(
                 count: ranges.len() as _,
                 stage_flags: *flags,
                 samplers: None,
+                flags: Default::default(),
             },
             DescriptorResource::StorageBuffers(ranges, flags) => DescriptorSetLayoutBinding {
                 binding: i as _,
@@ -45,6 +46,7 @@ pub(crate) fn create_descriptor_set<'r>(
                 count: ranges.len() as _,
                 stage_flags: *flags,
                 samplers: None,
+                flags: Default::default(),
             },
             &DescriptorResource::ImageSamplers {
                 ref images,
@@ -73,6 +75,7 @@ pub(crate) fn create_descriptor_set<'r>(
                     count,
                     stage_flags: stage_flags,
                     samplers,
+                    flags: Default::default(),
                 }
             }
         })
@@ -80,25 +83,34 @@ pub(crate) fn create_descriptor_set<'r>(
     let desc = device::DescriptorSetLayoutDesc { bindings };
     let layout = layout_cache.get_or_create_named(&desc, name);
     let mut set = heap.alloc(lifetime, &layout);
-    // TODO: Prooobably actually fully support DescriptorSet::update
-    // instead of making several separate calls.
-    for (i, res) in resources.iter().enumerate() {
-        match res {
-            DescriptorResource::UniformBuffers(ranges, _) => {
-                set.write_buffers(i as _, 0, ranges);
-            }
-            DescriptorResource::StorageBuffers(ranges, _) => {
-                set.write_buffers(i as _, 0, ranges);
-            }
-            &DescriptorResource::ImageSamplers {
-                ref images,
-                ref samplers,
-                ..
-            } => {
-                let samplers: Vec<_> = samplers.iter().map(|s| &***s).collect();
-                set.write_image_samplers(i as _, 0, images, &samplers[..]);
+    let samplers: Vec<Vec<&Sampler>> = resources
+        .iter()
+        .map(|res| match res {
+            DescriptorResource::ImageSamplers { samplers, .. } => {
+                samplers.iter().map(|s| &***s).collect()
             }
-        }
-    }
+            _ => Vec::new(),
+        })
+        .collect();
+    let writes: Vec<_> = resources
+        .iter()
+        .zip(samplers.iter())
+        .enumerate()
+        .map(|(i, (res, samplers))| match res {
+            DescriptorResource::UniformBuffers(buffers, _)
+            | DescriptorResource::StorageBuffers(buffers, _) => DescriptorWrite::Buffers {
+                binding: i as _,
+                first_element: 0,
+                buffers,
+            },
+            DescriptorResource::ImageSamplers { images, .. } => DescriptorWrite::ImageSamplers {
+                binding: i as _,
+                first_element: 0,
+                views: images,
+                samplers,
+            },
+        })
+        .collect();
+    set.update(&writes, &[]);
     set
 }
@@ -11,22 +11,42 @@ use crate::*;
 #[derive(Debug)]
 pub struct Settings {
     staging_buffer_size: vk::DeviceSize,
+    /// Present mode and surface format preferences. Ignored when
+    /// headless.
+    pub swapchain: device::SwapchainOptions,
+    /// Logs a memory report at the start of every frame.
+    pub log_memory_report: bool,
+    /// Suballocation algorithm of the buffer and image heaps.
+    pub allocator: device::AllocatorKind,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             staging_buffer_size: 8 * 1024 * 1024,
+            swapchain: Default::default(),
+            log_memory_report: false,
+            allocator: Default::default(),
         }
     }
 }
 
+/// The image the engine renders the final frame into.
+#[derive(Debug)]
+enum Backbuffer {
+    Swapchain(device::Swapchain),
+    /// Stands in for the swapchain when running headless.
+    Offscreen(Arc<device::ImageView>),
+}
+
 #[derive(Debug)]
 pub struct Engine {
     settings: Settings,
     queues: Vec<Vec<Arc<device::Queue>>>,
     graphics_queue: Arc<device::Queue>,
-    swapchain: device::Swapchain,
+    device: Arc<device::Device>,
+    backbuffer: Backbuffer,
+    window_extent: device::Extent2D,
     swapchain_index: u32,
     acquire_semaphore: device::BinarySemaphore,
     buffer_heap: Arc<device::BufferHeap>,
@@ -41,7 +61,6 @@ pub struct Engine {
     // TODO: Not a huge fan of mutexing this. A side effect of shoving
     // everything on one big struct.
     staging: Mutex<StagingBuffer>,
-    // TODO: (optional) staging upload queue
     // TODO?: image/(vertex) buffer memory management with garbage
     // collection. Possibly out of scope but solves a basic problem
     // while also ensuring images aren't deleted before frame is over.
@@ -53,31 +72,76 @@ impl Engine {
         window: &impl device::Window,
         settings: Settings,
     ) -> DeviceResult<Self> {
-        let (swapchain, queues) = device::init_device_and_swapchain(app_info, window)?;
-        let device = swapchain.device();
+        let (swapchain, queues) =
+            device::init_device_and_swapchain(app_info, window, settings.swapchain.clone())?;
+        let device = Arc::clone(swapchain.device());
+        Ok(Self::new(
+            device,
+            queues,
+            |_| Backbuffer::Swapchain(swapchain),
+            window.extent(),
+            settings,
+        ))
+    }
+
+    /// Creates an engine with no window or surface which renders into
+    /// an offscreen backbuffer of the given size.
+    pub fn headless(
+        app_info: device::AppInfo,
+        extent: device::Extent2D,
+        settings: Settings,
+    ) -> DeviceResult<Self> {
+        let (device, queues) = device::init_device_headless(app_info)?;
+        Ok(Self::new(
+            device,
+            queues,
+            |heap| {
+                let format = device::Format::BGRA8_SRGB;
+                Backbuffer::Offscreen(device::create_offscreen_backbuffer(heap, format, extent))
+            },
+            extent,
+            settings,
+        ))
+    }
+
+    fn new(
+        device: Arc<device::Device>,
+        queues: Vec<Vec<Arc<device::Queue>>>,
+        backbuffer: impl FnOnce(&device::ImageHeap) -> Backbuffer,
+        window_extent: device::Extent2D,
+        settings: Settings,
+    ) -> Self {
         let graphics_queue = Arc::clone(&queues[0][0]);
-        Ok(Self {
+        // Uploads go through a dedicated transfer queue if available.
+        let transfer_queue = Arc::clone(queues[2].first().unwrap_or(&graphics_queue));
+        let image_heap = device::ImageHeap::with_allocator(Arc::clone(&device), settings.allocator);
+        Self {
             queues,
-            buffer_heap: device::BufferHeap::new(Arc::clone(device)),
-            image_heap: device::ImageHeap::new(Arc::clone(device)),
+            buffer_heap: device::BufferHeap::with_allocator(
+                Arc::clone(&device),
+                settings.allocator,
+            ),
+            backbuffer: backbuffer(&image_heap),
+            image_heap,
+            window_extent,
             swapchain_index: 0,
-            acquire_semaphore: device::BinarySemaphore::new(Arc::clone(device)),
+            acquire_semaphore: device::BinarySemaphore::new(Arc::clone(&device)),
             framebuffers: Default::default(),
             shaders: Default::default(),
             cache_key: 0,
-            pipelines: device::PipelineCache::new(device),
-            set_layouts: device::DescriptorSetLayoutCache::new(Arc::clone(device)),
-            descriptor_heap: Arc::new(device::DescriptorHeap::new(device)),
-            samplers: device::SamplerCache::new(Arc::clone(device)),
+            pipelines: device::PipelineCache::new(&device),
+            set_layouts: device::DescriptorSetLayoutCache::new(Arc::clone(&device)),
+            descriptor_heap: Arc::new(device::DescriptorHeap::new(&device)),
+            samplers: device::SamplerCache::new(Arc::clone(&device)),
             staging: Mutex::new(StagingBuffer::new(
                 Arc::clone(&graphics_queue),
-                Arc::clone(&graphics_queue),
+                transfer_queue,
                 settings.staging_buffer_size,
             )),
             graphics_queue,
-            swapchain,
+            device,
             settings,
-        })
+        }
     }
 
     pub fn settings(&self) -> &Settings {
@@ -88,35 +152,191 @@ impl Engine {
         &self.queues
     }
 
-    pub fn swapchain(&self) -> &device::Swapchain {
-        &self.swapchain
+    #[inline]
+    pub fn graphics_queue(&self) -> &Arc<device::Queue> {
+        &self.graphics_queue
+    }
+
+    /// The queue staging uploads are submitted to. This is the graphics
+    /// queue if the device has no dedicated transfer queue family.
+    pub fn transfer_queue(&self) -> Arc<device::Queue> {
+        Arc::clone(self.staging.lock().unwrap().transfer_queue())
+    }
+
+    /// Returns the swapchain, or `None` if running headless.
+    pub fn swapchain(&self) -> Option<&device::Swapchain> {
+        match &self.backbuffer {
+            Backbuffer::Swapchain(swapchain) => Some(swapchain),
+            Backbuffer::Offscreen(_) => None,
+        }
+    }
+
+    pub fn swapchain_mut(&mut self) -> Option<&mut device::Swapchain> {
+        match &mut self.backbuffer {
+            Backbuffer::Swapchain(swapchain) => Some(swapchain),
+            Backbuffer::Offscreen(_) => None,
+        }
     }
 
-    pub fn swapchain_mut(&mut self) -> &mut device::Swapchain {
-        &mut self.swapchain
+    #[inline]
+    pub fn is_headless(&self) -> bool {
+        self.swapchain().is_none()
     }
 
     pub fn swapchain_index(&self) -> u32 {
         self.swapchain_index
     }
 
-    pub fn swapchain_image(&self) -> &Arc<device::SwapchainView> {
-        &self.swapchain().views()[self.swapchain_index as usize]
+    /// Returns the acquired swapchain image, or `None` if running
+    /// headless. See also `backbuffer`.
+    pub fn swapchain_image(&self) -> Option<&Arc<device::SwapchainView>> {
+        Some(&self.swapchain()?.views()[self.swapchain_index as usize])
+    }
+
+    /// Returns the image to render the current frame into: either the
+    /// acquired swapchain image or the offscreen backbuffer.
+    pub fn backbuffer(&self) -> device::AttachmentImage {
+        match &self.backbuffer {
+            Backbuffer::Swapchain(swapchain) => {
+                Arc::clone(&swapchain.views()[self.swapchain_index as usize]).into()
+            }
+            Backbuffer::Offscreen(view) => Arc::clone(view).into(),
+        }
+    }
+
+    pub fn backbuffer_format(&self) -> device::Format {
+        match &self.backbuffer {
+            Backbuffer::Swapchain(swapchain) => swapchain.format(),
+            Backbuffer::Offscreen(view) => view.format(),
+        }
+    }
+
+    pub fn backbuffer_extent(&self) -> device::Extent2D {
+        match &self.backbuffer {
+            Backbuffer::Swapchain(swapchain) => swapchain.extent(),
+            Backbuffer::Offscreen(view) => view.extent().to_2d(),
+        }
+    }
+
+    /// Informs the engine that the window has been resized. The
+    /// swapchain is recreated when the next image is acquired. Does
+    /// nothing when headless.
+    pub fn resize(&mut self, window_extent: device::Extent2D) {
+        self.window_extent = window_extent;
+        if let Backbuffer::Swapchain(swapchain) = &mut self.backbuffer {
+            swapchain.mark_out_of_date();
+        }
+    }
+
+    /// Recreates the swapchain, waiting for the device to go idle
+    /// first. Returns false if the window is minimized, in which case
+    /// there is nothing to render to.
+    pub fn recreate_swapchain(&mut self) -> DeviceResult<bool> {
+        let swapchain = match &mut self.backbuffer {
+            Backbuffer::Swapchain(swapchain) => swapchain,
+            Backbuffer::Offscreen(_) => return Ok(true),
+        };
+        self.device.wait_idle();
+        if !unsafe { swapchain.recreate(self.window_extent)? } {
+            return Ok(false);
+        }
+        // New views may reuse the handles of old ones.
+        self.framebuffers.clear_invalid();
+        Ok(true)
+    }
+
+    /// Acquires the next swapchain image, recreating the swapchain if
+    /// it is out of date. Returns `None` if the window is minimized,
+    /// in which case the frame should be skipped. Does nothing when
+    /// headless.
+    pub fn acquire_next_image(&mut self) -> DeviceResult<Option<u32>> {
+        loop {
+            let swapchain = match &mut self.backbuffer {
+                Backbuffer::Swapchain(swapchain) => swapchain,
+                Backbuffer::Offscreen(_) => return Ok(Some(0)),
+            };
+            if swapchain.is_out_of_date() {
+                if !self.recreate_swapchain()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+            match swapchain.acquire_next_image(&mut self.acquire_semaphore) {
+                Ok(index) => {
+                    self.swapchain_index = index;
+                    return Ok(Some(index));
+                }
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
     }
 
-    pub fn acquire_next_image(&mut self) -> DeviceResult<u32> {
-        let index = self
-            .swapchain
-            .acquire_next_image(&mut self.acquire_semaphore)?;
-        self.swapchain_index = index;
-        Ok(index)
+    /// Presents the current swapchain image. Does nothing when
+    /// headless. If the swapchain turns out to be out of date, it is
+    /// recreated when the next image is acquired.
+    pub fn present(
+        &mut self,
+        wait_semaphores: &[&mut device::BinarySemaphore],
+    ) -> DeviceResult<()> {
+        if let Backbuffer::Swapchain(swapchain) = &mut self.backbuffer {
+            let result = unsafe {
+                self.graphics_queue
+                    .present(wait_semaphores, swapchain, self.swapchain_index)
+            };
+            match result {
+                vk::Result::SUBOPTIMAL_KHR | vk::Result::ERROR_OUT_OF_DATE_KHR => {}
+                _ => {
+                    result.check()?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    pub fn present(&mut self, wait_semaphores: &[&mut device::BinarySemaphore]) {
-        unsafe {
-            self.graphics_queue
-                .present(wait_semaphores, &mut self.swapchain, self.swapchain_index);
+    /// Submits a frame's commands to the graphics queue. The
+    /// submission first acquires ownership of resources uploaded on the
+    /// transfer queue and waits for all uploads to complete. When not
+    /// headless, it also waits for the acquired swapchain image.
+    pub unsafe fn submit_frame(
+        &mut self,
+        cmds: &[vk::CommandBuffer],
+        signal_semaphore: &mut device::BinarySemaphore,
+    ) -> device::Submission {
+        let mut all_cmds = Vec::with_capacity(cmds.len() + 1);
+        if self.staging.get_mut().unwrap().has_pending_acquires() {
+            let level = vk::CommandBufferLevel::PRIMARY;
+            let family = self.graphics_queue.family().index();
+            all_cmds.push(self.with_command_buffer(level, family, |mut acquire_cmds| {
+                acquire_cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+                self.staging
+                    .lock()
+                    .unwrap()
+                    .record_acquires(&mut acquire_cmds);
+                acquire_cmds.end()
+            }));
+        }
+        all_cmds.extend_from_slice(cmds);
+
+        let headless = self.is_headless();
+        let mut wait_sems = Vec::with_capacity(2);
+        if !headless {
+            wait_sems.push(device::WaitInfo {
+                semaphore: self.acquire_semaphore.inner_mut(),
+                value: 0,
+                stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT_BIT,
+            });
         }
+        wait_sems.extend(self.staging.get_mut().unwrap().wait_info());
+
+        self.graphics_queue.submit(&[device::SubmitInfo {
+            wait_sems: &wait_sems,
+            sig_sems: &[device::SignalInfo {
+                semaphore: signal_semaphore.inner_mut(),
+                value: 0,
+            }],
+            cmds: &all_cmds,
+        }])
     }
 
     pub fn acquire_semaphore_mut(&mut self) -> &mut device::BinarySemaphore {
@@ -124,11 +344,11 @@ impl Engine {
     }
 
     pub fn device(&self) -> &Arc<device::Device> {
-        self.swapchain.device()
+        &self.device
     }
 
     pub fn device_ref(&self) -> Arc<device::Device> {
-        Arc::clone(self.swapchain.device())
+        Arc::clone(&self.device)
     }
 
     pub fn image_heap(&self) -> &device::ImageHeap {
@@ -143,11 +363,81 @@ impl Engine {
         &mut self.pipelines
     }
 
+    /// Merges a pipeline cache previously written by
+    /// `save_pipeline_cache` into the engine's cache. Caches written by
+    /// a different device or driver are rejected with `InvalidData`
+    /// and leave the current cache untouched.
+    pub fn load_pipeline_cache(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let loaded = device::PipelineBinaryCache::from_path(Arc::clone(&self.device), path)?;
+        self.pipelines.binary_cache_mut().merge(&[&loaded]);
+        Ok(())
+    }
+
+    /// Writes the engine's pipeline cache to a file so it may be
+    /// loaded on a later run.
+    pub fn save_pipeline_cache(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        debug!("Saving pipeline cache to {:?}", path.as_ref());
+        self.pipelines.binary_cache().save(path)
+    }
+
     /// Does top-of-frame housekeeping.
     pub fn new_frame(&mut self) {
         self.pipelines.commit();
         self.set_layouts.commit();
         self.samplers.commit();
+        if self.settings.log_memory_report {
+            debug!("{}", self.memory_report());
+        }
+    }
+
+    /// Summarizes memory usage of the buffer and image heaps as well as
+    /// dedicated allocations.
+    pub fn memory_report(&self) -> device::MemoryReport {
+        let pools: [&dyn device::MemoryPools; 2] = [&*self.buffer_heap, &self.image_heap];
+        device::MemoryReport::collect(&self.device, &pools)
+    }
+
+    /// Compacts static buffer and image memory, releasing chunks which
+    /// end up empty. Waits for the device to become idle, so this is
+    /// best called between frames when memory usage has dropped, e.g.
+    /// after a level or project is unloaded.
+    ///
+    /// Only allocations in `buffers` and images of `images` can be
+    /// moved; see `BufferHeap::defragment` and `ImageHeap::defragment`.
+    /// Descriptor sets referring to them must be recreated afterwards,
+    /// except for the bindless heap, which is updated automatically.
+    pub unsafe fn defragment_memory(
+        &mut self,
+        buffers: &mut [&mut device::BufferAlloc],
+        images: &mut [&mut Arc<device::ImageView>],
+    ) -> DeviceResult<device::DefragStats> {
+        self.device.wait_idle();
+
+        let mut defrag = device::Defragmentation::default();
+        let level = vk::CommandBufferLevel::PRIMARY;
+        let family = self.graphics_queue.family().index();
+        let (cmds, res) = self.with_command_buffer(level, family, |mut cmds| {
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            // Take ownership of uploaded resources before moving them
+            self.staging.lock().unwrap().record_acquires(&mut cmds);
+            self.buffer_heap.defragment(&mut cmds, buffers, &mut defrag);
+            let res = self.image_heap.defragment(&mut cmds, images, &mut defrag);
+            (cmds.end(), res)
+        });
+        self.graphics_queue
+            .submit(&[device::SubmitInfo {
+                cmds: &[cmds],
+                ..Default::default()
+            }])
+            .wait(u64::MAX)
+            .unwrap();
+
+        // Moves recorded before an error have still taken place
+        let stats = defrag.stats();
+        std::mem::drop(defrag);
+        res?;
+        debug!("defragmented memory: {:?}", stats);
+        Ok(stats)
     }
 
     pub unsafe fn reclaim_transient_resources(&mut self) {
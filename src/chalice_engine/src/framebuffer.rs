@@ -62,6 +62,15 @@ impl FramebufferCache {
         }
     }
 
+    /// Removes framebuffers which reference the views of a swapchain
+    /// that has since been recreated.
+    pub fn clear_invalid(&mut self) {
+        self.framebuffers
+            .get_mut()
+            .unwrap()
+            .retain(|_, fb| fb.is_swapchain_valid());
+    }
+
     pub fn clear_unused(&mut self) {
         // If there are no external references to the views attached
         // to a framebuffer, we can remove it from the cache since it
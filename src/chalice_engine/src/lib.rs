@@ -2,11 +2,22 @@ mod commands;
 mod descriptor;
 mod engine;
 mod framebuffer;
+mod mipmap;
+mod profiler;
+mod readback;
+mod render_graph;
 mod staging;
 mod utils;
 
 pub use descriptor::*;
 pub use engine::*;
 pub use framebuffer::*;
+pub use mipmap::*;
+pub use profiler::*;
+pub use readback::*;
+pub use render_graph::*;
 pub use staging::*;
 pub use utils::*;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,157 @@
+use device::{
+    CmdBuffer, DeviceResult, Filter, Image, ImageFlags, ImageSubresources, ImageUsage, ImageUse,
+    SampleCount,
+};
+
+/// Checks whether mipmaps may be generated for an image of the given
+/// format by linear blitting.
+pub fn supports_mipmap_generation(device: &device::Device, format: device::Format) -> bool {
+    use vk::FormatFeatureFlags as F;
+    let features = device.format_properties(format).optimal_tiling_features;
+    features.contains(F::BLIT_SRC_BIT | F::BLIT_DST_BIT | F::SAMPLED_IMAGE_FILTER_LINEAR_BIT)
+}
+
+/// Fills mip levels 1 and up of every layer of `image` by repeatedly
+/// downsampling mip level 0 with a linear filter.
+///
+/// Mip level 0 must already hold the source data, e.g. as left by
+/// `StagingBuffer::stage_image`. The contents of the remaining levels
+/// are discarded. Afterwards, every level is ready to be sampled in
+/// fragment shaders.
+///
+/// Staging does not call this; callers uploading mipmapped images must
+/// do so themselves. `cmds` must belong to a graphics queue family.
+pub unsafe fn generate_mipmaps(cmds: &mut CmdBuffer<'_>, image: &Image) -> DeviceResult<()> {
+    let mip_levels = image.mip_levels();
+    if mip_levels == 1 {
+        return Ok(());
+    }
+
+    if !supports_mipmap_generation(cmds.device(), image.format()) {
+        let msg = format!("format {:?} does not support linear blits", image.format());
+        return Err(msg.into());
+    }
+    assert!(image.flags().contains(ImageFlags::TRANSFER_SRC));
+    assert_eq!(image.samples(), SampleCount::One);
+
+    let all = image.all_subresources();
+    let level_range = |base: u32, count: u32| ImageSubresources {
+        mip_levels: [base, base + count],
+        ..all
+    };
+
+    image.discard(&level_range(1, mip_levels - 1));
+    cmds.use_images(&[
+        ImageUse {
+            image,
+            subresources: level_range(0, 1),
+            usage: ImageUsage::TransferSrc,
+        },
+        ImageUse {
+            image,
+            subresources: level_range(1, mip_levels - 1),
+            usage: ImageUsage::TransferDst,
+        },
+    ]);
+
+    let corner = |level: u32| {
+        let extent = image.extent().mip_level(level);
+        vk::Offset3D {
+            x: extent.width as _,
+            y: extent.height as _,
+            z: extent.depth as _,
+        }
+    };
+    for level in 1..mip_levels {
+        if level > 1 {
+            cmds.use_image(image, level_range(level - 1, 1), ImageUsage::TransferSrc);
+        }
+        cmds.blit_image(
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[vk::ImageBlit {
+                src_subresource: all.to_mip_layers(level - 1),
+                src_offsets: [Default::default(), corner(level - 1)],
+                dst_subresource: all.to_mip_layers(level),
+                dst_offsets: [Default::default(), corner(level)],
+            }],
+            Filter::Linear,
+        );
+    }
+
+    cmds.use_image(image, all, ImageUsage::SampleFragment);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use device::{Extent2D, Format, ImageDef, ImageType};
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn generate() {
+        let engine = headless_engine("mipmap test", Extent2D::new(1, 1));
+        let device = engine.device();
+        assert!(supports_mipmap_generation(device, Format::RGBA8));
+
+        // Odd, non-power-of-two extent: 7x5 -> 3x2 -> 1x1
+        let image = ImageDef::new(
+            device,
+            ImageFlags::TRANSFER_SRC,
+            ImageType::Dim2,
+            Format::RGBA8,
+            SampleCount::One,
+            Extent2D::new(7, 5).into(),
+            3,
+            2,
+        )
+        .build_image(engine.image_heap());
+        // A solid color per layer survives any amount of filtering.
+        let colors = [[200u8, 100, 50, 255], [10, 20, 30, 40]];
+        let data: Vec<u8> = colors
+            .iter()
+            .flat_map(|color| color.iter().copied().cycle().take(7 * 5 * 4))
+            .collect();
+        upload_image(&engine, &image, &data);
+        submit_and_wait(&engine, |cmds| unsafe {
+            generate_mipmaps(cmds, &image).unwrap();
+        });
+
+        for (layer, color) in colors.iter().enumerate() {
+            let mid = engine.read_image(&image, 1, layer as _).unwrap();
+            assert_eq!(mid.extent, Extent2D::new(3, 2));
+            assert_eq!(mid.data, color.repeat(6));
+            let last = engine.read_image(&image, 2, layer as _).unwrap();
+            assert_eq!(last.extent, Extent2D::new(1, 1));
+            assert_eq!(&last.data[..], &color[..]);
+        }
+    }
+
+    #[test]
+    fn unsupported_format() {
+        let engine = headless_engine("mipmap test", Extent2D::new(1, 1));
+        let device = engine.device();
+        // Integer formats can't be filtered linearly.
+        assert!(!supports_mipmap_generation(device, Format::RGBA8U));
+
+        let image = ImageDef::new(
+            device,
+            ImageFlags::TRANSFER_SRC,
+            ImageType::Dim2,
+            Format::RGBA8U,
+            SampleCount::One,
+            Extent2D::new(4, 4).into(),
+            3,
+            1,
+        )
+        .build_image(engine.image_heap());
+        submit_and_wait(&engine, |cmds| unsafe {
+            assert!(generate_mipmaps(cmds, &image).is_err());
+        });
+    }
+}
@@ -0,0 +1,351 @@
+use std::sync::Arc;
+
+use device::{CmdBuffer, Device, QueryPool, QueryType, Submission};
+use log::warn;
+
+/// Measures the GPU time spent in named regions of a frame.
+///
+/// Timestamps are written into a per-frame query pool and read back
+/// once the frame's submission completes, so results lag a few frames
+/// behind recording.
+///
+/// If the device supports pipeline statistics queries, top-level
+/// regions also count primitives and shader invocations. Such regions
+/// must begin and end in the same command buffer and on the same side
+/// of a render pass boundary.
+#[derive(Debug)]
+pub struct GpuProfiler {
+    device: Arc<Device>,
+    frames: Vec<ProfilerFrame>,
+    cur_frame: usize,
+    stack: Vec<usize>,
+    results: Vec<RegionTiming>,
+}
+
+#[derive(Debug)]
+struct ProfilerFrame {
+    pool: QueryPool,
+    // One query per region; only top-level regions are recorded.
+    stats_pool: Option<QueryPool>,
+    regions: Vec<Region>,
+    submission: Option<Submission>,
+    timestamp_mask: u64,
+    // Set if the frame ran out of queries.
+    overflowed: bool,
+}
+
+#[derive(Debug)]
+struct Region {
+    name: String,
+    depth: u32,
+    begin: u32,
+    end: Option<u32>,
+}
+
+/// The measured duration of a single profiler region.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionTiming {
+    pub name: String,
+    /// Nesting depth of the region; top-level regions have depth 0.
+    pub depth: u32,
+    pub ms: f64,
+    /// Present for top-level regions if the device supports pipeline
+    /// statistics queries.
+    pub stats: Option<PipelineStats>,
+}
+
+/// Pipeline statistics gathered over a profiler region.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PipelineStats {
+    pub input_primitives: u64,
+    pub vertex_invocations: u64,
+    pub fragment_invocations: u64,
+    pub compute_invocations: u64,
+}
+
+// Results are written in order of increasing bit position.
+fn statistics() -> vk::QueryPipelineStatisticFlags {
+    use vk::QueryPipelineStatisticFlags as F;
+    F::INPUT_ASSEMBLY_PRIMITIVES_BIT
+        | F::VERTEX_SHADER_INVOCATIONS_BIT
+        | F::FRAGMENT_SHADER_INVOCATIONS_BIT
+        | F::COMPUTE_SHADER_INVOCATIONS_BIT
+}
+
+/// Identifies a region opened by `GpuProfiler::begin_region`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegionId(usize);
+
+impl ProfilerFrame {
+    fn next_query(&self) -> u32 {
+        2 * self.regions.len() as u32
+    }
+}
+
+impl GpuProfiler {
+    /// Creates a profiler which can track up to `max_regions` regions
+    /// per frame with up to `frames_in_flight` frames pending at once.
+    pub fn new(device: Arc<Device>, max_regions: u32, frames_in_flight: usize) -> Self {
+        assert!(frames_in_flight > 0);
+        let has_stats = device.features().pipeline_statistics_query == vk::TRUE;
+        let frames = (0..frames_in_flight)
+            .map(|i| {
+                let mut pool =
+                    QueryPool::new(Arc::clone(&device), QueryType::Timestamp, 2 * max_regions);
+                pool.set_name(format!("profiler.frames[{}].pool", i));
+                let stats_pool = has_stats.then(|| {
+                    let mut pool =
+                        QueryPool::with_statistics(Arc::clone(&device), statistics(), max_regions);
+                    pool.set_name(format!("profiler.frames[{}].stats_pool", i));
+                    pool
+                });
+                ProfilerFrame {
+                    pool,
+                    stats_pool,
+                    regions: Vec::new(),
+                    submission: None,
+                    timestamp_mask: 0,
+                    overflowed: false,
+                }
+            })
+            .collect();
+        Self {
+            device,
+            frames,
+            cur_frame: 0,
+            stack: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// Timings of the most recently completed frame.
+    #[inline]
+    pub fn results(&self) -> &[RegionTiming] {
+        &self.results
+    }
+
+    fn frame(&self) -> &ProfilerFrame {
+        &self.frames[self.cur_frame]
+    }
+
+    fn frame_mut(&mut self) -> &mut ProfilerFrame {
+        &mut self.frames[self.cur_frame]
+    }
+
+    /// Starts recording a new frame. `cmds` must be the first command
+    /// buffer of the frame and must not be inside a render pass.
+    ///
+    /// If the frame being recycled is still in flight, this blocks
+    /// until it completes.
+    pub fn begin_frame(&mut self, cmds: &mut CmdBuffer<'_>) {
+        assert!(self.stack.is_empty(), "unclosed profiler region");
+        self.cur_frame = (self.cur_frame + 1) % self.frames.len();
+        if let Some(submission) = self.frame_mut().submission.take() {
+            submission.wait(u64::MAX).unwrap();
+            self.read_back();
+        }
+
+        let valid_bits = cmds.queue_family().properties().timestamp_valid_bits;
+        let frame = self.frame_mut();
+        frame.regions.clear();
+        frame.overflowed = false;
+        frame.timestamp_mask = if valid_bits >= 64 {
+            !0
+        } else {
+            (1u64 << valid_bits) - 1
+        };
+        unsafe {
+            cmds.reset_query_pool(&frame.pool, 0, frame.pool.count());
+            if let Some(pool) = &frame.stats_pool {
+                cmds.reset_query_pool(pool, 0, pool.count());
+            }
+        }
+    }
+
+    /// Associates the submission which executes the frame's commands
+    /// with the frame. Results become available once it completes.
+    pub fn end_frame(&mut self, submission: Submission) {
+        assert!(self.stack.is_empty(), "unclosed profiler region");
+        self.frame_mut().submission = Some(submission);
+    }
+
+    /// Updates `results` with any frames whose submissions have
+    /// completed without blocking.
+    pub fn poll(&mut self) {
+        let count = self.frames.len();
+        // Visit frames oldest first so the newest finished frame wins.
+        for i in 1..=count {
+            let idx = (self.cur_frame + i) % count;
+            let done = self.frames[idx]
+                .submission
+                .as_ref()
+                .map_or(false, |submission| submission.is_complete());
+            if done {
+                self.frames[idx].submission = None;
+                self.read_back_frame(idx);
+            }
+        }
+    }
+
+    fn read_back(&mut self) {
+        self.read_back_frame(self.cur_frame);
+    }
+
+    fn read_back_frame(&mut self, idx: usize) {
+        let frame = &self.frames[idx];
+        if frame.overflowed {
+            warn!("profiler ran out of queries; some regions were dropped");
+        }
+        let query_count = frame.next_query();
+        if query_count == 0 {
+            self.results.clear();
+            return;
+        }
+
+        let mask = frame.timestamp_mask;
+        let period = self.device.limits().timestamp_period as f64;
+
+        let stamps = frame.pool.get_results(0, query_count).unwrap();
+        self.results = frame
+            .regions
+            .iter()
+            .filter_map(|region| {
+                let begin = stamps[region.begin as usize];
+                let end = stamps[region.end? as usize];
+                let ticks = end.wrapping_sub(begin) & mask;
+                let stats = match &frame.stats_pool {
+                    Some(pool) if region.depth == 0 => {
+                        let query = region.begin / 2;
+                        let values = pool.get_results(query, 1).unwrap();
+                        Some(PipelineStats {
+                            input_primitives: values[0],
+                            vertex_invocations: values[1],
+                            fragment_invocations: values[2],
+                            compute_invocations: values[3],
+                        })
+                    }
+                    _ => None,
+                };
+                Some(RegionTiming {
+                    name: region.name.clone(),
+                    depth: region.depth,
+                    ms: ticks as f64 * period / 1_000_000.0,
+                    stats,
+                })
+            })
+            .collect();
+    }
+
+    /// Opens a named region. Regions may be nested but must be closed
+    /// in reverse order of opening.
+    pub fn begin_region(&mut self, cmds: &mut CmdBuffer<'_>, name: impl Into<String>) -> RegionId {
+        let depth = self.stack.len() as u32;
+        let frame = self.frame_mut();
+        let begin = frame.next_query();
+        let id = RegionId(frame.regions.len());
+        if begin + 2 > frame.pool.count() {
+            frame.overflowed = true;
+        } else {
+            unsafe {
+                cmds.write_timestamp(vk::PipelineStageFlags::TOP_OF_PIPE_BIT, &frame.pool, begin);
+                if let (Some(pool), 0) = (&frame.stats_pool, depth) {
+                    cmds.begin_query(pool, id.0 as u32, false);
+                }
+            }
+            frame.regions.push(Region {
+                name: name.into(),
+                depth,
+                begin,
+                end: None,
+            });
+        }
+        self.stack.push(id.0);
+        id
+    }
+
+    pub fn end_region(&mut self, cmds: &mut CmdBuffer<'_>, id: RegionId) {
+        assert_eq!(self.stack.pop(), Some(id.0), "profiler regions not nested");
+        let frame = self.frame_mut();
+        if let Some(region) = frame.regions.get_mut(id.0) {
+            let end = region.begin + 1;
+            region.end = Some(end);
+            unsafe {
+                cmds.write_timestamp(vk::PipelineStageFlags::BOTTOM_OF_PIPE_BIT, &frame.pool, end);
+                if let (Some(pool), 0) = (&frame.stats_pool, region.depth) {
+                    cmds.end_query(pool, id.0 as u32);
+                }
+            }
+        }
+    }
+
+    /// Records the commands issued by `f` as a named region.
+    pub fn scope<'pool, R>(
+        &mut self,
+        cmds: &mut CmdBuffer<'pool>,
+        name: impl Into<String>,
+        f: impl FnOnce(&mut Self, &mut CmdBuffer<'pool>) -> R,
+    ) -> R {
+        let id = self.begin_region(cmds, name);
+        let res = f(self, &mut *cmds);
+        self.end_region(cmds, id);
+        res
+    }
+
+    /// The number of regions recorded so far this frame.
+    pub fn region_count(&self) -> usize {
+        self.frame().regions.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use device::{AppInfo, CmdPool, SubmitInfo};
+
+    use super::*;
+
+    #[test]
+    fn headless() {
+        let app_info = AppInfo {
+            name: "profiler test".to_owned(),
+            debug: true,
+            test: true,
+            ..Default::default()
+        };
+        let (device, queues) = device::init_device_headless(app_info).unwrap();
+        let queue = &queues[0][0];
+        let mut profiler = GpuProfiler::new(Arc::clone(&device), 4, 1);
+
+        let mut pool = CmdPool::new_transient(queue.family());
+        let mut cmds = CmdBuffer::new(&mut pool, vk::CommandBufferLevel::PRIMARY);
+        cmds.begin(Default::default(), None);
+        profiler.begin_frame(&mut cmds);
+        profiler.scope(&mut cmds, "outer", |profiler, cmds| {
+            profiler.scope(cmds, "inner", |_, _| {});
+        });
+        let cmds = cmds.end();
+        let submission = unsafe {
+            queue.submit(&[SubmitInfo {
+                cmds: &[cmds],
+                ..Default::default()
+            }])
+        };
+        profiler.end_frame(submission.clone());
+        submission.wait(u64::MAX).unwrap();
+        profiler.poll();
+
+        let results = profiler.results();
+        assert_eq!(results.len(), 2);
+        assert_eq!((&results[0].name[..], results[0].depth), ("outer", 0));
+        assert_eq!((&results[1].name[..], results[1].depth), ("inner", 1));
+        assert!(results.iter().all(|timing| timing.ms >= 0.0));
+
+        let stats = device.features().pipeline_statistics_query == vk::TRUE;
+        assert_eq!(results[0].stats.is_some(), stats);
+        assert_eq!(results[1].stats, None);
+    }
+}
@@ -0,0 +1,339 @@
+use std::io;
+use std::path::Path;
+
+use device::{
+    AttachmentImage, BufferBinding, DeviceResult, Extent2D, Extent3D, Format, Image,
+    ImageSubresources, ImageUsage, ImageView, Lifetime, MemoryMapping, MemoryRegion, SampleCount,
+    SwapchainView,
+};
+use log::debug;
+
+use crate::Engine;
+
+/// A 2D image which has been copied back into host memory.
+///
+/// Texels are tightly packed in row-major order. BGRA images are
+/// swizzled to RGBA order while being read back, though `format` still
+/// reports the format of the source image.
+#[derive(Clone, Debug)]
+pub struct ReadbackImage {
+    pub extent: Extent2D,
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Source<'a> {
+    // Barriers come from the image's tracked state.
+    Image(&'a Image, ImageSubresources),
+    // Swapchain images aren't tracked, so the caller supplies the
+    // layout.
+    Swapchain(&'a SwapchainView, vk::ImageLayout),
+}
+
+impl ReadbackImage {
+    /// Converts the image to an 8-bit RGBA image. Returns `None` if the
+    /// source format is not an 8-bit RGBA or BGRA format.
+    pub fn to_rgba_image(&self) -> Option<image::RgbaImage> {
+        match self.format {
+            Format::RGBA8 | Format::RGBA8_SRGB | Format::BGRA8 | Format::BGRA8_SRGB => {}
+            _ => return None,
+        }
+        image::RgbaImage::from_raw(self.extent.width, self.extent.height, self.data.clone())
+    }
+
+    /// Writes the image to a PNG file. Fails with `InvalidInput` if the
+    /// format can't be represented as an 8-bit RGBA image.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        debug!("Saving {:?} image to {:?}", self.format, path.as_ref());
+        let rgba = self.to_rgba_image().ok_or_else(|| {
+            let msg = format!("cannot save {:?} image as PNG", self.format);
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+        rgba.save_with_format(path, image::ImageFormat::Png)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+fn swizzle_bgra(data: &mut [u8]) {
+    for texel in data.chunks_exact_mut(4) {
+        texel.swap(0, 2);
+    }
+}
+
+impl Engine {
+    /// Copies a single layer of a single mip level of a 2D color image
+    /// back to the host. Blocks until the copy completes.
+    ///
+    /// The image must have been created with `ImageFlags::TRANSFER_SRC`
+    /// and all prior writes to it must have been submitted. Uploads
+    /// still owned by the transfer queue are acquired and waited on
+    /// first. The image's tracked state is updated to reflect the
+    /// transfer.
+    pub fn read_image(
+        &self,
+        image: &Image,
+        mip_level: u32,
+        layer: u32,
+    ) -> DeviceResult<ReadbackImage> {
+        if image.samples() != SampleCount::One {
+            Err("cannot read back a multisample image; resolve it first")?;
+        }
+        let sub = image.subresource_layers(mip_level, layer, 1);
+        let extent = image.extent().mip_level(mip_level);
+        unsafe { self.read_back(Source::Image(image, sub), sub.into(), extent) }
+    }
+
+    /// Copies a single layer of a single mip level of a view back to
+    /// the host. `mip_level` and `layer` are relative to the first mip
+    /// level and layer of the view.
+    pub fn read_image_view(
+        &self,
+        view: &ImageView,
+        mip_level: u32,
+        layer: u32,
+    ) -> DeviceResult<ReadbackImage> {
+        let sub = view.subresources();
+        assert!(mip_level < sub.mip_level_count(), "mip level out of range");
+        assert!(layer < sub.layer_count(), "layer out of range");
+        self.read_image(
+            view.image(),
+            sub.mip_levels[0] + mip_level,
+            sub.layers[0] + layer,
+        )
+    }
+
+    /// Copies a swapchain image back to the host. Fails if the
+    /// swapchain doesn't support being used as a transfer source.
+    ///
+    /// The image must be in `layout` with all prior writes submitted.
+    /// It is returned to `layout` afterwards.
+    pub fn read_swapchain_image(
+        &self,
+        view: &SwapchainView,
+        layout: vk::ImageLayout,
+    ) -> DeviceResult<ReadbackImage> {
+        if !view.usage().contains(vk::ImageUsageFlags::TRANSFER_SRC_BIT) {
+            Err("swapchain does not support TRANSFER_SRC usage")?;
+        }
+        let sub = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR_BIT,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let extent = view.extent().into();
+        unsafe { self.read_back(Source::Swapchain(view, layout), sub, extent) }
+    }
+
+    /// Copies the current backbuffer back to the host, e.g. to take a
+    /// screenshot after rendering a frame. `layout` is the layout of
+    /// the swapchain image and is ignored when headless.
+    pub fn read_backbuffer(&self, layout: vk::ImageLayout) -> DeviceResult<ReadbackImage> {
+        match self.backbuffer() {
+            AttachmentImage::Image(view) => self.read_image_view(&view, 0, 0),
+            AttachmentImage::Swapchain(view) => self.read_swapchain_image(&view, layout),
+        }
+    }
+
+    unsafe fn read_back(
+        &self,
+        src: Source<'_>,
+        sub: vk::ImageSubresourceLayers,
+        extent: Extent3D,
+    ) -> DeviceResult<ReadbackImage> {
+        let format = match src {
+            Source::Image(image, _) => image.format(),
+            Source::Swapchain(view, _) => view.format(),
+        };
+        if format.is_depth_stencil() {
+            Err(format!("cannot read back {:?} image", format))?;
+        }
+        if extent.depth != 1 {
+            Err("cannot read back a 3D image")?;
+        }
+        let texel = format.size() as vk::DeviceSize;
+        let size = extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * texel;
+        // The copy must start at a multiple of both the texel size and 4,
+        // which the pool's alignment doesn't guarantee for 3-component
+        // formats.
+        let copy_alignment = (1..=4).map(|n| n * texel).find(|x| x % 4 == 0).unwrap();
+        let alloc = self.buffer_heap().try_alloc(
+            BufferBinding::Storage,
+            Lifetime::Static,
+            MemoryMapping::Cached,
+            size + copy_alignment - 1,
+        )?;
+        let start = (copy_alignment - alloc.offset() % copy_alignment) % copy_alignment;
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: alloc.offset() + start,
+            image_subresource: sub,
+            image_extent: extent.into(),
+            ..Default::default()
+        };
+
+        let queue = self.graphics_queue();
+        let mut staging = self.staging().lock().unwrap();
+        let level = vk::CommandBufferLevel::PRIMARY;
+        let cmds = self.with_command_buffer(level, queue.family().index(), |mut cmds| {
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            // The image may have just been uploaded on the transfer queue
+            staging.record_acquires(&mut cmds);
+            let buffer_barrier = vk::BufferMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE_BIT,
+                dst_access_mask: vk::AccessFlags::HOST_READ_BIT,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                buffer: alloc.raw(),
+                offset: alloc.offset(),
+                size: alloc.size(),
+                ..Default::default()
+            };
+            match src {
+                Source::Image(image, sub) => {
+                    cmds.use_image(image, sub, ImageUsage::TransferSrc);
+                    cmds.copy_image_to_buffer(
+                        image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        alloc.buffer(),
+                        &[region],
+                    );
+                    cmds.pipeline_barrier(
+                        vk::PipelineStageFlags::TRANSFER_BIT,
+                        vk::PipelineStageFlags::HOST_BIT,
+                        Default::default(),
+                        &[],
+                        &[buffer_barrier],
+                        &[],
+                    );
+                }
+                Source::Swapchain(view, layout) => {
+                    assert_ne!(layout, vk::ImageLayout::UNDEFINED);
+                    let range = vk::ImageSubresourceRange {
+                        aspect_mask: sub.aspect_mask,
+                        base_mip_level: sub.mip_level,
+                        level_count: 1,
+                        base_array_layer: sub.base_array_layer,
+                        layer_count: sub.layer_count,
+                    };
+                    let barrier =
+                        |old_layout, new_layout, src_access, dst_access| vk::ImageMemoryBarrier {
+                            src_access_mask: src_access,
+                            dst_access_mask: dst_access,
+                            old_layout,
+                            new_layout,
+                            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            image: view.image(),
+                            subresource_range: range,
+                            ..Default::default()
+                        };
+                    cmds.pipeline_barrier(
+                        vk::PipelineStageFlags::ALL_COMMANDS_BIT,
+                        vk::PipelineStageFlags::TRANSFER_BIT,
+                        Default::default(),
+                        &[],
+                        &[],
+                        &[barrier(
+                            layout,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            vk::AccessFlags::MEMORY_WRITE_BIT,
+                            vk::AccessFlags::TRANSFER_READ_BIT,
+                        )],
+                    );
+                    cmds.copy_swapchain_image_to_buffer(
+                        view,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        alloc.buffer(),
+                        &[region],
+                    );
+                    cmds.pipeline_barrier(
+                        vk::PipelineStageFlags::TRANSFER_BIT,
+                        vk::PipelineStageFlags::ALL_COMMANDS_BIT | vk::PipelineStageFlags::HOST_BIT,
+                        Default::default(),
+                        &[],
+                        &[buffer_barrier],
+                        &[barrier(
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            layout,
+                            vk::AccessFlags::empty(),
+                            vk::AccessFlags::MEMORY_READ_BIT | vk::AccessFlags::MEMORY_WRITE_BIT,
+                        )],
+                    );
+                }
+            }
+            cmds.end()
+        });
+        let wait_sems: Vec<_> = staging.wait_info().into_iter().collect();
+        queue
+            .submit(&[device::SubmitInfo {
+                wait_sems: &wait_sems,
+                cmds: &[cmds],
+                ..Default::default()
+            }])
+            .wait(u64::MAX)
+            .unwrap();
+        std::mem::drop(staging);
+
+        alloc.invalidate()?;
+        let start = start as usize;
+        let mut data = alloc.as_bytes().unwrap()[start..start + size as usize].to_vec();
+        if let Format::BGRA8 | Format::BGRA8_SRGB = format {
+            swizzle_bgra(&mut data);
+        }
+        Ok(ReadbackImage {
+            extent: extent.to_2d(),
+            format,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use device::{ImageDef, ImageFlags, ImageType};
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn swizzle() {
+        let mut data = [1, 2, 3, 4, 5, 6, 7, 8];
+        swizzle_bgra(&mut data);
+        assert_eq!(data, [3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let extent = Extent2D::new(5, 3);
+        let engine = headless_engine("readback test", extent);
+        let pixels: Vec<u8> = (0..extent.width * extent.height * 4)
+            .map(|i| (i * 7) as u8)
+            .collect();
+
+        for &format in [Format::RGBA8, Format::BGRA8].iter() {
+            let image = ImageDef::new(
+                engine.device(),
+                ImageFlags::TRANSFER_SRC,
+                ImageType::Dim2,
+                format,
+                SampleCount::One,
+                extent.into(),
+                1,
+                1,
+            )
+            .build_image(engine.image_heap());
+            upload_image(&engine, &image, &pixels);
+
+            let readback = engine.read_image(&image, 0, 0).unwrap();
+            assert_eq!(readback.extent, extent);
+            assert_eq!(readback.format, format);
+            let mut expected = pixels.clone();
+            if format == Format::BGRA8 {
+                swizzle_bgra(&mut expected);
+            }
+            assert_eq!(readback.data, expected);
+        }
+    }
+}
@@ -0,0 +1,1112 @@
+use std::sync::Arc;
+
+use device::{
+    write_access, AttachmentDescription, AttachmentImage, BufferAlloc, CmdBuffer, DeviceBuffer,
+    DeviceResult, Extent2D, Format, ImageFlags, ImageUsage, ImageUse, ImageView, MemoryRegion,
+    RenderPass, SampleCount, SubpassContents, SubpassDesc,
+};
+use log::debug;
+
+use crate::{create_framebuffer_images, Engine, FramebufferImageInfo};
+
+/// Identifies a resource declared in a render graph.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ResourceId(usize);
+
+/// Identifies a pass declared in a render graph.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct PassId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearValue {
+    Color([f32; 4]),
+    DepthStencil(f32, u32),
+}
+
+#[derive(Debug)]
+enum ResourceKind {
+    /// An attachment allocated by the graph at the backbuffer extent.
+    Transient {
+        format: Format,
+        samples: SampleCount,
+    },
+    Backbuffer,
+    Image(Arc<ImageView>),
+    Buffer {
+        buffer: Arc<DeviceBuffer>,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    },
+}
+
+#[derive(Debug)]
+struct Resource {
+    name: String,
+    kind: ResourceKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Access {
+    Color,
+    DepthStencil,
+    DepthStencilRead,
+    Input,
+    Sample,
+    Buffer {
+        stages: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Use {
+    resource: ResourceId,
+    access: Access,
+    clear: Option<ClearValue>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PassKind {
+    Graphics,
+    Compute,
+}
+
+#[derive(Debug)]
+struct Pass {
+    name: String,
+    kind: PassKind,
+    uses: Vec<Use>,
+}
+
+/// Describes a frame as a sequence of passes and the resources they
+/// read and write.
+///
+/// Compiling the graph culls passes whose results are never used,
+/// merges consecutive graphics passes into subpasses of a single render
+/// pass where possible, and allocates transient attachments. Executing
+/// the compiled graph records the render passes along with any barriers
+/// required between them.
+///
+/// The backbuffer and imported resources are considered outputs of the
+/// graph; transient attachments are only kept alive by passes which
+/// read them.
+#[derive(Debug, Default)]
+pub struct RenderGraph {
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+}
+
+/// Declares the resources used by a single pass.
+#[derive(Debug)]
+pub struct PassBuilder<'g> {
+    graph: &'g mut RenderGraph,
+    id: PassId,
+}
+
+#[derive(Debug)]
+struct Step {
+    passes: Vec<PassId>,
+    render_pass: Option<Arc<RenderPass>>,
+    attachments: Vec<ResourceId>,
+    clear_values: Vec<Option<ClearValue>>,
+    // Image usages declared before the step, with whether to discard
+    // the previous contents.
+    image_uses: Vec<(ResourceId, ImageUsage, bool)>,
+    // Usages the render pass leaves attachments in.
+    final_usages: Vec<(ResourceId, ImageUsage)>,
+    buffer_src_stages: vk::PipelineStageFlags,
+    buffer_dst_stages: vk::PipelineStageFlags,
+    // (resource, src access, dst access)
+    buffer_barriers: Vec<(ResourceId, vk::AccessFlags, vk::AccessFlags)>,
+}
+
+/// A render graph ready to be executed. It must be recompiled if the
+/// backbuffer changes size.
+#[derive(Debug)]
+pub struct CompiledGraph {
+    resources: Vec<Resource>,
+    transients: Vec<Option<Arc<ImageView>>>,
+    live: Vec<bool>,
+    steps: Vec<Step>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct BufferState {
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    read_stages: vk::PipelineStageFlags,
+}
+
+impl From<ClearValue> for vk::ClearValue {
+    fn from(value: ClearValue) -> Self {
+        match value {
+            ClearValue::Color(color) => device::clear_color(color),
+            ClearValue::DepthStencil(depth, stencil) => device::clear_depth_stencil(depth, stencil),
+        }
+    }
+}
+
+impl Access {
+    fn is_attachment(self) -> bool {
+        matches!(
+            self,
+            Self::Color | Self::DepthStencil | Self::DepthStencilRead | Self::Input
+        )
+    }
+
+    fn writes(self) -> bool {
+        match self {
+            Self::Color | Self::DepthStencil => true,
+            Self::Buffer { access, .. } => access.intersects(write_access()),
+            _ => false,
+        }
+    }
+
+    fn image_usage(self, kind: PassKind) -> ImageUsage {
+        match (self, kind) {
+            (Self::Color, _) => ImageUsage::ColorAttachment,
+            (Self::DepthStencil, _) => ImageUsage::DepthStencilAttachment,
+            (Self::DepthStencilRead, _) => ImageUsage::DepthStencilReadOnly,
+            (Self::Input, _) => ImageUsage::InputAttachment,
+            (Self::Sample, PassKind::Graphics) => ImageUsage::SampleFragment,
+            (Self::Sample, PassKind::Compute) => ImageUsage::SampleCompute,
+            (Self::Buffer { .. }, _) => panic!("not an image access"),
+        }
+    }
+
+    fn stages_and_access(self, kind: PassKind) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+        if let Self::Buffer { stages, access } = self {
+            (stages, access)
+        } else {
+            let access = self.image_usage(kind).access();
+            (access.stages, access.access)
+        }
+    }
+}
+
+impl Use {
+    /// True if the previous contents of the resource are observed.
+    fn reads_contents(&self) -> bool {
+        match self.access {
+            Access::Color | Access::DepthStencil => self.clear.is_none(),
+            Access::Buffer { access, .. } => !(access & !write_access()).is_empty(),
+            _ => true,
+        }
+    }
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn add_resource(&mut self, name: impl Into<String>, kind: ResourceKind) -> ResourceId {
+        let id = ResourceId(self.resources.len());
+        self.resources.push(Resource {
+            name: name.into(),
+            kind,
+        });
+        id
+    }
+
+    /// Declares an attachment which is allocated by the graph and whose
+    /// contents don't outlive a frame.
+    pub fn transient(
+        &mut self,
+        name: impl Into<String>,
+        format: Format,
+        samples: SampleCount,
+    ) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient { format, samples })
+    }
+
+    /// Declares the engine's backbuffer, whose contents are undefined
+    /// at the start of the frame.
+    pub fn backbuffer(&mut self) -> ResourceId {
+        self.add_resource("backbuffer", ResourceKind::Backbuffer)
+    }
+
+    /// Declares an externally owned single-level 2D image.
+    pub fn import_image(&mut self, name: impl Into<String>, view: Arc<ImageView>) -> ResourceId {
+        self.add_resource(name, ResourceKind::Image(view))
+    }
+
+    /// Declares an externally owned buffer allocation. The graph keeps
+    /// the underlying buffer alive, but the allocation shouldn't be
+    /// freed or reused while the compiled graph is in use.
+    pub fn import_buffer(&mut self, name: impl Into<String>, alloc: &BufferAlloc) -> ResourceId {
+        let kind = ResourceKind::Buffer {
+            buffer: Arc::clone(alloc.buffer()),
+            offset: alloc.offset(),
+            size: alloc.size(),
+        };
+        self.add_resource(name, kind)
+    }
+
+    fn add_pass(&mut self, name: impl Into<String>, kind: PassKind) -> PassBuilder<'_> {
+        let id = PassId(self.passes.len());
+        self.passes.push(Pass {
+            name: name.into(),
+            kind,
+            uses: Vec::new(),
+        });
+        PassBuilder { graph: self, id }
+    }
+
+    /// Adds a pass which renders into attachments.
+    pub fn add_graphics_pass(&mut self, name: impl Into<String>) -> PassBuilder<'_> {
+        self.add_pass(name, PassKind::Graphics)
+    }
+
+    /// Adds a pass which runs outside of a render pass, e.g. compute
+    /// dispatches or transfers.
+    pub fn add_compute_pass(&mut self, name: impl Into<String>) -> PassBuilder<'_> {
+        self.add_pass(name, PassKind::Compute)
+    }
+
+    fn is_external(&self, res: ResourceId) -> bool {
+        !matches!(self.resources[res.0].kind, ResourceKind::Transient { .. })
+    }
+
+    // Walks passes backwards from the outputs of the graph.
+    fn find_live_passes(&self) -> Vec<bool> {
+        let mut needed: Vec<bool> = (0..self.resources.len())
+            .map(|i| self.is_external(ResourceId(i)))
+            .collect();
+        let mut live = vec![false; self.passes.len()];
+        for (i, pass) in self.passes.iter().enumerate().rev() {
+            live[i] = pass
+                .uses
+                .iter()
+                .any(|u| u.access.writes() && needed[u.resource.0]);
+            if !live[i] {
+                debug!("culling pass {:?}", pass.name);
+                continue;
+            }
+            for u in pass.uses.iter() {
+                if u.access.writes() && !u.reads_contents() && !self.is_external(u.resource) {
+                    needed[u.resource.0] = false;
+                }
+            }
+            for u in pass.uses.iter().filter(|u| u.reads_contents()) {
+                needed[u.resource.0] = true;
+            }
+        }
+        live
+    }
+
+    fn extent(&self, backbuffer_extent: Extent2D, res: ResourceId) -> Extent2D {
+        match &self.resources[res.0].kind {
+            ResourceKind::Image(view) => view.extent().to_2d(),
+            _ => backbuffer_extent,
+        }
+    }
+
+    fn format(&self, engine: &Engine, res: ResourceId) -> (Format, SampleCount) {
+        match &self.resources[res.0].kind {
+            &ResourceKind::Transient { format, samples } => (format, samples),
+            ResourceKind::Backbuffer => (engine.backbuffer_format(), SampleCount::One),
+            ResourceKind::Image(view) => (view.format(), view.samples()),
+            ResourceKind::Buffer { .. } => panic!("not an image: {}", self.resources[res.0].name),
+        }
+    }
+
+    // Checks whether a graphics pass may become another subpass of the
+    // render pass formed by `group`.
+    fn can_merge(&self, backbuffer_extent: Extent2D, group: &[PassId], pass: PassId) -> bool {
+        let group_uses = || group.iter().flat_map(|&p| self.passes[p.0].uses.iter());
+        let extent = group_uses()
+            .find(|u| u.access.is_attachment())
+            .map(|u| self.extent(backbuffer_extent, u.resource));
+        for u in self.passes[pass.0].uses.iter() {
+            let attachment_extent = self.extent(backbuffer_extent, u.resource);
+            if u.access.is_attachment() && Some(attachment_extent) != extent {
+                return false;
+            }
+            // Images which are sampled can't be attachments in the
+            // same render pass.
+            let sampled = u.access == Access::Sample;
+            let conflict = group_uses().any(|g| {
+                g.resource == u.resource
+                    && if sampled {
+                        g.access.is_attachment()
+                    } else {
+                        u.access.is_attachment() && g.access == Access::Sample
+                    }
+            });
+            if conflict {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn group_passes(&self, engine: &Engine, live: &[bool]) -> Vec<Vec<PassId>> {
+        let mut groups: Vec<Vec<PassId>> = Vec::new();
+        for (i, pass) in self.passes.iter().enumerate() {
+            if !live[i] {
+                continue;
+            }
+            let id = PassId(i);
+            if pass.kind == PassKind::Graphics {
+                assert!(
+                    pass.uses.iter().any(|u| u.access.is_attachment()),
+                    "graphics pass {:?} has no attachments",
+                    pass.name,
+                );
+            }
+            let merge = groups.last().map_or(false, |group| {
+                let last = &self.passes[group.last().unwrap().0];
+                last.kind == PassKind::Graphics
+                    && pass.kind == PassKind::Graphics
+                    && self.can_merge(engine.backbuffer_extent(), group, id)
+            });
+            if merge {
+                groups.last_mut().unwrap().push(id);
+            } else {
+                groups.push(vec![id]);
+            }
+        }
+        groups
+    }
+
+    /// Culls unused passes, derives render passes and barriers, and
+    /// allocates transient attachments. Fails if the transient
+    /// attachments can't be allocated.
+    pub fn compile(self, engine: &Engine) -> DeviceResult<CompiledGraph> {
+        let live = self.find_live_passes();
+        let groups = self.group_passes(engine, &live);
+
+        let mut last_step = vec![None; self.resources.len()];
+        for (i, group) in groups.iter().enumerate() {
+            for u in group.iter().flat_map(|&p| self.passes[p.0].uses.iter()) {
+                last_step[u.resource.0] = Some(i);
+            }
+        }
+
+        let mut compiler = Compiler {
+            graph: &self,
+            engine,
+            backbuffer_tracked: matches!(engine.backbuffer(), AttachmentImage::Image(_)),
+            defined: (0..self.resources.len())
+                .map(|i| matches!(self.resources[i].kind, ResourceKind::Image(_)))
+                .collect(),
+            backbuffer_layout: vk::ImageLayout::UNDEFINED,
+            buffers: vec![Default::default(); self.resources.len()],
+            last_step,
+        };
+        let steps = groups
+            .into_iter()
+            .enumerate()
+            .map(|(i, group)| compiler.compile_step(i, group))
+            .collect();
+
+        let transients = self.alloc_transients(engine, &live)?;
+        Ok(CompiledGraph {
+            resources: self.resources,
+            transients,
+            live,
+            steps,
+        })
+    }
+
+    fn alloc_transients(
+        &self,
+        engine: &Engine,
+        live: &[bool],
+    ) -> DeviceResult<Vec<Option<Arc<ImageView>>>> {
+        let live_uses = |res: usize| {
+            self.passes
+                .iter()
+                .enumerate()
+                .filter(move |&(i, _)| live[i])
+                .flat_map(|(_, pass)| pass.uses.iter())
+                .filter(move |u| u.resource.0 == res)
+        };
+
+        let mut ids = Vec::new();
+        let mut infos = Vec::new();
+        for (i, res) in self.resources.iter().enumerate() {
+            let (format, samples) = match res.kind {
+                ResourceKind::Transient { format, samples } => (format, samples),
+                _ => continue,
+            };
+            if live_uses(i).next().is_none() {
+                continue;
+            }
+            let mut flags = if format.is_depth_stencil() {
+                ImageFlags::DEPTH_STENCIL_ATTACHMENT
+            } else {
+                ImageFlags::COLOR_ATTACHMENT
+            };
+            if live_uses(i).any(|u| u.access == Access::Input) {
+                flags |= ImageFlags::INPUT_ATTACHMENT;
+            }
+            if !live_uses(i).any(|u| u.access == Access::Sample) {
+                flags |= ImageFlags::NO_SAMPLE;
+            }
+            ids.push(i);
+            infos.push(FramebufferImageInfo {
+                flags,
+                format,
+                samples,
+                name: Some(&res.name),
+            });
+        }
+
+        let mut transients = vec![None; self.resources.len()];
+        let images = create_framebuffer_images(engine, &infos)?;
+        for (i, image) in ids.into_iter().zip(images.iter()) {
+            transients[i] = Some(image.create_full_view());
+        }
+        Ok(transients)
+    }
+}
+
+impl<'g> PassBuilder<'g> {
+    #[inline]
+    pub fn id(&self) -> PassId {
+        self.id
+    }
+
+    fn add_use(self, resource: ResourceId, access: Access, clear: Option<ClearValue>) -> Self {
+        let pass = &mut self.graph.passes[self.id.0];
+        assert!(
+            !pass.uses.iter().any(|u| u.resource == resource),
+            "resource {:?} used twice by pass {:?}",
+            self.graph.resources[resource.0].name,
+            pass.name,
+        );
+        if access.is_attachment() {
+            assert_eq!(pass.kind, PassKind::Graphics);
+        }
+        pass.uses.push(Use {
+            resource,
+            access,
+            clear,
+        });
+        self
+    }
+
+    /// Renders to a color attachment, optionally clearing it first.
+    pub fn write_color(self, resource: ResourceId, clear: Option<[f32; 4]>) -> Self {
+        self.add_use(resource, Access::Color, clear.map(ClearValue::Color))
+    }
+
+    /// Uses a depth/stencil attachment for testing and writing,
+    /// optionally clearing it first.
+    pub fn write_depth_stencil(self, resource: ResourceId, clear: Option<(f32, u32)>) -> Self {
+        let clear = clear.map(|(depth, stencil)| ClearValue::DepthStencil(depth, stencil));
+        self.add_use(resource, Access::DepthStencil, clear)
+    }
+
+    /// Uses a depth/stencil attachment for testing only.
+    pub fn read_depth_stencil(self, resource: ResourceId) -> Self {
+        self.add_use(resource, Access::DepthStencilRead, None)
+    }
+
+    /// Reads an attachment written by a previous pass as an input
+    /// attachment.
+    pub fn read_input(self, resource: ResourceId) -> Self {
+        self.add_use(resource, Access::Input, None)
+    }
+
+    /// Samples an image in a fragment or compute shader.
+    pub fn sample(self, resource: ResourceId) -> Self {
+        self.add_use(resource, Access::Sample, None)
+    }
+
+    pub fn read_buffer(
+        self,
+        resource: ResourceId,
+        stages: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    ) -> Self {
+        assert!(!access.intersects(write_access()));
+        self.add_use(resource, Access::Buffer { stages, access }, None)
+    }
+
+    pub fn write_buffer(
+        self,
+        resource: ResourceId,
+        stages: vk::PipelineStageFlags,
+        access: vk::AccessFlags,
+    ) -> Self {
+        assert!(access.intersects(write_access()));
+        self.add_use(resource, Access::Buffer { stages, access }, None)
+    }
+}
+
+// State threaded through the steps of the graph during compilation.
+struct Compiler<'a> {
+    graph: &'a RenderGraph,
+    engine: &'a Engine,
+    backbuffer_tracked: bool,
+    // Whether each resource holds meaningful contents.
+    defined: Vec<bool>,
+    backbuffer_layout: vk::ImageLayout,
+    buffers: Vec<BufferState>,
+    last_step: Vec<Option<usize>>,
+}
+
+impl<'a> Compiler<'a> {
+    fn is_tracked(&self, res: ResourceId) -> bool {
+        match self.graph.resources[res.0].kind {
+            ResourceKind::Backbuffer => self.backbuffer_tracked,
+            ResourceKind::Buffer { .. } => false,
+            _ => true,
+        }
+    }
+
+    fn compile_step(&mut self, index: usize, passes: Vec<PassId>) -> Step {
+        let graph = self.graph;
+        let kind = graph.passes[passes[0].0].kind;
+        // (subpass, use) pairs in recording order
+        let uses: Vec<(u32, &Use)> = passes
+            .iter()
+            .enumerate()
+            .flat_map(move |(s, &p)| graph.passes[p.0].uses.iter().map(move |u| (s as u32, u)))
+            .collect();
+
+        let mut step = Step {
+            passes,
+            render_pass: None,
+            attachments: Vec::new(),
+            clear_values: Vec::new(),
+            image_uses: Vec::new(),
+            final_usages: Vec::new(),
+            buffer_src_stages: Default::default(),
+            buffer_dst_stages: Default::default(),
+            buffer_barriers: Vec::new(),
+        };
+
+        for &(_, u) in uses.iter().filter(|(_, u)| u.access == Access::Sample) {
+            assert!(
+                self.defined[u.resource.0],
+                "{} is sampled before being written",
+                graph.resources[u.resource.0].name,
+            );
+            assert!(self.is_tracked(u.resource), "cannot sample the swapchain");
+            let usage = u.access.image_usage(kind);
+            step.image_uses.push((u.resource, usage, false));
+        }
+
+        self.compile_buffer_barriers(&mut step, kind, &uses);
+        if kind == PassKind::Graphics {
+            self.compile_render_pass(index, &mut step, &uses);
+        }
+
+        step
+    }
+
+    fn compile_buffer_barriers(&mut self, step: &mut Step, kind: PassKind, uses: &[(u32, &Use)]) {
+        let mut seen = Vec::new();
+        for &(_, u) in uses.iter() {
+            if !matches!(
+                self.graph.resources[u.resource.0].kind,
+                ResourceKind::Buffer { .. }
+            ) {
+                continue;
+            }
+            let (stages, access) = u.access.stages_and_access(kind);
+            let state = &mut self.buffers[u.resource.0];
+
+            // Hazards within a render pass are handled by subpass
+            // dependencies, so only the first use needs a barrier.
+            if !seen.contains(&u.resource) {
+                seen.push(u.resource);
+                let src_stages = if u.access.writes() {
+                    state.write_stages | state.read_stages
+                } else {
+                    state.write_stages
+                };
+                if !src_stages.is_empty() {
+                    step.buffer_src_stages |= src_stages;
+                    step.buffer_dst_stages |= stages;
+                    step.buffer_barriers
+                        .push((u.resource, state.write_access, access));
+                }
+            }
+
+            if u.access.writes() {
+                *state = BufferState {
+                    write_stages: stages,
+                    write_access: access & write_access(),
+                    read_stages: Default::default(),
+                };
+            } else {
+                state.read_stages |= stages;
+            }
+        }
+    }
+
+    fn compile_render_pass(&mut self, index: usize, step: &mut Step, uses: &[(u32, &Use)]) {
+        let graph = self.graph;
+        let kind = PassKind::Graphics;
+        let subpass_count = step.passes.len() as u32;
+
+        for &(_, u) in uses.iter().filter(|(_, u)| u.access.is_attachment()) {
+            if !step.attachments.contains(&u.resource) {
+                step.attachments.push(u.resource);
+            }
+        }
+
+        let mut descs = Vec::new();
+        let mut subpasses: Vec<SubpassDesc> =
+            (0..subpass_count).map(|_| Default::default()).collect();
+        let mut dependencies: Vec<vk::SubpassDependency> = Vec::new();
+        let mut add_dependency = |dep: vk::SubpassDependency| {
+            let existing = dependencies.iter_mut().find(|d| {
+                (d.src_subpass, d.dst_subpass, d.dependency_flags)
+                    == (dep.src_subpass, dep.dst_subpass, dep.dependency_flags)
+            });
+            if let Some(d) = existing {
+                d.src_stage_mask |= dep.src_stage_mask;
+                d.dst_stage_mask |= dep.dst_stage_mask;
+                d.src_access_mask |= dep.src_access_mask;
+                d.dst_access_mask |= dep.dst_access_mask;
+            } else {
+                dependencies.push(dep);
+            }
+        };
+
+        for (idx, &res) in step.attachments.iter().enumerate() {
+            let res_uses: Vec<(u32, &Use)> = uses
+                .iter()
+                .filter(|(_, u)| u.resource == res)
+                .cloned()
+                .collect();
+            let (first_subpass, first) = res_uses[0];
+            let (last_subpass, last) = *res_uses.last().unwrap();
+            let first_usage = first.access.image_usage(kind);
+            let last_usage = last.access.image_usage(kind);
+            let first_layout = first_usage.access().layout;
+            let last_layout = last_usage.access().layout;
+
+            let needs_load = self.defined[res.0] && first.reads_contents();
+            let load_op = if first.clear.is_some() {
+                vk::AttachmentLoadOp::CLEAR
+            } else if needs_load {
+                vk::AttachmentLoadOp::LOAD
+            } else {
+                vk::AttachmentLoadOp::DONT_CARE
+            };
+            let store = graph.is_external(res) || self.last_step[res.0] > Some(index);
+            let store_op = if store {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            };
+            self.defined[res.0] = store;
+
+            let (initial_layout, final_layout) = if self.is_tracked(res) {
+                step.image_uses.push((res, first_usage, !needs_load));
+                step.final_usages.push((res, last_usage));
+                (first_layout, last_layout)
+            } else {
+                let initial_layout = if needs_load {
+                    self.backbuffer_layout
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                };
+                let final_layout = if self.last_step[res.0] == Some(index) {
+                    vk::ImageLayout::PRESENT_SRC_KHR
+                } else {
+                    last_layout
+                };
+                self.backbuffer_layout = final_layout;
+                (initial_layout, final_layout)
+            };
+
+            let (format, samples) = graph.format(self.engine, res);
+            let has_stencil = format.aspects().contains(vk::ImageAspectFlags::STENCIL_BIT);
+            descs.push(AttachmentDescription {
+                format,
+                samples,
+                load_op,
+                store_op,
+                stencil_load_op: if has_stencil {
+                    load_op
+                } else {
+                    vk::AttachmentLoadOp::DONT_CARE
+                },
+                stencil_store_op: if has_stencil {
+                    store_op
+                } else {
+                    vk::AttachmentStoreOp::DONT_CARE
+                },
+                initial_layout,
+                final_layout,
+            });
+            step.clear_values.push(first.clear);
+
+            for &(s, u) in res_uses.iter() {
+                let layout = u.access.image_usage(kind).access().layout;
+                let aref = vk::AttachmentReference {
+                    attachment: idx as u32,
+                    layout,
+                };
+                let subpass = &mut subpasses[s as usize];
+                match u.access {
+                    Access::Color => subpass.color_attchs.push(aref),
+                    Access::DepthStencil | Access::DepthStencilRead => {
+                        assert!(subpass.depth_stencil_attch.is_none());
+                        subpass.depth_stencil_attch = Some(aref);
+                    }
+                    Access::Input => subpass.input_attchs.push(aref),
+                    _ => unreachable!(),
+                }
+            }
+            for s in first_subpass + 1..last_subpass {
+                if !res_uses.iter().any(|&(t, _)| t == s) {
+                    subpasses[s as usize].preserve_attchs.push(idx as u32);
+                }
+            }
+
+            // Prior writes, including those in earlier render passes,
+            // and swapchain image acquisition.
+            let first_access = first_usage.access();
+            add_dependency(vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: first_subpass,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT_BIT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS_BIT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS_BIT,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE_BIT
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE_BIT,
+                dst_stage_mask: first_access.stages,
+                dst_access_mask: first_access.access,
+                ..Default::default()
+            });
+            for pair in res_uses.windows(2) {
+                let ((src, a), (dst, b)) = (pair[0], pair[1]);
+                let (a, b) = (
+                    a.access.image_usage(kind).access(),
+                    b.access.image_usage(kind).access(),
+                );
+                if src != dst && (a.is_write() || b.is_write() || a.layout != b.layout) {
+                    add_dependency(vk::SubpassDependency {
+                        src_subpass: src,
+                        dst_subpass: dst,
+                        src_stage_mask: a.stages,
+                        src_access_mask: a.access & write_access(),
+                        dst_stage_mask: b.stages,
+                        dst_access_mask: b.access,
+                        dependency_flags: vk::DependencyFlags::BY_REGION_BIT,
+                    });
+                }
+            }
+        }
+
+        // Buffers shared between subpasses
+        let buffer_uses = uses
+            .iter()
+            .filter(|(_, u)| matches!(u.access, Access::Buffer { .. }));
+        for (i, &(src, a)) in buffer_uses.clone().enumerate() {
+            let next = buffer_uses
+                .clone()
+                .skip(i + 1)
+                .find(|(_, b)| b.resource == a.resource);
+            if let Some(&(dst, b)) = next {
+                if src != dst && (a.access.writes() || b.access.writes()) {
+                    let (src_stages, src_access) = a.access.stages_and_access(kind);
+                    let (dst_stages, dst_access) = b.access.stages_and_access(kind);
+                    add_dependency(vk::SubpassDependency {
+                        src_subpass: src,
+                        dst_subpass: dst,
+                        src_stage_mask: src_stages,
+                        src_access_mask: src_access & write_access(),
+                        dst_stage_mask: dst_stages,
+                        dst_access_mask: dst_access,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        let render_pass = unsafe {
+            RenderPass::new(
+                Arc::clone(self.engine.device()),
+                descs,
+                subpasses,
+                dependencies,
+            )
+        };
+        step.render_pass = Some(render_pass);
+    }
+}
+
+impl CompiledGraph {
+    /// True if the pass was culled and will never be recorded.
+    #[inline]
+    pub fn is_culled(&self, pass: PassId) -> bool {
+        !self.live[pass.0]
+    }
+
+    /// The image allocated for a transient attachment, e.g. to create
+    /// descriptor sets for passes which sample it. Returns `None` if
+    /// the resource is not a transient or is never used.
+    #[inline]
+    pub fn transient_image(&self, res: ResourceId) -> Option<&Arc<ImageView>> {
+        self.transients[res.0].as_ref()
+    }
+
+    /// The render pass of each live graphics pass.
+    pub fn render_pass(&self, pass: PassId) -> Option<&Arc<RenderPass>> {
+        let step = self.steps.iter().find(|step| step.passes.contains(&pass))?;
+        step.render_pass.as_ref()
+    }
+
+    fn image_view(&self, engine: &Engine, res: ResourceId) -> Option<Arc<ImageView>> {
+        match &self.resources[res.0].kind {
+            ResourceKind::Transient { .. } => self.transients[res.0].clone(),
+            ResourceKind::Image(view) => Some(Arc::clone(view)),
+            ResourceKind::Backbuffer => match engine.backbuffer() {
+                AttachmentImage::Image(view) => Some(view),
+                AttachmentImage::Swapchain(_) => None,
+            },
+            ResourceKind::Buffer { .. } => None,
+        }
+    }
+
+    fn attachment(&self, engine: &Engine, res: ResourceId) -> AttachmentImage {
+        match self.resources[res.0].kind {
+            ResourceKind::Backbuffer => engine.backbuffer(),
+            _ => self.image_view(engine, res).unwrap().into(),
+        }
+    }
+
+    unsafe fn record_barriers(&self, engine: &Engine, cmds: &mut CmdBuffer<'_>, step: &Step) {
+        let views: Vec<_> = step
+            .image_uses
+            .iter()
+            .filter_map(|&(res, usage, discard)| {
+                let view = self.image_view(engine, res)?;
+                if discard {
+                    view.image().discard(&view.subresources());
+                }
+                Some((view, usage))
+            })
+            .collect();
+        let image_uses: Vec<_> = views
+            .iter()
+            .map(|(view, usage)| ImageUse {
+                image: view.image(),
+                subresources: view.subresources(),
+                usage: *usage,
+            })
+            .collect();
+        cmds.use_images(&image_uses);
+
+        let buffer_barriers: Vec<_> = step
+            .buffer_barriers
+            .iter()
+            .map(|&(res, src_access_mask, dst_access_mask)| {
+                let (buffer, offset, size) = match self.resources[res.0].kind {
+                    ResourceKind::Buffer {
+                        ref buffer,
+                        offset,
+                        size,
+                    } => (buffer.inner(), offset, size),
+                    _ => unreachable!(),
+                };
+                vk::BufferMemoryBarrier {
+                    src_access_mask,
+                    dst_access_mask,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    buffer,
+                    offset,
+                    size,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        if !buffer_barriers.is_empty() {
+            cmds.pipeline_barrier(
+                step.buffer_src_stages,
+                step.buffer_dst_stages,
+                Default::default(),
+                &[],
+                &buffer_barriers,
+                &[],
+            );
+        }
+    }
+
+    /// Records every live pass in order. `record` is invoked once per
+    /// pass to record its commands; graphics passes are recorded inside
+    /// their subpass.
+    pub fn execute(
+        &self,
+        engine: &Engine,
+        cmds: &mut CmdBuffer<'_>,
+        mut record: impl FnMut(PassId, &mut CmdBuffer<'_>),
+    ) {
+        let family = cmds.queue_family().index();
+        for step in self.steps.iter() {
+            unsafe {
+                self.record_barriers(engine, cmds, step);
+            }
+
+            let render_pass = if let Some(render_pass) = &step.render_pass {
+                render_pass
+            } else {
+                for &pass in step.passes.iter() {
+                    record(pass, cmds);
+                }
+                continue;
+            };
+
+            let attachments: Vec<_> = step
+                .attachments
+                .iter()
+                .map(|&res| self.attachment(engine, res))
+                .collect();
+            let default_clear = ClearValue::Color([0.0; 4]);
+            let clear_values: Vec<vk::ClearValue> = step
+                .clear_values
+                .iter()
+                .map(|clear| clear.unwrap_or(default_clear).into())
+                .collect();
+            engine.begin_render_pass(cmds, render_pass, &attachments, &clear_values);
+            for (i, &pass) in step.passes.iter().enumerate() {
+                if i > 0 {
+                    cmds.next_subpass(SubpassContents::Inline);
+                }
+                record(pass, cmds);
+            }
+            cmds.end_render_pass();
+
+            for &(res, usage) in step.final_usages.iter() {
+                if let Some(view) = self.image_view(engine, res) {
+                    unsafe {
+                        view.image()
+                            .assume_usage(&view.subresources(), usage, family);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn transient(graph: &mut RenderGraph, name: &str) -> ResourceId {
+        graph.transient(name, Format::RGBA8, SampleCount::One)
+    }
+
+    #[test]
+    fn cull_dead_passes() {
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.backbuffer();
+        let gbuffer = transient(&mut graph, "gbuffer");
+        let debug = transient(&mut graph, "debug");
+        let blurred = transient(&mut graph, "blurred");
+
+        // Overwritten by a clear before anything reads it
+        graph.add_graphics_pass("stale").write_color(gbuffer, None);
+        graph
+            .add_graphics_pass("geometry")
+            .write_color(gbuffer, Some([0.0; 4]));
+        // Only read by a dead pass
+        graph
+            .add_graphics_pass("blur")
+            .sample(gbuffer)
+            .write_color(blurred, None);
+        graph
+            .add_graphics_pass("debug")
+            .sample(blurred)
+            .write_color(debug, None);
+        graph
+            .add_graphics_pass("lighting")
+            .read_input(gbuffer)
+            .write_color(backbuffer, None);
+
+        assert_eq!(graph.find_live_passes(), [false, true, false, false, true]);
+    }
+
+    #[test]
+    fn merge_decisions() {
+        let extent = Extent2D::new(320, 200);
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.backbuffer();
+        let gbuffer = transient(&mut graph, "gbuffer");
+        let depth = graph.transient("depth", Format::D32F, SampleCount::One);
+        let geometry = graph
+            .add_graphics_pass("geometry")
+            .write_color(gbuffer, Some([0.0; 4]))
+            .write_depth_stencil(depth, Some((1.0, 0)))
+            .id();
+        let lighting = graph
+            .add_graphics_pass("lighting")
+            .read_input(gbuffer)
+            .read_depth_stencil(depth)
+            .write_color(backbuffer, None)
+            .id();
+        let blur = graph
+            .add_graphics_pass("blur")
+            .sample(gbuffer)
+            .write_color(backbuffer, None)
+            .id();
+
+        // Input attachments are read in a later subpass
+        assert!(graph.can_merge(extent, &[geometry], lighting));
+        // An attachment of the render pass can't also be sampled
+        assert!(!graph.can_merge(extent, &[geometry], blur));
+        assert!(!graph.can_merge(extent, &[blur], geometry));
+    }
+
+    #[test]
+    fn execute() {
+        let extent = Extent2D::new(4, 4);
+        let engine = headless_engine("render graph test", extent);
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.backbuffer();
+        let gbuffer = transient(&mut graph, "gbuffer");
+        let geometry = graph
+            .add_graphics_pass("geometry")
+            .write_color(gbuffer, Some([1.0, 0.0, 0.0, 1.0]))
+            .id();
+        let lighting = graph
+            .add_graphics_pass("lighting")
+            .read_input(gbuffer)
+            .write_color(backbuffer, Some([0.0, 1.0, 0.0, 1.0]))
+            .id();
+
+        let graph = graph.compile(&engine).unwrap();
+        let render_pass = graph.render_pass(geometry).unwrap();
+        assert!(Arc::ptr_eq(
+            render_pass,
+            graph.render_pass(lighting).unwrap()
+        ));
+        assert_eq!(render_pass.subpasses().len(), 2);
+        assert!(render_pass
+            .dependencies()
+            .iter()
+            .any(|dep| (dep.src_subpass, dep.dst_subpass) == (0, 1)));
+        let gbuffer_view = graph.transient_image(gbuffer).unwrap();
+        assert_eq!(gbuffer_view.extent().to_2d(), extent);
+        assert!(gbuffer_view
+            .image()
+            .flags()
+            .contains(ImageFlags::INPUT_ATTACHMENT));
+
+        let mut recorded = Vec::new();
+        submit_and_wait(&engine, |cmds| {
+            graph.execute(&engine, cmds, |pass, cmds| {
+                recorded.push((pass, cmds.subpass_index()));
+            });
+        });
+        assert_eq!(recorded, [(geometry, 0), (lighting, 1)]);
+
+        // The layout is ignored when headless
+        let layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        let readback = engine.read_backbuffer(layout).unwrap();
+        assert_eq!(readback.extent, extent);
+        assert_eq!(readback.data, [0, 255, 0, 255].repeat(16));
+    }
+}
@@ -1,10 +1,19 @@
 use std::sync::Arc;
 
 use bitflags::bitflags;
-use device::{CmdBuffer, Device, Image, MemoryRegion, Queue};
+use device::{
+    CmdBuffer, Device, Image, ImageSubresources, ImageUsage, MemoryRegion, MemoryRegionMut, Queue,
+};
 
 /// Handles uploading data from the host to the device. Both the
 /// discrete and UMA cases are equally handled.
+///
+/// If the transfer queue belongs to a different family than the
+/// graphics queue, staged resources are released to the graphics
+/// family when the upload is recorded. The matching acquire barriers
+/// are recorded by `record_acquires` before the resources are used,
+/// and graphics work must wait on the staging semaphore (see
+/// `wait_info`).
 #[derive(Debug)]
 pub struct StagingBuffer {
     graphics_queue: Arc<Queue>,
@@ -13,6 +22,39 @@ pub struct StagingBuffer {
     offset: usize,
     semaphore: device::TimelineSemaphore,
     pending_transfer: u64,
+    submission: Option<device::Submission>,
+    // Released by the commands currently being recorded
+    staged: Vec<Acquire>,
+    // Released by submitted commands but not yet acquired
+    acquires: Vec<Acquire>,
+}
+
+/// A resource released to the graphics family by the transfer queue.
+#[derive(Debug)]
+enum Acquire {
+    Image(Arc<Image>, ImageSubresources),
+    Buffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    },
+}
+
+// Stages and accesses through which staged buffers may be read.
+fn buffer_read_stages() -> vk::PipelineStageFlags {
+    vk::PipelineStageFlags::DRAW_INDIRECT_BIT
+        | vk::PipelineStageFlags::VERTEX_INPUT_BIT
+        | vk::PipelineStageFlags::VERTEX_SHADER_BIT
+        | vk::PipelineStageFlags::FRAGMENT_SHADER_BIT
+        | vk::PipelineStageFlags::COMPUTE_SHADER_BIT
+}
+
+fn buffer_read_access() -> vk::AccessFlags {
+    vk::AccessFlags::INDIRECT_COMMAND_READ_BIT
+        | vk::AccessFlags::INDEX_READ_BIT
+        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ_BIT
+        | vk::AccessFlags::UNIFORM_READ_BIT
+        | vk::AccessFlags::SHADER_READ_BIT
 }
 
 bitflags! {
@@ -44,6 +86,9 @@ impl StagingBuffer {
             offset: 0,
             semaphore,
             pending_transfer: 0,
+            submission: None,
+            staged: Vec::new(),
+            acquires: Vec::new(),
         }
     }
 
@@ -51,8 +96,21 @@ impl StagingBuffer {
         self.buffer.device()
     }
 
+    pub fn transfer_queue(&self) -> &Arc<Queue> {
+        &self.transfer_queue
+    }
+
+    /// True if uploads happen on a different queue family than
+    /// graphics, requiring ownership transfers.
+    #[inline]
+    pub fn transfers_ownership(&self) -> bool {
+        self.transfer_queue.family().index() != self.graphics_queue.family().index()
+    }
+
     pub fn pending(&self) -> bool {
-        self.semaphore.get_value() != self.pending_transfer
+        self.submission
+            .as_ref()
+            .map_or(false, |submission| !submission.is_complete())
     }
 
     fn stage_data(&mut self, src: &[u8]) -> Option<usize> {
@@ -71,31 +129,69 @@ impl StagingBuffer {
         &mut self,
         cmds: &mut CmdBuffer<'_>,
         src: &[u8],
-        // TODO: smh... BufferRange holds an immutable reference to the
-        // byte array, which we are accessing here mutably...
-        // Need a BufferRangeMut struct.
-        dest: &mut device::BufferRange<'_>,
+        dest: &mut device::BufferRangeMut<'_>,
         _flags: StageFlags,
     ) -> Option<()> {
         if let Some(bytes) = dest.as_bytes_mut() {
             bytes.copy_from_slice(src);
         } else {
             let offset = self.stage_data(src)?;
+            let buffer = dest.raw();
+            let size = src.len() as vk::DeviceSize;
             unsafe {
                 cmds.copy_buffer(
                     &self.buffer,
-                    dest.buffer,
+                    dest.buffer(),
                     &[vk::BufferCopy {
                         src_offset: offset as _,
-                        dst_offset: dest.offset,
-                        size: src.len() as _,
+                        dst_offset: dest.offset(),
+                        size,
                     }],
                 );
             }
+
+            let mut barrier = vk::BufferMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE_BIT,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                buffer,
+                offset: dest.offset(),
+                size,
+                ..Default::default()
+            };
+            let dst_stages = if self.transfers_ownership() {
+                barrier.src_queue_family_index = self.transfer_queue.family().index();
+                barrier.dst_queue_family_index = self.graphics_queue.family().index();
+                self.staged.push(Acquire::Buffer {
+                    buffer,
+                    offset: dest.offset(),
+                    size,
+                });
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE_BIT
+            } else {
+                barrier.dst_access_mask = buffer_read_access();
+                buffer_read_stages()
+            };
+            unsafe {
+                cmds.pipeline_barrier(
+                    vk::PipelineStageFlags::TRANSFER_BIT,
+                    dst_stages,
+                    Default::default(),
+                    &[],
+                    &[barrier],
+                    &[],
+                );
+            }
         }
         Some(())
     }
 
+    /// Uploads `src` into mip level 0 of the given layers of `dest`.
+    ///
+    /// Other mip levels are left untouched; staging never generates
+    /// mipmaps, as the transfer queue may not support blits. To fill
+    /// them, call `generate_mipmaps` on the graphics queue once the
+    /// upload has been acquired (see `record_acquires`).
     pub fn stage_image_layers(
         &mut self,
         cmds: &mut CmdBuffer<'_>,
@@ -106,28 +202,12 @@ impl StagingBuffer {
         flags: StageFlags,
     ) -> Option<()> {
         let offset = self.stage_data(src)?;
+        let sub = dest.subresource_layers(0, base_layer, layer_count);
         unsafe {
-            let subresource_range: vk::ImageSubresourceRange =
-                dest.subresource_layers(0, base_layer, layer_count).into();
             if !flags.contains(StageFlags::NO_TRANSITION) {
-                cmds.pipeline_barrier(
-                    vk::PipelineStageFlags::TOP_OF_PIPE_BIT,
-                    vk::PipelineStageFlags::TRANSFER_BIT,
-                    Default::default(),
-                    &[],
-                    &[],
-                    &[vk::ImageMemoryBarrier {
-                        src_access_mask: vk::AccessFlags::empty(),
-                        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE_BIT,
-                        old_layout: vk::ImageLayout::UNDEFINED,
-                        new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        src_queue_family_index: self.transfer_queue.family().index(),
-                        dst_queue_family_index: self.graphics_queue.family().index(),
-                        image: dest.inner(),
-                        subresource_range,
-                        ..Default::default()
-                    }],
-                );
+                // The staged layers are overwritten entirely.
+                dest.discard(&sub);
+                cmds.use_image(dest, sub, ImageUsage::TransferDst);
             }
             cmds.copy_buffer_to_image(
                 &self.buffer,
@@ -135,35 +215,24 @@ impl StagingBuffer {
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &[vk::BufferImageCopy {
                     buffer_offset: offset as _,
-                    image_subresource: dest.subresource_layers(0, base_layer, layer_count).into(),
+                    image_subresource: sub.into(),
                     image_extent: dest.extent().into(),
                     ..Default::default()
                 }],
             );
             if !flags.contains(StageFlags::NO_TRANSITION) {
-                cmds.pipeline_barrier(
-                    vk::PipelineStageFlags::TRANSFER_BIT,
-                    vk::PipelineStageFlags::FRAGMENT_SHADER_BIT,
-                    Default::default(),
-                    &[],
-                    &[],
-                    &[vk::ImageMemoryBarrier {
-                        src_access_mask: vk::AccessFlags::TRANSFER_WRITE_BIT,
-                        dst_access_mask: vk::AccessFlags::SHADER_READ_BIT,
-                        old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        src_queue_family_index: self.transfer_queue.family().index(),
-                        dst_queue_family_index: self.graphics_queue.family().index(),
-                        image: dest.inner(),
-                        subresource_range,
-                        ..Default::default()
-                    }],
-                );
+                let family = self.graphics_queue.family().index();
+                cmds.release_image(dest, sub, ImageUsage::SampleFragment, family);
+                if self.transfers_ownership() {
+                    self.staged.push(Acquire::Image(Arc::clone(dest), sub));
+                }
             }
         }
         Some(())
     }
 
+    /// Uploads `src` into mip level 0 of the first layer of `dest`. See
+    /// `stage_image_layers`.
     pub fn stage_image(
         &mut self,
         cmds: &mut CmdBuffer<'_>,
@@ -174,12 +243,12 @@ impl StagingBuffer {
         self.stage_image_layers(cmds, src, dest, 0, 1, flags)
     }
 
-    pub fn submit(&mut self, cmds: CmdBuffer<'_>) -> u64 {
+    pub fn submit(&mut self, cmds: CmdBuffer<'_>) -> device::Submission {
         assert!(!self.pending());
         self.offset = 0;
         self.pending_transfer += 1;
         let cmds = cmds.end();
-        unsafe {
+        let submission = unsafe {
             self.transfer_queue.submit(&[device::SubmitInfo {
                 wait_sems: &[],
                 sig_sems: &[device::SignalInfo {
@@ -187,9 +256,75 @@ impl StagingBuffer {
                     value: self.pending_transfer,
                 }],
                 cmds: &[cmds],
-            }]);
+            }])
+        };
+        self.submission = Some(submission.clone());
+        self.acquires.append(&mut self.staged);
+        submission
+    }
+
+    /// True if submitted uploads released resources which have yet to
+    /// be acquired by the graphics queue.
+    #[inline]
+    pub fn has_pending_acquires(&self) -> bool {
+        !self.acquires.is_empty()
+    }
+
+    /// Records the acquire half of the ownership transfers of all
+    /// submitted uploads. `cmds` must be executed on the graphics queue
+    /// after waiting on `wait_info`.
+    pub unsafe fn record_acquires(&mut self, cmds: &mut CmdBuffer<'_>) {
+        assert_eq!(
+            cmds.queue_family().index(),
+            self.graphics_queue.family().index()
+        );
+        let src_family = self.transfer_queue.family().index();
+        let dst_family = self.graphics_queue.family().index();
+        let mut buffer_barriers = Vec::new();
+        for acquire in self.acquires.drain(..) {
+            match acquire {
+                Acquire::Image(image, sub) => {
+                    // The image state knows it was released to us.
+                    cmds.use_image(&image, sub, ImageUsage::SampleFragment);
+                }
+                Acquire::Buffer {
+                    buffer,
+                    offset,
+                    size,
+                } => buffer_barriers.push(vk::BufferMemoryBarrier {
+                    dst_access_mask: buffer_read_access(),
+                    src_queue_family_index: src_family,
+                    dst_queue_family_index: dst_family,
+                    buffer,
+                    offset,
+                    size,
+                    ..Default::default()
+                }),
+            }
+        }
+        if !buffer_barriers.is_empty() {
+            cmds.pipeline_barrier(
+                vk::PipelineStageFlags::TOP_OF_PIPE_BIT,
+                buffer_read_stages(),
+                Default::default(),
+                &[],
+                &buffer_barriers,
+                &[],
+            );
         }
-        self.pending_transfer
+    }
+
+    /// Makes graphics work wait on all submitted uploads. Returns
+    /// `None` if nothing has been uploaded yet.
+    pub fn wait_info(&mut self) -> Option<device::WaitInfo<'_>> {
+        if self.pending_transfer == 0 {
+            return None;
+        }
+        Some(device::WaitInfo {
+            semaphore: self.semaphore.inner_mut(),
+            value: self.pending_transfer,
+            stages: vk::PipelineStageFlags::ALL_COMMANDS_BIT,
+        })
     }
 
     #[inline]
@@ -198,6 +333,172 @@ impl StagingBuffer {
     }
 
     pub fn wait(&self, timeout: u64) -> device::WaitResult {
-        self.semaphore.wait(self.pending_transfer, timeout)
+        match self.submission {
+            Some(ref submission) => submission.wait(timeout),
+            None => device::WaitResult::Success,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use device::{
+        BinarySemaphore, BufferBinding, Extent2D, Format, ImageDef, ImageFlags, ImageType,
+        Lifetime, MemoryMapping, SampleCount,
+    };
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn upload() {
+        let extent = Extent2D::new(4, 3);
+        let mut engine = headless_engine("staging test", extent);
+        let pixels: Vec<u8> = (0..extent.width * extent.height * 4)
+            .map(|i| (i * 5) as u8)
+            .collect();
+        let data: Vec<u8> = (0..256).map(|i| (i * 3) as u8).collect();
+        let size = data.len() as vk::DeviceSize;
+
+        let image = ImageDef::new(
+            engine.device(),
+            ImageFlags::TRANSFER_SRC,
+            ImageType::Dim2,
+            Format::RGBA8,
+            SampleCount::One,
+            extent.into(),
+            1,
+            1,
+        )
+        .build_image(engine.image_heap());
+        let mut buffer = engine.buffer_heap().alloc(
+            BufferBinding::Storage,
+            Lifetime::Static,
+            MemoryMapping::DeviceLocal,
+            size,
+        );
+        let readback = engine.buffer_heap().alloc(
+            BufferBinding::Storage,
+            Lifetime::Static,
+            MemoryMapping::Cached,
+            size,
+        );
+
+        let level = vk::CommandBufferLevel::PRIMARY;
+        let family = engine.transfer_queue().family().index();
+        engine.with_command_buffer(level, family, |mut cmds| {
+            let mut staging = engine.staging().lock().unwrap();
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            staging
+                .stage_image(&mut cmds, &pixels, &image, Default::default())
+                .unwrap();
+            staging
+                .stage_buffer(
+                    &mut cmds,
+                    &data,
+                    &mut buffer.range_mut(),
+                    Default::default(),
+                )
+                .unwrap();
+            staging.submit(cmds);
+        });
+
+        let family = engine.graphics_queue().family().index();
+        let cmds = engine.with_command_buffer(level, family, |mut cmds| unsafe {
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            // Staged buffers are acquired for shader reads only.
+            cmds.pipeline_barrier(
+                vk::PipelineStageFlags::ALL_COMMANDS_BIT,
+                vk::PipelineStageFlags::TRANSFER_BIT,
+                Default::default(),
+                &[vk::MemoryBarrier {
+                    dst_access_mask: vk::AccessFlags::TRANSFER_READ_BIT,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+            cmds.copy_buffer(
+                buffer.buffer(),
+                readback.buffer(),
+                &[vk::BufferCopy {
+                    src_offset: buffer.offset(),
+                    dst_offset: readback.offset(),
+                    size,
+                }],
+            );
+            cmds.pipeline_barrier(
+                vk::PipelineStageFlags::TRANSFER_BIT,
+                vk::PipelineStageFlags::HOST_BIT,
+                Default::default(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE_BIT,
+                    dst_access_mask: vk::AccessFlags::HOST_READ_BIT,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+            cmds.end()
+        });
+        let mut semaphore = BinarySemaphore::new(engine.device_ref());
+        unsafe {
+            engine
+                .submit_frame(&[cmds], &mut semaphore)
+                .wait(u64::MAX)
+                .unwrap();
+        }
+        assert!(!engine.staging_mut().has_pending_acquires());
+
+        readback.invalidate().unwrap();
+        assert_eq!(&readback.as_bytes().unwrap()[..data.len()], &data[..]);
+        let image = engine.read_image(&image, 0, 0).unwrap();
+        assert_eq!(image.data, pixels);
+    }
+
+    #[test]
+    fn shared_family() {
+        let engine = headless_engine("staging test", Extent2D::new(1, 1));
+        let queue = engine.graphics_queue();
+        let mut staging = StagingBuffer::new(Arc::clone(queue), Arc::clone(queue), 1024);
+        assert!(!staging.transfers_ownership());
+
+        let image = ImageDef::new(
+            engine.device(),
+            Default::default(),
+            ImageType::Dim2,
+            Format::RGBA8,
+            SampleCount::One,
+            Extent2D::new(4, 4).into(),
+            1,
+            1,
+        )
+        .build_image(engine.image_heap());
+        let mut buffer = engine.buffer_heap().alloc(
+            BufferBinding::Storage,
+            Lifetime::Static,
+            MemoryMapping::DeviceLocal,
+            64,
+        );
+
+        let level = vk::CommandBufferLevel::PRIMARY;
+        engine.with_command_buffer(level, queue.family().index(), |mut cmds| {
+            cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+            staging
+                .stage_image(&mut cmds, &[0; 64], &image, Default::default())
+                .unwrap();
+            staging
+                .stage_buffer(
+                    &mut cmds,
+                    &[0; 64],
+                    &mut buffer.range_mut(),
+                    Default::default(),
+                )
+                .unwrap();
+            staging.submit(cmds);
+        });
+        assert!(!staging.has_pending_acquires());
+        assert!(staging.wait_info().is_some());
+        staging.wait(u64::MAX).unwrap();
     }
 }
@@ -0,0 +1,67 @@
+use device::{
+    AppInfo, BufferBinding, CmdBuffer, Extent2D, Image, ImageUsage, Lifetime, MemoryMapping,
+    MemoryRegion, SubmitInfo,
+};
+
+use crate::{Engine, Settings};
+
+pub(crate) fn headless_engine(name: &str, extent: Extent2D) -> Engine {
+    let app_info = AppInfo {
+        name: name.to_owned(),
+        debug: true,
+        test: true,
+        ..Default::default()
+    };
+    Engine::headless(app_info, extent, Settings::default()).unwrap()
+}
+
+/// Records commands on the graphics queue and waits for them to
+/// complete.
+pub(crate) fn submit_and_wait(engine: &Engine, f: impl FnOnce(&mut CmdBuffer<'_>)) {
+    let queue = engine.graphics_queue();
+    let level = vk::CommandBufferLevel::PRIMARY;
+    let cmds = engine.with_command_buffer(level, queue.family().index(), |mut cmds| {
+        cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
+        f(&mut cmds);
+        cmds.end()
+    });
+    unsafe {
+        queue
+            .submit(&[SubmitInfo {
+                cmds: &[cmds],
+                ..Default::default()
+            }])
+            .wait(u64::MAX)
+            .unwrap();
+    }
+}
+
+/// Copies tightly packed texels into mip level 0 of every layer of
+/// `image` without going through the staging buffer.
+pub(crate) fn upload_image(engine: &Engine, image: &Image, data: &[u8]) {
+    let mut src = engine.buffer_heap().alloc(
+        BufferBinding::Storage,
+        Lifetime::Static,
+        MemoryMapping::Mapped,
+        data.len() as _,
+    );
+    src.range_mut().copy_from_slice(0, data);
+    submit_and_wait(engine, |cmds| {
+        let sub = image.subresource_layers(0, 0, image.layers());
+        image.discard(&sub);
+        unsafe {
+            cmds.use_image(image, sub, ImageUsage::TransferDst);
+            cmds.copy_buffer_to_image(
+                src.buffer(),
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy {
+                    buffer_offset: src.offset(),
+                    image_subresource: sub.into(),
+                    image_extent: image.extent().into(),
+                    ..Default::default()
+                }],
+            );
+        }
+    });
+}
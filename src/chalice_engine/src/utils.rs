@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use device::DeviceResult;
+
 use crate::Engine;
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -12,11 +14,11 @@ pub struct FramebufferImageInfo<'s> {
 
 /// Provides a shortcut for creating images to use for rendering to the
 /// screen. Namely, your G-buffer(s), depth-stencil buffer(s), and
-/// multisample/HDR color buffers.
+/// multisample/HDR color buffers. Fails if the device is out of memory.
 pub fn create_framebuffer_images(
     engine: &Engine,
     infos: &[FramebufferImageInfo<'_>],
-) -> Vec<Arc<device::Image>> {
+) -> DeviceResult<Vec<Arc<device::Image>>> {
     infos
         .iter()
         .map(|info| {
@@ -26,14 +28,17 @@ pub fn create_framebuffer_images(
                 device::ImageType::Dim2,
                 info.format,
                 info.samples,
-                engine.swapchain().extent().into(),
+                engine.backbuffer_extent().into(),
                 1,
                 1,
             );
             if let Some(s) = info.name {
                 def.set_name(s.to_owned());
             }
-            Arc::new(device::Image::new(engine.image_heap(), Arc::new(def)))
+            Ok(Arc::new(device::Image::try_new(
+                engine.image_heap(),
+                Arc::new(def),
+            )?))
         })
         .collect()
 }
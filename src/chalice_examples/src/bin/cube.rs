@@ -5,53 +5,29 @@ use std::sync::Arc;
 
 use base::num::One;
 use base::partial_map;
-use device::RenderPass;
-use engine::Engine;
+use engine::{CompiledGraph, Engine, PassId, RenderGraph};
 use math::vec3;
 use smallvec::smallvec;
 use tinker::Tinker;
 
 #[derive(Debug)]
 struct CubeApp {
-    render_pass: Arc<RenderPass>,
+    graph: CompiledGraph,
+    cube_pass: PassId,
     index_buffer: device::BufferBox<[u32]>,
     vertex_buffer: device::BufferBox<[[f32; 3]]>,
     uniform_buffer: device::BufferBox<Uniforms>,
     descriptor_set: device::DescriptorSet,
 }
 
-fn create_render_pass(engine: &Engine) -> Arc<RenderPass> {
-    unsafe {
-        RenderPass::new(
-            engine.device_ref(),
-            vec![device::AttachmentDescription {
-                format: engine.swapchain().format(),
-                samples: device::SampleCount::One,
-                load_op: vk::AttachmentLoadOp::CLEAR,
-                store_op: vk::AttachmentStoreOp::STORE,
-                initial_layout: vk::ImageLayout::UNDEFINED,
-                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-                ..Default::default()
-            }],
-            vec![device::SubpassDesc::new(
-                vec![vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL],
-                vec![],
-                vec![0],
-                vec![],
-                vec![],
-                None,
-            )],
-            vec![vk::SubpassDependency {
-                src_subpass: vk::SUBPASS_EXTERNAL,
-                dst_subpass: 0,
-                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT_BIT,
-                src_access_mask: Default::default(),
-                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT_BIT,
-                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE_BIT,
-                ..Default::default()
-            }],
-        )
-    }
+fn create_render_graph(engine: &Engine) -> (CompiledGraph, PassId) {
+    let mut graph = RenderGraph::new();
+    let backbuffer = graph.backbuffer();
+    let pass = graph
+        .add_graphics_pass("cube")
+        .write_color(backbuffer, Some([0.0, 0.0, 0.0, 0.0]))
+        .id();
+    (graph.compile(engine).unwrap(), pass)
 }
 
 const INDEX_DATA: &'static [u32] = &[
@@ -154,27 +130,15 @@ fn record(app: &CubeApp, tinker: &Tinker) -> vk::CommandBuffer {
         .engine()
         .with_command_buffer(level, family, |mut cmds| {
             cmds.begin(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT_BIT, None);
-            begin_render_pass(tinker, &app.render_pass, &mut cmds);
-            draw_cube(app, tinker.engine(), &mut cmds);
+            let engine = tinker.engine();
+            app.graph.execute(engine, &mut cmds, |pass, cmds| {
+                assert_eq!(pass, app.cube_pass);
+                draw_cube(app, engine, cmds);
+            });
             cmds.end()
         })
 }
 
-fn begin_render_pass(tinker: &Tinker, render_pass: &Arc<RenderPass>, cmds: &mut device::CmdBuffer) {
-    let attachments: [device::AttachmentImage; 1] =
-        [Arc::clone(tinker.engine().swapchain_image()).into()];
-    tinker.engine().begin_render_pass(
-        cmds,
-        render_pass,
-        &attachments,
-        &[vk::ClearValue {
-            color: vk::ClearColorValue {
-                float_32: [0.0, 0.0, 0.0, 0.0],
-            },
-        }],
-    );
-}
-
 fn draw_cube(app: &CubeApp, engine: &Engine, cmds: &mut device::CmdBuffer) {
     let vert_shader = Arc::clone(engine.get_shader("cube_vert").unwrap());
     let frag_shader = Arc::clone(engine.get_shader("cube_frag").unwrap());
@@ -191,6 +155,7 @@ fn draw_cube(app: &CubeApp, engine: &Engine, cmds: &mut device::CmdBuffer) {
             subpass: cmds.subpass().unwrap(),
             layout: device::PipelineLayoutDesc {
                 set_layouts: smallvec![Arc::clone(app.descriptor_set.layout())],
+                ..Default::default()
             },
             vertex_layout: device::VertexInputLayout {
                 topology: device::PrimitiveTopology::TriangleList,
@@ -210,13 +175,21 @@ fn draw_cube(app: &CubeApp, engine: &Engine, cmds: &mut device::CmdBuffer) {
                 device::ShaderStage::Vertex => Arc::new(vert_shader.into()),
                 device::ShaderStage::Fragment => Arc::new(frag_shader.into()),
             },
+            patch_control_points: 0,
             cull_mode: device::CullMode::Back,
             wireframe: false,
             depth_test: false,
             depth_write: false,
             depth_cmp_op: Default::default(),
             depth_bias: false,
-            blend_state: device::default_color_blend_state(),
+            depth_clamp: false,
+            depth_bounds_test: false,
+            depth_bounds: [0.0, 1.0],
+            stencil_test: false,
+            stencil_front: Default::default(),
+            stencil_back: Default::default(),
+            blend_states: smallvec![device::default_color_blend_state()],
+            logic_op: None,
             blend_consts: [0.0; 4],
         });
         cmds.bind_gfx_pipe(&pipeline);
@@ -237,8 +210,10 @@ impl tinker::App for CubeApp {
 
     fn init(tinker: &mut Tinker) -> Self {
         let (idx, vtx, uniform) = create_buffers(tinker.engine());
+        let (graph, cube_pass) = create_render_graph(tinker.engine());
         CubeApp {
-            render_pass: create_render_pass(tinker.engine()),
+            graph,
+            cube_pass,
             descriptor_set: create_descriptor_set(tinker.engine(), &uniform),
             index_buffer: idx,
             vertex_buffer: vtx,
@@ -250,6 +225,14 @@ impl tinker::App for CubeApp {
         update_uniforms(tinker, &mut self.uniform_buffer);
         vec![record(self, tinker)]
     }
+
+    fn handle_event(&mut self, tinker: &mut Tinker, event: tinker::Event) {
+        if let tinker::Event::Resized(_) = event {
+            let (graph, cube_pass) = create_render_graph(tinker.engine());
+            self.graph = graph;
+            self.cube_pass = cube_pass;
+        }
+    }
 }
 
 fn main() {
@@ -26,7 +26,7 @@ fn create_render_pass(engine: &Engine) -> Arc<RenderPass> {
         RenderPass::new(
             engine.device_ref(),
             vec![device::AttachmentDescription {
-                format: engine.swapchain().format(),
+                format: engine.backbuffer_format(),
                 samples: device::SampleCount::One,
                 load_op: vk::AttachmentLoadOp::CLEAR,
                 store_op: vk::AttachmentStoreOp::STORE,
@@ -170,13 +170,13 @@ fn upload_data(tinker: &Tinker, app: &mut TextureApp) {
         staging.stage_buffer(
             &mut cmds,
             base::slice_to_bytes(INDEX_DATA),
-            &mut app.index_buffer.range(),
+            &mut app.index_buffer.range_mut(),
             Default::default(),
         );
         staging.stage_buffer(
             &mut cmds,
             base::slice_to_bytes(VERTEX_DATA),
-            &mut app.vertex_buffer.range(),
+            &mut app.vertex_buffer.range_mut(),
             Default::default(),
         );
         staging.submit(cmds);
@@ -218,8 +218,7 @@ fn record(app: &TextureApp, tinker: &Tinker) -> vk::CommandBuffer {
 }
 
 fn begin_render_pass(tinker: &Tinker, render_pass: &Arc<RenderPass>, cmds: &mut device::CmdBuffer) {
-    let attachments: [device::AttachmentImage; 1] =
-        [Arc::clone(tinker.engine().swapchain_image()).into()];
+    let attachments: [device::AttachmentImage; 1] = [tinker.engine().backbuffer()];
     tinker.engine().begin_render_pass(
         cmds,
         render_pass,
@@ -245,6 +244,7 @@ fn draw_thing(app: &TextureApp, engine: &Engine, cmds: &mut device::CmdBuffer) {
             subpass: cmds.subpass().unwrap(),
             layout: device::PipelineLayoutDesc {
                 set_layouts: smallvec![Arc::clone(app.descriptor_set.layout())],
+                ..Default::default()
             },
             vertex_layout: device::VertexInputLayout {
                 topology: device::PrimitiveTopology::TriangleList,
@@ -272,13 +272,21 @@ fn draw_thing(app: &TextureApp, engine: &Engine, cmds: &mut device::CmdBuffer) {
                 device::ShaderStage::Vertex => Arc::new(vert_shader.into()),
                 device::ShaderStage::Fragment => Arc::new(frag_shader.into()),
             },
+            patch_control_points: 0,
             cull_mode: device::CullMode::None,
             wireframe: false,
             depth_test: false,
             depth_write: false,
             depth_cmp_op: Default::default(),
             depth_bias: false,
-            blend_state: device::default_color_blend_state(),
+            depth_clamp: false,
+            depth_bounds_test: false,
+            depth_bounds: [0.0, 1.0],
+            stencil_test: false,
+            stencil_front: Default::default(),
+            stencil_back: Default::default(),
+            blend_states: smallvec![device::default_color_blend_state()],
+            logic_op: None,
             blend_consts: [0.0; 4],
         });
         cmds.bind_gfx_pipe(&pipeline);
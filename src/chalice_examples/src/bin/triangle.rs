@@ -17,7 +17,7 @@ fn create_render_pass(engine: &Engine) -> Arc<device::RenderPass> {
         device::RenderPass::new(
             engine.device_ref(),
             vec![device::AttachmentDescription {
-                format: engine.swapchain().format(),
+                format: engine.backbuffer_format(),
                 samples: device::SampleCount::One,
                 load_op: vk::AttachmentLoadOp::CLEAR,
                 store_op: vk::AttachmentStoreOp::STORE,
@@ -65,8 +65,7 @@ fn begin_render_pass(
     render_pass: &Arc<device::RenderPass>,
     cmds: &mut device::CmdBuffer,
 ) {
-    let attachments: [device::AttachmentImage; 1] =
-        [Arc::clone(tinker.engine().swapchain_image()).into()];
+    let attachments: [device::AttachmentImage; 1] = [tinker.engine().backbuffer()];
     tinker.engine().begin_render_pass(
         cmds,
         render_pass,
@@ -90,19 +89,28 @@ fn draw_triangle(engine: &Engine, cmds: &mut device::CmdBuffer) {
             subpass: cmds.subpass().unwrap(),
             layout: device::PipelineLayoutDesc {
                 set_layouts: smallvec![],
+                ..Default::default()
             },
             vertex_layout: Default::default(),
             stages: partial_map! {
                 device::ShaderStage::Vertex => Arc::new(vert_shader.into()),
                 device::ShaderStage::Fragment => Arc::new(frag_shader.into()),
             },
+            patch_control_points: 0,
             cull_mode: device::CullMode::None,
             wireframe: false,
             depth_test: false,
             depth_write: false,
             depth_cmp_op: Default::default(),
             depth_bias: false,
-            blend_state: device::default_color_blend_state(),
+            depth_clamp: false,
+            depth_bounds_test: false,
+            depth_bounds: [0.0, 1.0],
+            stencil_test: false,
+            stencil_front: Default::default(),
+            stencil_back: Default::default(),
+            blend_states: smallvec![device::default_color_blend_state()],
+            logic_op: None,
             blend_consts: [0.0; 4],
         });
         cmds.bind_gfx_pipe(&pipeline);
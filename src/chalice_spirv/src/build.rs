@@ -1,3 +1,4 @@
+use fnv::FnvHashMap as HashMap;
 use rspirv::{self, dr};
 use rspirv::binary::{Consumer, ParseAction};
 use spirv_headers as spv;
@@ -49,10 +50,12 @@ macro_rules! parse_operand {
 }
 
 fn raise_module(raw: &RawModule) -> Module {
+    let types = TypeLayouts::new(raw);
     let mut module = Module::new();
     build_decoration_sets(&mut module, raw);
-    raise_variables(&mut module, raw);
+    raise_variables(&mut module, raw, &types);
     raise_entry_points(&mut module, raw);
+    raise_push_constants(&mut module, raw, &types);
     module.decorations = Default::default(); // No longer needed
     module
 }
@@ -98,13 +101,17 @@ fn apply_name(module: &mut Module, operands: &[dr::Operand]) {
     decos.name = Some(name);
 }
 
-fn raise_variables(module: &mut Module, raw: &RawModule) {
+fn raise_variables(module: &mut Module, raw: &RawModule, types: &TypeLayouts<'_>) {
     for inst in raw.occurrences(spv::Op::Variable) {
-        raise_variable(module, inst);
+        raise_variable(module, inst, types);
     }
 }
 
-fn raise_variable(module: &mut Module, inst: &dr::Instruction) {
+fn raise_variable(
+    module: &mut Module,
+    inst: &dr::Instruction,
+    types: &TypeLayouts<'_>,
+) {
     assert_eq!(inst.class.opcode, spv::Op::Variable);
     let mut ops = inst.operands.iter();
     let id = inst.result_id.unwrap();
@@ -116,9 +123,13 @@ fn raise_variable(module: &mut Module, inst: &dr::Instruction) {
     match (decos.location, decos.set, decos.binding) {
         (Some(location), _, _) => {
             assert!(is_interface_storage(storage_class));
+            let pointer = types.def(inst.result_type.unwrap());
+            assert_eq!(pointer.class.opcode, spv::Op::TypePointer);
+            let pointee = parse_operand!(pointer.operands.iter().skip(1), IdRef);
             module.variables.insert(id, data::Variable {
                 storage_class,
                 location,
+                ty: types.interface_type(pointee),
                 name: decos.name.clone(),
             });
         },
@@ -169,6 +180,200 @@ fn raise_entry_point(module: &mut Module, inst: &dr::Instruction) {
     });
 }
 
+/// Type definitions plus explicit layout information for types used in
+/// uniform blocks.
+#[derive(Debug, Default)]
+struct TypeLayouts<'a> {
+    defs: HashMap<u32, &'a dr::Instruction>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), (u32, bool)>,
+    array_strides: HashMap<u32, u32>,
+}
+
+impl<'a> TypeLayouts<'a> {
+    fn new(raw: &'a RawModule) -> Self {
+        let mut layouts = Self::default();
+        for inst in raw.instructions.iter() {
+            let mut ops = inst.operands.iter();
+            match inst.class.opcode {
+                spv::Op::Decorate => {
+                    let target = parse_operand!(ops, IdRef);
+                    let decoration = parse_operand!(ops, Decoration);
+                    if decoration == spv::Decoration::ArrayStride {
+                        let stride = parse_operand!(ops, LiteralInt32);
+                        layouts.array_strides.insert(target, stride);
+                    }
+                },
+                spv::Op::MemberDecorate => {
+                    let target = parse_operand!(ops, IdRef);
+                    let member = parse_operand!(ops, LiteralInt32);
+                    let key = (target, member);
+                    match parse_operand!(ops, Decoration) {
+                        spv::Decoration::Offset => {
+                            let offset = parse_operand!(ops, LiteralInt32);
+                            layouts.member_offsets.insert(key, offset);
+                        },
+                        spv::Decoration::MatrixStride => {
+                            let stride = parse_operand!(ops, LiteralInt32);
+                            layouts.matrix_strides.entry(key).or_default().0 = stride;
+                        },
+                        spv::Decoration::RowMajor => {
+                            layouts.matrix_strides.entry(key).or_default().1 = true;
+                        },
+                        _ => {},
+                    }
+                },
+                _ => {
+                    if let Some(id) = inst.result_id {
+                        layouts.defs.insert(id, inst);
+                    }
+                },
+            }
+        }
+        layouts
+    }
+
+    fn def(&self, id: u32) -> &'a dr::Instruction {
+        self.defs[&id]
+    }
+
+    fn constant_value(&self, id: u32) -> u32 {
+        let inst = self.def(id);
+        assert_eq!(inst.class.opcode, spv::Op::Constant);
+        let mut ops = inst.operands.iter();
+        parse_operand!(ops, LiteralInt32)
+    }
+
+    /// Returns the byte range spanned by the members of a struct.
+    fn struct_range(&self, id: u32) -> (u32, u32) {
+        let inst = self.def(id);
+        assert_eq!(inst.class.opcode, spv::Op::TypeStruct);
+        let members = parse_operand!(inst.operands.iter(), IdRef*);
+        let mut start = u32::max_value();
+        let mut end = 0;
+        for (idx, &ty) in members.iter().enumerate() {
+            let key = (id, idx as u32);
+            let offset = self.member_offsets[&key];
+            let matrix = self.matrix_strides.get(&key).copied();
+            start = start.min(offset);
+            end = end.max(offset + self.size_of(ty, matrix));
+        }
+        (start.min(end), end)
+    }
+
+    /// Describes the type of an interface variable. Returns `None` for
+    /// types without a simple numeric shape, such as structs.
+    fn interface_type(&self, id: u32) -> Option<data::InterfaceType> {
+        let inst = self.def(id);
+        let mut ops = inst.operands.iter();
+        let scalar = match inst.class.opcode {
+            spv::Op::TypeInt => {
+                let width = parse_operand!(ops, LiteralInt32);
+                if parse_operand!(ops, LiteralInt32) != 0 {
+                    data::ScalarType::Int(width)
+                } else {
+                    data::ScalarType::Uint(width)
+                }
+            },
+            spv::Op::TypeFloat =>
+                data::ScalarType::Float(parse_operand!(ops, LiteralInt32)),
+            spv::Op::TypeVector => {
+                let component = parse_operand!(ops, IdRef);
+                let count = parse_operand!(ops, LiteralInt32);
+                let mut ty = self.interface_type(component)?;
+                ty.components = count;
+                return Some(ty);
+            },
+            spv::Op::TypeMatrix => {
+                let column = parse_operand!(ops, IdRef);
+                let columns = parse_operand!(ops, LiteralInt32);
+                let mut ty = self.interface_type(column)?;
+                ty.dims.push(columns);
+                return Some(ty);
+            },
+            spv::Op::TypeArray => {
+                let elem = parse_operand!(ops, IdRef);
+                let len = self.constant_value(parse_operand!(ops, IdRef));
+                let mut ty = self.interface_type(elem)?;
+                ty.dims.insert(0, len);
+                return Some(ty);
+            },
+            _ => return None,
+        };
+        Some(data::InterfaceType { scalar, components: 1, dims: Vec::new() })
+    }
+
+    /// Computes the size of a type with explicit layout. `matrix` is
+    /// the matrix stride and majorness inherited from the enclosing
+    /// struct member, if any.
+    fn size_of(&self, id: u32, matrix: Option<(u32, bool)>) -> u32 {
+        let inst = self.def(id);
+        let mut ops = inst.operands.iter();
+        match inst.class.opcode {
+            spv::Op::TypeBool => 4,
+            spv::Op::TypeInt | spv::Op::TypeFloat => {
+                let width = parse_operand!(ops, LiteralInt32);
+                width / 8
+            },
+            spv::Op::TypeVector => {
+                let component = parse_operand!(ops, IdRef);
+                let count = parse_operand!(ops, LiteralInt32);
+                count * self.size_of(component, None)
+            },
+            spv::Op::TypeMatrix => {
+                let column = parse_operand!(ops, IdRef);
+                let columns = parse_operand!(ops, LiteralInt32);
+                match matrix {
+                    Some((stride, false)) => columns * stride,
+                    Some((stride, true)) => {
+                        let rows = parse_operand!(
+                            self.def(column).operands.iter().skip(1), LiteralInt32);
+                        rows * stride
+                    },
+                    None => columns * self.size_of(column, None),
+                }
+            },
+            spv::Op::TypeArray => {
+                let elem = parse_operand!(ops, IdRef);
+                let len = self.constant_value(parse_operand!(ops, IdRef));
+                let stride = self.array_strides.get(&id).copied()
+                    .unwrap_or_else(|| self.size_of(elem, matrix));
+                len * stride
+            },
+            spv::Op::TypeStruct => self.struct_range(id).1,
+            op => panic!("unsupported type in uniform block: {:?}", op),
+        }
+    }
+}
+
+fn raise_push_constants(
+    module: &mut Module,
+    raw: &RawModule,
+    layouts: &TypeLayouts<'_>,
+) {
+    for inst in raw.occurrences(spv::Op::Variable) {
+        let mut ops = inst.operands.iter();
+        let storage_class = parse_operand!(ops, StorageClass);
+        if storage_class != spv::StorageClass::PushConstant { continue; }
+
+        let pointer = layouts.def(inst.result_type.unwrap());
+        assert_eq!(pointer.class.opcode, spv::Op::TypePointer);
+        let block = parse_operand!(pointer.operands.iter().skip(1), IdRef);
+        let (offset, end) = layouts.struct_range(block);
+
+        let id = inst.result_id.unwrap();
+        let name = module.decorations.get(&id)
+            .and_then(|decos| decos.name.clone());
+        // Assumes there is only one entry point per module
+        assert!(module.push_constants.is_none());
+        module.push_constants = Some(data::PushConstants {
+            offset,
+            size: end - offset,
+            name,
+        });
+    }
+}
+
 impl Consumer for RawModule {
     fn initialize(&mut self) -> ParseAction {
         ParseAction::Continue
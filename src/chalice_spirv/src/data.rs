@@ -10,6 +10,7 @@ pub struct Module {
     pub(crate) variables: HashMap<u32, Variable>,
     pub(crate) uniforms: HashMap<u32, Uniform>,
     pub(crate) entry_points: HashMap<String, EntryPoint>,
+    pub(crate) push_constants: Option<PushConstants>,
     pub(crate) decorations: HashMap<u32, DecorationSet>,
 }
 
@@ -29,9 +30,29 @@ pub(crate) struct Variable {
     #[derivative(Default(value = "spv::StorageClass::UniformConstant"))]
     pub(crate) storage_class: spv::StorageClass,
     pub(crate) location: u32,
+    pub(crate) ty: Option<InterfaceType>,
     pub(crate) name: Option<String>,
 }
 
+/// Component type of an interface variable, with its bit width.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ScalarType {
+    Int(u32),
+    Uint(u32),
+    Float(u32),
+}
+
+/// The type of an interface variable. Matrices are treated as arrays
+/// of column vectors.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct InterfaceType {
+    pub scalar: ScalarType,
+    /// Number of vector components, or 1 for scalars.
+    pub components: u32,
+    /// Array lengths, outermost first.
+    pub dims: Vec<u32>,
+}
+
 #[derive(Debug, Derivative)]
 #[derivative(Default)]
 pub(crate) struct Uniform {
@@ -50,3 +71,10 @@ pub(crate) struct EntryPoint {
     pub(crate) inputs: Vec<u32>,
     pub(crate) outputs: Vec<u32>,
 }
+
+#[derive(Debug, Default)]
+pub(crate) struct PushConstants {
+    pub(crate) offset: u32,
+    pub(crate) size: u32,
+    pub(crate) name: Option<String>,
+}
@@ -7,7 +7,7 @@ mod data;
 mod view;
 
 pub use build::{parse_bytes, parse_words};
-pub use data::Module;
+pub use data::{InterfaceType, Module, ScalarType};
 pub use view::*;
 
 pub use spv::ExecutionModel;
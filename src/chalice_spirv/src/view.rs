@@ -37,6 +37,13 @@ macro_rules! indexed_type {
 indexed_type!(Variable);
 indexed_type!(Uniform);
 
+/// The byte range spanned by the members of a push constant block.
+#[derive(Debug)]
+pub struct PushConstants<'m> {
+    module: &'m Module,
+    inner: &'m data::PushConstants,
+}
+
 #[derive(Debug)]
 pub struct EntryPoint<'m> {
     module: &'m Module,
@@ -51,6 +58,7 @@ impl Module {
             entry_points: Default::default(),
             variables: Default::default(),
             uniforms: Default::default(),
+            push_constants: Default::default(),
             decorations: Default::default(),
         }
     }
@@ -81,6 +89,11 @@ impl Module {
         let inner = self.uniforms.get(&index)?;
         Some(Uniform { module: self, index, inner })
     }
+
+    pub fn push_constants(&self) -> Option<PushConstants<'_>> {
+        let inner = self.push_constants.as_ref()?;
+        Some(PushConstants { module: self, inner })
+    }
 }
 
 impl<'m> EntryPoint<'m> {
@@ -120,6 +133,12 @@ impl Variable<'_> {
         self.inner().location
     }
 
+    /// The type of the variable, or `None` if it has no simple numeric
+    /// type, e.g. a struct.
+    pub fn ty(&self) -> Option<&data::InterfaceType> {
+        self.inner().ty.as_ref()
+    }
+
     pub fn name(&self) -> Option<&str> {
         Some(&self.inner().name.as_ref()?)
     }
@@ -142,3 +161,28 @@ impl Uniform<'_> {
         Some(&self.inner().name.as_ref()?)
     }
 }
+
+impl<'m> PushConstants<'m> {
+    pub fn module(&self) -> &'m Module {
+        self.module
+    }
+
+    fn inner(&self) -> &'m data::PushConstants {
+        self.inner
+    }
+
+    /// Offset of the first member of the block.
+    pub fn offset(&self) -> u32 {
+        self.inner().offset
+    }
+
+    /// Size of the block, not counting any leading bytes before
+    /// `offset`.
+    pub fn size(&self) -> u32 {
+        self.inner().size
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        Some(&self.inner().name.as_ref()?)
+    }
+}
@@ -21,6 +21,17 @@ fn parse() {
     let tex_coord = entry.outputs().find(|var| var.location() == 1).unwrap();
     assert_eq!(tex_coord.storage_class(), spv::StorageClass::Output);
     assert_eq!(tex_coord.name(), Some("out_texcoord0"));
+    assert_eq!(
+        tex_coord.ty(),
+        Some(&InterfaceType {
+            scalar: ScalarType::Float(32),
+            components: 2,
+            dims: Vec::new(),
+        })
+    );
+
+    let pos = entry.inputs().find(|var| var.location() == 0).unwrap();
+    assert_eq!(pos.ty().unwrap().components, 3);
 
     let instances = module
         .uniforms()
@@ -28,3 +39,15 @@ fn parse() {
         .unwrap();
     assert_eq!(instances.storage_class(), spv::StorageClass::Uniform);
 }
+
+#[test]
+fn push_constants() {
+    let data = std::fs::read("data/push_vert.spv").unwrap();
+    let module = parse_bytes(&data);
+
+    let push = module.push_constants().unwrap();
+    assert_eq!(push.offset(), 0);
+    // mat4 + vec4
+    assert_eq!(push.size(), 80);
+    assert_eq!(push.name(), Some("push"));
+}
@@ -4,14 +4,18 @@ use std::path::Path;
 use std::sync::mpsc::{channel, Receiver, TryRecvError};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use device::{AppInfo, Device};
+use device::{AppInfo, Device, Extent2D};
 use engine::Engine;
 use winit::window::Window;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Event {
     Close,
+    /// The backbuffer was resized. Anything which depends on its
+    /// extent, such as framebuffer images, must be recreated.
+    Resized(Extent2D),
 }
 
 /// Implements the main loop of an app.
@@ -27,8 +31,8 @@ pub struct Tinker {
     transfer_queue: Arc<device::Queue>,
     // Semaphore which presentation waits on
     backbuffer_semaphore: device::BinarySemaphore,
-    // Single semaphore for keeping track of rendering completion
-    frame_semaphore: device::TimelineSemaphore,
+    // Tracks completion of the last frame
+    last_frame: Option<device::Submission>,
 }
 
 pub trait App: Send + 'static {
@@ -37,20 +41,21 @@ pub trait App: Send + 'static {
     fn init(tinker: &mut Tinker) -> Self;
 
     fn frame(&mut self, tinker: &mut Tinker) -> Vec<vk::CommandBuffer>;
+
+    fn handle_event(&mut self, _tinker: &mut Tinker, _event: Event) {}
 }
 
 impl Tinker {
     fn new(window: Window, engine: Engine, receiver: Receiver<Event>) -> Self {
-        let queue = Arc::clone(&engine.queues()[0][0]);
         Self {
             window,
             receiver,
             start_time: std::time::Instant::now(),
             tick: 0,
-            graphics_queue: Arc::clone(&queue),
-            transfer_queue: queue,
+            graphics_queue: Arc::clone(engine.graphics_queue()),
+            transfer_queue: engine.transfer_queue(),
             backbuffer_semaphore: device::BinarySemaphore::new(engine.device_ref()),
-            frame_semaphore: device::TimelineSemaphore::new(engine.device_ref(), 0),
+            last_frame: None,
             engine,
         }
     }
@@ -83,39 +88,46 @@ impl Tinker {
         }
     }
 
-    pub unsafe fn new_frame(&mut self) {
-        self.frame_semaphore.wait(self.tick, 50_000_000).unwrap();
+    // Handles events sent by the window. Returns false if the app
+    // should close.
+    fn handle_events(&mut self) -> bool {
+        while let Some(event) = self.poll() {
+            match event {
+                Event::Close => return false,
+                Event::Resized(extent) => self.engine.resize(extent),
+            }
+        }
+        true
+    }
+
+    /// Begins a new frame. Returns false if there is nothing to render
+    /// to, e.g. because the window is minimized, in which case the
+    /// frame should be skipped.
+    pub unsafe fn new_frame(&mut self) -> bool {
+        if let Some(frame) = self.last_frame.take() {
+            frame.wait(50_000_000).unwrap();
+        }
+        if self.engine.acquire_next_image().unwrap().is_none() {
+            return false;
+        }
         self.tick += 1;
         self.engine.new_frame();
         unsafe { self.engine.reclaim_transient_resources() };
-        self.engine.acquire_next_image().unwrap();
+        true
     }
 
     pub fn present(&mut self) {
-        self.engine.present(&[&mut self.backbuffer_semaphore]);
+        self.engine
+            .present(&[&mut self.backbuffer_semaphore])
+            .unwrap();
     }
 
     pub fn submit_commands(&mut self, commands: &[vk::CommandBuffer]) {
-        unsafe {
-            self.graphics_queue.submit(&[device::SubmitInfo {
-                wait_sems: &[device::WaitInfo {
-                    semaphore: self.engine.acquire_semaphore_mut().inner_mut(),
-                    value: 0,
-                    stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT_BIT,
-                }],
-                sig_sems: &[
-                    device::SignalInfo {
-                        semaphore: self.backbuffer_semaphore.inner_mut(),
-                        value: 0,
-                    },
-                    device::SignalInfo {
-                        semaphore: self.frame_semaphore.inner_mut(),
-                        value: self.tick,
-                    },
-                ],
-                cmds: commands,
-            }]);
-        }
+        let submission = unsafe {
+            self.engine
+                .submit_frame(commands, &mut self.backbuffer_semaphore)
+        };
+        self.last_frame = Some(submission);
     }
 
     pub fn start_time(&self) -> std::time::Instant {
@@ -147,7 +159,6 @@ pub fn run_app<A: App>(shader_dir: &Path) {
             width: 1600,
             height: 900,
         })
-        .with_resizable(false)
         .build(&event_loop)
         .unwrap();
 
@@ -157,16 +168,24 @@ pub fn run_app<A: App>(shader_dir: &Path) {
     let (sender, receiver) = channel();
     let mut tinker = Tinker::new(window, engine, receiver);
     let mut app = A::init(&mut tinker);
-    let mut j = Some(thread::spawn(move || loop {
-        unsafe { tinker.new_frame() };
-        let cmds = app.frame(&mut tinker);
-        tinker.submit_commands(&cmds[..]);
-        tinker.present();
-        if let Some(Event::Close) = tinker.poll() {
-            tinker.device().wait_idle();
-            std::mem::drop(app);
-            break;
+    let mut j = Some(thread::spawn(move || {
+        while tinker.handle_events() {
+            let extent = tinker.engine().backbuffer_extent();
+            if !unsafe { tinker.new_frame() } {
+                // Wait for the window to be restored.
+                thread::sleep(Duration::from_millis(20));
+                continue;
+            }
+            let new_extent = tinker.engine().backbuffer_extent();
+            if new_extent != extent {
+                app.handle_event(&mut tinker, Event::Resized(new_extent));
+            }
+            let cmds = app.frame(&mut tinker);
+            tinker.submit_commands(&cmds[..]);
+            tinker.present();
         }
+        tinker.device().wait_idle();
+        std::mem::drop(app);
     }));
 
     event_loop.run(move |event, _, control_flow| {
@@ -185,6 +204,12 @@ pub fn run_app<A: App>(shader_dir: &Path) {
                 let _ = j.take().unwrap().join();
                 control_flow.set_exit();
             }
+            winit::event::Event::WindowEvent {
+                event: winit::event::WindowEvent::Resized(size),
+                ..
+            } => {
+                let _ = sender.send(Event::Resized(Extent2D::new(size.width, size.height)));
+            }
             _ => {}
         }
     });